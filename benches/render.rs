@@ -0,0 +1,163 @@
+//! Timings for each part of drawing a meme: measuring and wrapping its text,
+//! rasterizing the glyphs, and encoding the finished PNG. Run them with
+//! `cargo bench` before and after changing any of these.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use did_you_just_say_bot::layout::{measure_text, wrap_text};
+use did_you_just_say_bot::output::{encode_png, OutputSettings};
+use did_you_just_say_bot::raster::{
+    clear_glyph_cache, rasterize_text, StrokeJoin, TextEffect, TextEffectKind,
+};
+use image::png::CompressionType;
+use image::{Rgba, RgbaImage};
+use rusttype::{Font, FontCollection, Scale};
+use std::sync::Arc;
+
+const TEXTS: [(&str, &str); 3] = [
+    ("short", "did you just say"),
+    (
+        "medium",
+        "did you just say that the build is green when nobody has run the tests",
+    ),
+    (
+        "long",
+        "did you just say that the build is green when nobody has run the tests since \
+         last tuesday, the lock file was regenerated by hand, and half of the benchmarks \
+         were commented out because they took too long to finish on the laptop",
+    ),
+];
+
+// The sizes of a small square template and a large landscape one
+const TEMPLATES: [(&str, u32, u32); 2] = [("small", 500, 500), ("large", 1200, 900)];
+
+/// The first installed font the bench can open, as the bot would load it by
+/// family name
+fn system_font() -> Option<Arc<Font<'static>>> {
+    let mut system_fonts = fontdb::Database::new();
+    system_fonts.load_system_fonts();
+
+    let font = system_fonts.faces().find_map(|face| {
+        system_fonts
+            .with_face_data(face.id, |data, index| {
+                FontCollection::from_bytes(data.to_vec())
+                    .ok()?
+                    .font_at(index as usize)
+                    .ok()
+            })
+            .flatten()
+    });
+
+    font.map(Arc::new)
+}
+
+/// A template-sized image with some variation in it, so that PNG encoding
+/// isn't timed on a single flat color
+fn template_image(width: u32, height: u32) -> RgbaImage {
+    RgbaImage::from_fn(width, height, |x, y| {
+        Rgba([(x % 256) as u8, (y % 256) as u8, ((x ^ y) % 256) as u8, 255])
+    })
+}
+
+fn bench_layout(c: &mut Criterion, font: &Font<'static>) {
+    let scale = Scale::uniform(48f32);
+    let mut group = c.benchmark_group("layout");
+
+    for (name, text) in TEXTS.iter() {
+        group.throughput(Throughput::Bytes(text.len() as u64));
+
+        group.bench_with_input(BenchmarkId::new("measure_text", name), text, |b, text| {
+            b.iter(|| measure_text(font, scale, text, None, false))
+        });
+        group.bench_with_input(BenchmarkId::new("wrap_text", name), text, |b, text| {
+            b.iter(|| wrap_text(font, scale, text, 400, false))
+        });
+        group.bench_with_input(
+            BenchmarkId::new("wrap_text_hyphenated", name),
+            text,
+            |b, text| b.iter(|| wrap_text(font, scale, text, 150, true)),
+        );
+    }
+
+    group.finish();
+}
+
+fn bench_raster(c: &mut Criterion, font: &Arc<Font<'static>>) {
+    let scale = Scale::uniform(48f32);
+    let wave = TextEffect {
+        kind: TextEffectKind::Wave,
+        amplitude: 8f32,
+        frequency: 2f32,
+        seed: 0,
+    };
+    let mut group = c.benchmark_group("raster");
+
+    for (name, text) in TEXTS.iter() {
+        group.throughput(Throughput::Bytes(text.len() as u64));
+
+        // Every glyph is rasterized again, as for the first meme made with a font
+        group.bench_with_input(BenchmarkId::new("uncached", name), text, |b, text| {
+            b.iter(|| {
+                clear_glyph_cache();
+                rasterize_text(font, scale, text, TextEffect::NONE)
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("cached", name), text, |b, text| {
+            b.iter(|| rasterize_text(font, scale, text, TextEffect::NONE))
+        });
+        group.bench_with_input(BenchmarkId::new("wave", name), text, |b, text| {
+            b.iter(|| rasterize_text(font, scale, text, wave))
+        });
+        group.bench_with_input(BenchmarkId::new("stroked", name), text, |b, text| {
+            let mask = rasterize_text(font, scale, text, TextEffect::NONE);
+
+            b.iter(|| mask.stroke(3, StrokeJoin::Round))
+        });
+        group.bench_with_input(BenchmarkId::new("draw", name), text, |b, text| {
+            let mask = rasterize_text(font, scale, text, TextEffect::NONE);
+            let mut image = template_image(1200, 900);
+
+            b.iter(|| mask.draw(&mut image, 10, 10, Rgba([255, 255, 255, 255])))
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode_png");
+    group.sample_size(20);
+
+    for (name, width, height) in TEMPLATES.iter() {
+        let image = template_image(*width, *height);
+
+        for compression in ["fast", "default", "best"].iter() {
+            let settings = OutputSettings {
+                compression: OutputSettings::compression_from_name(compression)
+                    .unwrap_or(CompressionType::Fast),
+                ..OutputSettings::default()
+            };
+
+            group.bench_with_input(BenchmarkId::new(*compression, name), &image, |b, image| {
+                b.iter(|| encode_png(image, settings))
+            });
+        }
+    }
+
+    group.finish();
+}
+
+fn bench_render(c: &mut Criterion) {
+    // PNG encoding doesn't need a font, so it's timed even without one
+    bench_encode(c);
+
+    match system_font() {
+        Some(font) => {
+            bench_layout(c, &font);
+            bench_raster(c, &font);
+        }
+        None => eprintln!("No fonts are installed, so text layout and rasterizing are skipped"),
+    }
+}
+
+criterion_group!(benches, bench_render);
+criterion_main!(benches);
@@ -0,0 +1,262 @@
+use std::time::{Duration, UNIX_EPOCH};
+
+use serenity::model::prelude::Message;
+use serenity::prelude::Context;
+
+use crate::aliases::resolve_alias;
+use crate::color::format_color;
+use crate::guild_settings::GuildSettingsKey;
+use crate::hotkeys::display_emoji;
+use crate::raster::TextEffectKind;
+use crate::registry::{Meme, MemesKey};
+use crate::usage::UsageCountsKey;
+use crate::BotSettingsKey;
+
+/// The fields of the `about` embed, as (name, value) pairs
+fn describe(
+    meme: &Meme,
+    aliases: &[String],
+    hotkeys: &[String],
+    uses: u64,
+    usage_retention: Option<Duration>,
+) -> Vec<(String, String)> {
+    let (width, height) = meme.image.dimensions();
+    let loaded_at = meme
+        .loaded_at
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+
+    let mut fields = vec![(
+        "Template".to_string(),
+        format!(
+            "{}×{} pixels, version `{}`, loaded <t:{}:R>",
+            width, height, meme.version, loaded_at
+        ),
+    )];
+
+    if let Some(license) = &meme.license {
+        fields.push(("License".into(), license.clone()));
+    }
+
+    if let Some(source_url) = &meme.source_url {
+        fields.push(("Source".into(), source_url.clone()));
+    }
+
+    if meme.avatar_regions.is_empty() {
+        fields.push((
+            "Text area".into(),
+            format!(
+                "left {}, top {}, right {}, bottom {}",
+                meme.left, meme.top, meme.right, meme.bottom
+            ),
+        ));
+    } else {
+        let regions: Vec<String> = meme
+            .avatar_regions
+            .iter()
+            .map(|avatar_region| {
+                let region = avatar_region.region;
+
+                format!(
+                    "left {}, top {}, right {}, bottom {}",
+                    region.left, region.top, region.right, region.bottom
+                )
+            })
+            .collect();
+
+        fields.push(("Avatar areas".into(), regions.join("\n")));
+    }
+
+    let mut font = format!("`{}` at {}", meme.font, meme.scale.y);
+
+    if let Some(code_font) = &meme.code_font {
+        font += &format!(", code in `{}`", code_font);
+    }
+
+    fields.push(("Font".into(), font));
+
+    let mut style = vec![
+        format!("color {}", format_color(meme.text_color)),
+        format!("aligned {}", meme.alignment.name()),
+        format!("direction {}", meme.direction.name()),
+        format!("transform {}", meme.transform.name()),
+    ];
+
+    if meme.stroke_width > 0 {
+        style.push(format!(
+            "{}px {} outline in {}",
+            meme.stroke_width,
+            meme.stroke_join.name(),
+            format_color(meme.stroke_color)
+        ));
+    }
+
+    if meme.effect.kind != TextEffectKind::None {
+        style.push(format!(
+            "{} effect of {}px",
+            meme.effect.kind.name(),
+            meme.effect.amplitude
+        ));
+    }
+
+    if meme.hyphenate {
+        style.push("hyphenated".into());
+    }
+
+    if meme.allow_zalgo {
+        style.push("zalgo allowed".into());
+    }
+
+    fields.push(("Style".into(), style.join(", ")));
+
+    if let Some(animation) = meme.animation {
+        fields.push((
+            "Animation".into(),
+            format!(
+                "{} over {} ms at {} fps",
+                animation.kind.name(),
+                animation.duration_ms,
+                animation.fps
+            ),
+        ));
+    }
+
+    if let Some(plugin) = &meme.plugin {
+        fields.push(("Plugin".into(), format!("`{}`", plugin)));
+    }
+
+    if !meme.text_prefix.is_empty() || !meme.text_suffix.is_empty() {
+        fields.push((
+            "Added text".into(),
+            format!("\"{}\" … \"{}\"", meme.text_prefix, meme.text_suffix),
+        ));
+    }
+
+    let mut picking = vec![format!("weight {}", meme.weight)];
+
+    if meme.is_default {
+        picking.push("default".into());
+    }
+
+    if let Some(category) = &meme.category {
+        picking.push(format!("category `{}`", category));
+    }
+
+    if meme.schedule.is_some() {
+        picking.push("seasonal".into());
+    }
+
+    fields.push(("Picking".into(), picking.join(", ")));
+
+    if !aliases.is_empty() {
+        fields.push(("Aliases".into(), aliases.join(", ")));
+    }
+
+    if !hotkeys.is_empty() {
+        fields.push(("Hotkeys".into(), hotkeys.join(" ")));
+    }
+
+    let period = match usage_retention {
+        Some(retention) => format!("in the last {} days", retention.as_secs() / (24 * 60 * 60)),
+        None => "since the bot started".into(),
+    };
+
+    fields.push(("Used".into(), format!("{} times {}", uses, period)));
+
+    fields
+}
+
+/// Shows everything that decides how the meme `rest` is drawn
+pub fn about_command(ctx: &Context, msg: &Message, rest: &str) {
+    let command = rest.trim().to_lowercase();
+
+    let data = ctx.data.read();
+
+    let command = msg
+        .guild_id
+        .and_then(|guild_id| resolve_alias(&data, guild_id.0, &command))
+        .unwrap_or(command);
+
+    let memes = data
+        .get::<MemesKey>()
+        .expect("Command about: Unable to retrieve memes")
+        .load();
+
+    let meme = match memes.find(&command) {
+        Some(meme) => meme,
+        None => {
+            drop(data);
+
+            let reply = if command.is_empty() {
+                "Usage: `about <meme>`".to_string()
+            } else {
+                format!("There's no meme called `{}`.", command)
+            };
+
+            msg.channel_id.say(ctx, reply).ok();
+            return;
+        }
+    };
+
+    let settings = msg.guild_id.and_then(|guild_id| {
+        data.get::<GuildSettingsKey>()
+            .expect("Command about: Unable to retrieve guild settings")
+            .get(guild_id.0)
+    });
+
+    let (aliases, hotkeys) = match settings {
+        Some(settings) => (
+            settings
+                .aliases
+                .iter()
+                .filter(|(_, target)| **target == meme.command)
+                .map(|(alias, _)| format!("`{}`", alias))
+                .collect(),
+            settings
+                .hotkeys
+                .iter()
+                .filter(|(_, target)| **target == meme.command)
+                .map(|(emoji, _)| display_emoji(emoji))
+                .collect(),
+        ),
+        None => (Vec::new(), Vec::new()),
+    };
+
+    let uses = data
+        .get::<UsageCountsKey>()
+        .expect("Command about: Unable to retrieve usage counts")
+        .get(&meme.command);
+    let usage_retention = data
+        .get::<BotSettingsKey>()
+        .expect("Command about: Unable to retrieve bot settings")
+        .retention
+        .usage;
+
+    let fields = describe(meme, &aliases, &hotkeys, uses, usage_retention);
+    let thumbnail_filename = format!("{}-{}.png", meme.command, meme.version);
+
+    msg.channel_id
+        .send_message(ctx, |m| {
+            m.embed(|e| {
+                e.title(format!("`{}`", meme.command));
+
+                if let Some(description) = &meme.description {
+                    e.description(description);
+                }
+
+                if !meme.thumbnail.is_empty() {
+                    e.thumbnail(format!("attachment://{}", thumbnail_filename));
+                }
+
+                e.fields(fields.iter().map(|(name, value)| (name, value, false)))
+            });
+
+            if !meme.thumbnail.is_empty() {
+                m.add_file((&meme.thumbnail[..], thumbnail_filename.as_str()));
+            }
+
+            m
+        })
+        .ok();
+}
@@ -0,0 +1,89 @@
+use std::fs::{create_dir_all, read_to_string, write};
+use std::path::Path;
+use yaml_rust::yaml::{Hash, Yaml};
+use yaml_rust::{YamlEmitter, YamlLoader};
+
+/// Memes added while the bot is running, like with `addmeme`, are kept in
+/// their own file in the same format as the config, and always stored locally
+/// so that they can be written even if the config comes from object storage.
+/// They're loaded after the config, so they can extend the memes in it.
+#[derive(Clone)]
+pub struct AddedMemesFile {
+    pub filename: String,
+    // Where the templates of added memes are saved
+    pub images_dir: String,
+}
+
+impl AddedMemesFile {
+    /// Returns the meme sections in the file. A missing file is not an error,
+    /// since no memes have been added yet.
+    pub fn read_sections(&self) -> Result<Vec<Yaml>, String> {
+        let contents = match read_to_string(&self.filename) {
+            Ok(contents) => contents,
+            Err(_) => return Ok(Vec::new()),
+        };
+
+        let yaml = match YamlLoader::load_from_str(&contents) {
+            Ok(yaml) => yaml,
+            Err(reason) => {
+                return Err(format!(
+                    "Unable to parse added memes file \"{}\": {}",
+                    self.filename, reason
+                ));
+            }
+        };
+
+        match yaml.into_iter().next() {
+            Some(Yaml::Array(sections)) => Ok(sections),
+            None => Ok(Vec::new()),
+            Some(_) => Err(format!(
+                "Added memes file \"{}\" does not contain a list of memes",
+                self.filename
+            )),
+        }
+    }
+
+    pub fn write_sections(&self, sections: Vec<Yaml>) -> Result<(), String> {
+        let mut contents = String::new();
+
+        if let Err(reason) = YamlEmitter::new(&mut contents).dump(&Yaml::Array(sections)) {
+            return Err(format!("Unable to serialize added memes: {:?}", reason));
+        }
+
+        if let Err(reason) = write(&self.filename, contents + "\n") {
+            return Err(format!(
+                "Unable to write added memes file \"{}\": {}",
+                self.filename, reason
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Saves the PNG-encoded template of the meme `command`, returning the
+    /// path to put in its `filename`
+    pub fn save_image(&self, command: &str, png: &[u8]) -> Result<String, String> {
+        if let Err(reason) = create_dir_all(&self.images_dir) {
+            return Err(format!(
+                "Unable to create directory \"{}\": {}",
+                self.images_dir, reason
+            ));
+        }
+
+        let path = Path::new(&self.images_dir).join(format!("{}.png", command));
+        let path = path.to_string_lossy().to_string();
+
+        if let Err(reason) = write(&path, png) {
+            return Err(format!("Unable to write image \"{}\": {}", path, reason));
+        }
+
+        Ok(path)
+    }
+
+    pub fn append(&self, section: Hash) -> Result<(), String> {
+        let mut sections = self.read_sections()?;
+        sections.push(Yaml::Hash(section));
+
+        self.write_sections(sections)
+    }
+}
@@ -0,0 +1,216 @@
+use log::warn;
+use std::collections::BTreeMap;
+use std::fs::{read_to_string, write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use yaml_rust::yaml::{Hash, Yaml};
+use yaml_rust::{YamlEmitter, YamlLoader};
+
+use serenity::prelude::{Mutex, TypeMapKey};
+
+use crate::redact;
+
+/// A user who authenticated with the admin password, and until when they stay
+/// authenticated
+pub struct AdminSession {
+    pub user_id: u64,
+    pub name: String,
+    // Seconds since the Unix epoch
+    pub expires_at: u64,
+}
+
+/// The admin sessions, persisted to a YAML file so that admins stay
+/// authenticated across restarts
+pub struct AdminSessionStore {
+    filename: String,
+    lifetime: Duration,
+    sessions: BTreeMap<u64, AdminSession>,
+}
+
+pub struct AdminSessionsKey;
+
+// Locked on their own, so that logging in and out doesn't need exclusive access
+// to everything else
+impl TypeMapKey for AdminSessionsKey {
+    type Value = Mutex<AdminSessionStore>;
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or(0)
+}
+
+impl AdminSession {
+    pub fn remaining(&self) -> Duration {
+        Duration::from_secs(self.expires_at.saturating_sub(now()))
+    }
+}
+
+impl AdminSessionStore {
+    /// Loads the sessions from `filename`, dropping any that have expired. A
+    /// missing file is not an error, since nobody has authenticated yet.
+    pub fn load(filename: &str, lifetime: Duration) -> Result<AdminSessionStore, String> {
+        let mut store = AdminSessionStore {
+            filename: filename.into(),
+            lifetime,
+            sessions: BTreeMap::new(),
+        };
+
+        let contents = match read_to_string(filename) {
+            Ok(contents) => contents,
+            Err(_) => return Ok(store),
+        };
+
+        let yaml = match YamlLoader::load_from_str(&contents) {
+            Ok(yaml) => yaml,
+            Err(reason) => {
+                return Err(format!(
+                    "Unable to parse admin sessions file \"{}\": {}",
+                    filename, reason
+                ));
+            }
+        };
+
+        if let Some(Yaml::Hash(sessions)) = yaml.first() {
+            for (user_id, session) in sessions {
+                let user_id = match user_id.as_str().and_then(|id| id.parse::<u64>().ok()) {
+                    Some(user_id) => user_id,
+                    None => {
+                        warn!(
+                            "Admin sessions file contains invalid user ID \"{:?}\"",
+                            user_id
+                        );
+                        continue;
+                    }
+                };
+
+                let name = session["name"].as_str().unwrap_or("").to_string();
+
+                let expires_at = match session["expires_at"].as_i64() {
+                    Some(expires_at) if expires_at >= 0 => expires_at as u64,
+                    _ => {
+                        warn!(
+                            "Admin session for user {} is malformed",
+                            redact::user_id(user_id)
+                        );
+                        continue;
+                    }
+                };
+
+                store.sessions.insert(
+                    user_id,
+                    AdminSession {
+                        user_id,
+                        name,
+                        expires_at,
+                    },
+                );
+            }
+        }
+
+        store.remove_expired();
+
+        Ok(store)
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let mut sessions = Hash::new();
+
+        for session in self.sessions.values() {
+            let mut entry = Hash::new();
+            entry.insert(
+                Yaml::String("name".into()),
+                Yaml::String(session.name.clone()),
+            );
+            entry.insert(
+                Yaml::String("expires_at".into()),
+                Yaml::Integer(session.expires_at as i64),
+            );
+
+            sessions.insert(Yaml::String(session.user_id.to_string()), Yaml::Hash(entry));
+        }
+
+        let mut contents = String::new();
+
+        if let Err(reason) = YamlEmitter::new(&mut contents).dump(&Yaml::Hash(sessions)) {
+            return Err(format!("Unable to serialize admin sessions: {:?}", reason));
+        }
+
+        if let Err(reason) = write(&self.filename, contents + "\n") {
+            return Err(format!(
+                "Unable to write admin sessions file \"{}\": {}",
+                self.filename, reason
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn remove_expired(&mut self) {
+        let now = now();
+
+        self.sessions.retain(|_, session| session.expires_at > now);
+    }
+
+    pub fn is_admin(&self, user_id: u64) -> bool {
+        match self.sessions.get(&user_id) {
+            Some(session) => session.expires_at > now(),
+            None => false,
+        }
+    }
+
+    /// Starts a session for `user_id`, replacing any existing one
+    pub fn start(&mut self, user_id: u64, name: &str) {
+        self.remove_expired();
+
+        self.sessions.insert(
+            user_id,
+            AdminSession {
+                user_id,
+                name: name.into(),
+                // Kept within what the sessions file can hold, so that very
+                // long sessions survive being saved and loaded again
+                expires_at: now()
+                    .saturating_add(self.lifetime.as_secs())
+                    .min(i64::MAX as u64),
+            },
+        );
+    }
+
+    pub fn list(&mut self) -> Vec<&AdminSession> {
+        self.remove_expired();
+
+        self.sessions.values().collect()
+    }
+
+    /// Returns whether there was a session to revoke
+    pub fn revoke(&mut self, user_id: u64) -> bool {
+        self.sessions.remove(&user_id).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_very_long_sessions_across_restarts() {
+        let filename = std::env::temp_dir()
+            .join(format!("admin-sessions-{}.yml", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        let lifetime = Duration::from_secs(u64::MAX);
+
+        let mut store = AdminSessionStore::load(&filename, lifetime).unwrap();
+        store.start(1, "admin");
+
+        assert!(store.is_admin(1));
+        store.save().unwrap();
+
+        let loaded = AdminSessionStore::load(&filename, lifetime);
+        std::fs::remove_file(&filename).unwrap();
+
+        assert!(loaded.unwrap().is_admin(1));
+    }
+}
@@ -0,0 +1,157 @@
+use log::warn;
+
+use serenity::model::prelude::Message;
+use serenity::prelude::{Context, ShareMap};
+
+use crate::guild_settings::GuildSettingsKey;
+use crate::permissions::is_manager;
+use crate::registry::MemesKey;
+use crate::split_first_word;
+use crate::wizard::{check_command, RESERVED_COMMANDS};
+
+// Aliases for this stand for the default meme, like the text without a meme
+pub const DEFAULT_MEME: &str = "default";
+
+const MAX_ALIASES: usize = 100;
+
+/// Returns what `word` stands for in the guild `guild_id`, if it's one of
+/// its aliases
+pub fn resolve_alias(data: &ShareMap, guild_id: u64, word: &str) -> Option<String> {
+    data.get::<GuildSettingsKey>()
+        .expect("Aliases: Unable to retrieve guild settings")
+        .get(guild_id)
+        .and_then(|settings| settings.aliases.get(word))
+        .cloned()
+}
+
+fn list_aliases(ctx: &Context, msg: &Message, guild_id: u64) {
+    let data = ctx.data.read();
+    let aliases = data
+        .get::<GuildSettingsKey>()
+        .expect("Command aliases: Unable to retrieve guild settings")
+        .get(guild_id)
+        .map(|settings| &settings.aliases);
+
+    let reply = match aliases {
+        Some(aliases) if !aliases.is_empty() => {
+            let lines: Vec<String> = aliases
+                .iter()
+                .map(|(alias, command)| format!("`{}` - `{}`", alias, command))
+                .collect();
+
+            format!("This server's aliases:\n{}", lines.join("\n"))
+        }
+        _ => "This server has no aliases. (A manager can add one with `aliases add <alias> <command>`.)"
+            .into(),
+    };
+
+    drop(data);
+
+    msg.channel_id.say(ctx, reply).ok();
+}
+
+fn check_target(ctx: &Context, command: &str) -> Result<(), String> {
+    if command == DEFAULT_MEME || RESERVED_COMMANDS.contains(&command) {
+        return Ok(());
+    }
+
+    if ctx
+        .data
+        .read()
+        .get::<MemesKey>()
+        .expect("Command aliases: Unable to retrieve memes")
+        .load()
+        .contains(command)
+    {
+        return Ok(());
+    }
+
+    Err(format!(
+        "There's no meme or command called `{}`. (Use `{}` for the default meme.)",
+        command, DEFAULT_MEME
+    ))
+}
+
+pub fn aliases_command(ctx: &Context, msg: &Message, rest: &str) {
+    let guild_id = match msg.guild_id {
+        Some(guild_id) => guild_id.0,
+        None => {
+            msg.channel_id
+                .say(ctx, "Aliases can only be set up in a server.")
+                .ok();
+            return;
+        }
+    };
+
+    let (action, rest) = split_first_word(rest);
+    let (alias, rest) = split_first_word(rest);
+    let (command, _) = split_first_word(rest);
+
+    let action = action.to_lowercase();
+    let alias = alias.to_lowercase();
+    let command = command.to_lowercase();
+
+    if action.is_empty() {
+        list_aliases(ctx, msg, guild_id);
+        return;
+    }
+
+    if !is_manager(ctx, msg) {
+        msg.channel_id
+            .say(
+                ctx,
+                "You need to be a manager of this server (or have the Manage Server permission) to do that.",
+            )
+            .ok();
+        return;
+    }
+
+    let checked = match action.as_str() {
+        "add" if !alias.is_empty() && !command.is_empty() => {
+            check_command(ctx, &alias).and_then(|_| check_target(ctx, &command))
+        }
+        "remove" if !alias.is_empty() => Ok(()),
+        _ => {
+            msg.channel_id
+                .say(
+                    ctx,
+                    "Usage: `aliases [add <alias> <command>|remove <alias>]`",
+                )
+                .ok();
+            return;
+        }
+    };
+
+    if let Err(reason) = checked {
+        msg.channel_id.say(ctx, reason).ok();
+        return;
+    }
+
+    let mut data = ctx.data.write();
+    let guild_settings = data
+        .get_mut::<GuildSettingsKey>()
+        .expect("Command aliases: Unable to retrieve guild settings");
+    let aliases = &mut guild_settings.get_mut(guild_id).aliases;
+
+    let reply = if action == "add" {
+        if aliases.len() >= MAX_ALIASES && !aliases.contains_key(&alias) {
+            format!("A server can't have more than {} aliases.", MAX_ALIASES)
+        } else {
+            aliases.insert(alias.clone(), command.clone());
+
+            format!("`{}` now stands for `{}`.", alias, command)
+        }
+    } else if aliases.remove(&alias).is_some() {
+        format!("`{}` isn't an alias anymore.", alias)
+    } else {
+        format!("`{}` isn't an alias.", alias)
+    };
+
+    if let Err(reason) = guild_settings.save() {
+        warn!("Command aliases: {}", reason);
+    }
+
+    drop(data);
+
+    msg.channel_id.say(ctx, reply).ok();
+}
@@ -0,0 +1,464 @@
+use gif::{Encoder, Frame, Repeat, SetParameter};
+use image::imageops::{self, FilterType};
+use image::RgbaImage;
+use log::{debug, warn};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rusttype::Font;
+use std::cell::Cell;
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use crate::registry::Meme;
+use crate::render::{render_meme_frame, TextFrame, TextStyle};
+
+// Animations have at most this many frames; longer or faster ones get fewer
+// frames, each shown for longer
+const MAX_FRAMES: u32 = 50;
+
+// Discord doesn't accept larger uploads without a boosted server, so this is
+// the budget where the server isn't known
+pub const MAX_ANIMATION_BYTES: usize = 8 * 1024 * 1024;
+
+// The last frame of animations that end is shown this much longer, so that the
+// finished text can be read before the animation starts over
+const HOLD_MS: u32 = 1500;
+
+// Animations that are too large are shrunk this many times before giving up
+const MAX_DOWNSCALES: u32 = 3;
+
+// Animations are encoded at most this many times while finding a size that
+// fits, since each try takes about as long as rendering all of the frames
+const MAX_ENCODES: u32 = 3;
+
+// How hard each frame is quantized to a palette, from 1 (best) to 30 (fastest)
+const QUANTIZE_SPEED: i32 = 10;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum AnimationKind {
+    // The text appears one character at a time
+    Typewriter,
+    // The text fades in
+    Fade,
+    // The text is shaken around, over and over
+    Shake,
+}
+
+impl AnimationKind {
+    /// Parses the name of an animation; `none` is `Ok(None)`
+    pub fn from_name(name: &str) -> Result<Option<AnimationKind>, ()> {
+        match name.to_lowercase().as_str() {
+            "none" => Ok(None),
+            "typewriter" => Ok(Some(AnimationKind::Typewriter)),
+            "fade" => Ok(Some(AnimationKind::Fade)),
+            "shake" => Ok(Some(AnimationKind::Shake)),
+            _ => Err(()),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AnimationKind::Typewriter => "typewriter",
+            AnimationKind::Fade => "fade",
+            AnimationKind::Shake => "shake",
+        }
+    }
+}
+
+/// How the text of a meme is animated, making it a GIF
+#[derive(Clone, Copy)]
+pub struct Animation {
+    pub kind: AnimationKind,
+    pub duration_ms: u32,
+    pub fps: u32,
+}
+
+/// One frame of a finished meme, with how long it's shown for
+pub struct AnimationFrame {
+    pub image: RgbaImage,
+    // 0 for memes that aren't animated
+    pub delay_ms: u32,
+}
+
+impl AnimationFrame {
+    pub fn still(image: RgbaImage) -> AnimationFrame {
+        AnimationFrame { image, delay_ms: 0 }
+    }
+}
+
+/// Draws each frame of the meme with its text animated
+pub fn render_animation(
+    meme: &Meme,
+    font: &Arc<Font<'static>>,
+    text: &str,
+    style: TextStyle,
+    animation: Animation,
+) -> Vec<AnimationFrame> {
+    let count = (animation.duration_ms * animation.fps / 1000).clamp(2, MAX_FRAMES);
+    let delay_ms = animation.duration_ms / count;
+
+    debug!(
+        "Animating meme \"{}\" with {} frames of {} ms",
+        meme.command, count, delay_ms
+    );
+
+    let amplitude = ((meme.scale.y / 12f32).round() as i32).max(1);
+    let mut rng = StdRng::seed_from_u64(style.seed);
+
+    (0..count)
+        .map(|index| {
+            let progress = (index + 1) as f32 / count as f32;
+
+            let frame = match animation.kind {
+                AnimationKind::Typewriter => TextFrame {
+                    reveal: progress,
+                    ..TextFrame::STILL
+                },
+                AnimationKind::Fade => TextFrame {
+                    opacity: progress,
+                    ..TextFrame::STILL
+                },
+                AnimationKind::Shake => TextFrame {
+                    offset: (
+                        rng.gen_range(-amplitude, amplitude + 1),
+                        rng.gen_range(-amplitude, amplitude + 1),
+                    ),
+                    ..TextFrame::STILL
+                },
+            };
+
+            let is_held = index + 1 == count && animation.kind != AnimationKind::Shake;
+
+            AnimationFrame {
+                image: render_meme_frame(meme, font, text, style, frame),
+                delay_ms: if is_held {
+                    delay_ms + HOLD_MS
+                } else {
+                    delay_ms
+                },
+            }
+        })
+        .collect()
+}
+
+/// Counts the bytes written through it, so that the size of each frame in a
+/// GIF can be told apart
+struct CountingWriter<'a> {
+    buffer: &'a mut Vec<u8>,
+    count: &'a Cell<usize>,
+}
+
+impl Write for CountingWriter<'_> {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.count.set(self.count.get() + bytes.len());
+        self.buffer.write(bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// A GIF, along with how many bytes each of its frames takes up in it
+type EncodedGif = (Vec<u8>, Vec<usize>);
+
+/// Encodes `frames` as a looping GIF, returning it along with how many bytes
+/// each frame takes up in it
+fn encode_gif(frames: &[AnimationFrame]) -> Result<EncodedGif, String> {
+    let (width, height) = match frames.first() {
+        Some(frame) => frame.image.dimensions(),
+        None => return Err("Unable to encode animation: No frames".into()),
+    };
+
+    let mut buffer = Vec::new();
+    let mut frame_sizes = Vec::with_capacity(frames.len());
+    let count = Cell::new(0);
+
+    {
+        let writer = CountingWriter {
+            buffer: &mut buffer,
+            count: &count,
+        };
+        let mut encoder = Encoder::new(writer, width as u16, height as u16, &[])
+            .map_err(|reason| format!("Unable to encode animation: {}", reason))?;
+
+        encoder
+            .set(Repeat::Infinite)
+            .map_err(|reason| format!("Unable to encode animation: {}", reason))?;
+
+        for frame in frames {
+            let mut pixels = frame.image.clone().into_raw();
+            let mut gif_frame =
+                Frame::from_rgba_speed(width as u16, height as u16, &mut pixels, QUANTIZE_SPEED);
+            // GIFs count in hundredths of a second
+            gif_frame.delay = (frame.delay_ms / 10).max(2) as u16;
+
+            let start = count.get();
+
+            encoder
+                .write_frame(&gif_frame)
+                .map_err(|reason| format!("Unable to encode animation: {}", reason))?;
+
+            frame_sizes.push(count.get() - start);
+        }
+    }
+
+    Ok((buffer, frame_sizes))
+}
+
+/// Whether `decimate` keeps the frame at `index` out of `count`
+fn is_kept(index: usize, count: usize) -> bool {
+    // The last frame is kept, since it's the one with all of the text
+    index.is_multiple_of(2) || index + 1 == count
+}
+
+/// Leaves out every other frame, showing the others for longer
+fn decimate(frames: Vec<AnimationFrame>) -> Vec<AnimationFrame> {
+    let count = frames.len();
+    let mut kept = Vec::<AnimationFrame>::new();
+
+    for (index, frame) in frames.into_iter().enumerate() {
+        match kept.last_mut() {
+            Some(last) if !is_kept(index, count) => last.delay_ms += frame.delay_ms,
+            _ => kept.push(frame),
+        }
+    }
+
+    kept
+}
+
+/// Shrinks the frames to 3/4 of their size `times` times over, in one go
+fn downscale(frames: Vec<AnimationFrame>, times: u32) -> Vec<AnimationFrame> {
+    let shrink = |length: u32| ((length as u64 * 3u64.pow(times) / 4u64.pow(times)) as u32).max(1);
+
+    frames
+        .into_iter()
+        .map(|frame| {
+            let width = shrink(frame.image.width());
+            let height = shrink(frame.image.height());
+
+            AnimationFrame {
+                image: imageops::resize(&frame.image, width, height, FilterType::Triangle),
+                delay_ms: frame.delay_ms,
+            }
+        })
+        .collect()
+}
+
+/// How to make an animation small enough: how many times to leave out every
+/// other frame, and then how many times to shrink the frames
+#[derive(Debug, PartialEq)]
+struct Reduction {
+    decimations: u32,
+    downscales: u32,
+}
+
+/// Picks how to get an animation that came to `size` bytes, with each frame
+/// taking up `frame_sizes`, down to `max_bytes`, without encoding it again.
+/// Frames are encoded on their own, so the size without some of them is
+/// known; shrinking them is assumed to shrink the animation with their area.
+/// Returns `None` if it won't fit even with `downscales_left` downscales.
+fn plan_reduction(
+    size: usize,
+    frame_sizes: &[usize],
+    max_bytes: usize,
+    downscales_left: u32,
+) -> Option<Reduction> {
+    let overhead = size - frame_sizes.iter().sum::<usize>();
+    let mut frame_sizes = frame_sizes.to_vec();
+    let mut estimate = size;
+    let mut reduction = Reduction {
+        decimations: 0,
+        downscales: 0,
+    };
+
+    while estimate > max_bytes && frame_sizes.len() > MAX_FRAMES as usize / 4 {
+        let count = frame_sizes.len();
+
+        frame_sizes = frame_sizes
+            .into_iter()
+            .enumerate()
+            .filter(|(index, _)| is_kept(*index, count))
+            .map(|(_, size)| size)
+            .collect();
+        estimate = overhead + frame_sizes.iter().sum::<usize>();
+        reduction.decimations += 1;
+    }
+
+    // Each downscale leaves 9/16 of the pixels
+    while estimate > max_bytes && reduction.downscales < downscales_left {
+        estimate = estimate * 9 / 16;
+        reduction.downscales += 1;
+    }
+
+    if estimate > max_bytes {
+        return None;
+    }
+
+    Some(reduction)
+}
+
+/// Encodes `frames` as a looping GIF of at most `max_bytes`, leaving out
+/// frames and then shrinking them until it fits. Errors are meant to be shown
+/// to users.
+pub fn encode_animation(frames: Vec<AnimationFrame>, max_bytes: usize) -> Result<Vec<u8>, String> {
+    fit_animation(frames, max_bytes, encode_gif)
+}
+
+/// The same as `encode_animation`, encoding with `encode`
+fn fit_animation(
+    frames: Vec<AnimationFrame>,
+    max_bytes: usize,
+    mut encode: impl FnMut(&[AnimationFrame]) -> Result<EncodedGif, String>,
+) -> Result<Vec<u8>, String> {
+    let too_large = || {
+        Err(
+            "This meme is too large to animate. (Try shorter text, or a meme that isn't animated.)"
+                .into(),
+        )
+    };
+
+    let mut frames = frames;
+    let mut downscales = 0;
+
+    for encodes in 1..=MAX_ENCODES {
+        let (gif, frame_sizes) = match encode(&frames) {
+            Ok(encoded) => encoded,
+            Err(reason) => {
+                warn!("Animation: {}", reason);
+                return Err("Sorry, something went wrong! Maybe try again?".into());
+            }
+        };
+
+        if gif.len() <= max_bytes {
+            return Ok(gif);
+        }
+
+        if encodes == MAX_ENCODES {
+            break;
+        }
+
+        let reduction = match plan_reduction(
+            gif.len(),
+            &frame_sizes,
+            max_bytes,
+            MAX_DOWNSCALES - downscales,
+        ) {
+            Some(reduction) => reduction,
+            None => return too_large(),
+        };
+
+        debug!(
+            "Animation of {} frames is too large ({} bytes), so it's decimated {} times and downscaled {} times",
+            frames.len(),
+            gif.len(),
+            reduction.decimations,
+            reduction.downscales
+        );
+
+        for _ in 0..reduction.decimations {
+            frames = decimate(frames);
+        }
+
+        if reduction.downscales > 0 {
+            frames = downscale(frames, reduction.downscales);
+            downscales += reduction.downscales;
+        }
+    }
+
+    too_large()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    /// Frames of random noise, which GIFs can't compress much
+    fn noise(count: usize, size: u32) -> Vec<AnimationFrame> {
+        let mut rng = StdRng::seed_from_u64(1456);
+
+        (0..count)
+            .map(|_| AnimationFrame {
+                image: RgbaImage::from_fn(size, size, |_, _| {
+                    Rgba([rng.gen(), rng.gen(), rng.gen(), 255])
+                }),
+                delay_ms: 100,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn knows_the_size_without_left_out_frames() {
+        let (gif, frame_sizes) = encode_gif(&noise(9, 16)).unwrap();
+        let (decimated, _) = encode_gif(&decimate(noise(9, 16))).unwrap();
+
+        let overhead = gif.len() - frame_sizes.iter().sum::<usize>();
+        let kept: usize = frame_sizes
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| is_kept(*index, frame_sizes.len()))
+            .map(|(_, size)| size)
+            .sum();
+
+        assert_eq!(decimated.len(), overhead + kept);
+    }
+
+    #[test]
+    fn plans_reductions() {
+        let frame_sizes = vec![100; 40];
+        let plan = |max_bytes, downscales_left| {
+            plan_reduction(4100, &frame_sizes, max_bytes, downscales_left)
+        };
+
+        // 40 frames become 20, then 10, which is few enough to stop at
+        assert_eq!(
+            plan(2500, 3),
+            Some(Reduction {
+                decimations: 1,
+                downscales: 0
+            })
+        );
+        assert_eq!(
+            plan(1500, 3),
+            Some(Reduction {
+                decimations: 2,
+                downscales: 0
+            })
+        );
+        assert_eq!(
+            plan(500, 3),
+            Some(Reduction {
+                decimations: 2,
+                downscales: 2
+            })
+        );
+        assert_eq!(plan(500, 1), None);
+        assert_eq!(plan(100, 3), None);
+    }
+
+    /// Encodes like `encode_gif`, counting each time in `encodes`
+    fn encode_counting(
+        encodes: &Cell<u32>,
+    ) -> impl FnMut(&[AnimationFrame]) -> Result<EncodedGif, String> + '_ {
+        move |frames| {
+            encodes.set(encodes.get() + 1);
+            encode_gif(frames)
+        }
+    }
+
+    #[test]
+    fn encodes_large_animations_only_a_few_times() {
+        let encodes = Cell::new(0);
+        let gif = fit_animation(noise(50, 64), 20_000, encode_counting(&encodes)).unwrap();
+
+        assert!(gif.len() <= 20_000);
+        assert!(encodes.get() <= MAX_ENCODES);
+
+        // Animations that can't be made small enough are given up on at once
+        let encodes = Cell::new(0);
+
+        assert!(fit_animation(noise(50, 64), 100, encode_counting(&encodes)).is_err());
+        assert_eq!(encodes.get(), 1);
+    }
+}
@@ -0,0 +1,51 @@
+use image::RgbaImage;
+
+use serenity::model::user::User;
+
+use crate::decode::{decode_image, MAX_IMAGE_BYTES};
+use crate::fetch;
+use crate::moderation;
+use crate::redact;
+
+// Avatars and emoji are fetched at this size, which is plenty for pasting
+// into memes
+const AVATAR_SIZE: u32 = 256;
+
+fn avatar_url(user: &User) -> String {
+    match &user.avatar {
+        // Request PNGs, which can always be decoded, rather than the default
+        // WebP or GIF
+        Some(hash) => format!(
+            "https://cdn.discordapp.com/avatars/{}/{}.png?size={}",
+            user.id.0, hash, AVATAR_SIZE
+        ),
+        None => user.default_avatar_url(),
+    }
+}
+
+pub fn fetch_avatar(user: &User) -> Result<RgbaImage, String> {
+    let url = avatar_url(user);
+    let contents = fetch::download(&url, MAX_IMAGE_BYTES)?;
+    moderation::check(&contents, &format!("avatar of {}", redact::user(user)))?;
+
+    match decode_image(&contents) {
+        Ok(image) => Ok(image),
+        Err(reason) => Err(format!("Unable to decode avatar \"{}\": {}", url, reason)),
+    }
+}
+
+/// Fetches the custom emoji `id` from Discord's CDN. Animated emoji come as
+/// their first frame.
+pub fn fetch_emoji(id: u64) -> Result<RgbaImage, String> {
+    let url = format!(
+        "https://cdn.discordapp.com/emojis/{}.png?size={}",
+        id, AVATAR_SIZE
+    );
+    let contents = fetch::download(&url, MAX_IMAGE_BYTES)?;
+    moderation::check(&contents, &format!("emoji {}", id))?;
+
+    match decode_image(&contents) {
+        Ok(image) => Ok(image),
+        Err(reason) => Err(format!("Unable to decode emoji \"{}\": {}", url, reason)),
+    }
+}
@@ -0,0 +1,288 @@
+use image::{Rgba, RgbaImage};
+use log::warn;
+use regex::Regex;
+use rusttype::{Font, Scale};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serenity::model::prelude::Message;
+use serenity::prelude::{Context, TypeMapKey};
+
+use crate::raster::{rasterize_text, StrokeJoin, TextEffect};
+use crate::registry::{MemesKey, Region};
+use crate::render::{encode_png, OutputSettings};
+use crate::suggest::draw_region_preview;
+use crate::{select_font, FontsKey};
+
+// Calibrations that haven't been answered for this long are abandoned
+const CALIBRATION_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+// The longer side of the template is split into this many cells, so columns
+// only need the letters A to P
+const GRID_CELLS: u32 = 16;
+
+// Cells are never smaller than this, so that their labels stay readable
+const MIN_CELL_SIZE: u32 = 16;
+
+/// A template posted with a grid, waiting for the admin to pick the corners
+/// of the text area
+pub struct Calibration {
+    command: String,
+    cell_size: u32,
+    width: u32,
+    height: u32,
+    started: Instant,
+}
+
+pub struct CalibrationsKey;
+
+impl TypeMapKey for CalibrationsKey {
+    // User IDs to their calibrations
+    type Value = HashMap<u64, Calibration>;
+}
+
+fn column_name(column: u32) -> char {
+    (b'A' + column as u8) as char
+}
+
+/// Returns a copy of `image` with a grid of `cell_size` cells drawn over it,
+/// each labeled with its column letter and row number, like "B3"
+fn draw_grid(image: &RgbaImage, font: &Arc<Font<'static>>, cell_size: u32) -> RgbaImage {
+    let mut grid = image.clone();
+    let line_color = Rgba([255, 0, 128, 255]);
+
+    for y in 0..grid.height() {
+        for x in 0..grid.width() {
+            if x % cell_size == 0 || y % cell_size == 0 {
+                let pixel = grid.get_pixel_mut(x, y);
+
+                for channel in 0..3 {
+                    pixel[channel] =
+                        ((pixel[channel] as u32 + line_color[channel] as u32) / 2) as u8;
+                }
+            }
+        }
+    }
+
+    let scale = Scale::uniform((cell_size / 3).max(8) as f32);
+
+    for row in 0..grid.height().div_ceil(cell_size) {
+        for column in 0..grid.width().div_ceil(cell_size) {
+            let label = format!("{}{}", column_name(column), row + 1);
+            let mask = rasterize_text(font, scale, &label, TextEffect::NONE);
+            let x = (column * cell_size + 2) as i32;
+            let y = (row * cell_size + 2) as i32;
+
+            mask.stroke(1, StrokeJoin::Round)
+                .draw(&mut grid, x, y, Rgba([255, 255, 255, 255]));
+            mask.draw(&mut grid, x, y, Rgba([0, 0, 0, 255]));
+        }
+    }
+
+    grid
+}
+
+/// Starts calibrating the text area of the meme `rest` by posting its
+/// template with a grid over it
+pub fn start_calibration(ctx: &Context, msg: &Message, rest: &str) {
+    let command = rest.trim().to_lowercase();
+
+    let data = ctx.data.read();
+    let memes = data
+        .get::<MemesKey>()
+        .expect("Command calibrate: Unable to retrieve memes")
+        .load();
+    let fonts = data
+        .get::<FontsKey>()
+        .expect("Command calibrate: Unable to retrieve fonts");
+
+    let meme = match memes.find(&command) {
+        Some(meme) => meme,
+        None => {
+            msg.channel_id.say(ctx, "Usage: `calibrate <meme>`").ok();
+            return;
+        }
+    };
+
+    let font = match select_font(fonts, meme) {
+        Some(font) => font.clone(),
+        None => {
+            msg.channel_id.say(ctx, "No fonts are loaded.").ok();
+            return;
+        }
+    };
+
+    // Drawing the grid can take a while, so it's done without holding on to
+    // the data
+    drop(data);
+
+    let (width, height) = meme.image.dimensions();
+    let cell_size = width.max(height).div_ceil(GRID_CELLS).max(MIN_CELL_SIZE);
+
+    let grid = encode_png(
+        &draw_grid(&meme.image, &font, cell_size),
+        OutputSettings::default(),
+    );
+
+    let grid = match grid {
+        Ok(grid) => grid,
+        Err(reason) => {
+            warn!("Command calibrate: {}", reason);
+
+            msg.channel_id
+                .say(ctx, format!("Unable to draw the grid: {}", reason))
+                .ok();
+            return;
+        }
+    };
+
+    ctx.data
+        .write()
+        .get_mut::<CalibrationsKey>()
+        .expect("Command calibrate: Unable to retrieve calibrations")
+        .insert(
+            msg.author.id.0,
+            Calibration {
+                command: command.clone(),
+                cell_size,
+                width,
+                height,
+                started: Instant::now(),
+            },
+        );
+
+    msg.channel_id
+        .send_files(ctx, vec![(&grid[..], "grid.png")], |m| {
+            m.content(format!(
+                "Reply with the cells at the top-left and bottom-right corners of the text area of `{}`, like `B2 H5`. (Send `cancel` to stop.)",
+                command
+            ))
+        })
+        .ok();
+}
+
+/// Converts the cells in a reply like "B2 H5" into the pixels they cover
+fn cells_to_region(text: &str, calibration: &Calibration) -> Option<Region> {
+    let re_cells = Regex::new(r"^([A-Za-z])(\d+)[\s,]+([A-Za-z])(\d+)$")
+        .expect("Unable to create cell matching pattern");
+
+    let captures = re_cells.captures(text)?;
+
+    let column = |index: usize| (captures[index].to_ascii_uppercase().as_bytes()[0] - b'A') as u32;
+    let row = |index: usize| captures[index].parse::<u32>().ok()?.checked_sub(1);
+
+    let (first_column, last_column) = (column(1), column(3));
+    let (first_row, last_row) = (row(2)?, row(4)?);
+
+    let cell_size = calibration.cell_size;
+
+    let region = Region {
+        left: first_column.min(last_column) * cell_size,
+        top: first_row.min(last_row) * cell_size,
+        right: ((first_column.max(last_column) + 1) * cell_size).min(calibration.width),
+        bottom: ((first_row.max(last_row) + 1) * cell_size).min(calibration.height),
+    };
+
+    if region.left >= region.right || region.top >= region.bottom {
+        return None;
+    }
+
+    Some(region)
+}
+
+/// Handles a DM from someone in the middle of calibrating a meme, returning
+/// whether it was an answer to the calibration. Other messages are left for
+/// the usual commands.
+pub fn handle_calibration_message(ctx: &Context, msg: &Message) -> bool {
+    let user_id = msg.author.id.0;
+    let text = msg.content.trim();
+
+    let mut data = ctx.data.write();
+    let calibrations = data
+        .get_mut::<CalibrationsKey>()
+        .expect("Command calibrate: Unable to retrieve calibrations");
+
+    let calibration = match calibrations.get(&user_id) {
+        Some(calibration) if calibration.started.elapsed() < CALIBRATION_TIMEOUT => calibration,
+        _ => return false,
+    };
+
+    if text.eq_ignore_ascii_case("cancel") {
+        calibrations.remove(&user_id);
+        drop(data);
+
+        msg.channel_id.say(ctx, "Okay, calibration cancelled.").ok();
+        return true;
+    }
+
+    let region = match cells_to_region(text, calibration) {
+        Some(region) => region,
+        None => return false,
+    };
+
+    let calibration = calibrations
+        .remove(&user_id)
+        .expect("Command calibrate: Calibration disappeared");
+
+    let memes = data
+        .get::<MemesKey>()
+        .expect("Command calibrate: Unable to retrieve memes")
+        .load();
+
+    let preview = match memes.find(&calibration.command) {
+        Some(meme) => encode_png(
+            &draw_region_preview(&meme.image, region),
+            OutputSettings::default(),
+        )
+        .ok(),
+        None => None,
+    };
+
+    drop(data);
+
+    let content = format!(
+        "Region for `{}`:\n```yml\n  left: {}\n  top: {}\n  right: {}\n  bottom: {}\n```\nFor memes added with `addmeme`, send `setregion {} {} {} {} {}` to use it.",
+        calibration.command,
+        region.left,
+        region.top,
+        region.right,
+        region.bottom,
+        calibration.command,
+        region.left,
+        region.top,
+        region.right,
+        region.bottom
+    );
+
+    match preview {
+        Some(preview) => {
+            msg.channel_id
+                .send_files(ctx, vec![(&preview[..], "region.png")], |m| {
+                    m.content(content)
+                })
+                .ok();
+        }
+        None => {
+            msg.channel_id.say(ctx, content).ok();
+        }
+    }
+
+    true
+}
+
+#[cfg(test)]
+pub mod mock {
+    use super::*;
+
+    /// A calibration of a 100x100 template with 20 pixel cells
+    pub fn calibration(command: &str) -> Calibration {
+        Calibration {
+            command: command.into(),
+            cell_size: 20,
+            width: 100,
+            height: 100,
+            started: Instant::now(),
+        }
+    }
+}
@@ -0,0 +1,290 @@
+use std::sync::OnceLock;
+
+use log::warn;
+
+use serenity::model::prelude::Message;
+use serenity::prelude::{Context, ShareMap};
+
+use crate::guild_settings::GuildSettingsKey;
+use crate::permissions::is_manager;
+use crate::pipeline::STAGES;
+use crate::split_first_word;
+
+/// The parts of the bot that reach beyond its own config, which operators can
+/// turn off
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    // Downloading anything over HTTP, like fonts, templates, and avatars
+    Fetching,
+    // Reading the text of text files attached to messages
+    TextAttachments,
+    // Pasting users' avatars into memes
+    Avatars,
+    // Adding memes while the bot is running, with `addmeme` and `import`
+    AddingMemes,
+}
+
+impl Capability {
+    /// Says what's turned off, to tell users why a command didn't work
+    pub fn disabled_message(self) -> &'static str {
+        match self {
+            Capability::Fetching => "Downloads are turned off.",
+            Capability::TextAttachments => "Reading attached text files is turned off.",
+            Capability::Avatars => "Memes with avatars are turned off.",
+            Capability::AddingMemes => "Adding memes is turned off.",
+        }
+    }
+}
+
+/// A part of the bot that each server can turn on or off with the `features`
+/// command. The effects, like `deepfry`, are features too.
+pub struct Feature {
+    pub name: &'static str,
+    pub description: &'static str,
+    // The capability the feature needs, which safe mode can turn off whatever
+    // the servers choose
+    capability: Option<Capability>,
+}
+
+pub const FEATURES: &[Feature] = &[
+    Feature {
+        name: "animations",
+        description: "animated text on the memes that have it",
+        capability: None,
+    },
+    Feature {
+        name: "avatars",
+        description: "memes with users' avatars",
+        capability: Some(Capability::Avatars),
+    },
+    Feature {
+        name: "code",
+        description: "drawing code blocks in a code font",
+        capability: None,
+    },
+    Feature {
+        name: "hotkeys",
+        description: "creating memes by reacting with hotkeys",
+        capability: None,
+    },
+    Feature {
+        name: "presets",
+        description: "saving and using presets",
+        capability: None,
+    },
+    Feature {
+        name: "random",
+        description: "the `random` command",
+        capability: None,
+    },
+    Feature {
+        name: "replies",
+        description: "meming the message that's replied to",
+        capability: None,
+    },
+    Feature {
+        name: "text-attachments",
+        description: "reading attached text files",
+        capability: Some(Capability::TextAttachments),
+    },
+];
+
+/// Returns the names and descriptions of all features, including effects
+pub fn all_features() -> Vec<(&'static str, &'static str)> {
+    FEATURES
+        .iter()
+        .map(|feature| (feature.name, feature.description))
+        .chain(STAGES.iter().map(|stage| (stage.name, stage.description)))
+        .collect()
+}
+
+pub fn is_feature(name: &str) -> bool {
+    all_features().iter().any(|(feature, _)| *feature == name)
+}
+
+pub struct CapabilitySettings {
+    // Turns off everything that fetches from the network or changes the
+    // memes, for a bot that only ever uses its pre-vetted templates
+    pub safe_mode: bool,
+    // Features that are off unless a server turns them on
+    pub disabled_features: Vec<String>,
+}
+
+static SETTINGS: OnceLock<CapabilitySettings> = OnceLock::new();
+
+/// Sets which capabilities are available, once when the bot starts. Before
+/// this is called, everything is.
+pub fn configure(settings: CapabilitySettings) -> Result<(), String> {
+    if SETTINGS.set(settings).is_err() {
+        return Err("Capabilities have already been configured".into());
+    }
+
+    Ok(())
+}
+
+pub fn is_enabled(capability: Capability) -> bool {
+    let safe_mode = SETTINGS.get().is_some_and(|settings| settings.safe_mode);
+
+    match capability {
+        Capability::Fetching
+        | Capability::TextAttachments
+        | Capability::Avatars
+        | Capability::AddingMemes => !safe_mode,
+    }
+}
+
+/// Returns whether the feature `name` is on in the guild `guild_id`, or by
+/// default for DMs
+pub fn is_feature_enabled(data: &ShareMap, guild_id: Option<u64>, name: &str) -> bool {
+    let capability = FEATURES
+        .iter()
+        .find(|feature| feature.name == name)
+        .and_then(|feature| feature.capability);
+
+    if capability.is_some_and(|capability| !is_enabled(capability)) {
+        return false;
+    }
+
+    let chosen = guild_id.and_then(|guild_id| {
+        data.get::<GuildSettingsKey>()
+            .expect("Features: Unable to retrieve guild settings")
+            .get(guild_id)
+            .and_then(|settings| settings.features.get(name).copied())
+    });
+
+    chosen.unwrap_or_else(|| is_enabled_by_default(name))
+}
+
+pub fn is_enabled_by_default(name: &str) -> bool {
+    !SETTINGS.get().is_some_and(|settings| {
+        settings
+            .disabled_features
+            .iter()
+            .any(|feature| feature == name)
+    })
+}
+
+fn list_features(ctx: &Context, msg: &Message, guild_id: u64) {
+    let data = ctx.data.read();
+
+    let lines: Vec<String> = all_features()
+        .into_iter()
+        .map(|(name, description)| {
+            format!(
+                "{} `{}` - {}",
+                if is_feature_enabled(&data, Some(guild_id), name) {
+                    "On:"
+                } else {
+                    "Off:"
+                },
+                name,
+                description
+            )
+        })
+        .collect();
+
+    drop(data);
+
+    msg.channel_id
+        .say(
+            ctx,
+            format!(
+                "**Features**\n{}\n(Managers can change these with `features enable <feature>`, `features disable <feature>`, or `features reset <feature>`.)",
+                lines.join("\n")
+            ),
+        )
+        .ok();
+}
+
+pub fn features_command(ctx: &Context, msg: &Message, rest: &str) {
+    let guild_id = match msg.guild_id {
+        Some(guild_id) => guild_id.0,
+        None => {
+            msg.channel_id
+                .say(ctx, "Features can only be chosen in a server.")
+                .ok();
+            return;
+        }
+    };
+
+    let (action, rest) = split_first_word(rest);
+    let (name, _) = split_first_word(rest);
+
+    let action = action.to_lowercase();
+    let name = name.to_lowercase();
+
+    if action.is_empty() {
+        list_features(ctx, msg, guild_id);
+        return;
+    }
+
+    if !is_manager(ctx, msg) {
+        msg.channel_id
+            .say(
+                ctx,
+                "You need to be a manager of this server (or have the Manage Server permission) to do that.",
+            )
+            .ok();
+        return;
+    }
+
+    let enabled = match action.as_str() {
+        "enable" if !name.is_empty() => Some(true),
+        "disable" if !name.is_empty() => Some(false),
+        "reset" if !name.is_empty() => None,
+        _ => {
+            msg.channel_id
+                .say(ctx, "Usage: `features [enable|disable|reset <feature>]`")
+                .ok();
+            return;
+        }
+    };
+
+    if !is_feature(&name) {
+        msg.channel_id
+            .say(
+                ctx,
+                format!("There's no feature called `{}`. (Try `features`.)", name),
+            )
+            .ok();
+        return;
+    }
+
+    let mut data = ctx.data.write();
+    let guild_settings = data
+        .get_mut::<GuildSettingsKey>()
+        .expect("Command features: Unable to retrieve guild settings");
+    let features = &mut guild_settings.get_mut(guild_id).features;
+
+    match enabled {
+        Some(enabled) => features.insert(name.clone(), enabled),
+        None => features.remove(&name),
+    };
+
+    if let Err(reason) = guild_settings.save() {
+        warn!("Command features: {}", reason);
+    }
+
+    let is_enabled = is_feature_enabled(&data, Some(guild_id), &name);
+
+    drop(data);
+
+    let reply = match (enabled, is_enabled) {
+        (Some(true), false) => format!(
+            "`{}` is turned on for this server, but the bot's operator has turned it off everywhere.",
+            name
+        ),
+        (Some(_), _) => format!(
+            "`{}` is now {}.",
+            name,
+            if is_enabled { "on" } else { "off" }
+        ),
+        (None, _) => format!(
+            "`{}` is back to the default, which is {}.",
+            name,
+            if is_enabled { "on" } else { "off" }
+        ),
+    };
+
+    msg.channel_id.say(ctx, reply).ok();
+}
@@ -0,0 +1,235 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+// Locale-specific casing rules from Unicode's SpecialCasing.txt that
+// `str::to_uppercase` doesn't apply, since it only implements the
+// language-independent mappings
+
+const COMBINING_DOT_ABOVE: char = '\u{307}';
+const COMBINING_ACUTE_ACCENT: char = '\u{301}';
+const COMBINING_GREEK_TONOS: char = '\u{344}';
+
+/// Returns the language part of a locale tag, e.g. "tr" for "tr-TR"
+fn language(locale: &str) -> String {
+    locale.split(['-', '_']).next().unwrap_or("").to_lowercase()
+}
+
+fn is_soft_dotted(c: char) -> bool {
+    matches!(c, 'i' | 'j' | 'į' | 'ɨ' | 'ʝ')
+}
+
+fn remove_greek_accent(c: char) -> Option<char> {
+    let base = match c {
+        'ά' | 'Ά' => 'Α',
+        'έ' | 'Έ' => 'Ε',
+        'ή' | 'Ή' => 'Η',
+        'ί' | 'Ί' => 'Ι',
+        'ΐ' => 'Ϊ',
+        'ό' | 'Ό' => 'Ο',
+        'ύ' | 'Ύ' => 'Υ',
+        'ΰ' => 'Ϋ',
+        'ώ' | 'Ώ' => 'Ω',
+        _ => return None,
+    };
+
+    Some(base)
+}
+
+/// Uppercases `text` using the casing rules of `locale` (a BCP 47 tag such as
+/// "tr" or "el-GR"), falling back to the default Unicode mapping for locales
+/// without special rules.
+pub fn to_uppercase(text: &str, locale: Option<&str>) -> String {
+    let language = match locale {
+        Some(locale) => language(locale),
+        None => return text.to_uppercase(),
+    };
+
+    let mut result = String::with_capacity(text.len());
+    let mut previous: Option<char> = None;
+
+    for c in text.chars() {
+        match language.as_str() {
+            // Turkish and Azeri keep the dot: i -> İ, ı -> I
+            "tr" | "az" if c == 'i' => result.push('İ'),
+            // Lithuanian drops the dot that was kept on a lowercase soft-dotted
+            // letter to retain it under an accent
+            "lt" if c == COMBINING_DOT_ABOVE && previous.is_some_and(is_soft_dotted) => {}
+            // Greek drops accents when uppercasing
+            "el" => match remove_greek_accent(c) {
+                Some(base) => result.push(base),
+                None if c == COMBINING_ACUTE_ACCENT || c == COMBINING_GREEK_TONOS => {}
+                None => result.extend(c.to_uppercase()),
+            },
+            _ => result.extend(c.to_uppercase()),
+        }
+
+        previous = Some(c);
+    }
+
+    result
+}
+
+/// Lowercases `text` using the casing rules of `locale`, like `to_uppercase`
+pub fn to_lowercase(text: &str, locale: Option<&str>) -> String {
+    let language = match locale {
+        Some(locale) => language(locale),
+        None => return text.to_lowercase(),
+    };
+
+    if language != "tr" && language != "az" {
+        return text.to_lowercase();
+    }
+
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            // Turkish and Azeri keep the dot: İ -> i, I -> ı, and an I with
+            // a combining dot is just i
+            'İ' => result.push('i'),
+            'I' if chars.peek() == Some(&COMBINING_DOT_ABOVE) => {
+                chars.next();
+                result.push('i');
+            }
+            'I' => result.push('ı'),
+            _ => result.extend(c.to_lowercase()),
+        }
+    }
+
+    result
+}
+
+/// How the user's text is cased before it's drawn
+#[derive(Clone, Copy, PartialEq)]
+pub enum Transform {
+    Upper,
+    // Alternating lowercase and uppercase letters, like mocking SpongeBob
+    Mock,
+    // Lowercase and uppercase letters picked at random
+    MockRandom,
+}
+
+impl Transform {
+    pub fn from_name(name: &str) -> Option<Transform> {
+        match name.to_lowercase().as_str() {
+            "upper" => Some(Transform::Upper),
+            "mock" => Some(Transform::Mock),
+            "mock_random" => Some(Transform::MockRandom),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Transform::Upper => "upper",
+            Transform::Mock => "mock",
+            Transform::MockRandom => "mock_random",
+        }
+    }
+}
+
+/// Changes the case of every letter in `text`, starting with lowercase and
+/// then alternating, or at random with `rng`. Spaces and punctuation don't
+/// count toward the alternation.
+fn to_mocking_case(text: &str, locale: Option<&str>, mut rng: Option<StdRng>) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut upper = false;
+
+    for c in text.chars() {
+        if !c.is_alphabetic() {
+            result.push(c);
+            continue;
+        }
+
+        if let Some(rng) = &mut rng {
+            upper = rng.gen_bool(0.5);
+        }
+
+        if upper {
+            result.push_str(&to_uppercase(&c.to_string(), locale));
+        } else {
+            result.push_str(&to_lowercase(&c.to_string(), locale));
+        }
+
+        if rng.is_none() {
+            upper = !upper;
+        }
+    }
+
+    result
+}
+
+/// Cases `text` according to `transform` and the rules of `locale`. `seed`
+/// makes `Transform::MockRandom` pick the same cases every time.
+pub fn apply_transform(
+    text: &str,
+    transform: Transform,
+    locale: Option<&str>,
+    seed: Option<u64>,
+) -> String {
+    match transform {
+        Transform::Upper => to_uppercase(text, locale),
+        Transform::Mock => to_mocking_case(text, locale, None),
+        Transform::MockRandom => {
+            let seed = seed.unwrap_or_else(|| rand::thread_rng().gen());
+
+            to_mocking_case(text, locale, Some(StdRng::seed_from_u64(seed)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uppercases_turkish_dotted_i() {
+        assert_eq!(to_uppercase("istanbul ılık", Some("tr")), "İSTANBUL ILIK");
+        assert_eq!(to_uppercase("istanbul", None), "ISTANBUL");
+    }
+
+    #[test]
+    fn lowercases_turkish_dotless_i() {
+        assert_eq!(
+            to_lowercase("İSTANBUL ILIK", Some("tr-TR")),
+            "istanbul ılık"
+        );
+        assert_eq!(to_lowercase("I\u{307}", Some("az")), "i");
+        assert_eq!(to_lowercase("ISTANBUL", Some("en")), "istanbul");
+        assert_eq!(to_lowercase("ISTANBUL", None), "istanbul");
+    }
+
+    #[test]
+    fn mocks_with_the_locale_in_both_directions() {
+        assert_eq!(
+            apply_transform("IIII", Transform::Mock, Some("tr"), None),
+            "ıIıI"
+        );
+        assert_eq!(
+            apply_transform("iiii", Transform::Mock, Some("tr"), None),
+            "iİiİ"
+        );
+        assert_eq!(
+            apply_transform("İIİI", Transform::Mock, None, None),
+            "i\u{307}Ii\u{307}I"
+        );
+    }
+
+    #[test]
+    fn mocks_only_letters() {
+        assert_eq!(
+            apply_transform("ab, cd!", Transform::Mock, None, None),
+            "aB, cD!"
+        );
+    }
+
+    #[test]
+    fn random_mocking_follows_the_seed() {
+        let first = apply_transform("some text here", Transform::MockRandom, None, Some(7));
+        let second = apply_transform("some text here", Transform::MockRandom, None, Some(7));
+
+        assert_eq!(first, second);
+        assert_eq!(first.to_lowercase(), "some text here");
+    }
+}
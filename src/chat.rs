@@ -0,0 +1,350 @@
+use serde_json::Value;
+use serenity::http::request::RequestBuilder;
+use serenity::http::routing::RouteInfo;
+use serenity::model::id::{ChannelId, GuildId, MessageId, UserId};
+use serenity::model::prelude::Message;
+use serenity::prelude::{Context, RwLock, ShareMap};
+
+/// What the message handler needs from Discord: the bot's data, the
+/// permissions of server members, ways to reply, and other messages to read.
+/// Commands go through this instead of `Context`, so that they can be tested
+/// without connecting to Discord.
+pub trait Chat {
+    fn data(&self) -> &RwLock<ShareMap>;
+
+    /// Returns whether `user_id` owns `guild_id` or has the Administrator
+    /// permission in it
+    fn is_guild_admin(&self, guild_id: GuildId, user_id: UserId) -> bool;
+
+    /// Returns whether `user_id` has the Manage Server permission in
+    /// `guild_id`
+    fn can_manage_guild(&self, guild_id: GuildId, user_id: UserId) -> bool;
+
+    /// Sends `content` to `channel_id`. Like every other reply, it's dropped if
+    /// it can't be sent.
+    fn say(&self, channel_id: ChannelId, content: &str);
+
+    /// Sends `files`, each a filename and its contents, to `channel_id` along
+    /// with `content`, if there is any. Returns whether they were sent.
+    fn send_files(&self, channel_id: ChannelId, files: &[(&str, &[u8])], content: &str) -> bool;
+
+    fn get_message(&self, channel_id: ChannelId, message_id: MessageId) -> Result<Message, String>;
+
+    /// Returns the snapshots of the messages forwarded in a message. The
+    /// library doesn't know about forwarded messages, so the message is
+    /// fetched again as JSON to find them.
+    fn get_forwarded_snapshots(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+    ) -> Result<Vec<Value>, String>;
+}
+
+impl Chat for Context {
+    fn data(&self) -> &RwLock<ShareMap> {
+        &self.data
+    }
+
+    fn is_guild_admin(&self, guild_id: GuildId, user_id: UserId) -> bool {
+        let guild = match guild_id.to_guild_cached(self) {
+            Some(guild) => guild,
+            None => return false,
+        };
+
+        let guild = guild.read();
+
+        guild.owner_id == user_id || guild.member_permissions(user_id).administrator()
+    }
+
+    fn can_manage_guild(&self, guild_id: GuildId, user_id: UserId) -> bool {
+        guild_id
+            .to_guild_cached(self)
+            .is_some_and(|guild| guild.read().member_permissions(user_id).manage_guild())
+    }
+
+    fn say(&self, channel_id: ChannelId, content: &str) {
+        channel_id.say(self, content).ok();
+    }
+
+    fn send_files(&self, channel_id: ChannelId, files: &[(&str, &[u8])], content: &str) -> bool {
+        let files: Vec<(&[u8], &str)> = files
+            .iter()
+            .map(|(filename, contents)| (*contents, *filename))
+            .collect();
+
+        channel_id
+            .send_files(self, files, |m| {
+                if content.is_empty() {
+                    m
+                } else {
+                    m.content(content)
+                }
+            })
+            .is_ok()
+    }
+
+    fn get_message(&self, channel_id: ChannelId, message_id: MessageId) -> Result<Message, String> {
+        match self.http.get_message(channel_id.0, message_id.0) {
+            Ok(message) => Ok(message),
+            Err(reason) => Err(format!(
+                "Unable to retrieve message {}: {}",
+                message_id, reason
+            )),
+        }
+    }
+
+    fn get_forwarded_snapshots(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+    ) -> Result<Vec<Value>, String> {
+        let route = RouteInfo::GetMessage {
+            channel_id: channel_id.0,
+            message_id: message_id.0,
+        };
+
+        match self.http.fire::<Value>(RequestBuilder::new(route).build()) {
+            Ok(json) => Ok(json["message_snapshots"]
+                .as_array()
+                .cloned()
+                .unwrap_or_default()),
+            Err(reason) => Err(format!(
+                "Unable to retrieve forwarded messages of {}: {}",
+                message_id, reason
+            )),
+        }
+    }
+}
+
+/// Stand-ins for Discord in tests
+#[cfg(test)]
+pub mod mock {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Barrier, Mutex};
+    use std::time::Duration;
+
+    use super::*;
+    use crate::admin_sessions::{AdminSessionStore, AdminSessionsKey};
+    use crate::guild_settings::{GuildSettingsKey, GuildSettingsStore};
+
+    /// A `Chat` for tests, which keeps what's said and sent instead of sending
+    /// it
+    pub struct MockChat {
+        pub data: RwLock<ShareMap>,
+        // Users who own or administer each guild
+        pub guild_admins: Vec<(GuildId, UserId)>,
+        // Users with the Manage Server permission in each guild
+        pub guild_managers: Vec<(GuildId, UserId)>,
+        // The messages that can be looked up
+        pub messages: Vec<Message>,
+        // Makes looking up a message wait at this barrier twice, once when the
+        // lookup starts and once more before it returns, like a slow request
+        pub lookup_gate: Option<Barrier>,
+        said: Mutex<Vec<(ChannelId, String)>>,
+        sent_files: Mutex<Vec<String>>,
+        filenames: Vec<String>,
+    }
+
+    impl MockChat {
+        /// Starts with nobody authenticated and no guild settings, saved to files
+        /// of their own in the temporary directory
+        pub fn new() -> MockChat {
+            static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+            let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+            let filename = |name: &str| {
+                std::env::temp_dir()
+                    .join(format!(
+                        "mock-chat-{}-{}-{}.yml",
+                        std::process::id(),
+                        id,
+                        name
+                    ))
+                    .to_string_lossy()
+                    .into_owned()
+            };
+            let filenames = vec![filename("admin-sessions"), filename("guild-settings")];
+
+            let mut data = ShareMap::custom();
+            data.insert::<AdminSessionsKey>(serenity::prelude::Mutex::new(
+                AdminSessionStore::load(&filenames[0], Duration::from_secs(60 * 60))
+                    .expect("Mock chat: Unable to create admin sessions"),
+            ));
+            data.insert::<GuildSettingsKey>(
+                GuildSettingsStore::load(&filenames[1])
+                    .expect("Mock chat: Unable to create guild settings"),
+            );
+
+            MockChat {
+                data: RwLock::new(data),
+                guild_admins: Vec::new(),
+                guild_managers: Vec::new(),
+                messages: Vec::new(),
+                lookup_gate: None,
+                said: Mutex::new(Vec::new()),
+                sent_files: Mutex::new(Vec::new()),
+                filenames,
+            }
+        }
+
+        /// Takes everything said so far
+        pub fn take_said(&self) -> Vec<String> {
+            self.said
+                .lock()
+                .expect("Mock chat: Unable to lock replies")
+                .drain(..)
+                .map(|(_, content)| content)
+                .collect()
+        }
+
+        /// Takes the names of every file sent so far
+        pub fn take_sent_files(&self) -> Vec<String> {
+            self.sent_files
+                .lock()
+                .expect("Mock chat: Unable to lock sent files")
+                .drain(..)
+                .collect()
+        }
+    }
+
+    impl Drop for MockChat {
+        fn drop(&mut self) {
+            for filename in &self.filenames {
+                std::fs::remove_file(filename).ok();
+            }
+        }
+    }
+
+    impl Chat for MockChat {
+        fn data(&self) -> &RwLock<ShareMap> {
+            &self.data
+        }
+
+        fn is_guild_admin(&self, guild_id: GuildId, user_id: UserId) -> bool {
+            self.guild_admins.contains(&(guild_id, user_id))
+        }
+
+        fn can_manage_guild(&self, guild_id: GuildId, user_id: UserId) -> bool {
+            self.guild_managers.contains(&(guild_id, user_id))
+        }
+
+        fn say(&self, channel_id: ChannelId, content: &str) {
+            self.said
+                .lock()
+                .expect("Mock chat: Unable to lock replies")
+                .push((channel_id, content.into()));
+        }
+
+        fn send_files(
+            &self,
+            channel_id: ChannelId,
+            files: &[(&str, &[u8])],
+            content: &str,
+        ) -> bool {
+            if !content.is_empty() {
+                self.say(channel_id, content);
+            }
+
+            self.sent_files
+                .lock()
+                .expect("Mock chat: Unable to lock sent files")
+                .extend(files.iter().map(|(filename, _)| filename.to_string()));
+
+            true
+        }
+
+        fn get_message(
+            &self,
+            channel_id: ChannelId,
+            message_id: MessageId,
+        ) -> Result<Message, String> {
+            if let Some(gate) = &self.lookup_gate {
+                gate.wait();
+                gate.wait();
+            }
+
+            self.messages
+                .iter()
+                .find(|message| message.channel_id == channel_id && message.id == message_id)
+                .cloned()
+                .ok_or_else(|| format!("Unknown message {}", message_id))
+        }
+
+        fn get_forwarded_snapshots(
+            &self,
+            _channel_id: ChannelId,
+            _message_id: MessageId,
+        ) -> Result<Vec<Value>, String> {
+            Ok(Vec::new())
+        }
+    }
+
+    /// Makes a message from `author_id` in channel 10, which is in `guild_id` or
+    /// otherwise a DM
+    pub fn message(guild_id: Option<u64>, author_id: u64, content: &str) -> Message {
+        serde_json::from_value(message_json(guild_id, author_id, content))
+            .expect("Mock chat: Unable to create message")
+    }
+
+    /// Makes a message like `message` does, replying to `replied`, which has to
+    /// have a different ID
+    pub fn reply(
+        guild_id: Option<u64>,
+        author_id: u64,
+        content: &str,
+        replied: &Message,
+    ) -> Message {
+        let mut json = message_json(guild_id, author_id, content);
+
+        json["id"] = serde_json::json!("2");
+        json["message_reference"] = serde_json::json!({
+            "channel_id": replied.channel_id.to_string(),
+            "message_id": replied.id.to_string(),
+        });
+
+        serde_json::from_value(json).expect("Mock chat: Unable to create reply")
+    }
+
+    /// Adds an attachment called `filename` to `message`, as if it had been
+    /// uploaded along with it
+    pub fn with_attachment(mut message: Message, filename: &str) -> Message {
+        let attachment = serde_json::from_value(serde_json::json!({
+            "id": "3",
+            "filename": filename,
+            "height": null,
+            "width": null,
+            "proxy_url": format!("https://media.discordapp.net/attachments/10/3/{}", filename),
+            "size": 100,
+            "url": format!("https://cdn.discordapp.com/attachments/10/3/{}", filename),
+        }))
+        .expect("Mock chat: Unable to create attachment");
+
+        message.attachments.push(attachment);
+        message
+    }
+
+    fn message_json(guild_id: Option<u64>, author_id: u64, content: &str) -> Value {
+        serde_json::json!({
+            "id": "1",
+            "attachments": [],
+            "author": {
+                "id": author_id.to_string(),
+                "username": "user",
+                "discriminator": "0001",
+                "avatar": null,
+            },
+            "channel_id": "10",
+            "content": content,
+            "edited_timestamp": null,
+            "embeds": [],
+            "guild_id": guild_id.map(|guild_id| guild_id.to_string()),
+            "type": 0,
+            "mention_everyone": false,
+            "mention_roles": [],
+            "mentions": [],
+            "pinned": false,
+            "timestamp": "2026-01-01T00:00:00+00:00",
+            "tts": false,
+        })
+    }
+}
@@ -0,0 +1,32 @@
+use std::ops::Range;
+
+use crate::layout::trim_blank_lines;
+
+const FENCE: &str = "```";
+
+/// Returns where the first fenced code block in `text` is, fences included
+pub fn code_block_range(text: &str) -> Option<Range<usize>> {
+    let start = text.find(FENCE)?;
+    let length = text[start + FENCE.len()..].find(FENCE)?;
+
+    Some(start..start + FENCE.len() + length + FENCE.len())
+}
+
+/// Returns the code in the first fenced code block in `text`, without the
+/// language that can follow the opening fence, like "```rust"
+pub fn find_code_block(text: &str) -> Option<&str> {
+    let range = code_block_range(text)?;
+    let code = &text[range.start + FENCE.len()..range.end - FENCE.len()];
+
+    let code = match code.find('\n') {
+        Some(newline)
+            if !code[..newline].trim().is_empty()
+                && !code[..newline].trim().contains(char::is_whitespace) =>
+        {
+            &code[newline + 1..]
+        }
+        _ => code,
+    };
+
+    Some(trim_blank_lines(code))
+}
@@ -0,0 +1,414 @@
+use image::Rgba;
+
+// The CSS named colors, sorted by name so that they can be binary searched.
+// X11 defines some of these names slightly differently (e.g. `gray`); the CSS
+// values are used since they're what people will find when looking up colors.
+const NAMED_COLORS: &[(&str, [u8; 3])] = &[
+    ("aliceblue", [240, 248, 255]),
+    ("antiquewhite", [250, 235, 215]),
+    ("aqua", [0, 255, 255]),
+    ("aquamarine", [127, 255, 212]),
+    ("azure", [240, 255, 255]),
+    ("beige", [245, 245, 220]),
+    ("bisque", [255, 228, 196]),
+    ("black", [0, 0, 0]),
+    ("blanchedalmond", [255, 235, 205]),
+    ("blue", [0, 0, 255]),
+    ("blueviolet", [138, 43, 226]),
+    ("brown", [165, 42, 42]),
+    ("burlywood", [222, 184, 135]),
+    ("cadetblue", [95, 158, 160]),
+    ("chartreuse", [127, 255, 0]),
+    ("chocolate", [210, 105, 30]),
+    ("coral", [255, 127, 80]),
+    ("cornflowerblue", [100, 149, 237]),
+    ("cornsilk", [255, 248, 220]),
+    ("crimson", [220, 20, 60]),
+    ("cyan", [0, 255, 255]),
+    ("darkblue", [0, 0, 139]),
+    ("darkcyan", [0, 139, 139]),
+    ("darkgoldenrod", [184, 134, 11]),
+    ("darkgray", [169, 169, 169]),
+    ("darkgreen", [0, 100, 0]),
+    ("darkgrey", [169, 169, 169]),
+    ("darkkhaki", [189, 183, 107]),
+    ("darkmagenta", [139, 0, 139]),
+    ("darkolivegreen", [85, 107, 47]),
+    ("darkorange", [255, 140, 0]),
+    ("darkorchid", [153, 50, 204]),
+    ("darkred", [139, 0, 0]),
+    ("darksalmon", [233, 150, 122]),
+    ("darkseagreen", [143, 188, 143]),
+    ("darkslateblue", [72, 61, 139]),
+    ("darkslategray", [47, 79, 79]),
+    ("darkslategrey", [47, 79, 79]),
+    ("darkturquoise", [0, 206, 209]),
+    ("darkviolet", [148, 0, 211]),
+    ("deeppink", [255, 20, 147]),
+    ("deepskyblue", [0, 191, 255]),
+    ("dimgray", [105, 105, 105]),
+    ("dimgrey", [105, 105, 105]),
+    ("dodgerblue", [30, 144, 255]),
+    ("firebrick", [178, 34, 34]),
+    ("floralwhite", [255, 250, 240]),
+    ("forestgreen", [34, 139, 34]),
+    ("fuchsia", [255, 0, 255]),
+    ("gainsboro", [220, 220, 220]),
+    ("ghostwhite", [248, 248, 255]),
+    ("gold", [255, 215, 0]),
+    ("goldenrod", [218, 165, 32]),
+    ("gray", [128, 128, 128]),
+    ("green", [0, 128, 0]),
+    ("greenyellow", [173, 255, 47]),
+    ("grey", [128, 128, 128]),
+    ("honeydew", [240, 255, 240]),
+    ("hotpink", [255, 105, 180]),
+    ("indianred", [205, 92, 92]),
+    ("indigo", [75, 0, 130]),
+    ("ivory", [255, 255, 240]),
+    ("khaki", [240, 230, 140]),
+    ("lavender", [230, 230, 250]),
+    ("lavenderblush", [255, 240, 245]),
+    ("lawngreen", [124, 252, 0]),
+    ("lemonchiffon", [255, 250, 205]),
+    ("lightblue", [173, 216, 230]),
+    ("lightcoral", [240, 128, 128]),
+    ("lightcyan", [224, 255, 255]),
+    ("lightgoldenrodyellow", [250, 250, 210]),
+    ("lightgray", [211, 211, 211]),
+    ("lightgreen", [144, 238, 144]),
+    ("lightgrey", [211, 211, 211]),
+    ("lightpink", [255, 182, 193]),
+    ("lightsalmon", [255, 160, 122]),
+    ("lightseagreen", [32, 178, 170]),
+    ("lightskyblue", [135, 206, 250]),
+    ("lightslategray", [119, 136, 153]),
+    ("lightslategrey", [119, 136, 153]),
+    ("lightsteelblue", [176, 196, 222]),
+    ("lightyellow", [255, 255, 224]),
+    ("lime", [0, 255, 0]),
+    ("limegreen", [50, 205, 50]),
+    ("linen", [250, 240, 230]),
+    ("magenta", [255, 0, 255]),
+    ("maroon", [128, 0, 0]),
+    ("mediumaquamarine", [102, 205, 170]),
+    ("mediumblue", [0, 0, 205]),
+    ("mediumorchid", [186, 85, 211]),
+    ("mediumpurple", [147, 112, 219]),
+    ("mediumseagreen", [60, 179, 113]),
+    ("mediumslateblue", [123, 104, 238]),
+    ("mediumspringgreen", [0, 250, 154]),
+    ("mediumturquoise", [72, 209, 204]),
+    ("mediumvioletred", [199, 21, 133]),
+    ("midnightblue", [25, 25, 112]),
+    ("mintcream", [245, 255, 250]),
+    ("mistyrose", [255, 228, 225]),
+    ("moccasin", [255, 228, 181]),
+    ("navajowhite", [255, 222, 173]),
+    ("navy", [0, 0, 128]),
+    ("oldlace", [253, 245, 230]),
+    ("olive", [128, 128, 0]),
+    ("olivedrab", [107, 142, 35]),
+    ("orange", [255, 165, 0]),
+    ("orangered", [255, 69, 0]),
+    ("orchid", [218, 112, 214]),
+    ("palegoldenrod", [238, 232, 170]),
+    ("palegreen", [152, 251, 152]),
+    ("paleturquoise", [175, 238, 238]),
+    ("palevioletred", [219, 112, 147]),
+    ("papayawhip", [255, 239, 213]),
+    ("peachpuff", [255, 218, 185]),
+    ("peru", [205, 133, 63]),
+    ("pink", [255, 192, 203]),
+    ("plum", [221, 160, 221]),
+    ("powderblue", [176, 224, 230]),
+    ("purple", [128, 0, 128]),
+    ("rebeccapurple", [102, 51, 153]),
+    ("red", [255, 0, 0]),
+    ("rosybrown", [188, 143, 143]),
+    ("royalblue", [65, 105, 225]),
+    ("saddlebrown", [139, 69, 19]),
+    ("salmon", [250, 128, 114]),
+    ("sandybrown", [244, 164, 96]),
+    ("seagreen", [46, 139, 87]),
+    ("seashell", [255, 245, 238]),
+    ("sienna", [160, 82, 45]),
+    ("silver", [192, 192, 192]),
+    ("skyblue", [135, 206, 235]),
+    ("slateblue", [106, 90, 205]),
+    ("slategray", [112, 128, 144]),
+    ("slategrey", [112, 128, 144]),
+    ("snow", [255, 250, 250]),
+    ("springgreen", [0, 255, 127]),
+    ("steelblue", [70, 130, 180]),
+    ("tan", [210, 180, 140]),
+    ("teal", [0, 128, 128]),
+    ("thistle", [216, 191, 216]),
+    ("tomato", [255, 99, 71]),
+    ("turquoise", [64, 224, 208]),
+    ("violet", [238, 130, 238]),
+    ("wheat", [245, 222, 179]),
+    ("white", [255, 255, 255]),
+    ("whitesmoke", [245, 245, 245]),
+    ("yellow", [255, 255, 0]),
+    ("yellowgreen", [154, 205, 50]),
+];
+
+const HINT: &str = "Try a hex code like `#ff0066`, `rgb(255, 0, 102)`, or a name like `red`.";
+
+/// Parses 3, 4, 6, or 8 hex digits as RGB with an optional alpha
+fn parse_hex(text: &str, hex: &str) -> Result<Rgba<u8>, String> {
+    if let Some(invalid) = hex.chars().find(|c| !c.is_ascii_hexdigit()) {
+        return Err(format!(
+            "\"{}\" isn't a color: '{}' isn't a hex digit. {}",
+            text, invalid, HINT
+        ));
+    }
+
+    let width = match hex.len() {
+        3 | 4 => 1,
+        6 | 8 => 2,
+        length => {
+            return Err(format!(
+                "\"{}\" isn't a color: hex codes have 3, 4, 6, or 8 digits, not {}. {}",
+                text, length, HINT
+            ));
+        }
+    };
+
+    let mut channels = [255u8; 4];
+
+    for (index, channel) in channels.iter_mut().enumerate().take(hex.len() / width) {
+        let value = u8::from_str_radix(&hex[index * width..(index + 1) * width], 16)
+            .expect("Unable to parse validated hex digits");
+
+        // A single digit is repeated, so that `f` means `ff`
+        *channel = if width == 1 { value * 17 } else { value };
+    }
+
+    Ok(Rgba(channels))
+}
+
+/// Parses a color channel of `rgb()`, which is either 0 to 255 or a percentage
+fn parse_channel(text: &str, channel: &str) -> Result<u8, String> {
+    let (value, scale) = match channel.strip_suffix('%') {
+        Some(percentage) => (percentage, 255f32 / 100f32),
+        None => (channel, 1f32),
+    };
+
+    match value.parse::<f32>() {
+        Ok(value) if (0f32..=255f32).contains(&(value * scale)) => {
+            Ok((value * scale).round() as u8)
+        }
+        _ => Err(format!(
+            "\"{}\" isn't a color: \"{}\" isn't a number from 0 to 255 or a percentage. {}",
+            text, channel, HINT
+        )),
+    }
+}
+
+/// Parses the alpha of `rgba()`, which is either 0 to 1 or a percentage
+fn parse_alpha(text: &str, alpha: &str) -> Result<u8, String> {
+    let (value, scale) = match alpha.strip_suffix('%') {
+        Some(percentage) => (percentage, 1f32 / 100f32),
+        None => (alpha, 1f32),
+    };
+
+    match value.parse::<f32>() {
+        Ok(value) if (0f32..=1f32).contains(&(value * scale)) => {
+            Ok((value * scale * 255f32).round() as u8)
+        }
+        _ => Err(format!(
+            "\"{}\" isn't a color: the alpha \"{}\" isn't a number from 0 to 1 or a percentage. {}",
+            text, alpha, HINT
+        )),
+    }
+}
+
+/// Parses the arguments of `rgb()` or `rgba()`, which can be separated by
+/// commas (`255, 0, 102, 0.5`) or spaces with the alpha after a slash
+/// (`255 0 102 / 50%`)
+fn parse_rgb(text: &str, arguments: &str) -> Result<Rgba<u8>, String> {
+    let (channels, alpha) = match arguments.split_once('/') {
+        Some((channels, alpha)) => (channels, Some(alpha.trim())),
+        None => (arguments, None),
+    };
+
+    let mut parts: Vec<&str> = channels
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|part| !part.is_empty())
+        .collect();
+
+    let alpha = match (alpha, parts.len()) {
+        (Some(alpha), 3) => Some(alpha),
+        (None, 4) => parts.pop(),
+        (None, 3) => None,
+        _ => {
+            return Err(format!(
+                "\"{}\" isn't a color: `rgb()` takes a red, green, and blue value, and optionally an alpha. {}",
+                text, HINT
+            ));
+        }
+    };
+
+    Ok(Rgba([
+        parse_channel(text, parts[0])?,
+        parse_channel(text, parts[1])?,
+        parse_channel(text, parts[2])?,
+        match alpha {
+            Some(alpha) => parse_alpha(text, alpha)?,
+            None => 255,
+        },
+    ]))
+}
+
+/// Parses a color written as a hex code (`#rgb`, `#rgba`, `#rrggbb`, or
+/// `#rrggbbaa`), as `rgb()` or `rgba()`, or as a CSS color name like `red`.
+/// The error says what's wrong with the color, so it can be shown to users.
+pub fn parse_color(text: &str) -> Result<Rgba<u8>, String> {
+    let text = text.trim();
+    let color = text.to_lowercase();
+
+    if let Some(hex) = color.strip_prefix('#') {
+        return parse_hex(text, hex);
+    }
+
+    for function in &["rgba(", "rgb("] {
+        if let Some(arguments) = color.strip_prefix(function) {
+            return match arguments.strip_suffix(')') {
+                Some(arguments) => parse_rgb(text, arguments),
+                None => Err(format!(
+                    "\"{}\" isn't a color: it's missing a closing parenthesis. {}",
+                    text, HINT
+                )),
+            };
+        }
+    }
+
+    if color == "transparent" {
+        return Ok(Rgba([0, 0, 0, 0]));
+    }
+
+    match NAMED_COLORS.binary_search_by(|(name, _)| name.cmp(&color.as_str())) {
+        Ok(index) => {
+            let [r, g, b] = NAMED_COLORS[index].1;
+
+            Ok(Rgba([r, g, b, 255]))
+        }
+        // Hex codes are often written without the `#`
+        Err(_) if parse_hex(text, &color).is_ok() => Err(format!(
+            "\"{}\" isn't a color. Did you mean `#{}`?",
+            text, color
+        )),
+        Err(_) => Err(format!("\"{}\" isn't a color. {}", text, HINT)),
+    }
+}
+
+/// Writes `color` as a hex code, leaving out the alpha if it's opaque
+pub fn format_color(color: Rgba<u8>) -> String {
+    let [r, g, b, a] = color.0;
+
+    if a == 255 {
+        format!("#{:02x}{:02x}{:02x}", r, g, b)
+    } else {
+        format!("#{:02x}{:02x}{:02x}{:02x}", r, g, b, a)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_hex_codes() {
+        assert_eq!(parse_color("#f06"), Ok(Rgba([255, 0, 102, 255])));
+        assert_eq!(parse_color("#f068"), Ok(Rgba([255, 0, 102, 136])));
+        assert_eq!(parse_color("#FF0066"), Ok(Rgba([255, 0, 102, 255])));
+        assert_eq!(parse_color(" #ff006680 "), Ok(Rgba([255, 0, 102, 128])));
+    }
+
+    #[test]
+    fn parses_rgb_functions() {
+        assert_eq!(
+            parse_color("rgb(255, 0, 102)"),
+            Ok(Rgba([255, 0, 102, 255]))
+        );
+        assert_eq!(
+            parse_color("RGBA(255, 0, 102, 0.5)"),
+            Ok(Rgba([255, 0, 102, 128]))
+        );
+        assert_eq!(
+            parse_color("rgb(100% 0% 40% / 25%)"),
+            Ok(Rgba([255, 0, 102, 64]))
+        );
+
+        assert_eq!(
+            parse_color("rgb(256, 0, 102)"),
+            Err(format!(
+                "\"rgb(256, 0, 102)\" isn't a color: \"256\" isn't a number from 0 to 255 or a percentage. {}",
+                HINT
+            ))
+        );
+        assert_eq!(
+            parse_color("rgb(255, -1, 102)"),
+            Err(format!(
+                "\"rgb(255, -1, 102)\" isn't a color: \"-1\" isn't a number from 0 to 255 or a percentage. {}",
+                HINT
+            ))
+        );
+        assert_eq!(
+            parse_color("rgba(255, 0, 102, 1.5)"),
+            Err(format!(
+                "\"rgba(255, 0, 102, 1.5)\" isn't a color: the alpha \"1.5\" isn't a number from 0 to 1 or a percentage. {}",
+                HINT
+            ))
+        );
+        assert_eq!(
+            parse_color("rgb(255, 0)"),
+            Err(format!(
+                "\"rgb(255, 0)\" isn't a color: `rgb()` takes a red, green, and blue value, and optionally an alpha. {}",
+                HINT
+            ))
+        );
+        assert_eq!(
+            parse_color("rgb(255, 0, 102"),
+            Err(format!(
+                "\"rgb(255, 0, 102\" isn't a color: it's missing a closing parenthesis. {}",
+                HINT
+            ))
+        );
+    }
+
+    #[test]
+    fn parses_names_regardless_of_case() {
+        assert_eq!(parse_color("red"), Ok(Rgba([255, 0, 0, 255])));
+        assert_eq!(parse_color("AliceBlue"), Ok(Rgba([240, 248, 255, 255])));
+        assert_eq!(parse_color("TRANSPARENT"), Ok(Rgba([0, 0, 0, 0])));
+    }
+
+    #[test]
+    fn explains_what_isnt_a_color() {
+        assert_eq!(
+            parse_color("#ff00zz"),
+            Err(format!(
+                "\"#ff00zz\" isn't a color: 'z' isn't a hex digit. {}",
+                HINT
+            ))
+        );
+        assert_eq!(
+            parse_color("#ff00f"),
+            Err(format!(
+                "\"#ff00f\" isn't a color: hex codes have 3, 4, 6, or 8 digits, not 5. {}",
+                HINT
+            ))
+        );
+        assert_eq!(
+            parse_color("ff0066"),
+            Err("\"ff0066\" isn't a color. Did you mean `#ff0066`?".into())
+        );
+        assert_eq!(
+            parse_color("reddish"),
+            Err(format!("\"reddish\" isn't a color. {}", HINT))
+        );
+    }
+}
@@ -0,0 +1,111 @@
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::cell::RefCell;
+
+// Reports list at most this many warnings, so that they fit in a message
+const MAX_LISTED: usize = 10;
+
+// Longer warnings are cut off in reports, though not in the log
+const MAX_WARNING_CHARS: usize = 150;
+
+thread_local! {
+    // The warnings logged on this thread while something is collecting them
+    static COLLECTED: RefCell<Option<Vec<String>>> = const { RefCell::new(None) };
+}
+
+/// Logs like `env_logger` does, and also passes warnings and errors to
+/// whatever is collecting them on the thread that logged them, whatever
+/// `RUST_LOG` is set to
+struct CollectingLogger {
+    inner: env_logger::Logger,
+}
+
+impl Log for CollectingLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= Level::Warn || self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if record.level() <= Level::Warn {
+            COLLECTED.with(|collected| {
+                if let Some(warnings) = collected.borrow_mut().as_mut() {
+                    warnings.push(record.args().to_string());
+                }
+            });
+        }
+
+        if self.inner.matches(record) {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// Sets up logging from `RUST_LOG`, the way `env_logger::init` does
+pub fn init() {
+    let inner = env_logger::Builder::from_default_env().build();
+    let max_level = inner.filter().max(LevelFilter::Warn);
+
+    log::set_boxed_logger(Box::new(CollectingLogger { inner }))
+        .expect("Logging: Unable to set up the logger");
+    log::set_max_level(max_level);
+}
+
+/// Runs `f`, returning what it returns along with the warnings and errors it
+/// logged
+pub fn collect<T>(f: impl FnOnce() -> T) -> (T, Vec<String>) {
+    COLLECTED.with(|collected| *collected.borrow_mut() = Some(Vec::new()));
+
+    let result = f();
+
+    let warnings = COLLECTED
+        .with(|collected| collected.borrow_mut().take())
+        .unwrap_or_default();
+
+    (result, warnings)
+}
+
+/// Lists the warnings as a numbered report for the admins, leaving out any past
+/// `MAX_LISTED`
+pub fn report(warnings: &[String]) -> Option<String> {
+    if warnings.is_empty() {
+        return None;
+    }
+
+    let mut lines = vec![format!(
+        "The config has {} warning{}:",
+        warnings.len(),
+        if warnings.len() == 1 { "" } else { "s" }
+    )];
+
+    lines.extend(
+        warnings
+            .iter()
+            .take(MAX_LISTED)
+            .enumerate()
+            .map(|(index, warning)| {
+                let warning: String = if warning.chars().count() > MAX_WARNING_CHARS {
+                    warning
+                        .chars()
+                        .take(MAX_WARNING_CHARS - 1)
+                        .chain(std::iter::once('…'))
+                        .collect()
+                } else {
+                    warning.clone()
+                };
+
+                format!("{}. {}", index + 1, warning.replace('`', "'"))
+            }),
+    );
+
+    if warnings.len() > MAX_LISTED {
+        lines.push(format!(
+            "…and {} more, which are in the log.",
+            warnings.len() - MAX_LISTED
+        ));
+    }
+
+    Some(lines.join("\n"))
+}
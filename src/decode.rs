@@ -0,0 +1,76 @@
+use image::io::Reader;
+use image::{ImageFormat, RgbaImage};
+use std::io::Cursor;
+
+// Limits on images that are decoded, so that a small file that decodes to a
+// huge image (a "decompression bomb") can't exhaust the bot's memory
+pub const MAX_IMAGE_BYTES: usize = 16 * 1024 * 1024;
+pub const MAX_IMAGE_DIMENSION: u32 = 8192;
+pub const MAX_IMAGE_PIXELS: u64 = 40_000_000;
+
+// Only these formats are decoded. The format is recognized from the file's
+// contents rather than its name, which might not match.
+const ALLOWED_FORMATS: &[ImageFormat] = &[
+    ImageFormat::Png,
+    ImageFormat::Jpeg,
+    ImageFormat::WebP,
+    ImageFormat::Gif,
+];
+
+/// Decodes an image, first checking its file size and the dimensions given in
+/// its header against the limits
+pub fn decode_image(contents: &[u8]) -> Result<RgbaImage, String> {
+    if contents.len() > MAX_IMAGE_BYTES {
+        return Err(format!(
+            "Image is too large ({} bytes; the limit is {} bytes)",
+            contents.len(),
+            MAX_IMAGE_BYTES
+        ));
+    }
+
+    let reader = match Reader::new(Cursor::new(contents)).with_guessed_format() {
+        Ok(reader) => reader,
+        Err(reason) => return Err(format!("Unable to read image: {}", reason)),
+    };
+
+    match reader.format() {
+        Some(format) if ALLOWED_FORMATS.contains(&format) => {}
+        Some(format) => {
+            return Err(format!(
+                "Images in the {:?} format are not supported (use PNG, JPEG, WebP, or GIF)",
+                format
+            ))
+        }
+        None => return Err("File is not a supported image (use PNG, JPEG, WebP, or GIF)".into()),
+    }
+
+    let (width, height) = match reader.into_dimensions() {
+        Ok(dimensions) => dimensions,
+        Err(reason) => return Err(format!("Unable to read image: {}", reason)),
+    };
+
+    if width > MAX_IMAGE_DIMENSION || height > MAX_IMAGE_DIMENSION {
+        return Err(format!(
+            "Image is too large ({}x{}; the limit is {} pixels on each side)",
+            width, height, MAX_IMAGE_DIMENSION
+        ));
+    }
+
+    if width as u64 * height as u64 > MAX_IMAGE_PIXELS {
+        return Err(format!(
+            "Image is too large ({}x{}; the limit is {} pixels in total)",
+            width, height, MAX_IMAGE_PIXELS
+        ));
+    }
+
+    // The header was consumed to read the dimensions, so start over
+    let reader = match Reader::new(Cursor::new(contents)).with_guessed_format() {
+        Ok(reader) => reader,
+        Err(reason) => return Err(format!("Unable to read image: {}", reason)),
+    };
+
+    match reader.decode() {
+        Ok(image) => Ok(image.to_rgba()),
+        Err(reason) => Err(format!("Unable to decode image: {}", reason)),
+    }
+}
@@ -0,0 +1,205 @@
+use log::{info, warn};
+use yaml_rust::yaml::{Hash, Yaml};
+use yaml_rust::YamlEmitter;
+
+use serenity::model::prelude::Message;
+use serenity::prelude::Context;
+
+use crate::color::format_color;
+use crate::redact;
+use crate::registry::{Meme, MemesKey};
+
+fn insert(hash: &mut Hash, key: &str, value: Yaml) {
+    hash.insert(Yaml::String(key.into()), value);
+}
+
+fn insert_string(hash: &mut Hash, key: &str, value: &str) {
+    insert(hash, key, Yaml::String(value.into()));
+}
+
+/// Writes out the meme as a config section with every setting it ended up
+/// with, as if it didn't extend anything
+fn meme_to_yaml(meme: &Meme) -> Yaml {
+    let mut hash = Hash::new();
+
+    insert_string(&mut hash, "command", &meme.command);
+    insert_string(&mut hash, "filename", &meme.filename);
+    insert_string(&mut hash, "font", &meme.font);
+    insert(&mut hash, "font_size", Yaml::Integer(meme.scale.y as i64));
+
+    if meme.avatar_regions.is_empty() {
+        insert(&mut hash, "left", Yaml::Integer(meme.left as i64));
+        insert(&mut hash, "top", Yaml::Integer(meme.top as i64));
+        insert(&mut hash, "right", Yaml::Integer(meme.right as i64));
+        insert(&mut hash, "bottom", Yaml::Integer(meme.bottom as i64));
+    } else {
+        let regions = meme
+            .avatar_regions
+            .iter()
+            .map(|avatar_region| {
+                let region = avatar_region.region;
+                let mut entry = Hash::new();
+
+                insert(&mut entry, "left", Yaml::Integer(region.left as i64));
+                insert(&mut entry, "top", Yaml::Integer(region.top as i64));
+                insert(&mut entry, "right", Yaml::Integer(region.right as i64));
+                insert(&mut entry, "bottom", Yaml::Integer(region.bottom as i64));
+
+                if let Some(label) = &avatar_region.label {
+                    insert_string(&mut entry, "label", label);
+                }
+
+                Yaml::Hash(entry)
+            })
+            .collect();
+
+        insert(&mut hash, "avatar_regions", Yaml::Array(regions));
+        insert(
+            &mut hash,
+            "remove_avatar_background",
+            Yaml::Boolean(meme.remove_avatar_background),
+        );
+    }
+
+    if !meme.text_prefix.is_empty() {
+        insert_string(&mut hash, "text_prefix", &meme.text_prefix);
+    }
+
+    if !meme.text_suffix.is_empty() {
+        insert_string(&mut hash, "text_suffix", &meme.text_suffix);
+    }
+
+    insert_string(&mut hash, "transform", meme.transform.name());
+
+    if let Some(seed) = meme.transform_seed {
+        insert(&mut hash, "transform_seed", Yaml::Integer(seed as i64));
+    }
+
+    insert(&mut hash, "is_default", Yaml::Boolean(meme.is_default));
+
+    for (key, value) in [
+        ("category", &meme.category),
+        ("description", &meme.description),
+        ("license", &meme.license),
+        ("source_url", &meme.source_url),
+        ("plugin", &meme.plugin),
+        ("code_font", &meme.code_font),
+    ] {
+        if let Some(value) = value {
+            insert_string(&mut hash, key, value);
+        }
+    }
+
+    insert(&mut hash, "allow_zalgo", Yaml::Boolean(meme.allow_zalgo));
+    insert(&mut hash, "hyphenate", Yaml::Boolean(meme.hyphenate));
+    insert_string(&mut hash, "direction", meme.direction.name());
+    insert_string(&mut hash, "align", meme.alignment.name());
+    insert_string(&mut hash, "text_color", &format_color(meme.text_color));
+    insert(
+        &mut hash,
+        "stroke_width",
+        Yaml::Integer(meme.stroke_width as i64),
+    );
+    insert_string(&mut hash, "stroke_color", &format_color(meme.stroke_color));
+    insert_string(&mut hash, "stroke_join", meme.stroke_join.name());
+    insert_string(&mut hash, "effect", meme.effect.kind.name());
+    insert(
+        &mut hash,
+        "effect_amplitude",
+        Yaml::Real(meme.effect.amplitude.to_string()),
+    );
+    insert(
+        &mut hash,
+        "effect_frequency",
+        Yaml::Real(meme.effect.frequency.to_string()),
+    );
+    insert_string(&mut hash, "code_color", &format_color(meme.code_color));
+
+    if let Some(code_background) = meme.code_background {
+        insert_string(&mut hash, "code_background", &format_color(code_background));
+    }
+
+    if let Some(animation) = meme.animation {
+        insert_string(&mut hash, "animate", animation.kind.name());
+        insert(
+            &mut hash,
+            "animate_duration_ms",
+            Yaml::Integer(animation.duration_ms as i64),
+        );
+        insert(
+            &mut hash,
+            "animate_fps",
+            Yaml::Integer(animation.fps as i64),
+        );
+    }
+
+    if let Some(output_max_width) = meme.output_max_width {
+        insert(
+            &mut hash,
+            "output_max_width",
+            Yaml::Integer(output_max_width as i64),
+        );
+    }
+
+    insert(&mut hash, "weight", Yaml::Integer(meme.weight as i64));
+
+    if let Some(schedule) = meme.schedule {
+        let (from, until) = schedule.bounds();
+
+        if let Some(from) = from {
+            insert_string(&mut hash, "active_from", &from);
+        }
+
+        if let Some(until) = until {
+            insert_string(&mut hash, "active_until", &until);
+        }
+    }
+
+    Yaml::Hash(hash)
+}
+
+/// Uploads the memes that are loaded right now as a config file, with the
+/// memes added at runtime merged in and every setting inherited from `extends`
+/// filled in, to compare against the files on disk
+pub fn dump_config(ctx: &Context, msg: &Message) {
+    let data = ctx.data.read();
+    let memes: Vec<Yaml> = data
+        .get::<MemesKey>()
+        .expect("Command dumpconfig: Unable to retrieve memes")
+        .load()
+        .loaded()
+        .iter()
+        .map(meme_to_yaml)
+        .collect();
+    drop(data);
+
+    let count = memes.len();
+    let mut contents = String::new();
+
+    if let Err(reason) = YamlEmitter::new(&mut contents).dump(&Yaml::Array(memes)) {
+        warn!(
+            "Command dumpconfig: Unable to serialize config: {:?}",
+            reason
+        );
+
+        msg.channel_id
+            .say(ctx, "Unable to write out the config.")
+            .ok();
+        return;
+    }
+
+    let contents = format!(
+        "# The effective config of the {} memes that were loaded\n{}\n",
+        count, contents
+    );
+
+    info!("Config dumped by {}", redact::user(&msg.author));
+
+    if let Err(reason) = msg.channel_id.send_files(
+        ctx,
+        vec![(contents.as_bytes(), "effective_config.yml")],
+        |m| m.content(format!("The {} memes that are loaded right now:", count)),
+    ) {
+        warn!("Command dumpconfig: Unable to send config: {}", reason);
+    }
+}
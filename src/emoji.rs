@@ -0,0 +1,178 @@
+use image::imageops::{self, FilterType};
+use image::RgbaImage;
+use log::{info, warn};
+
+use serenity::model::prelude::Message;
+use serenity::prelude::Context;
+
+use crate::aliases::resolve_alias;
+use crate::flags::take_flags;
+use crate::permissions::is_manager;
+use crate::redact;
+use crate::registry::MemesKey;
+use crate::render::{encode_png, render_meme, OutputSettings};
+use crate::{meme_text, split_first_word, MemeSettings};
+
+// Discord shows emoji at up to this size, and scales down anything larger
+const EMOJI_SIZE: u32 = 128;
+
+// Discord doesn't accept larger emoji
+const MAX_EMOJI_BYTES: usize = 256 * 1024;
+
+/// Centers `image` on a transparent square of `EMOJI_SIZE`, shrinking it to
+/// fit
+fn to_square(image: &RgbaImage) -> RgbaImage {
+    let scale = EMOJI_SIZE as f32 / image.width().max(image.height()) as f32;
+
+    let width = ((image.width() as f32 * scale).round() as u32).clamp(1, EMOJI_SIZE);
+    let height = ((image.height() as f32 * scale).round() as u32).clamp(1, EMOJI_SIZE);
+
+    let resized = imageops::resize(image, width, height, FilterType::Lanczos3);
+
+    let mut square = RgbaImage::new(EMOJI_SIZE, EMOJI_SIZE);
+    imageops::overlay(
+        &mut square,
+        &resized,
+        (EMOJI_SIZE - width) / 2,
+        (EMOJI_SIZE - height) / 2,
+    );
+
+    square
+}
+
+/// Turns a meme's command into an emoji name, which can only have 2 to 32
+/// letters, numbers, and underscores
+fn emoji_name(command: &str) -> String {
+    let mut name: String = command
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .take(32)
+        .collect();
+
+    while name.len() < 2 {
+        name.push('_');
+    }
+
+    name
+}
+
+/// Draws the meme as a PNG that Discord accepts as an emoji
+fn render_emoji(
+    ctx: &Context,
+    guild_id: u64,
+    command: &str,
+    text: &str,
+) -> Result<Vec<u8>, String> {
+    let data = ctx.data.read();
+
+    let command = resolve_alias(&data, guild_id, command).unwrap_or(command.to_string());
+
+    let memes = data
+        .get::<MemesKey>()
+        .expect("Command makeemoji: Unable to retrieve memes")
+        .load();
+
+    let meme = match memes.find(&command) {
+        Some(meme) => meme,
+        None => return Err(format!("There's no meme called `{}`.", command)),
+    };
+
+    if !meme.avatar_regions.is_empty() {
+        return Err("Memes with avatars can't be made into emoji.".into());
+    }
+
+    // Rendering can take a while, so it's done without holding on to the data
+    let settings = MemeSettings::read(&data, Some(guild_id), meme);
+    drop(data);
+
+    let font = match &settings.font {
+        Some(font) => font,
+        None => return Err("No fonts are loaded.".into()),
+    };
+
+    let (text, flags) = take_flags(text)?;
+    let text = meme_text(
+        meme,
+        &text,
+        settings.locale.as_deref(),
+        flags.transform_seed(meme),
+    );
+
+    let image = to_square(&render_meme(meme, font, &text, flags.text_style(meme)));
+
+    let png = encode_png(&image, OutputSettings::default())?;
+
+    if png.len() > MAX_EMOJI_BYTES {
+        return Err("This meme is too large to be an emoji.".into());
+    }
+
+    Ok(png)
+}
+
+/// Renders a meme and adds it to the server as an emoji
+pub fn make_emoji(ctx: &Context, msg: &Message, rest: &str) {
+    let guild_id = match msg.guild_id {
+        Some(guild_id) => guild_id,
+        None => {
+            msg.channel_id
+                .say(ctx, "Emoji can only be made in a server.")
+                .ok();
+            return;
+        }
+    };
+
+    if !is_manager(ctx, msg) {
+        msg.channel_id
+            .say(
+                ctx,
+                "You need to be a manager of this server (or have the Manage Server permission) to do that.",
+            )
+            .ok();
+        return;
+    }
+
+    let (command, text) = split_first_word(rest);
+    let command = command.to_lowercase();
+
+    if command.is_empty() || text.trim().is_empty() {
+        msg.channel_id
+            .say(ctx, "Usage: `makeemoji <meme> <text>`")
+            .ok();
+        return;
+    }
+
+    let png = match render_emoji(ctx, guild_id.0, &command, text) {
+        Ok(png) => png,
+        Err(reason) => {
+            msg.channel_id.say(ctx, reason).ok();
+            return;
+        }
+    };
+
+    let image = format!("data:image/png;base64,{}", base64::encode(&png));
+
+    match guild_id.create_emoji(ctx, &emoji_name(&command), &image) {
+        Ok(emoji) => {
+            info!(
+                "Emoji \"{}\" made from meme \"{}\" by {}",
+                emoji.name,
+                command,
+                redact::user(&msg.author)
+            );
+
+            msg.channel_id
+                .say(ctx, format!("Added {} as `:{}:`.", emoji, emoji.name))
+                .ok();
+        }
+        Err(reason) => {
+            warn!("Command makeemoji: Unable to create emoji: {}", reason);
+
+            msg.channel_id
+                .say(
+                    ctx,
+                    "Unable to add the emoji. (The bot needs the Manage Emojis permission, and the server may be out of emoji slots.)",
+                )
+                .ok();
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use image::imageops::{self, FilterType};
+use image::RgbaImage;
+use log::{info, warn};
+use regex::Regex;
+
+use serenity::model::prelude::Message;
+use serenity::prelude::Context;
+
+use crate::avatars::fetch_emoji;
+use crate::capabilities::{self, Capability};
+use crate::redact;
+use crate::registry::MemesKey;
+use crate::render::{add_caption, encode_png};
+use crate::{select_font, BotSettingsKey, FontsKey};
+
+const USAGE: &str = "Usage: `emojistrip <emoji>... [caption]`, with custom emoji";
+
+// Each emoji is scaled to this height, wider ones keeping their shape
+const STRIP_HEIGHT: u32 = 256;
+
+// Space around and between the emoji
+const GAP: u32 = 16;
+
+const MAX_EMOJI: usize = 10;
+
+/// Takes the custom emoji off the start of `text`, like "<:pog:123456789>",
+/// which may or may not be separated by spaces. Returns their IDs and the text
+/// after them.
+fn take_emoji(text: &str) -> (Vec<u64>, &str) {
+    let re_custom_emoji =
+        Regex::new(r"^\s*<a?:\w+:(\d+)>").expect("Unable to create custom emoji matching pattern");
+
+    let mut ids = Vec::new();
+    let mut rest = text;
+
+    while let Some(captures) = re_custom_emoji.captures(rest) {
+        match captures[1].parse::<u64>() {
+            Ok(id) => ids.push(id),
+            Err(_) => break,
+        }
+
+        rest = &rest[captures[0].len()..];
+    }
+
+    (ids, rest.trim())
+}
+
+/// Lines up the emoji from left to right at the same height, on a transparent
+/// background
+fn compose_strip(emoji: &[RgbaImage]) -> RgbaImage {
+    let resized: Vec<RgbaImage> = emoji
+        .iter()
+        .map(|image| {
+            let width = (image.width() * STRIP_HEIGHT / image.height().max(1)).max(1);
+
+            imageops::resize(image, width, STRIP_HEIGHT, FilterType::Lanczos3)
+        })
+        .collect();
+
+    let width = resized.iter().map(|image| image.width() + GAP).sum::<u32>() + GAP;
+    let mut strip = RgbaImage::new(width, STRIP_HEIGHT + 2 * GAP);
+
+    let mut x = GAP;
+
+    for image in &resized {
+        imageops::overlay(&mut strip, image, x, GAP);
+        x += image.width() + GAP;
+    }
+
+    strip
+}
+
+/// Puts custom emoji side by side in one image, with an optional caption
+/// below them in the default meme's font
+pub fn emoji_strip(ctx: &Context, msg: &Message, rest: &str) {
+    let (ids, caption) = take_emoji(rest);
+
+    if ids.is_empty() {
+        msg.channel_id.say(ctx, USAGE).ok();
+        return;
+    }
+
+    if ids.len() > MAX_EMOJI {
+        msg.channel_id
+            .say(
+                ctx,
+                format!("A strip can have at most {} emoji.", MAX_EMOJI),
+            )
+            .ok();
+        return;
+    }
+
+    if !capabilities::is_enabled(Capability::Fetching) {
+        msg.channel_id
+            .say(ctx, Capability::Fetching.disabled_message())
+            .ok();
+        return;
+    }
+
+    let mut emoji = Vec::with_capacity(ids.len());
+
+    for id in ids {
+        match fetch_emoji(id) {
+            Ok(image) => emoji.push(image),
+            Err(reason) => {
+                warn!("Command emojistrip: {}", reason);
+
+                msg.channel_id
+                    .say(ctx, "Unable to download one of the emoji.")
+                    .ok();
+                return;
+            }
+        }
+    }
+
+    let strip = compose_strip(&emoji);
+
+    let data = ctx.data.read();
+    let output_settings = data
+        .get::<BotSettingsKey>()
+        .expect("Command emojistrip: Unable to retrieve bot settings")
+        .output_settings;
+    let memes = data
+        .get::<MemesKey>()
+        .expect("Command emojistrip: Unable to retrieve memes")
+        .load();
+    let font = memes.default_meme(None).and_then(|meme| {
+        select_font(
+            data.get::<FontsKey>()
+                .expect("Command emojistrip: Unable to retrieve fonts"),
+            meme,
+        )
+        .cloned()
+    });
+
+    // Captioning can take a while, so it's done without holding on to the data
+    drop(data);
+
+    let image = if caption.is_empty() {
+        strip
+    } else {
+        match memes.default_meme(None).zip(font.as_ref()) {
+            Some((meme, font)) => add_caption(&strip, meme, font, caption),
+            None => {
+                msg.channel_id
+                    .say(ctx, "Captions need a default meme, for its font.")
+                    .ok();
+                return;
+            }
+        }
+    };
+
+    let png = match encode_png(&image, output_settings) {
+        Ok(png) => png,
+        Err(reason) => {
+            warn!("Command emojistrip: {}", reason);
+
+            msg.channel_id.say(ctx, "Unable to create the strip.").ok();
+            return;
+        }
+    };
+
+    info!(
+        "Emoji strip of {} emoji made by {}",
+        emoji.len(),
+        redact::user(&msg.author)
+    );
+
+    if let Err(reason) = msg
+        .channel_id
+        .send_files(ctx, vec![(&png[..], "emojistrip.png")], |m| m)
+    {
+        warn!("Command emojistrip: Unable to send strip: {}", reason);
+    }
+}
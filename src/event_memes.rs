@@ -0,0 +1,134 @@
+use log::warn;
+
+use serenity::model::id::ChannelId;
+use serenity::model::user::User;
+use serenity::prelude::Context;
+
+use crate::avatars::fetch_avatar;
+use crate::capabilities::{self, Capability};
+use crate::flags::Flags;
+use crate::registry::MemesKey;
+use crate::render::{encode_meme_png, render_avatar_meme, render_meme};
+use crate::{meme_text, MemeSettings};
+
+/// Replaces `{user}` in the text of a meme made for an event with the name of
+/// the user it's about, and each of `extra` with its value
+pub fn fill_placeholders(text: &str, user: &User, extra: &[(&str, &str)]) -> String {
+    extra
+        .iter()
+        .fold(text.replace("{user}", &user.name), |text, (name, value)| {
+            text.replace(&format!("{{{}}}", name), value)
+        })
+}
+
+/// Draws the meme `command` about `user`, putting their avatar in its first
+/// avatar region if it has any, with `text` as the label
+fn render_user_meme(
+    ctx: &Context,
+    guild_id: u64,
+    command: &str,
+    user: &User,
+    text: &str,
+) -> Result<Vec<u8>, String> {
+    let memes = ctx
+        .data
+        .read()
+        .get::<MemesKey>()
+        .expect("Event meme: Unable to retrieve memes")
+        .load();
+
+    let has_avatars = match memes.find(command) {
+        Some(meme) => !meme.avatar_regions.is_empty(),
+        None => return Err(format!("There's no meme called \"{}\"", command)),
+    };
+
+    // Fetched before looking at the memes again, so that the download doesn't
+    // hold up everything else
+    let avatar = if has_avatars {
+        if !capabilities::is_enabled(Capability::Avatars) {
+            return Err(Capability::Avatars.disabled_message().into());
+        }
+
+        Some(fetch_avatar(user)?)
+    } else {
+        None
+    };
+
+    let data = ctx.data.read();
+
+    let memes = data
+        .get::<MemesKey>()
+        .expect("Event meme: Unable to retrieve memes")
+        .load();
+
+    let meme = match memes.find(command) {
+        Some(meme) => meme,
+        None => return Err(format!("There's no meme called \"{}\"", command)),
+    };
+
+    // Rendering can take a while, so it's done without holding on to the data
+    let settings = MemeSettings::read(&data, Some(guild_id), meme);
+    drop(data);
+
+    let font = match &settings.font {
+        Some(font) => font,
+        None => return Err("No fonts are loaded".into()),
+    };
+
+    let style = Flags::default().text_style(meme);
+    let text = meme_text(meme, text, settings.locale.as_deref(), meme.transform_seed);
+
+    let image = match avatar {
+        Some(avatar) if !meme.avatar_regions.is_empty() => {
+            let mut avatars = vec![(None, None); meme.avatar_regions.len()];
+            avatars[0] = (Some(avatar), Some(text));
+
+            render_avatar_meme(meme, font, &avatars, style)
+        }
+        _ => render_meme(meme, font, &text, style),
+    };
+
+    encode_meme_png(&image, meme, settings.output_settings)
+}
+
+/// Posts the meme `command` about `user` in `channel_id`, along with
+/// `content` if there is any, logging anything that goes wrong, since there's
+/// nobody to reply to
+pub fn post_user_meme(
+    ctx: &Context,
+    guild_id: u64,
+    channel_id: ChannelId,
+    command: &str,
+    user: &User,
+    text: &str,
+    content: Option<&str>,
+) {
+    let png = match render_user_meme(ctx, guild_id, command, user, text) {
+        Ok(png) => png,
+        Err(reason) => {
+            warn!(
+                "Unable to create meme \"{}\" for guild {}: {}",
+                command, guild_id, reason
+            );
+            return;
+        }
+    };
+
+    let filename = format!("{}.png", command);
+
+    if let Err(reason) =
+        channel_id.send_files(
+            ctx,
+            vec![(&png[..], filename.as_str())],
+            |m| match content {
+                Some(content) => m.content(content),
+                None => m,
+            },
+        )
+    {
+        warn!(
+            "Unable to post meme \"{}\" in channel {}: {}",
+            command, channel_id, reason
+        );
+    }
+}
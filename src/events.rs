@@ -0,0 +1,326 @@
+use log::{info, warn};
+use std::thread;
+
+use serenity::model::id::{ChannelId, UserId};
+use serenity::model::prelude::Message;
+use serenity::prelude::Context;
+
+use crate::event_memes::{fill_placeholders, post_user_meme};
+use crate::guild_settings::{Event, EventMeme, GuildSettingsKey};
+use crate::permissions::is_manager;
+use crate::redact;
+use crate::registry::MemesKey;
+use crate::schedule::{parse_month_day, Date};
+use crate::{split_first_word, SCHEDULE_REFRESH_INTERVAL};
+
+const DEFAULT_TEXT: &str = "Happy {event}, {user}!";
+
+const DEFAULT_NAME: &str = "birthday";
+
+const MAX_EVENTS: usize = 200;
+
+const MAX_NAME_LENGTH: usize = 32;
+
+fn is_mention(word: &str) -> bool {
+    word.starts_with("<@") && word.ends_with('>')
+}
+
+fn describe(event_meme: Option<&EventMeme>, events: &[Event]) -> String {
+    let mut lines = vec![match event_meme {
+        Some(event_meme) => format!(
+            "On the day of each event, `{}` is posted in <#{}> with the text \"{}\".",
+            event_meme.meme, event_meme.channel, event_meme.text
+        ),
+        None => "No meme is posted for this server's events. (A manager can choose one with `event here <meme>`.)".into(),
+    }];
+
+    if events.is_empty() {
+        lines.push("There are no events yet. (Add your birthday with `event add <MM-DD>`.)".into());
+    } else {
+        let mut events: Vec<&Event> = events.iter().collect();
+        events.sort_by_key(|event| (event.month, event.day));
+
+        lines.extend(events.iter().map(|event| {
+            format!(
+                "{:02}-{:02} - <@{}>'s {}",
+                event.month, event.day, event.user, event.name
+            )
+        }));
+    }
+
+    lines.join("\n")
+}
+
+/// Works out which user the command is about: whoever it mentions, or
+/// otherwise its author. Only managers can change other users' events.
+fn target_user(ctx: &Context, msg: &Message) -> Result<u64, String> {
+    // The bot itself is mentioned too, when the command is sent in a server
+    match msg.mentions.iter().find(|user| !user.bot) {
+        Some(user) if user.id != msg.author.id => {
+            if is_manager(ctx, msg) {
+                Ok(user.id.0)
+            } else {
+                Err("Only managers can change other users' events.".into())
+            }
+        }
+        _ => Ok(msg.author.id.0),
+    }
+}
+
+/// Applies the command's `action` to the guild's events, returning the reply
+fn change(
+    ctx: &Context,
+    msg: &Message,
+    guild_id: u64,
+    action: &str,
+    rest: &str,
+) -> Result<String, String> {
+    let words: Vec<&str> = rest
+        .split_whitespace()
+        .filter(|word| !is_mention(word))
+        .collect();
+
+    match action {
+        "here" | "off" => {
+            if !is_manager(ctx, msg) {
+                return Err("You need to be a manager of this server (or have the Manage Server permission) to do that.".into());
+            }
+
+            let event_meme = if action == "here" {
+                let (command, text) = split_first_word(rest);
+                let command = command.to_lowercase();
+
+                if command.is_empty() {
+                    return Err("Usage: `event here <meme> [text]`".into());
+                }
+
+                if !ctx
+                    .data
+                    .read()
+                    .get::<MemesKey>()
+                    .expect("Command event: Unable to retrieve memes")
+.load()
+                    .contains(&command)
+                {
+                    return Err(format!("There's no meme called `{}`.", command));
+                }
+
+                Some(EventMeme {
+                    channel: msg.channel_id.0,
+                    meme: command,
+                    text: match text.trim() {
+                        "" => DEFAULT_TEXT.to_string(),
+                        text => text.to_string(),
+                    },
+                })
+            } else {
+                None
+            };
+
+            let mut data = ctx.data.write();
+            let guild_settings = data
+                .get_mut::<GuildSettingsKey>()
+                .expect("Command event: Unable to retrieve guild settings");
+
+            let settings = guild_settings.get_mut(guild_id);
+            settings.event_meme = event_meme;
+            let reply = describe(settings.event_meme.as_ref(), &settings.events);
+
+            if let Err(reason) = guild_settings.save() {
+                warn!("Command event: {}", reason);
+            }
+
+            Ok(reply)
+        }
+        "add" => {
+            let user = target_user(ctx, msg)?;
+
+            let (month, day) = match words.first().and_then(|date| parse_month_day(date)) {
+                Some(date) => date,
+                None => return Err("Usage: `event add [@user] <MM-DD> [name]`, like `event add 10-31` for a birthday on October 31".into()),
+            };
+
+            let name = match words[1..].join(" ").to_lowercase() {
+                name if name.is_empty() => DEFAULT_NAME.to_string(),
+                name => name,
+            };
+
+            if name.chars().count() > MAX_NAME_LENGTH {
+                return Err(format!(
+                    "Event names can be at most {} characters long.",
+                    MAX_NAME_LENGTH
+                ));
+            }
+
+            let mut data = ctx.data.write();
+            let guild_settings = data
+                .get_mut::<GuildSettingsKey>()
+                .expect("Command event: Unable to retrieve guild settings");
+
+            let events = &mut guild_settings.get_mut(guild_id).events;
+            events.retain(|event| event.user != user || event.name != name);
+
+            if events.len() >= MAX_EVENTS {
+                return Err(format!(
+                    "This server already has {} events, which is the most it can have.",
+                    MAX_EVENTS
+                ));
+            }
+
+            events.push(Event {
+                user,
+                name: name.clone(),
+                month,
+                day,
+                last_posted: None,
+            });
+
+            if let Err(reason) = guild_settings.save() {
+                warn!("Command event: {}", reason);
+            }
+
+            Ok(format!(
+                "Added <@{}>'s {} on {:02}-{:02}.",
+                user, name, month, day
+            ))
+        }
+        "remove" => {
+            let user = target_user(ctx, msg)?;
+            let name = words.join(" ").to_lowercase();
+
+            let mut data = ctx.data.write();
+            let guild_settings = data
+                .get_mut::<GuildSettingsKey>()
+                .expect("Command event: Unable to retrieve guild settings");
+
+            let events = &mut guild_settings.get_mut(guild_id).events;
+            let count = events.len();
+
+            // Without a name, all of the user's events are removed
+            events.retain(|event| event.user != user || !(name.is_empty() || event.name == name));
+
+            if events.len() == count {
+                return Err(if name.is_empty() {
+                    format!("<@{}> has no events.", user)
+                } else {
+                    format!("<@{}> has no event called \"{}\".", user, name)
+                });
+            }
+
+            let removed = count - events.len();
+
+            if let Err(reason) = guild_settings.save() {
+                warn!("Command event: {}", reason);
+            }
+
+            Ok(format!(
+                "Removed {} of <@{}>'s events.",
+                removed, user
+            ))
+        }
+        _ => Err("Usage: `event [list|add [@user] <MM-DD> [name]|remove [@user] [name]|here <meme> [text]|off]`".into()),
+    }
+}
+
+pub fn event_command(ctx: &Context, msg: &Message, rest: &str) {
+    let guild_id = match msg.guild_id {
+        Some(guild_id) => guild_id.0,
+        None => {
+            msg.channel_id
+                .say(ctx, "Events can only be set up in a server.")
+                .ok();
+            return;
+        }
+    };
+
+    let (action, rest) = split_first_word(rest);
+    let action = action.to_lowercase();
+
+    if action.is_empty() || action == "list" {
+        let data = ctx.data.read();
+        let settings = data
+            .get::<GuildSettingsKey>()
+            .expect("Command event: Unable to retrieve guild settings")
+            .get(guild_id);
+
+        let reply = describe(
+            settings.and_then(|settings| settings.event_meme.as_ref()),
+            settings.map(|settings| &settings.events[..]).unwrap_or(&[]),
+        );
+
+        drop(data);
+
+        msg.channel_id.say(ctx, reply).ok();
+        return;
+    }
+
+    let reply = match change(ctx, msg, guild_id, &action, rest) {
+        Ok(reply) => reply,
+        Err(reason) => reason,
+    };
+
+    msg.channel_id.say(ctx, reply).ok();
+}
+
+/// Posts the memes for the events that are on today, once each year
+fn post_due_events(ctx: &Context) {
+    let mut data = ctx.data.write();
+    let guild_settings = data
+        .get_mut::<GuildSettingsKey>()
+        .expect("Events: Unable to retrieve guild settings");
+
+    let due = guild_settings.take_due_events(Date::today());
+
+    if due.is_empty() {
+        return;
+    }
+
+    // Saved before posting, so that a meme that can't be posted isn't tried
+    // again every time
+    if let Err(reason) = guild_settings.save() {
+        warn!("Events: {}", reason);
+    }
+
+    drop(data);
+
+    for (guild_id, event_meme, event) in due {
+        let user = match UserId(event.user).to_user(ctx) {
+            Ok(user) => user,
+            Err(reason) => {
+                warn!(
+                    "Events: Unable to retrieve user {}: {}",
+                    redact::user_id(event.user),
+                    reason
+                );
+                continue;
+            }
+        };
+
+        info!(
+            "Posting event meme \"{}\" for {}'s {} in guild {}",
+            event_meme.meme,
+            redact::user(&user),
+            event.name,
+            guild_id
+        );
+
+        post_user_meme(
+            ctx,
+            guild_id,
+            ChannelId(event_meme.channel),
+            &event_meme.meme,
+            &user,
+            &fill_placeholders(&event_meme.text, &user, &[("event", &event.name)]),
+            Some(&format!("<@{}>", user.id)),
+        );
+    }
+}
+
+/// Checks for events that are on today as often as the seasonal memes are
+/// refreshed
+pub fn start_events(ctx: Context) {
+    thread::spawn(move || loop {
+        post_due_events(&ctx);
+        thread::sleep(SCHEDULE_REFRESH_INTERVAL);
+    });
+}
@@ -0,0 +1,208 @@
+use log::{info, warn};
+
+use serenity::model::prelude::Message;
+use serenity::prelude::Context;
+
+use crate::aliases::resolve_alias;
+use crate::capabilities::is_feature_enabled;
+use crate::flags::Flags;
+use crate::message_text::{linked_message_ids, message_text};
+use crate::redact;
+use crate::registry::MemesKey;
+use crate::render::{encode_meme_png, render_meme};
+use crate::{meme_text, split_first_word, MemeSettings};
+
+const USAGE: &str =
+    "Usage: `factcheck <meme> [<message link>]`, replying to the message or linking to it";
+
+// Longer messages are cut off, though their statistics are of the whole text
+const MAX_QUOTE_CHARS: usize = 280;
+
+const WORDS_PER_MINUTE: usize = 200;
+
+fn plural(count: usize, noun: &str) -> String {
+    format!("{} {}{}", count, noun, if count == 1 { "" } else { "s" })
+}
+
+/// Describes how long the text takes to read, at `WORDS_PER_MINUTE`
+fn reading_time(text: &str) -> String {
+    let words = text.split_whitespace().count();
+    let seconds = (words * 60).div_ceil(WORDS_PER_MINUTE);
+
+    if seconds < 60 {
+        plural(seconds.max(1), "second")
+    } else {
+        plural(seconds.div_ceil(60), "minute")
+    }
+}
+
+/// The ratio of the message's most common reaction to all of the others, not
+/// counting the bot's own
+fn reaction_ratio(message: &Message) -> Option<String> {
+    let mut counts: Vec<u64> = message
+        .reactions
+        .iter()
+        .map(|reaction| reaction.count - if reaction.me { 1 } else { 0 })
+        .filter(|count| *count > 0)
+        .collect();
+
+    counts.sort_unstable_by(|a, b| b.cmp(a));
+
+    let (top, others) = counts.split_first()?;
+
+    Some(format!("ratio {}:{}", top, others.iter().sum::<u64>()))
+}
+
+/// Lays out the message as a quote, followed by its author and statistics
+fn fact_check_text(message: &Message, text: &str) -> String {
+    let text = text.trim();
+    let length = text.chars().count();
+
+    let quote: String = if length > MAX_QUOTE_CHARS {
+        text.chars()
+            .take(MAX_QUOTE_CHARS - 1)
+            .chain(std::iter::once('…'))
+            .collect()
+    } else {
+        text.into()
+    };
+
+    let mut stats = vec![
+        plural(length, "character"),
+        format!("{} read", reading_time(text)),
+    ];
+    stats.extend(reaction_ratio(message));
+
+    format!(
+        "\u{201C}{}\u{201D}\n\u{2014} {}\n\n{}",
+        quote,
+        message.author.name,
+        stats.join(" \u{00B7} ")
+    )
+}
+
+/// Draws a message, along with how long it is and how it was reacted to, into
+/// a meme
+pub fn fact_check(ctx: &Context, msg: &Message, rest: &str) {
+    let (command, rest) = split_first_word(rest);
+    let command = command.to_lowercase();
+
+    if command.is_empty() {
+        msg.channel_id.say(ctx, USAGE).ok();
+        return;
+    }
+
+    let (channel_id, message_id) = match linked_message_ids(ctx, msg, rest, USAGE) {
+        Ok(ids) => ids,
+        Err(reason) => {
+            msg.channel_id.say(ctx, reason).ok();
+            return;
+        }
+    };
+
+    let message = match ctx.http.get_message(channel_id.0, message_id.0) {
+        Ok(message) => message,
+        Err(reason) => {
+            warn!("Command factcheck: Unable to retrieve message: {}", reason);
+
+            msg.channel_id
+                .say(
+                    ctx,
+                    "Unable to find that message. (The bot needs to be able to read its channel.)",
+                )
+                .ok();
+            return;
+        }
+    };
+
+    let guild_id = msg.guild_id.map(|guild_id| guild_id.0);
+
+    let read_attachments = is_feature_enabled(&ctx.data.read(), guild_id, "text-attachments");
+    let text = message_text(ctx, &message, read_attachments);
+
+    if text.trim().is_empty() {
+        msg.channel_id
+            .say(ctx, "That message has no text to check.")
+            .ok();
+        return;
+    }
+
+    let data = ctx.data.read();
+
+    let command = guild_id
+        .and_then(|guild_id| resolve_alias(&data, guild_id, &command))
+        .unwrap_or(command);
+
+    let memes = data
+        .get::<MemesKey>()
+        .expect("Command factcheck: Unable to retrieve memes")
+        .load();
+
+    let meme = match memes.find(&command) {
+        Some(meme) if meme.avatar_regions.is_empty() => meme,
+        Some(_) => {
+            drop(data);
+
+            msg.channel_id
+                .say(ctx, "Memes with avatars can't be used for fact checks.")
+                .ok();
+            return;
+        }
+        None => {
+            drop(data);
+
+            msg.channel_id
+                .say(ctx, format!("There's no meme called `{}`.", command))
+                .ok();
+            return;
+        }
+    };
+
+    // Rendering can take a while, so it's done without holding on to the data
+    let settings = MemeSettings::read(&data, guild_id, meme);
+    drop(data);
+
+    let font = match &settings.font {
+        Some(font) => font,
+        None => {
+            msg.channel_id.say(ctx, "No fonts are loaded.").ok();
+            return;
+        }
+    };
+
+    let text = meme_text(
+        meme,
+        &fact_check_text(&message, &text),
+        settings.locale.as_deref(),
+        meme.transform_seed,
+    );
+
+    let image = render_meme(meme, font, &text, Flags::default().text_style(meme));
+    let png = encode_meme_png(&image, meme, settings.output_settings);
+
+    let png = match png {
+        Ok(png) => png,
+        Err(reason) => {
+            warn!("Command factcheck: {}", reason);
+
+            msg.channel_id.say(ctx, "Unable to create the meme.").ok();
+            return;
+        }
+    };
+
+    info!(
+        "Fact check \"{}\" made from message {} by {}",
+        command,
+        message.id,
+        redact::user(&msg.author)
+    );
+
+    let filename = format!("{}.png", command);
+
+    if let Err(reason) = msg
+        .channel_id
+        .send_files(ctx, vec![(&png[..], filename.as_str())], |m| m)
+    {
+        warn!("Command factcheck: Unable to send meme: {}", reason);
+    }
+}
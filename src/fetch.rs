@@ -0,0 +1,736 @@
+use crc32fast::Hasher;
+use reqwest::blocking::{Client, ClientBuilder, Response};
+use reqwest::header::{CONTENT_TYPE, LOCATION};
+use reqwest::redirect::Policy;
+use reqwest::{Method, Proxy, StatusCode, Url};
+use std::fs::{create_dir_all, remove_file, rename, write};
+use std::io::Read;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs};
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::capabilities::{self, Capability};
+
+// Downloads of templates and fonts are cut off at this size. Large fonts
+// covering CJK scripts can be tens of megabytes.
+pub const MAX_FILE_BYTES: usize = 64 * 1024 * 1024;
+
+pub fn is_url(location: &str) -> bool {
+    location.starts_with("http://") || location.starts_with("https://")
+}
+
+/// How outgoing requests are made. Every download goes through the same
+/// client, configured once when the bot starts.
+pub struct FetchSettings {
+    pub timeout: Duration,
+    pub max_redirects: usize,
+    // The proxy resolves hosts itself, so with one, the addresses checked
+    // aren't necessarily the ones it connects to
+    pub proxy: Option<String>,
+    // Whether URLs may point at loopback, private, and link-local addresses,
+    // like a template store inside the same network
+    pub allow_private_addresses: bool,
+}
+
+impl Default for FetchSettings {
+    fn default() -> FetchSettings {
+        FetchSettings {
+            timeout: Duration::from_secs(10),
+            max_redirects: 5,
+            proxy: None,
+            allow_private_addresses: false,
+        }
+    }
+}
+
+struct Fetcher {
+    // Used as-is when private addresses are allowed; otherwise each request
+    // gets a client of its own, pinned to the addresses that were checked
+    client: Client,
+    timeout: Duration,
+    proxy: Option<Proxy>,
+    max_redirects: usize,
+    allow_private_addresses: bool,
+}
+
+static FETCHER: OnceLock<Fetcher> = OnceLock::new();
+
+/// Sets up the client used for all downloads. Downloads made before this is
+/// called use the default settings.
+pub fn configure(settings: FetchSettings) -> Result<(), String> {
+    let fetcher = build_fetcher(settings)?;
+
+    if FETCHER.set(fetcher).is_err() {
+        return Err("The HTTP client has already been configured".into());
+    }
+
+    Ok(())
+}
+
+fn client_builder(timeout: Duration, proxy: &Option<Proxy>) -> ClientBuilder {
+    // Redirects are followed by `send`, which checks where each one goes,
+    // since a public URL could redirect to a private one
+    let builder = Client::builder().timeout(timeout).redirect(Policy::none());
+
+    match proxy {
+        Some(proxy) => builder.proxy(proxy.clone()),
+        None => builder,
+    }
+}
+
+fn build_fetcher(settings: FetchSettings) -> Result<Fetcher, String> {
+    let proxy = match &settings.proxy {
+        Some(proxy) => match Proxy::all(proxy.as_str()) {
+            Ok(proxy) => Some(proxy),
+            Err(reason) => return Err(format!("Invalid proxy \"{}\": {}", proxy, reason)),
+        },
+        None => None,
+    };
+
+    match client_builder(settings.timeout, &proxy).build() {
+        Ok(client) => Ok(Fetcher {
+            client,
+            timeout: settings.timeout,
+            proxy,
+            max_redirects: settings.max_redirects,
+            allow_private_addresses: settings.allow_private_addresses,
+        }),
+        Err(reason) => Err(format!("Unable to create HTTP client: {}", reason)),
+    }
+}
+
+fn fetcher() -> &'static Fetcher {
+    FETCHER.get_or_init(|| {
+        build_fetcher(FetchSettings::default())
+            .expect("fetcher(): Unable to create HTTP client with default settings")
+    })
+}
+
+impl Fetcher {
+    /// Returns the client to send a request to `url` with. Unless private
+    /// addresses are allowed, `url` is checked first, and the client connects
+    /// only to the addresses its host resolved to then, so that the host
+    /// can't be made to resolve somewhere else by the time it's connected to.
+    fn client_for(&self, url: &Url, allow_private_addresses: bool) -> Result<Client, String> {
+        if allow_private_addresses {
+            return Ok(self.client.clone());
+        }
+
+        let addresses = check_public(url)?;
+
+        match client_builder(self.timeout, &self.proxy)
+            .resolve_to_addrs(url.host_str().unwrap_or_default(), &addresses)
+            .build()
+        {
+            Ok(client) => Ok(client),
+            Err(reason) => Err(format!("Unable to create HTTP client: {}", reason)),
+        }
+    }
+
+    /// Sends a `method` request to `url`, with `body` and its content type if
+    /// there is one, and follows any redirects. Every URL along the way is
+    /// checked with `check_public` unless `allow_private_addresses` is set.
+    /// Errors start with "Unable to `action` `url`".
+    fn send(
+        &self,
+        method: Method,
+        url: &str,
+        body: Option<(&[u8], &str)>,
+        action: &str,
+        allow_private_addresses: bool,
+    ) -> Result<Response, String> {
+        let mut current_url = match Url::parse(url) {
+            Ok(parsed_url) => parsed_url,
+            Err(reason) => return Err(format!("Invalid URL \"{}\": {}", url, reason)),
+        };
+        let mut method = method;
+        let mut body = body;
+
+        for _ in 0..=self.max_redirects {
+            let client = self.client_for(&current_url, allow_private_addresses)?;
+            let mut request = client.request(method.clone(), current_url.clone());
+
+            if let Some((contents, content_type)) = body {
+                request = request
+                    .header(CONTENT_TYPE, content_type)
+                    .body(contents.to_vec());
+            }
+
+            let response = match request.send() {
+                Ok(response) => response,
+                Err(reason) => return Err(format!("Unable to {} \"{}\": {}", action, url, reason)),
+            };
+
+            let location = response
+                .headers()
+                .get(LOCATION)
+                .and_then(|location| location.to_str().ok())
+                .and_then(|location| current_url.join(location).ok());
+
+            current_url = match location {
+                Some(location) if response.status().is_redirection() => location,
+                _ => return Ok(response),
+            };
+
+            // As with browsers, anything but a HEAD turns into a GET without a
+            // body when it's redirected by one of these
+            let status = response.status();
+
+            if method != Method::HEAD
+                && (status == StatusCode::MOVED_PERMANENTLY
+                    || status == StatusCode::FOUND
+                    || status == StatusCode::SEE_OTHER)
+            {
+                method = Method::GET;
+                body = None;
+            }
+        }
+
+        Err(format!(
+            "Unable to {} \"{}\": more than {} redirects",
+            action, url, self.max_redirects
+        ))
+    }
+}
+
+fn is_private_address(address: IpAddr) -> bool {
+    match address {
+        IpAddr::V4(address) => {
+            let octets = address.octets();
+
+            address.is_private()
+                || address.is_loopback()
+                || address.is_link_local()
+                || address.is_unspecified()
+                || address.is_multicast()
+                || address.is_documentation()
+                || octets[0] == 0
+                // Reserved (240.0.0.0/4), which includes the broadcast address
+                || octets[0] >= 240
+                // Carrier-grade NAT (100.64.0.0/10)
+                || (octets[0] == 100 && (octets[1] & 0xc0) == 64)
+                // Benchmarking (198.18.0.0/15)
+                || (octets[0] == 198 && (octets[1] & 0xfe) == 18)
+        }
+        IpAddr::V6(address) => {
+            let segments = address.segments();
+
+            if let Some(address) = address.to_ipv4_mapped() {
+                return is_private_address(IpAddr::V4(address));
+            }
+
+            // NAT64 (64:ff9b::/96) reaches the IPv4 address in the last 32
+            // bits, so it's as private as that address is
+            if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+                let octets = address.octets();
+                let address = Ipv4Addr::new(octets[12], octets[13], octets[14], octets[15]);
+
+                return is_private_address(IpAddr::V4(address));
+            }
+
+            address.is_loopback()
+                || address.is_unspecified()
+                || address.is_multicast()
+                // Unique local (fc00::/7) and link-local (fe80::/10)
+                || (segments[0] & 0xfe00) == 0xfc00
+                || (segments[0] & 0xffc0) == 0xfe80
+                // Documentation (2001:db8::/32)
+                || (segments[0] == 0x2001 && segments[1] == 0xdb8)
+                // NAT64 for local networks (64:ff9b:1::/48)
+                || segments[..3] == [0x64, 0xff9b, 1]
+        }
+    }
+}
+
+/// Makes sure that `url` is an HTTP(S) URL whose host only resolves to public
+/// addresses, so that URLs can't be used to reach services on the bot's own
+/// network. Returns the addresses, which are the only ones that should then
+/// be connected to.
+fn check_public(url: &Url) -> Result<Vec<SocketAddr>, String> {
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err(format!("\"{}\" is not an HTTP URL", url));
+    }
+
+    let host = match url.host_str() {
+        Some(host) => host.trim_start_matches('[').trim_end_matches(']'),
+        None => return Err(format!("\"{}\" has no host", url)),
+    };
+    let port = url.port_or_known_default().unwrap_or(80);
+
+    let addresses: Vec<SocketAddr> = match (host, port).to_socket_addrs() {
+        Ok(addresses) => addresses.collect(),
+        Err(reason) => return Err(format!("Unable to resolve \"{}\": {}", host, reason)),
+    };
+
+    if addresses.is_empty() {
+        return Err(format!("\"{}\" doesn't resolve to any addresses", host));
+    }
+
+    for address in &addresses {
+        if is_private_address(address.ip()) {
+            return Err(format!(
+                "\"{}\" resolves to a private address ({})",
+                host,
+                address.ip()
+            ));
+        }
+    }
+
+    Ok(addresses)
+}
+
+/// Returns whether there is a file at `url`, without downloading it
+pub fn exists(url: &str) -> bool {
+    if !capabilities::is_enabled(Capability::Fetching) {
+        return false;
+    }
+
+    let fetcher = fetcher();
+
+    match fetcher.send(
+        Method::HEAD,
+        url,
+        None,
+        "download",
+        fetcher.allow_private_addresses,
+    ) {
+        Ok(response) => response.status().is_success(),
+        Err(_) => false,
+    }
+}
+
+/// Downloads the file at `url`, giving up as soon as it turns out to be larger
+/// than `max_bytes`
+pub fn download(url: &str, max_bytes: usize) -> Result<Vec<u8>, String> {
+    if !capabilities::is_enabled(Capability::Fetching) {
+        return Err(format!(
+            "Unable to download \"{}\": downloads are turned off in safe mode",
+            url
+        ));
+    }
+
+    let fetcher = fetcher();
+    let mut response = fetcher.send(
+        Method::GET,
+        url,
+        None,
+        "download",
+        fetcher.allow_private_addresses,
+    )?;
+
+    if !response.status().is_success() {
+        return Err(format!(
+            "Unable to download \"{}\": server responded with {}",
+            url,
+            response.status()
+        ));
+    }
+
+    let too_large = || {
+        Err(format!(
+            "Unable to download \"{}\": file is larger than {} bytes",
+            url, max_bytes
+        ))
+    };
+
+    if let Some(length) = response.content_length() {
+        if length > max_bytes as u64 {
+            return too_large();
+        }
+    }
+
+    // The Content-Length can be missing or wrong, so stop reading just past
+    // the limit too
+    let mut buffer = Vec::new();
+
+    if let Err(reason) = response
+        .by_ref()
+        .take(max_bytes as u64 + 1)
+        .read_to_end(&mut buffer)
+    {
+        return Err(format!("Unable to download \"{}\": {}", url, reason));
+    }
+
+    if buffer.len() > max_bytes {
+        return too_large();
+    }
+
+    Ok(buffer)
+}
+
+/// Uploads `contents` to `url` with a PUT request, which is how object storage
+/// buckets and simple file servers take new files
+pub fn upload(url: &str, contents: Vec<u8>, content_type: &str) -> Result<(), String> {
+    if !capabilities::is_enabled(Capability::Fetching) {
+        return Err(format!(
+            "Unable to upload to \"{}\": requests are turned off in safe mode",
+            url
+        ));
+    }
+
+    let fetcher = fetcher();
+    let response = fetcher.send(
+        Method::PUT,
+        url,
+        Some((&contents, content_type)),
+        "upload to",
+        fetcher.allow_private_addresses,
+    )?;
+
+    if !response.status().is_success() {
+        return Err(format!(
+            "Unable to upload to \"{}\": server responded with {}",
+            url,
+            response.status()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Sends `contents` to `url` with a POST request, for services that look at a
+/// file and reply with what they found. Returns the reply, cut off at
+/// `max_bytes`. `allow_private_addresses` lets the request go to a private
+/// address even when other requests can't, which is only safe for URLs that
+/// come from the bot's own configuration rather than from users.
+pub fn post(
+    url: &str,
+    contents: &[u8],
+    content_type: &str,
+    max_bytes: usize,
+    allow_private_addresses: bool,
+) -> Result<String, String> {
+    if !capabilities::is_enabled(Capability::Fetching) {
+        return Err(format!(
+            "Unable to post to \"{}\": requests are turned off in safe mode",
+            url
+        ));
+    }
+
+    let fetcher = fetcher();
+    let response = fetcher.send(
+        Method::POST,
+        url,
+        Some((contents, content_type)),
+        "post to",
+        allow_private_addresses || fetcher.allow_private_addresses,
+    )?;
+
+    if !response.status().is_success() {
+        return Err(format!(
+            "Unable to post to \"{}\": server responded with {}",
+            url,
+            response.status()
+        ));
+    }
+
+    let mut reply = String::new();
+
+    if let Err(reason) = response.take(max_bytes as u64).read_to_string(&mut reply) {
+        return Err(format!(
+            "Unable to read the reply from \"{}\": {}",
+            url, reason
+        ));
+    }
+
+    Ok(reply)
+}
+
+/// The name a download of `url` is cached under, which stays the same from
+/// one run to the next
+fn cache_filename(url: &str) -> String {
+    let mut crc = Hasher::new();
+    crc.update(url.as_bytes());
+
+    // Keep the extension so that the file type can still be guessed from it
+    let extension = Path::new(url.split(['?', '#']).next().unwrap_or(url))
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| format!(".{}", extension))
+        .unwrap_or_default();
+
+    format!("{:08x}{}", crc.finalize(), extension)
+}
+
+/// Returns the path of a local copy of the file at `url`, downloading it into
+/// `cache_dir` unless an earlier download is already there
+pub fn download_cached(url: &str, cache_dir: &str) -> Result<String, String> {
+    let filename = cache_filename(url);
+    let path = Path::new(cache_dir).join(&filename);
+    let path_string = path.to_string_lossy().to_string();
+
+    if path.exists() {
+        return Ok(path_string);
+    }
+
+    let contents = download(url, MAX_FILE_BYTES)?;
+
+    if let Err(reason) = create_dir_all(cache_dir) {
+        return Err(format!(
+            "Unable to create cache directory \"{}\": {}",
+            cache_dir, reason
+        ));
+    }
+
+    // The file is written under another name and then moved into place, so
+    // that a download that's cut short or made twice at once never leaves a
+    // partial file behind under the name that's looked for
+    static DOWNLOADS: AtomicUsize = AtomicUsize::new(0);
+
+    let temp_path = Path::new(cache_dir).join(format!(
+        ".{}.{}-{}.tmp",
+        filename,
+        process::id(),
+        DOWNLOADS.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    if let Err(reason) = write(&temp_path, contents) {
+        let _ = remove_file(&temp_path);
+
+        return Err(format!(
+            "Unable to write file \"{}\": {}",
+            temp_path.to_string_lossy(),
+            reason
+        ));
+    }
+
+    if let Err(reason) = rename(&temp_path, &path) {
+        let _ = remove_file(&temp_path);
+
+        return Err(format!(
+            "Unable to move \"{}\" to \"{}\": {}",
+            temp_path.to_string_lossy(),
+            path_string,
+            reason
+        ));
+    }
+
+    Ok(path_string)
+}
+
+#[cfg(test)]
+pub mod mock {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread::{self, JoinHandle};
+
+    /// Answers one connection on a local port with each of `responses` in
+    /// turn. Returns the port, and a handle giving the request lines received.
+    pub fn serve(responses: Vec<&'static str>) -> (u16, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let handle = thread::spawn(move || {
+            let mut requests = Vec::new();
+
+            for response in responses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut line = String::new();
+
+                reader.read_line(&mut line).unwrap();
+                requests.push(line.trim_end().to_string());
+
+                // Skip the headers and body, which aren't looked at
+                while line != "\r\n" {
+                    line.clear();
+                    reader.read_line(&mut line).unwrap();
+                }
+
+                reader.get_mut().write_all(response.as_bytes()).unwrap();
+            }
+
+            requests
+        });
+
+        (port, handle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mock::serve;
+    use super::*;
+    use std::net::TcpListener;
+
+    fn local_fetcher(max_redirects: usize) -> Fetcher {
+        build_fetcher(FetchSettings {
+            max_redirects,
+            allow_private_addresses: true,
+            ..FetchSettings::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn follows_redirects() {
+        let (port, server) = serve(vec![
+            "HTTP/1.1 303 See Other\r\nLocation: /result\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            "HTTP/1.1 307 Temporary Redirect\r\nLocation: /final\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok",
+        ]);
+        let url = format!("http://127.0.0.1:{}/check", port);
+
+        // Bodies are only sent to the first URL, which redirects with a 303
+        let response = local_fetcher(2)
+            .send(
+                Method::POST,
+                &url,
+                Some((&[], "image/png")),
+                "post to",
+                true,
+            )
+            .unwrap();
+
+        assert_eq!(response.text().unwrap(), "ok");
+        assert_eq!(
+            server.join().unwrap(),
+            vec![
+                "POST /check HTTP/1.1",
+                "GET /result HTTP/1.1",
+                "GET /final HTTP/1.1"
+            ]
+        );
+    }
+
+    #[test]
+    fn stops_following_redirects_after_the_limit() {
+        let (port, server) = serve(vec![
+            "HTTP/1.1 302 Found\r\nLocation: /again\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+            2
+        ]);
+        let url = format!("http://127.0.0.1:{}/", port);
+
+        let result = local_fetcher(1).send(Method::GET, &url, None, "download", true);
+
+        assert_eq!(
+            result.err(),
+            Some(format!(
+                "Unable to download \"{}\": more than 1 redirects",
+                url
+            ))
+        );
+        assert_eq!(server.join().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn refuses_private_addresses_without_connecting() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+
+        let fetcher = build_fetcher(FetchSettings::default()).unwrap();
+
+        assert!(fetcher
+            .send(Method::GET, &url, None, "download", false)
+            .unwrap_err()
+            .contains("private address"));
+
+        listener.set_nonblocking(true).unwrap();
+        assert!(listener.accept().is_err());
+    }
+
+    #[test]
+    fn names_cached_files_the_same_every_time() {
+        assert_eq!(cache_filename("https://example.com/font"), "af25e649");
+        assert_eq!(
+            cache_filename("https://example.com/fonts/Impact.ttf?v=2"),
+            "ca137e2b.ttf"
+        );
+    }
+
+    #[test]
+    fn uses_cached_files_without_downloading() {
+        let cache_dir = std::env::temp_dir().join(format!("fetch-cache-{}", process::id()));
+        let url = "https://example.com/cached.ttf";
+        let path = cache_dir.join(cache_filename(url));
+
+        create_dir_all(&cache_dir).unwrap();
+        write(&path, b"font").unwrap();
+
+        let cached = download_cached(url, &cache_dir.to_string_lossy());
+
+        std::fs::remove_dir_all(&cache_dir).unwrap();
+
+        assert_eq!(cached, Ok(path.to_string_lossy().to_string()));
+    }
+
+    #[test]
+    fn tells_private_addresses_apart() {
+        let private = [
+            "0.1.2.3",
+            "10.0.0.1",
+            "100.64.0.1",
+            "127.0.0.1",
+            "169.254.169.254",
+            "172.16.0.1",
+            "192.0.2.1",
+            "192.168.1.1",
+            "198.18.0.1",
+            "198.19.255.255",
+            "198.51.100.1",
+            "203.0.113.1",
+            "224.0.0.1",
+            "239.255.255.250",
+            "240.0.0.1",
+            "255.255.255.255",
+            "::",
+            "::1",
+            "::ffff:127.0.0.1",
+            "64:ff9b::7f00:1",
+            "64:ff9b::a00:1",
+            "64:ff9b:1::1",
+            "fc00::1",
+            "fd12:3456::1",
+            "fe80::1",
+            "ff02::1",
+            "2001:db8::1",
+        ];
+        let public = [
+            "1.1.1.1",
+            "8.8.8.8",
+            "100.128.0.1",
+            "198.20.0.1",
+            "223.255.255.255",
+            "::ffff:8.8.8.8",
+            "64:ff9b::808:808",
+            "2606:4700:4700::1111",
+        ];
+
+        for address in private.iter() {
+            assert!(
+                is_private_address(address.parse().unwrap()),
+                "{} is private",
+                address
+            );
+        }
+
+        for address in public.iter() {
+            assert!(
+                !is_private_address(address.parse().unwrap()),
+                "{} is public",
+                address
+            );
+        }
+    }
+
+    #[test]
+    fn checks_urls_before_connecting() {
+        let check = |url: &str| check_public(&Url::parse(url).unwrap());
+
+        assert_eq!(
+            check("https://8.8.8.8/meme.png"),
+            Ok(vec!["8.8.8.8:443".parse().unwrap()])
+        );
+        assert_eq!(
+            check("http://[2606:4700:4700::1111]:8080/"),
+            Ok(vec!["[2606:4700:4700::1111]:8080".parse().unwrap()])
+        );
+        assert!(check("http://127.0.0.1/").is_err());
+        assert!(check("http://[64:ff9b::a9fe:a9fe]/latest/meta-data").is_err());
+        assert!(check("http://224.0.0.251:5353/").is_err());
+        assert!(check("ftp://8.8.8.8/meme.png").is_err());
+    }
+}
@@ -0,0 +1,153 @@
+use image::imageops;
+use image::jpeg::JPEGEncoder;
+use image::{ColorType, DynamicImage, Rgba, RgbaImage};
+use std::collections::VecDeque;
+
+// How different (as a distance in RGB space) a pixel can be from the
+// background color and still count as background
+const BACKGROUND_TOLERANCE: f32 = 48f32;
+
+// Pixels this much further from the background color than the tolerance are
+// faded out partially, which softens the edge of the cutout
+const BACKGROUND_FEATHER: f32 = 24f32;
+
+fn color_distance(a: &Rgba<u8>, b: &Rgba<u8>) -> f32 {
+    (0..3)
+        .map(|channel| {
+            let difference = a[channel] as f32 - b[channel] as f32;
+            difference * difference
+        })
+        .sum::<f32>()
+        .sqrt()
+}
+
+/// Makes the background of `image` transparent, treating the area flooding
+/// inwards from the edges that is close to the color of the corners as the
+/// background. Works well for avatars and stickers on flat backgrounds.
+pub fn remove_background(image: &mut RgbaImage) {
+    let (width, height) = image.dimensions();
+
+    if width == 0 || height == 0 {
+        return;
+    }
+
+    let corners = [
+        *image.get_pixel(0, 0),
+        *image.get_pixel(width - 1, 0),
+        *image.get_pixel(0, height - 1),
+        *image.get_pixel(width - 1, height - 1),
+    ];
+
+    let mut background = [0u32; 4];
+
+    for corner in &corners {
+        for channel in 0..4 {
+            background[channel] += corner[channel] as u32;
+        }
+    }
+
+    let background = Rgba([
+        (background[0] / 4) as u8,
+        (background[1] / 4) as u8,
+        (background[2] / 4) as u8,
+        (background[3] / 4) as u8,
+    ]);
+
+    let mut visited = vec![false; (width * height) as usize];
+    let mut queue = VecDeque::<(u32, u32)>::new();
+
+    for x in 0..width {
+        queue.push_back((x, 0));
+        queue.push_back((x, height - 1));
+    }
+
+    for y in 0..height {
+        queue.push_back((0, y));
+        queue.push_back((width - 1, y));
+    }
+
+    while let Some((x, y)) = queue.pop_front() {
+        let index = (y * width + x) as usize;
+
+        if visited[index] {
+            continue;
+        }
+
+        visited[index] = true;
+
+        let pixel = image.get_pixel_mut(x, y);
+        let distance = color_distance(pixel, &background);
+
+        if distance > BACKGROUND_TOLERANCE + BACKGROUND_FEATHER {
+            continue;
+        }
+
+        if distance > BACKGROUND_TOLERANCE {
+            // Part of the edge: fade it, but don't flood past it
+            let opacity = (distance - BACKGROUND_TOLERANCE) / BACKGROUND_FEATHER;
+            pixel[3] = (pixel[3] as f32 * opacity) as u8;
+            continue;
+        }
+
+        pixel[3] = 0;
+
+        if x > 0 {
+            queue.push_back((x - 1, y));
+        }
+
+        if x + 1 < width {
+            queue.push_back((x + 1, y));
+        }
+
+        if y > 0 {
+            queue.push_back((x, y - 1));
+        }
+
+        if y + 1 < height {
+            queue.push_back((x, y + 1));
+        }
+    }
+}
+
+/// Oversaturates and oversharpens the image and runs it through a very lossy
+/// JPEG encoding, like a meme that has been reposted too many times
+pub fn deep_fry(image: &RgbaImage) -> Result<RgbaImage, String> {
+    let mut fried = imageops::contrast(image, 60f32);
+
+    for pixel in fried.pixels_mut() {
+        let gray = (pixel[0] as f32 + pixel[1] as f32 + pixel[2] as f32) / 3f32;
+
+        for channel in 0..3 {
+            let saturated = gray + (pixel[channel] as f32 - gray) * 2.5;
+            pixel[channel] = saturated.clamp(0f32, 255f32) as u8;
+        }
+    }
+
+    let fried = imageops::unsharpen(&fried, 2f32, 0);
+
+    let mut buffer = Vec::new();
+
+    if let Err(reason) = JPEGEncoder::new_with_quality(&mut buffer, 8).encode(
+        &DynamicImage::ImageRgba8(fried).to_rgb(),
+        image.width(),
+        image.height(),
+        ColorType::Rgb8,
+    ) {
+        return Err(format!("Unable to encode image: {}", reason));
+    }
+
+    match image::load_from_memory(&buffer) {
+        Ok(fried) => Ok(fried.to_rgba()),
+        Err(reason) => Err(format!("Unable to decode image: {}", reason)),
+    }
+}
+
+pub fn grayscale(image: &RgbaImage) -> RgbaImage {
+    DynamicImage::ImageRgba8(image.clone())
+        .grayscale()
+        .to_rgba()
+}
+
+pub fn mirror(image: &RgbaImage) -> RgbaImage {
+    imageops::flip_horizontal(image)
+}
@@ -0,0 +1,131 @@
+use image::Rgba;
+use regex::Regex;
+
+use crate::code_block::code_block_range;
+use crate::color::parse_color;
+use crate::layout::trim_blank_lines;
+use crate::registry::Meme;
+use crate::render::TextStyle;
+
+/// Options given with `--name value` or `--name` in a meme's text, which only
+/// apply to that meme
+#[derive(Clone, Copy, Default)]
+pub struct Flags {
+    pub color: Option<Rgba<u8>>,
+    // Lines are only broken where the user broke them, like for ASCII art
+    pub nowrap: bool,
+    // Seeds everything random about the meme, so that it can be made again
+    pub seed: Option<u64>,
+}
+
+impl Flags {
+    /// Returns how the meme's text is drawn with these flags
+    pub fn text_style(&self, meme: &Meme) -> TextStyle {
+        TextStyle {
+            color: self.color.unwrap_or(meme.text_color),
+            wrap: !self.nowrap,
+            seed: self.seed.unwrap_or(0),
+        }
+    }
+
+    /// Returns the seed for the random cases of `Transform::MockRandom`
+    pub fn transform_seed(&self, meme: &Meme) -> Option<u64> {
+        self.seed.or(meme.transform_seed)
+    }
+}
+
+/// Returns the word starting at or after `from`, and where it ends. A word
+/// with an opening parenthesis continues up to the closing one, so that
+/// values like `rgb(255 0 102)` can contain spaces.
+fn next_word(text: &str, from: usize) -> (&str, usize) {
+    let rest = &text[from..];
+    let start = from + (rest.len() - rest.trim_start().len());
+    let mut end = text[start..]
+        .find(char::is_whitespace)
+        .map_or(text.len(), |length| start + length);
+
+    let word = &text[start..end];
+
+    if word.contains('(') && !word.contains(')') {
+        if let Some(length) = text[end..].find(')') {
+            end += length + 1;
+        }
+    }
+
+    (&text[start..end], end)
+}
+
+/// Removes the flags from `text`, returning the remaining text along with the
+/// flags. Only known flags at the start of a word are recognized, so that
+/// other text starting with `--` is left alone.
+pub fn take_flags(text: &str) -> Result<(String, Flags), String> {
+    let re_flag = Regex::new(r"--([a-zA-Z]+)").expect("Unable to create flag matching pattern");
+
+    let mut flags = Flags::default();
+    let mut remaining = String::new();
+    let mut position = 0;
+    let code = code_block_range(text);
+
+    for captures in re_flag.captures_iter(text) {
+        let flag = captures.get(0).expect("Flag match has no text");
+
+        // Skip the matches inside a flag's value, in the middle of a word, or
+        // in code, like `ls --color`
+        if flag.start() < position
+            || code
+                .as_ref()
+                .is_some_and(|code| code.contains(&flag.start()))
+            || matches!(text[..flag.start()].chars().next_back(), Some(c) if !c.is_whitespace())
+        {
+            continue;
+        }
+
+        let end = match captures[1].to_lowercase().as_str() {
+            "color" | "colour" => {
+                let (value, end) = next_word(text, flag.end());
+
+                if value.is_empty() {
+                    return Err(
+                        "Give `--color` a color. (Like `--color #ff0066` or `--color red`.)".into(),
+                    );
+                }
+
+                flags.color = Some(parse_color(value)?);
+
+                end
+            }
+            "seed" => {
+                let (value, end) = next_word(text, flag.end());
+
+                flags.seed = match value.parse::<u64>() {
+                    Ok(seed) => Some(seed),
+                    Err(_) => return Err("Give `--seed` a number. (Like `--seed 42`.)".into()),
+                };
+
+                end
+            }
+            "nowrap" => {
+                flags.nowrap = true;
+
+                flag.end()
+            }
+            _ => continue,
+        };
+
+        remaining += &text[position..flag.start()];
+
+        // Drop the spaces separating the flag from the text after it, but not
+        // line breaks
+        position = end + (text[end..].len() - text[end..].trim_start_matches([' ', '\t']).len());
+    }
+
+    remaining += &text[position..];
+
+    Ok((trim_blank_lines(&remaining).to_string(), flags))
+}
+
+/// Returns the seed given with `--seed` in `text`, for choosing a meme before
+/// its text is otherwise looked at
+pub fn peek_seed(text: &str) -> Option<u64> {
+    take_flags(text).ok().and_then(|(_, flags)| flags.seed)
+}
@@ -0,0 +1,84 @@
+use log::info;
+use rusttype::{Font, FontCollection};
+use std::fs::read;
+
+use crate::fetch;
+use crate::storage::TemplateStore;
+
+/// Loads the fonts named in the config, which can be file paths, URLs, or the
+/// family names of fonts installed on the system
+pub struct FontLoader<'a> {
+    store: &'a dyn TemplateStore,
+    cache_dir: String,
+    // Scanning the system's fonts is slow, so it's only done once it's needed
+    system_fonts: Option<fontdb::Database>,
+}
+
+impl<'a> FontLoader<'a> {
+    pub fn new(store: &'a dyn TemplateStore, cache_dir: &str) -> FontLoader<'a> {
+        FontLoader {
+            store,
+            cache_dir: cache_dir.into(),
+            system_fonts: None,
+        }
+    }
+
+    pub fn load(&mut self, location: &str) -> Result<Font<'static>, String> {
+        if fetch::is_url(location) {
+            let filename = fetch::download_cached(location, &self.cache_dir)?;
+
+            return match read(&filename) {
+                Ok(contents) => load_font_data(&filename, contents),
+                Err(reason) => Err(format!("Unable to read file \"{}\": {}", filename, reason)),
+            };
+        }
+
+        if self.store.exists(location) {
+            return load_font_data(location, self.store.read(location)?);
+        }
+
+        match self.load_system_font(location) {
+            Some(font) => font,
+            None => Err(format!(
+                "\"{}\" is neither a font file nor the name of an installed font",
+                location
+            )),
+        }
+    }
+
+    fn load_system_font(&mut self, family: &str) -> Option<Result<Font<'static>, String>> {
+        let system_fonts = self.system_fonts.get_or_insert_with(|| {
+            info!("Scanning system fonts");
+
+            let mut system_fonts = fontdb::Database::new();
+            system_fonts.load_system_fonts();
+            system_fonts
+        });
+
+        let id = system_fonts.query(&fontdb::Query {
+            families: &[fontdb::Family::Name(family)],
+            ..fontdb::Query::default()
+        })?;
+
+        system_fonts.with_face_data(id, |data, index| {
+            let collection = match FontCollection::from_bytes(data.to_vec()) {
+                Ok(collection) => collection,
+                Err(reason) => {
+                    return Err(format!("Unable to open font \"{}\": {}", family, reason));
+                }
+            };
+
+            match collection.font_at(index as usize) {
+                Ok(font) => Ok(font),
+                Err(reason) => Err(format!("Unable to open font \"{}\": {}", family, reason)),
+            }
+        })
+    }
+}
+
+fn load_font_data(name: &str, contents: Vec<u8>) -> Result<Font<'static>, String> {
+    match Font::from_bytes(contents) {
+        Ok(font) => Ok(font),
+        Err(reason) => Err(format!("Unable to open font \"{}\": {}", name, reason)),
+    }
+}
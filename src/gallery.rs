@@ -0,0 +1,547 @@
+use log::{info, warn};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use yaml_rust::yaml::{Hash, Yaml};
+
+use serenity::model::prelude::Message;
+use serenity::prelude::{Context, TypeMapKey};
+
+use crate::decode::{decode_image, MAX_IMAGE_BYTES};
+use crate::fetch;
+use crate::meme_edits::{
+    added_memes_file, new_section, record_change, section_index, set_section_region, style_parent,
+};
+use crate::meme_history::Change;
+use crate::redact;
+use crate::registry::Region;
+use crate::render::{encode_png, OutputSettings};
+use crate::wizard::check_command;
+use crate::{reload_memes, split_first_word, BotSettingsKey};
+
+// Manifests of a few thousand templates fit in this easily
+const MAX_MANIFEST_BYTES: usize = 4 * 1024 * 1024;
+
+// Syncs that haven't been answered for this long are abandoned
+const SYNC_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+// Only this many changes are listed, and this many skipped templates, to keep
+// the reply within Discord's message limit; `all` still installs the rest
+const MAX_LISTED: usize = 20;
+const MAX_NOTES: usize = 5;
+
+// The key in the sections of added memes that records which gallery image they
+// were installed from, so that later syncs can tell when it changes
+pub const GALLERY_URL_KEY: &str = "gallery_url";
+
+/// A template listed in the gallery's manifest
+struct GalleryTemplate {
+    command: String,
+    url: String,
+    region: Region,
+    license: Option<String>,
+    // Where the template comes from, if not the gallery's copy of it
+    source_url: Option<String>,
+}
+
+#[derive(PartialEq)]
+enum SyncAction {
+    Install,
+    Update,
+}
+
+type SyncChange = (SyncAction, GalleryTemplate);
+
+/// The changes from a manifest, waiting for the admin to pick which to make
+pub struct GallerySync {
+    parent: String,
+    changes: Vec<SyncChange>,
+    started: Instant,
+}
+
+pub struct GallerySyncsKey;
+
+impl TypeMapKey for GallerySyncsKey {
+    // User IDs to their syncs
+    type Value = HashMap<u64, GallerySync>;
+}
+
+fn parse_region(region: &Value) -> Option<Region> {
+    let coordinate = |key: &str| region[key].as_u64().map(|value| value as u32);
+
+    let region = Region {
+        left: coordinate("left")?,
+        top: coordinate("top")?,
+        right: coordinate("right")?,
+        bottom: coordinate("bottom")?,
+    };
+
+    if region.left >= region.right || region.top >= region.bottom {
+        return None;
+    }
+
+    Some(region)
+}
+
+fn parse_template(template: &Value) -> Option<GalleryTemplate> {
+    Some(GalleryTemplate {
+        command: template["name"].as_str()?.trim().to_lowercase(),
+        url: template["url"].as_str()?.trim().into(),
+        // Memes have a single text area, so it goes in the first region
+        region: parse_region(template["regions"].as_array()?.first()?)?,
+        license: template["license"].as_str().map(String::from),
+        source_url: template["source_url"].as_str().map(String::from),
+    })
+}
+
+/// Records in the section of an added meme which gallery template it was
+/// installed from, along with the template's attribution
+fn record_template(section: &mut Hash, template: &GalleryTemplate) {
+    section.insert(
+        Yaml::String(GALLERY_URL_KEY.into()),
+        Yaml::String(template.url.clone()),
+    );
+    section.insert(
+        Yaml::String("source_url".into()),
+        Yaml::String(
+            template
+                .source_url
+                .as_ref()
+                .unwrap_or(&template.url)
+                .clone(),
+        ),
+    );
+
+    match &template.license {
+        Some(license) => section.insert(
+            Yaml::String("license".into()),
+            Yaml::String(license.clone()),
+        ),
+        None => section.remove(&Yaml::String("license".into())),
+    };
+}
+
+/// Downloads the manifest at `url`, a JSON list of templates (or an object
+/// with one as its `templates`), skipping any that are invalid
+fn fetch_manifest(url: &str) -> Result<Vec<GalleryTemplate>, String> {
+    let manifest = fetch::download(url, MAX_MANIFEST_BYTES)?;
+
+    let manifest: Value = match serde_json::from_slice(&manifest) {
+        Ok(manifest) => manifest,
+        Err(reason) => return Err(format!("The gallery sent an invalid manifest: {}", reason)),
+    };
+
+    let entries = match manifest.as_array().or(manifest["templates"].as_array()) {
+        Some(entries) => entries,
+        None => return Err("The gallery sent an invalid manifest: no templates".into()),
+    };
+
+    Ok(entries
+        .iter()
+        .filter_map(|entry| {
+            let template = parse_template(entry);
+
+            if template.is_none() {
+                warn!("Gallery manifest contains invalid template {}", entry);
+            }
+
+            template
+        })
+        .collect())
+}
+
+/// Works out which templates of the manifest are new and which were installed
+/// before but have changed since. Templates that clash with other memes are
+/// described in the returned notes.
+fn diff(
+    ctx: &Context,
+    templates: Vec<GalleryTemplate>,
+) -> Result<(Vec<SyncChange>, Vec<String>), String> {
+    let sections = added_memes_file(ctx).read_sections()?;
+    let mut changes = Vec::new();
+    let mut notes = Vec::new();
+    let mut seen = HashSet::new();
+
+    for template in templates {
+        if !seen.insert(template.command.clone()) {
+            notes.push(format!(
+                "`{}` is in the gallery more than once.",
+                template.command
+            ));
+            continue;
+        }
+
+        let section = section_index(&sections, &template.command).map(|index| &sections[index]);
+
+        match section.map(|section| (section, section[GALLERY_URL_KEY].as_str())) {
+            Some((section, Some(url))) => {
+                let coordinate = |key: &str| section[key].as_i64().unwrap_or(-1);
+                let region = &template.region;
+
+                let is_unchanged = url == template.url
+                    && section["license"].as_str() == template.license.as_deref()
+                    && coordinate("left") == region.left as i64
+                    && coordinate("top") == region.top as i64
+                    && coordinate("right") == region.right as i64
+                    && coordinate("bottom") == region.bottom as i64;
+
+                if !is_unchanged {
+                    changes.push((SyncAction::Update, template));
+                }
+            }
+            Some((_, None)) => notes.push(format!(
+                "`{}` was added by hand, so it's left as it is.",
+                template.command
+            )),
+            None => match check_command(ctx, &template.command) {
+                Ok(()) => changes.push((SyncAction::Install, template)),
+                Err(reason) => notes.push(format!("`{}`: {}", template.command, reason)),
+            },
+        }
+    }
+
+    Ok((changes, notes))
+}
+
+fn describe(index: usize, action: &SyncAction, template: &GalleryTemplate) -> String {
+    format!(
+        "{}. `{}` - {}{}",
+        index + 1,
+        template.command,
+        if *action == SyncAction::Install {
+            "new"
+        } else {
+            "updated"
+        },
+        template
+            .license
+            .as_ref()
+            .map(|license| format!(" ({})", license))
+            .unwrap_or_default()
+    )
+}
+
+fn start(ctx: &Context, msg: &Message, parent: &str) -> Result<String, String> {
+    let gallery_url = ctx
+        .data
+        .read()
+        .get::<BotSettingsKey>()
+        .expect("Command sync: Unable to retrieve bot settings")
+        .gallery_url
+        .clone();
+
+    let gallery_url = match gallery_url {
+        Some(gallery_url) => gallery_url,
+        None => return Err("There's no gallery to sync with. (Set `GALLERY_URL`.)".into()),
+    };
+
+    let parent = style_parent(ctx, parent)?;
+    let (changes, notes) = diff(ctx, fetch_manifest(&gallery_url)?)?;
+
+    let mut lines: Vec<String> = notes.iter().take(MAX_NOTES).cloned().collect();
+
+    if notes.len() > MAX_NOTES {
+        lines.push(format!(
+            "…and {} more templates were skipped.",
+            notes.len() - MAX_NOTES
+        ));
+    }
+
+    if changes.is_empty() {
+        lines.push("Everything from the gallery is already installed.".into());
+        return Ok(lines.join("\n"));
+    }
+
+    lines.extend(
+        changes
+            .iter()
+            .take(MAX_LISTED)
+            .enumerate()
+            .map(|(index, (action, template))| describe(index, action, template)),
+    );
+
+    if changes.len() > MAX_LISTED {
+        lines.push(format!("…and {} more.", changes.len() - MAX_LISTED));
+    }
+
+    lines.push(format!(
+        "Reply with the numbers of the templates to install, like `1 3`, or `all`. New ones use the style of `{}`. (Send `cancel` to stop.)",
+        parent
+    ));
+
+    ctx.data
+        .write()
+        .get_mut::<GallerySyncsKey>()
+        .expect("Command sync: Unable to retrieve syncs")
+        .insert(
+            msg.author.id.0,
+            GallerySync {
+                parent,
+                changes,
+                started: Instant::now(),
+            },
+        );
+
+    Ok(lines.join("\n"))
+}
+
+/// Fetches the gallery's manifest and lists the templates that aren't
+/// installed yet or have changed, for the admin to pick from
+pub fn sync(ctx: &Context, msg: &Message, rest: &str) {
+    let (parent, _) = split_first_word(rest);
+
+    let reply = match start(ctx, msg, &parent.to_lowercase()) {
+        Ok(reply) => reply,
+        Err(reason) => reason,
+    };
+
+    msg.channel_id.say(ctx, reply).ok();
+}
+
+/// Downloads each template and installs it into the added memes, returning
+/// what happened
+fn install(ctx: &Context, parent: &str, changes: Vec<SyncChange>) -> String {
+    let added_memes = added_memes_file(ctx);
+    let mut sections = match added_memes.read_sections() {
+        Ok(sections) => sections,
+        Err(reason) => return reason,
+    };
+
+    // Updated templates get images of their own, so that undoing the update
+    // brings back the old one
+    let updated_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or(0);
+
+    let mut history = Vec::new();
+    let mut lines = Vec::new();
+    let (mut installed, mut updated) = (0, 0);
+
+    for (action, template) in changes {
+        // Memes may have been added under the same name since the sync started
+        if action == SyncAction::Install {
+            if let Err(reason) = check_command(ctx, &template.command) {
+                lines.push(format!("`{}`: {}", template.command, reason));
+                continue;
+            }
+        }
+
+        let image = fetch::download(&template.url, MAX_IMAGE_BYTES).and_then(|image| {
+            let image = decode_image(&image)?;
+            let region = &template.region;
+
+            if region.right > image.width() || region.bottom > image.height() {
+                return Err(format!(
+                    "its text area doesn't fit in the image, which is {}x{}",
+                    image.width(),
+                    image.height()
+                ));
+            }
+
+            Ok(image)
+        });
+
+        let image = match image {
+            Ok(image) => image,
+            Err(reason) => {
+                warn!("Gallery template \"{}\": {}", template.command, reason);
+                lines.push(format!(
+                    "`{}` couldn't be installed: {}",
+                    template.command, reason
+                ));
+                continue;
+            }
+        };
+
+        let result = match action {
+            SyncAction::Install => new_section(
+                &added_memes,
+                &template.command,
+                parent,
+                &template.command,
+                &image,
+                template.region,
+            )
+            .map(|mut section| {
+                record_template(&mut section, &template);
+                sections.push(Yaml::Hash(section));
+
+                Change::Added {
+                    command: template.command.clone(),
+                }
+            }),
+            SyncAction::Update => match section_index(&sections, &template.command) {
+                Some(index) => encode_png(&image, OutputSettings::default())
+                    .and_then(|png| {
+                        added_memes
+                            .save_image(&format!("{}.{}", template.command, updated_at), &png)
+                    })
+                    .map(|filename| {
+                        let previous = sections[index].clone();
+
+                        if let Yaml::Hash(section) = &mut sections[index] {
+                            section.insert(Yaml::String("filename".into()), Yaml::String(filename));
+                            record_template(section, &template);
+                            set_section_region(section, template.region);
+                        }
+
+                        Change::Edited {
+                            command: template.command.clone(),
+                            section: previous,
+                        }
+                    }),
+                None => Err("it has been removed since".into()),
+            },
+        };
+
+        match result {
+            Ok(change) => {
+                if action == SyncAction::Install {
+                    installed += 1;
+                } else {
+                    updated += 1;
+                }
+
+                history.push(change);
+            }
+            Err(reason) => {
+                warn!("Gallery template \"{}\": {}", template.command, reason);
+                lines.push(format!(
+                    "`{}` couldn't be installed: {}",
+                    template.command, reason
+                ));
+            }
+        }
+    }
+
+    let mut reply = vec![format!(
+        "Installed {} new templates and updated {}.",
+        installed, updated
+    )];
+    reply.extend(lines.iter().take(MAX_NOTES).cloned());
+
+    if lines.len() > MAX_NOTES {
+        reply.push(format!(
+            "…and {} more couldn't be installed.",
+            lines.len() - MAX_NOTES
+        ));
+    }
+
+    if history.is_empty() {
+        return reply.join("\n");
+    }
+
+    if let Err(reason) = added_memes.write_sections(sections) {
+        return reason;
+    }
+
+    for change in history {
+        record_change(ctx, change);
+    }
+
+    if let Err(reason) = reload_memes(ctx) {
+        reply.push(reason);
+    }
+
+    reply.join("\n")
+}
+
+/// Picks the changes named in a reply like "1 3" or "all"
+fn pick(text: &str, count: usize) -> Option<Vec<usize>> {
+    if text.eq_ignore_ascii_case("all") {
+        return Some((0..count).collect());
+    }
+
+    let mut picked: Vec<usize> = text
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|part| !part.is_empty())
+        .map(|part| match part.parse::<usize>() {
+            Ok(number) if (1..=count).contains(&number) => Some(number - 1),
+            _ => None,
+        })
+        .collect::<Option<_>>()?;
+
+    picked.sort_unstable();
+    picked.dedup();
+
+    if picked.is_empty() {
+        return None;
+    }
+
+    Some(picked)
+}
+
+/// Handles a DM from someone in the middle of syncing, returning whether it
+/// was an answer to the sync. Other messages are left for the usual commands.
+pub fn handle_sync_message(ctx: &Context, msg: &Message) -> bool {
+    let user_id = msg.author.id.0;
+    let text = msg.content.trim();
+
+    let mut data = ctx.data.write();
+    let syncs = data
+        .get_mut::<GallerySyncsKey>()
+        .expect("Command sync: Unable to retrieve syncs");
+
+    let sync = match syncs.get(&user_id) {
+        Some(sync) if sync.started.elapsed() < SYNC_TIMEOUT => sync,
+        _ => return false,
+    };
+
+    if text.eq_ignore_ascii_case("cancel") {
+        syncs.remove(&user_id);
+        drop(data);
+
+        msg.channel_id.say(ctx, "Okay, sync cancelled.").ok();
+        return true;
+    }
+
+    let picked = match pick(text, sync.changes.len()) {
+        Some(picked) => picked,
+        None => return false,
+    };
+
+    let sync = syncs
+        .remove(&user_id)
+        .expect("Command sync: Sync disappeared");
+
+    drop(data);
+
+    let changes = sync
+        .changes
+        .into_iter()
+        .enumerate()
+        .filter(|(index, _)| picked.contains(index))
+        .map(|(_, change)| change)
+        .collect();
+
+    msg.channel_id
+        .say(ctx, "Installing, this may take a moment...")
+        .ok();
+
+    let reply = install(ctx, &sync.parent, changes);
+
+    info!(
+        "Gallery synced by {}: {}",
+        redact::user(&msg.author),
+        reply.replace('\n', " ")
+    );
+
+    msg.channel_id.say(ctx, reply).ok();
+
+    true
+}
+
+#[cfg(test)]
+pub mod mock {
+    use super::*;
+
+    /// A sync with nothing to install, using the style of `parent`
+    pub fn sync(parent: &str) -> GallerySync {
+        GallerySync {
+            parent: parent.into(),
+            changes: Vec::new(),
+            started: Instant::now(),
+        }
+    }
+}
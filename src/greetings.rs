@@ -0,0 +1,181 @@
+use log::warn;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use regex::Regex;
+use yaml_rust::{Yaml, YamlLoader};
+
+use serenity::prelude::TypeMapKey;
+
+use crate::storage::TemplateStore;
+
+// Used when no greetings file exists or it lists no mention responses
+const DEFAULT_MENTION_RESPONSE: &str = "Yes?";
+
+/// What the bot does when an easter egg is triggered
+#[derive(Clone)]
+pub enum EasterEggResponse {
+    Reply(String),
+    // The command of the meme to create from the message's text
+    Meme(String),
+}
+
+/// A response to messages matching `pattern`, sent instead of the usual one
+/// `chance` of the time
+pub struct EasterEgg {
+    pub pattern: Regex,
+    pub chance: f64,
+    pub response: EasterEggResponse,
+}
+
+/// The replies to bare mentions and the easter eggs, read from the greetings
+/// file
+#[derive(Default)]
+pub struct Greetings {
+    mention_responses: Vec<String>,
+    easter_eggs: Vec<EasterEgg>,
+}
+
+pub struct GreetingsKey;
+
+impl TypeMapKey for GreetingsKey {
+    type Value = Greetings;
+}
+
+fn load_easter_egg(section: &Yaml) -> Option<EasterEgg> {
+    let pattern = match section["pattern"].as_str().map(Regex::new) {
+        Some(Ok(pattern)) => pattern,
+        Some(Err(reason)) => {
+            warn!(
+                "Greetings file contains an easter egg with an invalid pattern: {}",
+                reason
+            );
+            return None;
+        }
+        None => {
+            warn!("Greetings file contains an easter egg without a pattern; skipping");
+            return None;
+        }
+    };
+
+    // Integers are accepted too, so that `chance: 1` works
+    let chance = match &section["chance"] {
+        Yaml::Real(_) => section["chance"].as_f64(),
+        Yaml::Integer(chance) => Some(*chance as f64),
+        Yaml::BadValue => Some(1f64),
+        _ => None,
+    };
+
+    let chance = match chance {
+        Some(chance) if (0f64..=1f64).contains(&chance) => chance,
+        _ => {
+            warn!(
+                "Greetings file contains an easter egg for \"{}\" with a chance that isn't between 0 and 1; skipping",
+                pattern
+            );
+            return None;
+        }
+    };
+
+    let response = match (section["reply"].as_str(), section["meme"].as_str()) {
+        (Some(reply), None) => EasterEggResponse::Reply(reply.into()),
+        (None, Some(meme)) => EasterEggResponse::Meme(meme.to_lowercase()),
+        _ => {
+            warn!(
+                "Greetings file contains an easter egg for \"{}\" without exactly one of reply or meme; skipping",
+                pattern
+            );
+            return None;
+        }
+    };
+
+    Some(EasterEgg {
+        pattern,
+        chance,
+        response,
+    })
+}
+
+impl Greetings {
+    /// Loads the greetings from `filename`. A missing file is not an error,
+    /// since the bot then just keeps to the default reply.
+    pub fn load(store: &dyn TemplateStore, filename: &str) -> Result<Greetings, String> {
+        let mut greetings = Greetings::default();
+
+        if !store.exists(filename) {
+            return Ok(greetings);
+        }
+
+        let contents = match store.read(filename).map(String::from_utf8) {
+            Ok(Ok(contents)) => contents,
+            Ok(Err(reason)) => {
+                return Err(format!(
+                    "Unable to read greetings file \"{}\": {}",
+                    filename, reason
+                ));
+            }
+            Err(reason) => return Err(reason),
+        };
+
+        let yaml = match YamlLoader::load_from_str(&contents) {
+            Ok(yaml) => yaml,
+            Err(reason) => {
+                return Err(format!(
+                    "Unable to parse greetings file \"{}\": {}",
+                    filename, reason
+                ));
+            }
+        };
+
+        let yaml = match yaml.first() {
+            Some(yaml) => yaml,
+            None => return Ok(greetings),
+        };
+
+        if let Some(mention_responses) = yaml["mention_responses"].as_vec() {
+            for response in mention_responses {
+                match response.as_str() {
+                    Some(response) if !response.trim().is_empty() => {
+                        greetings.mention_responses.push(response.into())
+                    }
+                    _ => warn!(
+                        "Greetings file contains invalid mention response \"{:?}\"",
+                        response
+                    ),
+                }
+            }
+        }
+
+        if let Some(easter_eggs) = yaml["easter_eggs"].as_vec() {
+            greetings.easter_eggs = easter_eggs.iter().filter_map(load_easter_egg).collect();
+        }
+
+        Ok(greetings)
+    }
+
+    /// Returns a random reply to a message that only mentions the bot
+    pub fn mention_response(&self) -> &str {
+        self.mention_responses
+            .choose(&mut rand::thread_rng())
+            .map(String::as_str)
+            .unwrap_or(DEFAULT_MENTION_RESPONSE)
+    }
+
+    /// Returns the response of the first easter egg whose pattern matches
+    /// `text`, if its chance comes up
+    pub fn find_easter_egg(&self, text: &str) -> Option<&EasterEggResponse> {
+        let easter_egg = self
+            .easter_eggs
+            .iter()
+            .find(|easter_egg| easter_egg.pattern.is_match(text))?;
+
+        if rand::thread_rng().gen_bool(easter_egg.chance) {
+            Some(&easter_egg.response)
+        } else {
+            None
+        }
+    }
+
+    pub fn easter_eggs(&self) -> &[EasterEgg] {
+        &self.easter_eggs
+    }
+}
@@ -0,0 +1,624 @@
+use log::warn;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{read_to_string, write};
+use yaml_rust::yaml::{Hash, Yaml};
+use yaml_rust::{YamlEmitter, YamlLoader};
+
+use serenity::prelude::TypeMapKey;
+
+use crate::schedule::{parse_month_day, Date};
+
+/// A meme posted whenever one of `users` joins a voice channel, set up with
+/// the `voicememes` command
+#[derive(Clone)]
+pub struct VoiceMemes {
+    pub channel: u64,
+    pub meme: String,
+    // The meme's text, where `{user}` and `{channel}` stand for who joined and
+    // where
+    pub text: String,
+    pub users: Vec<u64>,
+}
+
+/// A meme welcoming each new member, set up with the `welcome` command
+#[derive(Clone)]
+pub struct WelcomeMeme {
+    pub channel: u64,
+    pub meme: String,
+    // The meme's text, where `{user}` stands for the new member
+    pub text: String,
+}
+
+/// The meme posted on the day of each of a guild's events, set up with the
+/// `event` command
+#[derive(Clone)]
+pub struct EventMeme {
+    pub channel: u64,
+    pub meme: String,
+    // The meme's text, where `{user}` and `{event}` stand for whose event it
+    // is and what it's called
+    pub text: String,
+}
+
+/// A day of every year that a meme is posted about a user on, like their
+/// birthday, added with the `event` command
+#[derive(Clone)]
+pub struct Event {
+    pub user: u64,
+    pub name: String,
+    pub month: u32,
+    pub day: u32,
+    // The last year the meme was posted, so that it's only posted once even if
+    // the bot restarts on the day
+    pub last_posted: Option<i64>,
+}
+
+#[derive(Default)]
+pub struct GuildSettings {
+    pub locale: Option<String>,
+    // Users given the manager role with the `managers` command, on top of
+    // those with the Manage Server permission
+    pub managers: Vec<u64>,
+    // Whether an administrator has accepted the bot's terms of use, if the
+    // bot is configured to require that
+    pub terms_accepted: bool,
+    // The channel that the bot admins' announcements are posted to, if the
+    // guild has opted in to them
+    pub announcements_channel: Option<u64>,
+    // Emoji to the memes made from a message when someone reacts to it with
+    // them, set up with the `hotkeys` command
+    pub hotkeys: BTreeMap<String, String>,
+    // The guild's own names for memes and commands, to the commands they stand
+    // for, set up with the `aliases` command
+    pub aliases: BTreeMap<String, String>,
+    // Features the guild turned on or off with the `features` command, over
+    // the bot's defaults
+    pub features: BTreeMap<String, bool>,
+    pub voice_memes: Option<VoiceMemes>,
+    pub welcome_meme: Option<WelcomeMeme>,
+    pub event_meme: Option<EventMeme>,
+    pub events: Vec<Event>,
+}
+
+fn id_from_yaml(id: &Yaml) -> Option<u64> {
+    match id {
+        Yaml::Integer(id) => Some(*id as u64),
+        Yaml::String(id) => id.parse::<u64>().ok(),
+        _ => None,
+    }
+}
+
+fn string_map_from_yaml(kind: &str, hash: &Hash) -> BTreeMap<String, String> {
+    let mut map = BTreeMap::new();
+
+    for (key, value) in hash {
+        match (key.as_str(), value.as_str()) {
+            (Some(key), Some(value)) => {
+                map.insert(key.into(), value.into());
+            }
+            _ => {
+                warn!(
+                    "Guild settings contain invalid {} \"{:?}\": \"{:?}\"",
+                    kind, key, value
+                );
+            }
+        }
+    }
+
+    map
+}
+
+fn string_map_to_yaml(map: &BTreeMap<String, String>) -> Yaml {
+    Yaml::Hash(
+        map.iter()
+            .map(|(key, value)| (Yaml::String(key.clone()), Yaml::String(value.clone())))
+            .collect(),
+    )
+}
+
+impl GuildSettings {
+    /// Counts the entries that mention `user_id`: as a manager, as one of the
+    /// voice meme users, and in events
+    fn user_entries(&self, user_id: u64) -> usize {
+        let managers = self.managers.iter().filter(|id| **id == user_id).count();
+        let voice_memes = self.voice_memes.as_ref().map_or(0, |voice_memes| {
+            voice_memes
+                .users
+                .iter()
+                .filter(|id| **id == user_id)
+                .count()
+        });
+        let events = self
+            .events
+            .iter()
+            .filter(|event| event.user == user_id)
+            .count();
+
+        managers + voice_memes + events
+    }
+
+    fn forget_user(&mut self, user_id: u64) {
+        self.managers.retain(|id| *id != user_id);
+
+        if let Some(voice_memes) = &mut self.voice_memes {
+            voice_memes.users.retain(|id| *id != user_id);
+        }
+
+        self.events.retain(|event| event.user != user_id);
+    }
+
+    fn from_yaml(hash: &Hash) -> GuildSettings {
+        let mut settings = GuildSettings::default();
+
+        for (key, value) in hash {
+            match (key.as_str(), value) {
+                (Some("locale"), Yaml::String(locale)) => {
+                    settings.locale = Some(locale.clone());
+                }
+                (Some("terms_accepted"), Yaml::Boolean(terms_accepted)) => {
+                    settings.terms_accepted = *terms_accepted;
+                }
+                (Some("announcements_channel"), Yaml::String(channel_id)) => {
+                    match channel_id.parse::<u64>() {
+                        Ok(channel_id) => settings.announcements_channel = Some(channel_id),
+                        Err(_) => {
+                            warn!(
+                                "Guild settings contain invalid announcements channel \"{}\"",
+                                channel_id
+                            );
+                        }
+                    }
+                }
+                (Some("hotkeys"), Yaml::Hash(hotkeys)) => {
+                    settings.hotkeys = string_map_from_yaml("hotkey", hotkeys);
+                }
+                (Some("aliases"), Yaml::Hash(aliases)) => {
+                    settings.aliases = string_map_from_yaml("alias", aliases);
+                }
+                (Some("features"), Yaml::Hash(features)) => {
+                    for (feature, enabled) in features {
+                        match (feature.as_str(), enabled) {
+                            (Some(feature), Yaml::Boolean(enabled)) => {
+                                settings.features.insert(feature.into(), *enabled);
+                            }
+                            _ => {
+                                warn!(
+                                    "Guild settings contain invalid feature \"{:?}\": \"{:?}\"",
+                                    feature, enabled
+                                );
+                            }
+                        }
+                    }
+                }
+                (Some("voice_memes"), voice_memes @ Yaml::Hash(_)) => {
+                    match (
+                        id_from_yaml(&voice_memes["channel"]),
+                        voice_memes["meme"].as_str(),
+                        voice_memes["text"].as_str(),
+                    ) {
+                        (Some(channel), Some(meme), Some(text)) => {
+                            settings.voice_memes = Some(VoiceMemes {
+                                channel,
+                                meme: meme.into(),
+                                text: text.into(),
+                                users: voice_memes["users"]
+                                    .as_vec()
+                                    .map(|users| users.iter().filter_map(id_from_yaml).collect())
+                                    .unwrap_or_default(),
+                            });
+                        }
+                        _ => {
+                            warn!(
+                                "Guild settings contain invalid voice memes \"{:?}\"",
+                                voice_memes
+                            );
+                        }
+                    }
+                }
+                (Some("welcome_meme"), welcome_meme @ Yaml::Hash(_)) => {
+                    match (
+                        id_from_yaml(&welcome_meme["channel"]),
+                        welcome_meme["meme"].as_str(),
+                        welcome_meme["text"].as_str(),
+                    ) {
+                        (Some(channel), Some(meme), Some(text)) => {
+                            settings.welcome_meme = Some(WelcomeMeme {
+                                channel,
+                                meme: meme.into(),
+                                text: text.into(),
+                            });
+                        }
+                        _ => {
+                            warn!(
+                                "Guild settings contain invalid welcome meme \"{:?}\"",
+                                welcome_meme
+                            );
+                        }
+                    }
+                }
+                (Some("event_meme"), event_meme @ Yaml::Hash(_)) => {
+                    match (
+                        id_from_yaml(&event_meme["channel"]),
+                        event_meme["meme"].as_str(),
+                        event_meme["text"].as_str(),
+                    ) {
+                        (Some(channel), Some(meme), Some(text)) => {
+                            settings.event_meme = Some(EventMeme {
+                                channel,
+                                meme: meme.into(),
+                                text: text.into(),
+                            });
+                        }
+                        _ => {
+                            warn!(
+                                "Guild settings contain invalid event meme \"{:?}\"",
+                                event_meme
+                            );
+                        }
+                    }
+                }
+                (Some("events"), Yaml::Array(events)) => {
+                    for event in events {
+                        match (
+                            id_from_yaml(&event["user"]),
+                            event["name"].as_str(),
+                            event["date"].as_str().and_then(parse_month_day),
+                        ) {
+                            (Some(user), Some(name), Some((month, day))) => {
+                                settings.events.push(Event {
+                                    user,
+                                    name: name.into(),
+                                    month,
+                                    day,
+                                    last_posted: event["last_posted"].as_i64(),
+                                });
+                            }
+                            _ => {
+                                warn!("Guild settings contain invalid event \"{:?}\"", event);
+                            }
+                        }
+                    }
+                }
+                (Some("managers"), Yaml::Array(managers)) => {
+                    for manager in managers {
+                        match id_from_yaml(manager) {
+                            Some(manager_id) => settings.managers.push(manager_id),
+                            None => {
+                                warn!(
+                                    "Guild settings contain invalid manager ID \"{:?}\"",
+                                    manager
+                                );
+                            }
+                        }
+                    }
+                }
+                (key, value) => {
+                    warn!(
+                        "Guild settings contain invalid entry \"{:?}\": \"{:?}\"",
+                        key, value
+                    );
+                }
+            }
+        }
+
+        settings
+    }
+
+    fn to_yaml(&self) -> Yaml {
+        let mut hash = Hash::new();
+
+        if let Some(locale) = &self.locale {
+            hash.insert(Yaml::String("locale".into()), Yaml::String(locale.clone()));
+        }
+
+        if self.terms_accepted {
+            hash.insert(Yaml::String("terms_accepted".into()), Yaml::Boolean(true));
+        }
+
+        if let Some(channel_id) = self.announcements_channel {
+            hash.insert(
+                Yaml::String("announcements_channel".into()),
+                Yaml::String(channel_id.to_string()),
+            );
+        }
+
+        if !self.managers.is_empty() {
+            hash.insert(
+                Yaml::String("managers".into()),
+                Yaml::Array(
+                    self.managers
+                        .iter()
+                        .map(|manager| Yaml::String(manager.to_string()))
+                        .collect(),
+                ),
+            );
+        }
+
+        if !self.hotkeys.is_empty() {
+            hash.insert(
+                Yaml::String("hotkeys".into()),
+                string_map_to_yaml(&self.hotkeys),
+            );
+        }
+
+        if !self.aliases.is_empty() {
+            hash.insert(
+                Yaml::String("aliases".into()),
+                string_map_to_yaml(&self.aliases),
+            );
+        }
+
+        if !self.features.is_empty() {
+            hash.insert(
+                Yaml::String("features".into()),
+                Yaml::Hash(
+                    self.features
+                        .iter()
+                        .map(|(feature, enabled)| {
+                            (Yaml::String(feature.clone()), Yaml::Boolean(*enabled))
+                        })
+                        .collect(),
+                ),
+            );
+        }
+
+        if let Some(voice_memes) = &self.voice_memes {
+            let mut entry = Hash::new();
+            entry.insert(
+                Yaml::String("channel".into()),
+                Yaml::String(voice_memes.channel.to_string()),
+            );
+            entry.insert(
+                Yaml::String("meme".into()),
+                Yaml::String(voice_memes.meme.clone()),
+            );
+            entry.insert(
+                Yaml::String("text".into()),
+                Yaml::String(voice_memes.text.clone()),
+            );
+            entry.insert(
+                Yaml::String("users".into()),
+                Yaml::Array(
+                    voice_memes
+                        .users
+                        .iter()
+                        .map(|user| Yaml::String(user.to_string()))
+                        .collect(),
+                ),
+            );
+
+            hash.insert(Yaml::String("voice_memes".into()), Yaml::Hash(entry));
+        }
+
+        if let Some(welcome_meme) = &self.welcome_meme {
+            let mut entry = Hash::new();
+            entry.insert(
+                Yaml::String("channel".into()),
+                Yaml::String(welcome_meme.channel.to_string()),
+            );
+            entry.insert(
+                Yaml::String("meme".into()),
+                Yaml::String(welcome_meme.meme.clone()),
+            );
+            entry.insert(
+                Yaml::String("text".into()),
+                Yaml::String(welcome_meme.text.clone()),
+            );
+
+            hash.insert(Yaml::String("welcome_meme".into()), Yaml::Hash(entry));
+        }
+
+        if let Some(event_meme) = &self.event_meme {
+            let mut entry = Hash::new();
+            entry.insert(
+                Yaml::String("channel".into()),
+                Yaml::String(event_meme.channel.to_string()),
+            );
+            entry.insert(
+                Yaml::String("meme".into()),
+                Yaml::String(event_meme.meme.clone()),
+            );
+            entry.insert(
+                Yaml::String("text".into()),
+                Yaml::String(event_meme.text.clone()),
+            );
+
+            hash.insert(Yaml::String("event_meme".into()), Yaml::Hash(entry));
+        }
+
+        if !self.events.is_empty() {
+            hash.insert(
+                Yaml::String("events".into()),
+                Yaml::Array(
+                    self.events
+                        .iter()
+                        .map(|event| {
+                            let mut entry = Hash::new();
+                            entry.insert(
+                                Yaml::String("user".into()),
+                                Yaml::String(event.user.to_string()),
+                            );
+                            entry.insert(
+                                Yaml::String("name".into()),
+                                Yaml::String(event.name.clone()),
+                            );
+                            entry.insert(
+                                Yaml::String("date".into()),
+                                Yaml::String(format!("{:02}-{:02}", event.month, event.day)),
+                            );
+
+                            if let Some(year) = event.last_posted {
+                                entry.insert(
+                                    Yaml::String("last_posted".into()),
+                                    Yaml::Integer(year),
+                                );
+                            }
+
+                            Yaml::Hash(entry)
+                        })
+                        .collect(),
+                ),
+            );
+        }
+
+        Yaml::Hash(hash)
+    }
+}
+
+/// Settings chosen by the administrators of each guild, persisted to a YAML
+/// file so that they survive restarts.
+pub struct GuildSettingsStore {
+    filename: String,
+    guilds: HashMap<u64, GuildSettings>,
+}
+
+pub struct GuildSettingsKey;
+
+impl TypeMapKey for GuildSettingsKey {
+    type Value = GuildSettingsStore;
+}
+
+impl GuildSettingsStore {
+    /// Loads the settings from `filename`. A missing file is not an error,
+    /// since no guild has changed any settings yet.
+    pub fn load(filename: &str) -> Result<GuildSettingsStore, String> {
+        let mut store = GuildSettingsStore {
+            filename: filename.into(),
+            guilds: HashMap::new(),
+        };
+
+        let contents = match read_to_string(filename) {
+            Ok(contents) => contents,
+            Err(_) => return Ok(store),
+        };
+
+        let yaml = match YamlLoader::load_from_str(&contents) {
+            Ok(yaml) => yaml,
+            Err(reason) => {
+                return Err(format!(
+                    "Unable to parse guild settings file \"{}\": {}",
+                    filename, reason
+                ));
+            }
+        };
+
+        if let Some(Yaml::Hash(guilds)) = yaml.first() {
+            for (guild_id, settings) in guilds {
+                let guild_id = match guild_id {
+                    Yaml::Integer(guild_id) => *guild_id as u64,
+                    Yaml::String(guild_id) => match guild_id.parse::<u64>() {
+                        Ok(guild_id) => guild_id,
+                        Err(_) => {
+                            warn!("Guild settings contain invalid guild ID \"{}\"", guild_id);
+                            continue;
+                        }
+                    },
+                    guild_id => {
+                        warn!("Guild settings contain invalid guild ID \"{:?}\"", guild_id);
+                        continue;
+                    }
+                };
+
+                if let Yaml::Hash(settings) = settings {
+                    store
+                        .guilds
+                        .insert(guild_id, GuildSettings::from_yaml(settings));
+                } else {
+                    warn!("Guild settings for guild {} are malformed", guild_id);
+                }
+            }
+        }
+
+        Ok(store)
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let mut guilds = Hash::new();
+
+        for (guild_id, settings) in &self.guilds {
+            guilds.insert(Yaml::String(guild_id.to_string()), settings.to_yaml());
+        }
+
+        let mut contents = String::new();
+
+        if let Err(reason) = YamlEmitter::new(&mut contents).dump(&Yaml::Hash(guilds)) {
+            return Err(format!("Unable to serialize guild settings: {:?}", reason));
+        }
+
+        if let Err(reason) = write(&self.filename, contents + "\n") {
+            return Err(format!(
+                "Unable to write guild settings file \"{}\": {}",
+                self.filename, reason
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn get(&self, guild_id: u64) -> Option<&GuildSettings> {
+        self.guilds.get(&guild_id)
+    }
+
+    pub fn get_mut(&mut self, guild_id: u64) -> &mut GuildSettings {
+        self.guilds.entry(guild_id).or_default()
+    }
+
+    /// Counts the entries that mention `user_id` across every guild
+    pub fn user_entries(&self, user_id: u64) -> usize {
+        self.guilds
+            .values()
+            .map(|settings| settings.user_entries(user_id))
+            .sum()
+    }
+
+    /// Removes the entries that mention `user_id` from every guild, returning
+    /// how many there were
+    pub fn forget_user(&mut self, user_id: u64) -> usize {
+        let entries = self.user_entries(user_id);
+
+        for settings in self.guilds.values_mut() {
+            settings.forget_user(user_id);
+        }
+
+        entries
+    }
+
+    /// Returns the guilds that have opted in to announcements, along with the
+    /// channels to post them to
+    pub fn announcements_channels(&self) -> Vec<(u64, u64)> {
+        self.guilds
+            .iter()
+            .filter_map(|(guild_id, settings)| {
+                settings
+                    .announcements_channel
+                    .map(|channel_id| (*guild_id, channel_id))
+            })
+            .collect()
+    }
+
+    /// Returns the events that fall on `today` in guilds with an event meme,
+    /// along with the guild and its meme, and marks them as posted this year
+    pub fn take_due_events(&mut self, today: Date) -> Vec<(u64, EventMeme, Event)> {
+        let mut due = Vec::new();
+
+        for (guild_id, settings) in self.guilds.iter_mut() {
+            let event_meme = match &settings.event_meme {
+                Some(event_meme) => event_meme,
+                None => continue,
+            };
+
+            for event in settings.events.iter_mut() {
+                if event.last_posted == Some(today.year)
+                    || !today.is_anniversary((event.month, event.day))
+                {
+                    continue;
+                }
+
+                event.last_posted = Some(today.year);
+                due.push((*guild_id, event_meme.clone(), event.clone()));
+            }
+        }
+
+        due
+    }
+}
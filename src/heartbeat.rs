@@ -0,0 +1,100 @@
+use log::info;
+use std::fs::read_to_string;
+use std::thread;
+use std::time::Duration;
+
+use serenity::prelude::Context;
+
+use crate::calibrate::CalibrationsKey;
+use crate::gallery::GallerySyncsKey;
+use crate::registry::MemesKey;
+use crate::wizard::WizardsKey;
+use crate::ShardManagerKey;
+
+/// How much memory the process is using, in KB, on systems with procfs
+fn resident_memory_kb() -> Option<u64> {
+    read_to_string("/proc/self/status")
+        .ok()?
+        .lines()
+        .find(|line| line.starts_with("VmRSS:"))?
+        .split_whitespace()
+        .nth(1)?
+        .parse::<u64>()
+        .ok()
+}
+
+/// Logs one line of `key=value` pairs on how the bot is doing, for operators
+/// to follow from the logs alone
+fn log_heartbeat(ctx: &Context) {
+    let data = ctx.data.read();
+
+    let shards = match data.get::<ShardManagerKey>() {
+        Some(shard_manager) => {
+            let shard_manager = shard_manager.lock();
+            let runners = shard_manager.runners.lock();
+
+            let mut ids: Vec<_> = runners.keys().collect();
+            ids.sort_by_key(|id| id.0);
+
+            ids.iter()
+                .map(|id| {
+                    let runner = &runners[id];
+
+                    let latency = match runner.latency {
+                        Some(latency) => format!("{}ms", latency.as_millis()),
+                        None => "unknown".into(),
+                    };
+
+                    format!("{}:{}:{:?}", id.0, latency, runner.stage)
+                })
+                .collect::<Vec<String>>()
+                .join(",")
+        }
+        None => String::new(),
+    };
+
+    let memes = data
+        .get::<MemesKey>()
+        .expect("Heartbeat: Unable to retrieve memes")
+        .load()
+        .all()
+        .len();
+
+    // Conversations that are waiting on a reply from their user
+    let wizards = data
+        .get::<WizardsKey>()
+        .expect("Heartbeat: Unable to retrieve wizards")
+        .len();
+    let calibrations = data
+        .get::<CalibrationsKey>()
+        .expect("Heartbeat: Unable to retrieve calibrations")
+        .len();
+    let syncs = data
+        .get::<GallerySyncsKey>()
+        .expect("Heartbeat: Unable to retrieve gallery syncs")
+        .len();
+
+    drop(data);
+
+    let cache = ctx.cache.read();
+    let (guilds, channels, users) = (cache.guilds.len(), cache.channels.len(), cache.users.len());
+    drop(cache);
+
+    let memory = match resident_memory_kb() {
+        Some(kb) => kb.to_string(),
+        None => "unknown".into(),
+    };
+
+    info!(
+        "Heartbeat: shards={} guilds={} channels={} users={} memes={} wizards={} calibrations={} syncs={} memory_kb={}",
+        shards, guilds, channels, users, memes, wizards, calibrations, syncs, memory
+    );
+}
+
+/// Logs a heartbeat every `interval`
+pub fn start_heartbeat(ctx: Context, interval: Duration) {
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        log_heartbeat(&ctx);
+    });
+}
@@ -0,0 +1,274 @@
+use log::{info, warn};
+use regex::Regex;
+
+use serenity::model::prelude::{Message, Reaction, ReactionType};
+use serenity::prelude::Context;
+
+use crate::capabilities::is_feature_enabled;
+use crate::guild_settings::GuildSettingsKey;
+use crate::message_text::message_text;
+use crate::permissions::is_manager;
+use crate::registry::MemesKey;
+use crate::{send_meme, split_first_word, BotSettingsKey};
+
+// Discord doesn't allow more than this many different reactions on a message
+const MAX_HOTKEYS: usize = 20;
+
+/// Returns how `emoji` is stored in the guild settings: the ID of custom
+/// emoji, since they can be renamed, or the emoji itself
+pub fn emoji_key(emoji: &ReactionType) -> Option<String> {
+    match emoji {
+        ReactionType::Custom { id, .. } => Some(id.0.to_string()),
+        // Clients don't agree on whether to send the variation selector
+        ReactionType::Unicode(emoji) => Some(emoji.replace('\u{FE0F}', "")),
+        _ => None,
+    }
+}
+
+/// Parses an emoji typed into a message, like "🔥" or "<:pog:123456789>"
+pub fn parse_emoji(text: &str) -> Option<String> {
+    let re_custom_emoji =
+        Regex::new(r"^<a?:\w+:(\d+)>$").expect("Unable to create custom emoji matching pattern");
+
+    if let Some(captures) = re_custom_emoji.captures(text) {
+        return Some(captures[1].to_string());
+    }
+
+    // Anything else made of ASCII is more likely to be a meme than an emoji
+    if text.is_empty() || text.is_ascii() {
+        return None;
+    }
+
+    Some(text.replace('\u{FE0F}', ""))
+}
+
+pub fn display_emoji(key: &str) -> String {
+    if key.bytes().all(|byte| byte.is_ascii_digit()) {
+        format!("<:hotkey:{}>", key)
+    } else {
+        key.into()
+    }
+}
+
+fn list_hotkeys(ctx: &Context, msg: &Message, guild_id: u64) {
+    let data = ctx.data.read();
+    let hotkeys = data
+        .get::<GuildSettingsKey>()
+        .expect("Command hotkeys: Unable to retrieve guild settings")
+        .get(guild_id)
+        .map(|settings| &settings.hotkeys);
+
+    let reply = match hotkeys {
+        Some(hotkeys) if !hotkeys.is_empty() => {
+            let lines: Vec<String> = hotkeys
+                .iter()
+                .map(|(emoji, command)| format!("{} - `{}`", display_emoji(emoji), command))
+                .collect();
+
+            format!(
+                "React to a message with one of these to turn it into a meme:\n{}",
+                lines.join("\n")
+            )
+        }
+        _ => {
+            "This server has no hotkeys. (A manager can add one with `hotkeys add <emoji> <meme>`.)"
+                .into()
+        }
+    };
+
+    drop(data);
+
+    msg.channel_id.say(ctx, reply).ok();
+}
+
+pub fn hotkeys_command(ctx: &Context, msg: &Message, rest: &str) {
+    let guild_id = match msg.guild_id {
+        Some(guild_id) => guild_id.0,
+        None => {
+            msg.channel_id
+                .say(ctx, "Hotkeys can only be set up in a server.")
+                .ok();
+            return;
+        }
+    };
+
+    let (action, rest) = split_first_word(rest);
+    let (emoji, rest) = split_first_word(rest);
+    let (command, _) = split_first_word(rest);
+
+    let action = action.to_lowercase();
+    let command = command.to_lowercase();
+
+    if action.is_empty() {
+        list_hotkeys(ctx, msg, guild_id);
+        return;
+    }
+
+    if !is_manager(ctx, msg) {
+        msg.channel_id
+            .say(
+                ctx,
+                "You need to be a manager of this server (or have the Manage Server permission) to do that.",
+            )
+            .ok();
+        return;
+    }
+
+    let emoji = match (action.as_str(), parse_emoji(emoji)) {
+        ("add", Some(emoji)) if !command.is_empty() => emoji,
+        ("remove", Some(emoji)) => emoji,
+        _ => {
+            msg.channel_id
+                .say(ctx, "Usage: `hotkeys [add <emoji> <meme>|remove <emoji>]`")
+                .ok();
+            return;
+        }
+    };
+
+    let mut data = ctx.data.write();
+
+    if action == "add"
+        && !data
+            .get::<MemesKey>()
+            .expect("Command hotkeys: Unable to retrieve memes")
+            .load()
+            .contains(&command)
+    {
+        drop(data);
+
+        msg.channel_id
+            .say(ctx, format!("There's no meme called `{}`.", command))
+            .ok();
+        return;
+    }
+
+    let guild_settings = data
+        .get_mut::<GuildSettingsKey>()
+        .expect("Command hotkeys: Unable to retrieve guild settings");
+    let hotkeys = &mut guild_settings.get_mut(guild_id).hotkeys;
+
+    let reply = if action == "add" {
+        if hotkeys.len() >= MAX_HOTKEYS && !hotkeys.contains_key(&emoji) {
+            format!("A server can't have more than {} hotkeys.", MAX_HOTKEYS)
+        } else {
+            hotkeys.insert(emoji.clone(), command.clone());
+
+            format!(
+                "Reacting with {} now creates the `{}` meme.",
+                display_emoji(&emoji),
+                command
+            )
+        }
+    } else if hotkeys.remove(&emoji).is_some() {
+        format!("{} isn't a hotkey anymore.", display_emoji(&emoji))
+    } else {
+        format!("{} isn't a hotkey.", display_emoji(&emoji))
+    };
+
+    if let Err(reason) = guild_settings.save() {
+        warn!("Command hotkeys: {}", reason);
+    }
+
+    drop(data);
+
+    msg.channel_id.say(ctx, reply).ok();
+}
+
+/// Turns the message that was reacted to into a meme if the reaction is one
+/// of its server's hotkeys. Only the first such reaction does, so that others
+/// joining in don't post the meme again.
+pub fn handle_hotkey(ctx: &Context, reaction: &Reaction) {
+    let guild_id = match reaction.guild_id {
+        Some(guild_id) => guild_id.0,
+        None => return,
+    };
+
+    if reaction.user_id == ctx.cache.read().user.id {
+        return;
+    }
+
+    let key = match emoji_key(&reaction.emoji) {
+        Some(key) => key,
+        None => return,
+    };
+
+    let data = ctx.data.read();
+    let settings = match data
+        .get::<GuildSettingsKey>()
+        .expect("Hotkey: Unable to retrieve guild settings")
+        .get(guild_id)
+    {
+        Some(settings) => settings,
+        None => return,
+    };
+
+    let command = match settings.hotkeys.get(&key) {
+        Some(command) => command.clone(),
+        None => return,
+    };
+
+    let needs_terms = data
+        .get::<BotSettingsKey>()
+        .expect("Hotkey: Unable to retrieve bot settings")
+        .terms
+        .is_some();
+
+    if needs_terms && !settings.terms_accepted {
+        return;
+    }
+
+    if !is_feature_enabled(&data, Some(guild_id), "hotkeys") {
+        return;
+    }
+
+    let read_attachments = is_feature_enabled(&data, Some(guild_id), "text-attachments");
+
+    drop(data);
+
+    let message = match reaction.message(&ctx.http) {
+        Ok(message) => message,
+        Err(reason) => {
+            warn!("Hotkey: Unable to retrieve message: {}", reason);
+            return;
+        }
+    };
+
+    let count: u64 = message
+        .reactions
+        .iter()
+        .filter(|message_reaction| {
+            emoji_key(&message_reaction.reaction_type).as_ref() == Some(&key)
+        })
+        .map(|message_reaction| message_reaction.count)
+        .sum();
+
+    if count > 1 || message.author.bot {
+        return;
+    }
+
+    let text = message_text(ctx, &message, read_attachments);
+
+    if text.trim().is_empty() {
+        return;
+    }
+
+    let data = ctx.data.read();
+    let memes = data
+        .get::<MemesKey>()
+        .expect("Hotkey: Unable to retrieve memes")
+        .load();
+
+    let meme = match memes.find(&command) {
+        Some(meme) => meme,
+        None => return,
+    };
+
+    info!(
+        "Hotkey for \"{}\" used on message {} in guild {}",
+        command, message.id, guild_id
+    );
+
+    drop(data);
+
+    send_meme(ctx, &message, meme, &text);
+}
@@ -0,0 +1,217 @@
+use log::{debug, info, warn};
+use serde_json::Value;
+use std::io::Read;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::animation::{encode_animation, render_animation, AnimationFrame, MAX_ANIMATION_BYTES};
+use crate::capabilities::is_enabled_by_default;
+use crate::code_block::find_code_block;
+use crate::flags::take_flags;
+use crate::pipeline::{apply_stages, split_pipeline};
+use crate::registry::MemeRegistry;
+use crate::render::{encode_meme_png, fit_to_width, render_code, render_meme, OutputSettings};
+use crate::schedule::Date;
+use crate::{meme_text, select_code_font, select_font, Fonts};
+
+// Request bodies larger than this are rejected without being read
+const MAX_BODY_LENGTH: u64 = 16 * 1024;
+
+pub fn respond_with_error(request: Request, status: u16, reason: &str) {
+    let body = serde_json::json!({ "error": reason }).to_string();
+    let header = Header::from_bytes("Content-Type", "application/json")
+        .expect("HTTP API: Unable to create Content-Type header");
+
+    if let Err(reason) = request.respond(
+        Response::from_string(body)
+            .with_status_code(status)
+            .with_header(header),
+    ) {
+        warn!("HTTP API: Unable to send response: {}", reason);
+    }
+}
+
+/// Renders the meme described by a request body like
+/// `{"template": "example", "text": "some text"}`, returning the PNG (or GIF,
+/// for animated memes) and its content type along with the template's version
+fn render(
+    fonts: &Fonts,
+    memes: &MemeRegistry,
+    output_settings: OutputSettings,
+    body: &str,
+) -> Result<(Vec<u8>, &'static str, String), (u16, String)> {
+    let body: Value = match serde_json::from_str(body) {
+        Ok(body) => body,
+        Err(reason) => return Err((400, format!("Invalid JSON: {}", reason))),
+    };
+
+    let template = match body.get("template").map(Value::as_str) {
+        Some(Some(template)) => template,
+        None => return Err((400, "Missing \"template\"".into())),
+        Some(None) => return Err((400, "\"template\" must be a string".into())),
+    };
+
+    let text = match body.get("text").map(Value::as_str) {
+        Some(Some(text)) => text,
+        None => return Err((400, "Missing \"text\"".into())),
+        Some(None) => return Err((400, "\"text\" must be a string".into())),
+    };
+
+    let locale = body.get("locale").and_then(Value::as_str);
+
+    let meme = match memes.find(template) {
+        Some(meme) => meme,
+        None => return Err((404, format!("Unknown template \"{}\"", template))),
+    };
+
+    if !meme.avatar_regions.is_empty() {
+        return Err((
+            400,
+            format!("Template \"{}\" requires avatars", meme.command),
+        ));
+    }
+
+    let font = match select_font(fonts, meme) {
+        Some(font) => font,
+        None => return Err((500, "No fonts loaded".into())),
+    };
+
+    let (text, flags) = take_flags(text).map_err(|reason| (400, reason))?;
+    let (text, stages) = split_pipeline(&text);
+
+    if let Some((stage, _)) = stages
+        .iter()
+        .find(|(stage, _)| !is_enabled_by_default(stage.name))
+    {
+        return Err((400, format!("\"{}\" is turned off", stage.name)));
+    }
+
+    debug!("HTTP API: Creating meme \"{}\"", meme.command);
+
+    let frames = match find_code_block(text).filter(|_| is_enabled_by_default("code")) {
+        Some(code) => vec![AnimationFrame::still(render_code(
+            meme,
+            select_code_font(fonts, meme).unwrap_or(font),
+            code,
+            flags.color.unwrap_or(meme.code_color),
+            meme.code_background,
+        ))],
+        None => {
+            let text = meme_text(meme, text, locale, flags.transform_seed(meme));
+            let style = flags.text_style(meme);
+
+            match meme
+                .animation
+                .filter(|_| is_enabled_by_default("animations"))
+            {
+                Some(animation) => render_animation(meme, font, &text, style, animation),
+                None => vec![AnimationFrame::still(render_meme(meme, font, &text, style))],
+            }
+        }
+    };
+
+    let mut frames = frames
+        .into_iter()
+        .map(|AnimationFrame { image, delay_ms }| {
+            apply_stages(image, meme, font, &stages).map(|image| AnimationFrame {
+                image: fit_to_width(image, meme.output_max_width.or(output_settings.max_width)),
+                delay_ms,
+            })
+        })
+        .collect::<Result<Vec<AnimationFrame>, String>>()
+        .map_err(|reason| (400, reason))?;
+
+    if frames.len() == 1 {
+        encode_meme_png(&frames.remove(0).image, meme, output_settings)
+            .map(|png| (png, "image/png", meme.version.clone()))
+            .map_err(|reason| (500, reason))
+    } else {
+        encode_animation(frames, MAX_ANIMATION_BYTES)
+            .map(|gif| (gif, "image/gif", meme.version.clone()))
+            .map_err(|reason| (400, reason))
+    }
+}
+
+fn handle_request(
+    fonts: &Fonts,
+    memes: &MemeRegistry,
+    output_settings: OutputSettings,
+    mut request: Request,
+) {
+    let path = request.url().split('?').next().unwrap_or("");
+
+    #[cfg(feature = "web-preview")]
+    {
+        if *request.method() == Method::Get && (path == "/" || path == "/preview") {
+            crate::web_preview::handle_request(fonts, memes, request);
+            return;
+        }
+    }
+
+    if path != "/render" {
+        respond_with_error(request, 404, "Not found");
+        return;
+    }
+
+    if *request.method() != Method::Post {
+        respond_with_error(request, 405, "Use POST");
+        return;
+    }
+
+    let mut body = String::new();
+
+    if let Err(reason) = request
+        .as_reader()
+        .take(MAX_BODY_LENGTH + 1)
+        .read_to_string(&mut body)
+    {
+        respond_with_error(request, 400, &format!("Unable to read body: {}", reason));
+        return;
+    }
+
+    if body.len() as u64 > MAX_BODY_LENGTH {
+        respond_with_error(request, 413, "Body is too large");
+        return;
+    }
+
+    match render(fonts, memes, output_settings, &body) {
+        Ok((output, content_type, version)) => {
+            let header = Header::from_bytes("Content-Type", content_type)
+                .expect("HTTP API: Unable to create Content-Type header");
+            let version_header = Header::from_bytes("X-Template-Version", version)
+                .expect("HTTP API: Unable to create X-Template-Version header");
+
+            if let Err(reason) = request.respond(
+                Response::from_data(output)
+                    .with_header(header)
+                    .with_header(version_header),
+            ) {
+                warn!("HTTP API: Unable to send response: {}", reason);
+            }
+        }
+        Err((status, reason)) => respond_with_error(request, status, &reason),
+    }
+}
+
+/// Serves `POST /render`, and the preview page with the `web-preview`
+/// feature, on `address` until the process is stopped
+pub fn serve(
+    address: &str,
+    fonts: Fonts,
+    memes: MemeRegistry,
+    output_settings: OutputSettings,
+) -> Result<(), String> {
+    let server = match Server::http(address) {
+        Ok(server) => server,
+        Err(reason) => return Err(format!("Unable to listen on \"{}\": {}", address, reason)),
+    };
+
+    info!("Serving HTTP API on {}", address);
+
+    for request in server.incoming_requests() {
+        memes.refresh_schedule(Date::today());
+
+        handle_request(&fonts, &memes, output_settings, request);
+    }
+
+    Ok(())
+}
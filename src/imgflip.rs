@@ -0,0 +1,150 @@
+use image::RgbaImage;
+use log::info;
+use serde_json::Value;
+
+use serenity::model::prelude::Message;
+use serenity::prelude::Context;
+
+use crate::decode::{decode_image, MAX_IMAGE_BYTES};
+use crate::fetch;
+use crate::meme_edits::{add_meme, style_parent};
+use crate::redact;
+use crate::registry::Region;
+use crate::split_first_word;
+use crate::wizard::check_command;
+
+const IMGFLIP_MEMES_URL: &str = "https://api.imgflip.com/get_memes";
+
+// The list of templates is about 30 KB
+const MAX_RESPONSE_BYTES: usize = 1024 * 1024;
+
+struct ImgflipTemplate {
+    name: String,
+    url: String,
+    box_count: u32,
+}
+
+fn find_template(template_id: &str) -> Result<ImgflipTemplate, String> {
+    let response = fetch::download(IMGFLIP_MEMES_URL, MAX_RESPONSE_BYTES)?;
+
+    let response: Value = match serde_json::from_slice(&response) {
+        Ok(response) => response,
+        Err(reason) => return Err(format!("Imgflip sent an invalid response: {}", reason)),
+    };
+
+    if response["success"] != Value::Bool(true) {
+        return Err(format!(
+            "Imgflip responded with an error: {}",
+            response["error_message"]
+                .as_str()
+                .unwrap_or("unknown error")
+        ));
+    }
+
+    let templates = match response["data"]["memes"].as_array() {
+        Some(templates) => templates,
+        None => return Err("Imgflip sent an invalid response: no templates".into()),
+    };
+
+    let template = match templates
+        .iter()
+        .find(|template| template["id"].as_str() == Some(template_id))
+    {
+        Some(template) => template,
+        None => {
+            return Err(format!(
+                "Imgflip has no popular template with the ID `{}`.",
+                template_id
+            ))
+        }
+    };
+
+    match (template["name"].as_str(), template["url"].as_str()) {
+        (Some(name), Some(url)) => Ok(ImgflipTemplate {
+            name: name.into(),
+            url: url.into(),
+            box_count: template["box_count"].as_u64().unwrap_or(1).max(1) as u32,
+        }),
+        _ => Err(format!(
+            "Imgflip sent an invalid template for the ID `{}`",
+            template_id
+        )),
+    }
+}
+
+/// Imgflip only says how many text boxes a template has, which it stacks
+/// from top to bottom, so the text goes where the first of them would be
+fn first_box(image: &RgbaImage, box_count: u32) -> Region {
+    let height = image.height() / box_count;
+    let margin_x = image.width() / 20;
+    let margin_y = height / 10;
+
+    Region {
+        left: margin_x,
+        top: margin_y,
+        right: image.width() - margin_x,
+        bottom: height - margin_y,
+    }
+}
+
+fn import_imgflip(
+    ctx: &Context,
+    template_id: &str,
+    command: &str,
+    parent: &str,
+) -> Result<String, String> {
+    check_command(ctx, command)?;
+
+    let parent = style_parent(ctx, parent)?;
+
+    let template = find_template(template_id)?;
+    let image = decode_image(&fetch::download(&template.url, MAX_IMAGE_BYTES)?)?;
+
+    add_meme(
+        ctx,
+        command,
+        &parent,
+        &image,
+        first_box(&image, template.box_count),
+    )?;
+
+    Ok(format!(
+        "Imported \"{}\" as `{}`, in the style of `{}`. Use `setregion` if the text is in the wrong place.",
+        template.name, command, parent
+    ))
+}
+
+pub fn import(ctx: &Context, msg: &Message, rest: &str) {
+    let (source, rest) = split_first_word(rest);
+    let (template_id, rest) = split_first_word(rest);
+    let (command, rest) = split_first_word(rest);
+    let (parent, _) = split_first_word(rest);
+
+    if !source.eq_ignore_ascii_case("imgflip") || template_id.is_empty() || command.is_empty() {
+        msg.channel_id
+            .say(
+                ctx,
+                "Usage: `import imgflip <template ID> <command> [meme to take the style from]`",
+            )
+            .ok();
+        return;
+    }
+
+    let command = command.to_lowercase();
+
+    let reply = match import_imgflip(ctx, template_id, &command, &parent.to_lowercase()) {
+        Ok(reply) => {
+            info!(
+                "Imgflip template {} imported as \"{}\" by {}",
+                template_id,
+                command,
+                redact::user(&msg.author)
+            );
+
+            reply
+        }
+        Err(reason) => reason,
+    };
+
+    msg.channel_id.say(ctx, reply).ok();
+}
@@ -0,0 +1,111 @@
+use log::{debug, info, warn};
+use std::fs::{read_dir, remove_file};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use serenity::prelude::Context;
+
+use crate::usage::UsageCountsKey;
+use crate::voice_memes::{forget_expired_joins, VoiceJoinsKey};
+
+// How often old data is looked for
+const INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How long data is kept before the janitor removes it. Data without a
+/// retention window is kept for as long as the bot runs.
+#[derive(Clone)]
+pub struct RetentionSettings {
+    pub usage: Option<Duration>,
+    pub cache: Option<Duration>,
+    pub cache_dir: String,
+}
+
+/// Removes the files in `dir` that were last written more than `retention`
+/// ago, returning how many were removed and their total size in bytes
+fn remove_old_files(dir: &str, retention: Duration) -> (usize, u64) {
+    let entries = match read_dir(dir) {
+        Ok(entries) => entries,
+        // Nothing has been cached yet
+        Err(_) => return (0, 0),
+    };
+
+    let mut removed = (0, 0);
+
+    for entry in entries.flatten() {
+        let metadata = match entry.metadata() {
+            Ok(metadata) if metadata.is_file() => metadata,
+            _ => continue,
+        };
+
+        let is_old = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+            .is_some_and(|age| age > retention);
+
+        if !is_old {
+            continue;
+        }
+
+        match remove_file(entry.path()) {
+            Ok(()) => {
+                removed.0 += 1;
+                removed.1 += metadata.len();
+            }
+            Err(reason) => warn!(
+                "Janitor: Unable to remove \"{}\": {}",
+                entry.path().display(),
+                reason
+            ),
+        }
+    }
+
+    removed
+}
+
+/// Removes whatever has been kept past its retention window, and logs one line
+/// of `key=value` pairs on how much was removed
+fn clean_up(ctx: &Context, settings: &RetentionSettings) {
+    let data = ctx.data.read();
+
+    let usage_days = match settings.usage {
+        Some(retention) => data
+            .get::<UsageCountsKey>()
+            .expect("Janitor: Unable to retrieve usage counts")
+            .forget_older_than(retention),
+        None => 0,
+    };
+
+    drop(data);
+
+    // Joins are only kept for their cooldown, whatever the retention windows
+    let voice_joins = forget_expired_joins(
+        ctx.data
+            .write()
+            .get_mut::<VoiceJoinsKey>()
+            .expect("Janitor: Unable to retrieve voice joins"),
+    );
+
+    let (cache_files, cache_bytes) = match settings.cache {
+        Some(retention) => remove_old_files(&settings.cache_dir, retention),
+        None => (0, 0),
+    };
+
+    if usage_days == 0 && voice_joins == 0 && cache_files == 0 {
+        debug!("Janitor: Nothing to remove");
+        return;
+    }
+
+    info!(
+        "Janitor: usage_days={} voice_joins={} cache_files={} cache_bytes={}",
+        usage_days, voice_joins, cache_files, cache_bytes
+    );
+}
+
+/// Removes old data every `INTERVAL`
+pub fn start_janitor(ctx: Context, settings: RetentionSettings) {
+    thread::spawn(move || loop {
+        thread::sleep(INTERVAL);
+        clean_up(&ctx, &settings);
+    });
+}
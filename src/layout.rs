@@ -0,0 +1,427 @@
+use rusttype::{Font, Point, Scale};
+use unicode_bidi::{BidiInfo, Level};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Direction {
+    LeftToRight,
+    RightToLeft,
+}
+
+impl Direction {
+    pub fn from_name(name: &str) -> Option<Direction> {
+        match name.to_lowercase().as_str() {
+            "ltr" => Some(Direction::LeftToRight),
+            "rtl" => Some(Direction::RightToLeft),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Direction::LeftToRight => "ltr",
+            Direction::RightToLeft => "rtl",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Alignment {
+    Left,
+    Center,
+    Right,
+    // Left for left-to-right text, right for right-to-left text
+    Start,
+    // Right for left-to-right text, left for right-to-left text
+    End,
+}
+
+impl Alignment {
+    pub fn from_name(name: &str) -> Option<Alignment> {
+        match name.to_lowercase().as_str() {
+            "left" => Some(Alignment::Left),
+            "center" => Some(Alignment::Center),
+            "right" => Some(Alignment::Right),
+            "start" => Some(Alignment::Start),
+            "end" => Some(Alignment::End),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Alignment::Left => "left",
+            Alignment::Center => "center",
+            Alignment::Right => "right",
+            Alignment::Start => "start",
+            Alignment::End => "end",
+        }
+    }
+
+    fn resolve(self, direction: Direction) -> Alignment {
+        match (self, direction) {
+            (Alignment::Start, Direction::LeftToRight) => Alignment::Left,
+            (Alignment::Start, Direction::RightToLeft) => Alignment::Right,
+            (Alignment::End, Direction::LeftToRight) => Alignment::Right,
+            (Alignment::End, Direction::RightToLeft) => Alignment::Left,
+            (alignment, _) => alignment,
+        }
+    }
+}
+
+pub fn get_line_height(font: &Font, scale: Scale) -> u32 {
+    let v_metrics = font.v_metrics(scale);
+
+    (v_metrics.line_gap / 2f32 + v_metrics.ascent - v_metrics.descent) as u32
+}
+
+pub fn get_text_width(font: &Font, text: &str, scale: Scale) -> u32 {
+    // Return the rightmost edge of the last glyph in the text
+    let point = Point { x: 0f32, y: 0f32 };
+
+    let glyph = match font.layout(text, scale, point).last() {
+        Some(glyph) => glyph,
+        None => return 0,
+    };
+
+    match glyph.pixel_bounding_box() {
+        Some(point) => point.max.x as u32,
+        None => 0,
+    }
+}
+
+/// Breaks a word that is too wide to fit on a line by itself into pieces that
+/// each fit, ending every piece but the last with a hyphen. Every piece
+/// contains at least one character, even if that character alone is too wide.
+fn hyphenate(font: &Font, scale: Scale, word: &str, max_width: u32) -> Vec<String> {
+    let mut pieces = Vec::<String>::new();
+    let mut piece = String::new();
+
+    for c in word.chars() {
+        let candidate = format!("{}{}-", piece, c);
+
+        if !piece.is_empty() && get_text_width(font, &candidate, scale) > max_width {
+            pieces.push(piece + "-");
+            piece = String::new();
+        }
+
+        piece.push(c);
+    }
+
+    pieces.push(piece);
+
+    pieces
+}
+
+/// Splits `text` into lines no wider than `max_width`, breaking lines between
+/// words. Words that don't fit on a line by themselves are left to overflow
+/// unless `hyphenate_words` is set, in which case they're broken up.
+pub fn wrap_text(
+    font: &Font,
+    scale: Scale,
+    text: &str,
+    max_width: u32,
+    hyphenate_words: bool,
+) -> Vec<String> {
+    let mut lines = Vec::<String>::new();
+
+    for paragraph in text.lines() {
+        let mut line = String::new();
+
+        for word in paragraph.split_whitespace() {
+            let candidate = if line.is_empty() {
+                word.to_string()
+            } else {
+                format!("{} {}", line, word)
+            };
+
+            if get_text_width(font, &candidate, scale) <= max_width {
+                line = candidate;
+                continue;
+            }
+
+            if !line.is_empty() {
+                lines.push(line);
+            }
+
+            if hyphenate_words && get_text_width(font, word, scale) > max_width {
+                let mut pieces = hyphenate(font, scale, word, max_width);
+
+                line = pieces.pop().unwrap_or_default();
+                lines.extend(pieces);
+            } else {
+                line = word.to_string();
+            }
+        }
+
+        lines.push(line);
+    }
+
+    lines
+}
+
+/// How a piece of text is broken into lines and how much room it takes up
+pub struct TextLayout {
+    pub lines: Vec<String>,
+    // The width of each line, in the same order as `lines`
+    pub line_widths: Vec<u32>,
+    pub line_height: u32,
+    // The width of the widest line
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Lays out `text` the same way the bot does when drawing it. Line breaks in
+/// the text always start a new line. With a `max_width`, words are wrapped
+/// like `wrap_text`; without one, each line is kept exactly as typed,
+/// including runs of spaces, and only trailing spaces are dropped.
+pub fn measure_text(
+    font: &Font,
+    scale: Scale,
+    text: &str,
+    max_width: Option<u32>,
+    hyphenate_words: bool,
+) -> TextLayout {
+    let lines = match max_width {
+        Some(max_width) => wrap_text(font, scale, text, max_width, hyphenate_words),
+        None => text
+            .lines()
+            .map(|line| line.trim_end().to_string())
+            .collect(),
+    };
+    let line_widths: Vec<u32> = lines
+        .iter()
+        .map(|line| get_text_width(font, line, scale))
+        .collect();
+    let line_height = get_line_height(font, scale);
+
+    TextLayout {
+        width: line_widths.iter().copied().max().unwrap_or(0),
+        height: line_height * lines.len() as u32,
+        lines,
+        line_widths,
+        line_height,
+    }
+}
+
+/// Trims the blank lines from the start of `text` and whitespace from its end,
+/// keeping the indentation of its first line with any text, which matters
+/// when lines aren't wrapped
+pub fn trim_blank_lines(text: &str) -> &str {
+    let text = text.trim_end();
+    let content_start = text.len() - text.trim_start().len();
+
+    match text[..content_start].rfind('\n') {
+        Some(newline) => &text[newline + 1..],
+        None => text,
+    }
+}
+
+/// Reorders a line from the order it was typed in into the order its
+/// characters are drawn in from left to right, so that runs of right-to-left
+/// scripts read correctly. `direction` is the base direction of the line,
+/// which decides how runs of different directions are ordered relative to
+/// each other.
+pub fn to_visual_order(line: &str, direction: Direction) -> String {
+    let chars: Vec<char> = line.chars().collect();
+
+    visual_char_order(line, direction)
+        .into_iter()
+        .map(|index| chars[index])
+        .collect()
+}
+
+/// The same as `to_visual_order`, returning the positions in `line` of the
+/// characters in the order they're drawn in rather than the characters
+/// themselves, so that whatever goes with them can be reordered too
+pub fn visual_char_order(line: &str, direction: Direction) -> Vec<usize> {
+    let base_level = match direction {
+        Direction::LeftToRight => Level::ltr(),
+        Direction::RightToLeft => Level::rtl(),
+    };
+
+    let bidi_info = BidiInfo::new(line, Some(base_level));
+
+    // Runs are given as byte ranges
+    let mut char_indices = vec![0; line.len()];
+
+    for (char_index, (byte_index, _)) in line.char_indices().enumerate() {
+        char_indices[byte_index] = char_index;
+    }
+
+    let mut order = Vec::<usize>::with_capacity(char_indices.len());
+
+    for paragraph in &bidi_info.paragraphs {
+        let (levels, runs) = bidi_info.visual_runs(paragraph, paragraph.range.clone());
+
+        for run in runs {
+            let start = run.start;
+            let run_chars = line[run]
+                .char_indices()
+                .map(|(byte_index, _)| char_indices[start + byte_index]);
+
+            if levels[start].is_rtl() {
+                order.extend(run_chars.rev());
+            } else {
+                order.extend(run_chars);
+            }
+        }
+    }
+
+    order
+}
+
+/// Returns the x coordinate at which a line `width` pixels wide starts when it
+/// is aligned within the region between `left` and `right`
+pub fn get_line_x(
+    left: u32,
+    right: u32,
+    width: u32,
+    alignment: Alignment,
+    direction: Direction,
+) -> u32 {
+    match alignment.resolve(direction) {
+        Alignment::Left => left,
+        Alignment::Right => right.saturating_sub(width),
+        _ => ((left + right) / 2).saturating_sub(width / 2),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusttype::FontCollection;
+
+    const SCALE: Scale = Scale { x: 32f32, y: 32f32 };
+
+    /// The first installed font that can be opened
+    fn system_font() -> Option<Font<'static>> {
+        let mut system_fonts = fontdb::Database::new();
+        system_fonts.load_system_fonts();
+
+        let font = system_fonts.faces().find_map(|face| {
+            system_fonts
+                .with_face_data(face.id, |data, index| {
+                    FontCollection::from_bytes(data.to_vec())
+                        .ok()?
+                        .font_at(index as usize)
+                        .ok()
+                })
+                .flatten()
+        });
+
+        font
+    }
+
+    #[test]
+    fn breaks_lines_where_the_text_does() {
+        let font = match system_font() {
+            Some(font) => font,
+            None => {
+                eprintln!("Skipping: no fonts are installed");
+                return;
+            }
+        };
+
+        let text = "top text\n\nbottom text";
+
+        assert_eq!(
+            wrap_text(&font, SCALE, text, 10_000, false),
+            vec!["top text", "", "bottom text"]
+        );
+        assert_eq!(
+            measure_text(&font, SCALE, text, Some(10_000), false).lines,
+            vec!["top text", "", "bottom text"]
+        );
+        assert_eq!(
+            measure_text(&font, SCALE, text, None, false).lines,
+            vec!["top text", "", "bottom text"]
+        );
+    }
+
+    #[test]
+    fn wraps_long_lines_between_words() {
+        let font = match system_font() {
+            Some(font) => font,
+            None => {
+                eprintln!("Skipping: no fonts are installed");
+                return;
+            }
+        };
+
+        let text = "did you just say that the build is green";
+        let max_width = get_text_width(&font, "did you just say", SCALE);
+        let layout = measure_text(&font, SCALE, text, Some(max_width), false);
+
+        assert!(layout.lines.len() > 1);
+        assert_eq!(layout.lines.join(" "), text);
+        assert!(layout.line_widths.iter().all(|width| *width <= max_width));
+        assert_eq!(
+            layout.height,
+            layout.line_height * layout.lines.len() as u32
+        );
+    }
+
+    #[test]
+    fn wraps_each_line_of_the_text_on_its_own() {
+        let font = match system_font() {
+            Some(font) => font,
+            None => {
+                eprintln!("Skipping: no fonts are installed");
+                return;
+            }
+        };
+
+        // "say" would fit after "you", but the line break keeps it apart
+        let text = "did you\nsay that";
+        let max_width = get_text_width(&font, "did you say", SCALE);
+
+        assert_eq!(
+            wrap_text(&font, SCALE, text, max_width, false),
+            vec!["did you", "say that"]
+        );
+
+        let max_width = get_text_width(&font, "did", SCALE);
+
+        assert_eq!(
+            wrap_text(&font, SCALE, text, max_width, false),
+            vec!["did", "you", "say", "that"]
+        );
+    }
+
+    #[test]
+    fn keeps_lines_as_typed_without_wrapping() {
+        let font = match system_font() {
+            Some(font) => font,
+            None => {
+                eprintln!("Skipping: no fonts are installed");
+                return;
+            }
+        };
+
+        let long_line = "did  you   just say that the build is green when nobody ran the tests";
+        let text = format!("{}   \n  second", long_line);
+        let layout = measure_text(&font, SCALE, &text, None, false);
+
+        assert_eq!(layout.lines, vec![long_line, "  second"]);
+        assert_eq!(layout.width, get_text_width(&font, long_line, SCALE));
+        assert_eq!(layout.height, layout.line_height * 2);
+
+        // The same text wrapped to a narrower width loses its runs of spaces
+        let wrapped = measure_text(&font, SCALE, &text, Some(layout.width / 2), false);
+
+        assert!(wrapped.lines.len() > 2);
+        assert!(wrapped.width < layout.width);
+    }
+
+    #[test]
+    fn trims_blank_lines() {
+        assert_eq!(trim_blank_lines(""), "");
+        assert_eq!(trim_blank_lines(" \n\t\n  "), "");
+        assert_eq!(trim_blank_lines("text"), "text");
+        assert_eq!(trim_blank_lines("  indented"), "  indented");
+        assert_eq!(
+            trim_blank_lines("\n \n  indented\n\nnext  \n\n"),
+            "  indented\n\nnext"
+        );
+    }
+}
@@ -0,0 +1,7 @@
+//! The parts of the bot that other tools, like config editors and the
+//! rendering benchmarks, can use to lay out, draw, and encode text exactly as
+//! the bot does
+
+pub mod layout;
+pub mod output;
+pub mod raster;
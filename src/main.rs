@@ -1,27 +1,196 @@
 use dotenv::dotenv;
-use image::{Pixel, RgbaImage};
-use imageproc::drawing;
+use image::{Rgba, RgbaImage};
 use log::{debug, error, info, warn};
-use regex::Regex;
-use rusttype::{Font, Point, Scale};
+use regex::{Captures, Regex};
+use rusttype::{Font, Scale};
 use std::collections::HashMap;
-use std::fs::{read_to_string, remove_file, File};
-use std::io::Read;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
+use std::thread;
+use std::time::{Duration, SystemTime};
 use std::{env, process};
-use tempfile::tempdir;
 use yaml_rust::yaml::Yaml;
 use yaml_rust::YamlLoader;
 
+mod about;
+mod added_memes;
+mod admin_sessions;
+mod aliases;
+mod animation;
+mod avatars;
+mod calibrate;
+mod capabilities;
+mod casing;
+mod chat;
+mod code_block;
+mod color;
+mod config_warnings;
+mod decode;
+mod dump_config;
+mod emoji;
+mod emoji_strip;
+mod event_memes;
+mod events;
+mod fact_check;
+mod fetch;
+mod filters;
+mod flags;
+mod fonts;
+mod gallery;
+mod greetings;
+mod guild_settings;
+mod heartbeat;
+mod hotkeys;
+#[cfg(feature = "http-api")]
+mod http_api;
+mod imgflip;
+mod janitor;
+mod markup;
+mod meme_edits;
+mod meme_history;
+mod message_text;
+mod moderation;
+mod pagination;
+mod permissions;
+mod pipeline;
+mod plugins;
+mod poll;
+mod presence;
+mod presets;
+mod purge;
+mod redact;
+mod registry;
+mod render;
+mod schedule;
+mod schema;
+mod search;
+mod storage;
+mod suggest;
+mod timings;
+mod usage;
+mod voice_memes;
+#[cfg(feature = "web-preview")]
+mod web_preview;
+mod welcome_memes;
+mod wizard;
+mod zalgo;
+
+use about::about_command;
+use added_memes::AddedMemesFile;
+use admin_sessions::{AdminSessionStore, AdminSessionsKey};
+use aliases::{aliases_command, resolve_alias};
+use animation::{
+    encode_animation, render_animation, Animation, AnimationFrame, AnimationKind,
+    MAX_ANIMATION_BYTES,
+};
+use arc_swap::ArcSwap;
+use avatars::fetch_avatar;
+use calibrate::{handle_calibration_message, start_calibration, CalibrationsKey};
+use capabilities::{
+    features_command, is_feature, is_feature_enabled, Capability, CapabilitySettings, FEATURES,
+};
+use casing::Transform;
+use chat::Chat;
+use code_block::find_code_block;
+use color::parse_color;
+use decode::decode_image;
+use did_you_just_say_bot::layout::{self, Alignment, Direction};
+use did_you_just_say_bot::raster::{
+    self, clear_glyph_cache, StrokeJoin, TextEffect, TextEffectKind,
+};
+use dump_config::dump_config;
+use emoji::make_emoji;
+use emoji_strip::emoji_strip;
+use events::{event_command, start_events};
+use fact_check::fact_check;
+use fetch::FetchSettings;
+use flags::{peek_seed, take_flags};
+use fonts::FontLoader;
+use gallery::{handle_sync_message, sync, GallerySyncsKey, GALLERY_URL_KEY};
+use greetings::{EasterEggResponse, Greetings, GreetingsKey};
+use guild_settings::{GuildSettingsKey, GuildSettingsStore};
+use heartbeat::start_heartbeat;
+use hotkeys::{handle_hotkey, hotkeys_command};
+use imgflip::import;
+use janitor::{start_janitor, RetentionSettings};
+use meme_edits::{remove_meme, set_region, undo};
+use meme_history::{MemeHistory, MemeHistoryKey};
+use message_text::{attachment_text, replied_text};
+use moderation::WebhookModerator;
+use pagination::{handle_reaction, send_paginated, Paginations, PaginationsKey};
+use permissions::{is_bot_admin, is_guild_admin, is_manager};
+use pipeline::{apply_stages, split_pipeline, STAGES};
+use plugins::apply_text_plugin;
+use poll::poll_meme;
+use presence::{start_presence, BotActivity};
+use presets::{PresetStore, PresetsKey, MAX_PRESETS_PER_USER};
+use purge::{forget_me, purge_user};
+use redact::RedactionSettings;
+use registry::{
+    choose_weighted, configure_accent_folding, fold_command, template_version, AvatarRegion, Meme,
+    MemeRegistry, MemesKey, Region,
+};
+use render::{
+    encode_meme_png, encode_png, fit_to_width, make_thumbnail, render_avatar_meme, render_code,
+    render_meme, OutputSettings,
+};
+use schedule::{Date, Schedule};
+use search::search;
+use storage::{HttpStore, LocalStore, TemplateStore, UploadStore};
+use suggest::{draw_region_preview, suggest_region};
+use timings::Timings;
+use usage::{UsageCounts, UsageCountsKey};
+use voice_memes::{handle_voice_state, voice_memes_command, VoiceJoinsKey};
+use welcome_memes::{handle_member_addition, welcome_command};
+use wizard::{handle_wizard_message, start_wizard, WizardsKey};
+use zalgo::strip_zalgo;
+
 use serenity::client::bridge::gateway::ShardManager;
 use serenity::client::Client;
-use serenity::model::prelude::{Channel, Message, Ready, RoleId};
-use serenity::prelude::{Context, EventHandler, Mutex, TypeMapKey};
+use serenity::model::prelude::{
+    Channel, ChannelId, Guild, GuildId, Member, Message, PremiumTier, Reaction, Ready, RoleId,
+    UserId, VoiceState,
+};
+use serenity::prelude::{Context, EventHandler, Mutex, ShareMap, TypeMapKey};
 
 struct BotSettings {
     id: Option<u64>,
     admin_password: Option<String>,
-    admin_ids: Vec<u64>,
+    // Memes that take longer than this to create are logged as warnings
+    slow_request_budget: Duration,
+    output_settings: OutputSettings,
+    // Terms of use that an administrator of each guild has to accept before
+    // the bot responds there
+    terms: Option<String>,
+    activities: Vec<BotActivity>,
+    activity_interval: Duration,
+    // How often the bot's health is logged, if it is
+    heartbeat_interval: Option<Duration>,
+    // How long usage counts and cached downloads are kept
+    retention: RetentionSettings,
+    // How long replies to mistakes in memes stay up, if they're deleted
+    error_delete_delay: Option<Duration>,
+    // Where memes too large to attach are uploaded instead, if anywhere
+    overflow_store: Option<Arc<UploadStore>>,
+    // The presence, the events, the heartbeat, and the janitor are only
+    // started on the first ready event, not again after reconnecting
+    tasks_started: bool,
+    config_source: ConfigSource,
+    // For private deployments, the only guilds the bot stays in
+    allowed_guilds: Option<Vec<u64>>,
+    // Guilds the bot always leaves
+    denied_guilds: Vec<u64>,
+    // The manifest of the template gallery that `sync` installs from
+    gallery_url: Option<String>,
+}
+
+/// Where the config is read from, kept so that `reload` can read it again
+#[derive(Clone)]
+struct ConfigSource {
+    store: Arc<dyn TemplateStore + Send + Sync>,
+    config_filename: String,
+    greetings_filename: String,
+    added_memes: AddedMemesFile,
+    font_cache_dir: String,
 }
 
 struct BotSettingsKey;
@@ -30,31 +199,20 @@ impl TypeMapKey for BotSettingsKey {
     type Value = BotSettings;
 }
 
-struct FontsKey;
+// How long to wait between posting each message of a broadcast
+const BROADCAST_INTERVAL: Duration = Duration::from_secs(2);
 
-impl TypeMapKey for FontsKey {
-    type Value = HashMap<String, Font<'static>>;
-}
+// How often the memes that are in season are worked out again
+const SCHEDULE_REFRESH_INTERVAL: Duration = Duration::from_secs(10 * 60);
 
-struct Meme {
-    image: RgbaImage,
-    font: String,
-    scale: Scale,
-    left: u32,
-    top: u32,
-    right: u32,
-    bottom: u32,
-    center: Point<u32>,
-    text_prefix: String,
-    text_suffix: String,
-    command: String,
-    is_default: bool,
-}
+// Loaded fonts by name. Each is shared rather than copied, so that it keeps
+// one address for as long as it's in use, which the glyph cache relies on.
+type Fonts = HashMap<String, Arc<Font<'static>>>;
 
-struct MemesKey;
+struct FontsKey;
 
-impl TypeMapKey for MemesKey {
-    type Value = Vec<Meme>;
+impl TypeMapKey for FontsKey {
+    type Value = Fonts;
 }
 
 struct ShardManagerKey;
@@ -69,226 +227,333 @@ struct Command<'a> {
     rest: &'a str,
 }
 
-fn is_command<'a>(ctx: &Context, msg: &'a Message) -> Option<Command<'a>> {
-    // Check whether the message begins with a mention of the bot
-    let data = ctx.data.read();
-    let settings = data
-        .get::<BotSettingsKey>()
-        .expect("is_command(): Unable to retrieve bot settings");
-    let bot_user_id = settings
-        .id
-        .expect("is_command(): Unable to retrieve bot user ID");
-
-    let re_pattern = format!(r"^<@!?{}>\s*(\S*)\s*((?s).*)$", bot_user_id);
-    let re_command =
-        Regex::new(&re_pattern).expect("Unable to create command matching pattern with bot ID");
-
-    if let Some(captures) = re_command.captures(&msg.content) {
-        return Some(Command {
-            entire:
-                &msg.content[captures.get(1).expect("Unable to extract entire command text from message beginning with bot ID").start()..],
-            first_word: captures.get(1).expect("Unable to extract first word of command text from message beginning with bot ID").as_str(),
-            rest: captures.get(2).expect("Unable to extract rest of command text from message beginning with bot ID").as_str(),
-        });
-    }
-
-    // Check whether this is a DM
-    if let Some(channel) = msg.channel(&ctx) {
-        if channel.private().is_some() {
-            let re_pattern = r"(\S*)\s*((?s).*)";
-            let re_command = Regex::new(re_pattern)
-                .expect("Unable to create command matching pattern without bot ID");
-
-            if let Some(captures) = re_command.captures(&msg.content) {
-                return Some(Command {
-                    entire: captures.get(0).expect("Unable to extract entire command text from message beginning without bot ID").as_str(),
-                    first_word: captures.get(1).expect("Unable to extract first word of command text from message beginning without bot ID").as_str(),
-                    rest: captures.get(2).expect("Unable to extract rest of command text from message beginning without bot ID").as_str(),
-                });
-            }
+impl<'a> Command<'a> {
+    /// Splits the text after the mention (or the whole DM) into the command
+    /// and its text, the same way for both, without the whitespace around them
+    fn parse(text: &'a str) -> Command<'a> {
+        let entire = text.trim();
+        let (first_word, rest) = split_first_word(entire);
+
+        Command {
+            entire,
+            first_word,
+            rest,
         }
     }
-
-    return None;
 }
 
-fn expand_mentions(ctx: &Context, msg: &Message, mut text: String) -> String {
-    let re_user = Regex::new(r"<@!?(\d{1,32})>").expect("Unable to create user matching pattern");
+// Every message is checked for a mention, and mentions are replaced in the
+// text of every meme, so their patterns are only created once
+static RE_LEADING_MENTION: OnceLock<Regex> = OnceLock::new();
+static RE_USER_MENTION: OnceLock<Regex> = OnceLock::new();
+static RE_CHANNEL_MENTION: OnceLock<Regex> = OnceLock::new();
+static RE_ROLE_MENTION: OnceLock<Regex> = OnceLock::new();
+static RE_EMOJI: OnceLock<Regex> = OnceLock::new();
+
+/// Returns the text after the mention of the bot that `content` begins with,
+/// if it begins with one
+fn after_bot_mention(content: &str, bot_user_id: u64) -> Option<&str> {
+    // The mention has a `!` in it when the bot has a nickname in the server
+    let re_mention = RE_LEADING_MENTION.get_or_init(|| {
+        Regex::new(r"^\s*<@!?(\d{1,32})>").expect("Unable to create command matching pattern")
+    });
+
+    let mention = re_mention.captures(content)?;
+
+    if mention[1] != bot_user_id.to_string() {
+        return None;
+    }
 
-    while let Some(mention) = re_user.captures(&text) {
-        let entire_mention = mention
-            .get(0)
-            .expect("Unable to get entire user mention")
-            .as_str();
-        let mentioned_id = mention
-            .get(1)
-            .expect("Unable extract user ID from mention")
-            .as_str();
+    Some(&content[mention[0].len()..])
+}
 
-        let id = mentioned_id.parse::<u64>().unwrap_or(0);
+/// Parses `content` as a command if it begins with a mention of the bot, or
+/// if it's in a DM, where the mention can be left out. Both are parsed with
+/// `Command::parse`, so they're tokenized the same way.
+fn parse_command(
+    content: &str,
+    bot_user_id: u64,
+    is_private: impl FnOnce() -> bool,
+) -> Option<Command<'_>> {
+    if let Some(text) = after_bot_mention(content, bot_user_id) {
+        return Some(Command::parse(text));
+    }
 
-        if let Some(user) = msg.mentions.iter().filter(|u| u.id.0 == id).next() {
-            text = text.replace(entire_mention, format!("@{}", user.name).as_str());
-        } else {
-            text = text.replace(entire_mention, format!("@{}", mentioned_id).as_str());
-        }
+    if is_private() {
+        return Some(Command::parse(content));
     }
 
-    let re_channel =
-        Regex::new(r"<#(\d{1,32})>").expect("Unable to create channel matching pattern");
+    None
+}
 
-    let re_role = Regex::new(r"<@&(\d{1,32})>").expect("Unable to create role matching pattern");
+fn bot_user_id(ctx: &Context) -> u64 {
+    ctx.data
+        .read()
+        .get::<BotSettingsKey>()
+        .expect("bot_user_id(): Unable to retrieve bot settings")
+        .id
+        .expect("bot_user_id(): Unable to retrieve bot user ID")
+}
 
-    let mut guild_found = false;
+fn is_command<'a>(ctx: &Context, msg: &'a Message) -> Option<Command<'a>> {
+    let bot_user_id = bot_user_id(ctx);
 
-    if let Some(channel) = msg.channel(ctx) {
-        if let Channel::Guild(channel) = channel {
-            if let Some(guild) = channel.read().guild(&ctx) {
-                guild_found = true;
+    // Only looked up for messages that don't mention the bot
+    let is_private = || {
+        msg.channel(ctx)
+            .is_some_and(|channel| channel.private().is_some())
+    };
 
-                let channels = &guild.read().channels;
+    parse_command(&msg.content, bot_user_id, is_private)
+}
 
-                while let Some(mention) = re_channel.captures(&text) {
-                    let entire_mention = mention
-                        .get(0)
-                        .expect("Unable to get entire channel mention")
-                        .as_str();
-                    let mentioned_id = mention
-                        .get(1)
-                        .expect("Unable extract channel ID from mention")
-                        .as_str();
+/// Returns what to handle in place of the content of `msg` when Discord has
+/// moved its text into a message.txt attachment for being too long. The
+/// attachment is only read with `read_attachment` when the message is meant
+/// for the bot: either a DM with nothing else in it, or a message that's only
+/// a mention of the bot, so that long messages in channels the bot can merely
+/// see are never downloaded.
+fn long_message_content(
+    msg: &Message,
+    bot_user_id: u64,
+    is_private: impl FnOnce() -> bool,
+    read_attachment: impl FnOnce(&Message) -> Option<String>,
+) -> Option<String> {
+    if !msg
+        .attachments
+        .iter()
+        .any(|attachment| attachment.filename == "message.txt")
+    {
+        return None;
+    }
 
-                    let id = mentioned_id.parse::<u64>().unwrap_or(0);
+    let content = msg.content.trim();
 
-                    if let Some(channel) = channels.keys().filter(|c| *c.as_u64() == id).next() {
-                        text = text.replace(
-                            entire_mention,
-                            format!("#{}", channel.name(&ctx).unwrap_or(format!("{}", id)))
-                                .as_str(),
-                        );
-                    } else {
-                        text = text.replace(entire_mention, "#deleted-channel");
-                    }
-                }
+    let mention = match after_bot_mention(content, bot_user_id) {
+        Some(rest) if rest.trim().is_empty() => Some(content),
+        None if content.is_empty() && is_private() => None,
+        _ => return None,
+    };
 
-                let roles = &guild.read().roles;
+    let text = read_attachment(msg)?;
 
-                while let Some(mention) = re_role.captures(&text) {
-                    let entire_mention = mention
-                        .get(0)
-                        .expect("Unable to get entire role mention")
-                        .as_str();
-                    let mentioned_id = mention
-                        .get(1)
-                        .expect("Unable extract role ID from mention")
-                        .as_str();
+    // The text is addressed to the bot like the message was, unless it
+    // mentions the bot itself
+    match mention {
+        Some(mention) if after_bot_mention(&text, bot_user_id).is_none() => {
+            Some(format!("{} {}", mention, text))
+        }
+        _ => Some(text),
+    }
+}
 
-                    let id = mentioned_id.parse::<u64>().unwrap_or(0);
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum MentionKind {
+    User,
+    Channel,
+    Role,
+}
 
-                    if let Some(role) = roles.get(&RoleId(id)) {
-                        text = text.replace(entire_mention, format!("@{}", role.name).as_str());
-                    } else {
-                        text = text.replace(entire_mention, "@deleted-role");
-                    }
-                }
-            }
+/// Replaces the mentions in `text` with the names that `name_of` finds for
+/// their IDs. Users that aren't found are left as their IDs, and channels and
+/// roles as deleted ones. Custom emoji are replaced with their names.
+fn replace_mentions(text: &str, name_of: impl Fn(MentionKind, u64) -> Option<String>) -> String {
+    let re_user = RE_USER_MENTION.get_or_init(|| {
+        Regex::new(r"<@!?(\d{1,32})>").expect("Unable to create user matching pattern")
+    });
+    let re_channel = RE_CHANNEL_MENTION.get_or_init(|| {
+        Regex::new(r"<#(\d{1,32})>").expect("Unable to create channel matching pattern")
+    });
+    let re_role = RE_ROLE_MENTION.get_or_init(|| {
+        Regex::new(r"<@&(\d{1,32})>").expect("Unable to create role matching pattern")
+    });
+    let re_emoji = RE_EMOJI.get_or_init(|| {
+        Regex::new(r"<a?(:[a-zA-Z0-9~_]+:)\d{1,32}>")
+            .expect("Unable to create emoji matching pattern")
+    });
+
+    // Each kind of mention is replaced in a single pass, since the replacements
+    // (like role names) can themselves look like mentions. IDs too large for a
+    // u64 can't belong to anything.
+    let id = |mention: &Captures| mention[1].parse::<u64>().unwrap_or(0);
+
+    let text = re_user.replace_all(text, |mention: &Captures| {
+        match name_of(MentionKind::User, id(mention)) {
+            Some(name) => format!("@{}", name),
+            None => format!("@{}", &mention[1]),
         }
-    }
+    });
 
-    if !guild_found {
-        while let Some(mention) = re_channel.find(&text) {
-            text = text.replace(mention.as_str(), format!("#deleted-channel").as_str());
+    let text = re_channel.replace_all(&text, |mention: &Captures| {
+        match name_of(MentionKind::Channel, id(mention)) {
+            Some(name) => format!("#{}", name),
+            None => "#deleted-channel".into(),
         }
+    });
 
-        while let Some(mention) = re_role.find(&text) {
-            text = text.replace(mention.as_str(), format!("@deleted-role").as_str());
+    let text = re_role.replace_all(&text, |mention: &Captures| {
+        match name_of(MentionKind::Role, id(mention)) {
+            Some(name) => format!("@{}", name),
+            None => "@deleted-role".into(),
         }
-    }
+    });
 
-    let re_emoji = Regex::new(r"<a?(:[a-zA-Z0-9~_]+:)\d{1,32}>")
-        .expect("Unable to create emoji matching pattern");
+    re_emoji.replace_all(&text, "$1").into_owned()
+}
 
-    while let Some(emoji) = re_emoji.captures(&text) {
-        text = text.replace(
-            emoji
-                .get(0)
-                .expect("Unable to get entire emoji match")
-                .as_str(),
-            emoji
-                .get(1)
-                .expect("Unable to extract emoji name from match")
-                .as_str(),
-        );
-    }
+fn expand_mentions(ctx: &Context, msg: &Message, text: String) -> String {
+    let guild = match msg.channel(ctx) {
+        Some(Channel::Guild(channel)) => channel.read().guild(ctx),
+        _ => None,
+    };
 
-    text.into()
+    let guild = guild.as_ref().map(|guild| guild.read());
+
+    replace_mentions(&text, |kind, id| match kind {
+        MentionKind::User => msg
+            .mentions
+            .iter()
+            .find(|user| user.id.0 == id)
+            .map(|user| user.name.clone()),
+        MentionKind::Channel => guild
+            .as_ref()?
+            .channels
+            .keys()
+            .find(|channel| *channel.as_u64() == id)
+            .map(|channel| channel.name(ctx).unwrap_or(format!("{}", id))),
+        MentionKind::Role => guild
+            .as_ref()?
+            .roles
+            .get(&RoleId(id))
+            .map(|role| role.name.clone()),
+    })
 }
 
-fn load_font(filename: &str) -> Result<Font<'static>, String> {
-    let mut font_file = match File::open(&filename) {
-        Ok(file) => file,
+fn load_image(store: &dyn TemplateStore, filename: &str) -> Result<RgbaImage, String> {
+    let image = match decode_image(&store.read(filename)?) {
+        Ok(image) => image,
         Err(reason) => {
-            return Err(format!("Unable to open file \"{}\": {}", filename, reason));
+            return Err(format!("Unable to open image {}: {}", filename, reason));
         }
     };
 
-    let mut buffer = Vec::new();
-
-    if let Err(reason) = font_file.read_to_end(&mut buffer) {
-        return Err(format!("Unable to read file \"{}\": {}", filename, reason));
-    }
+    Ok(image)
+}
 
-    let font = match Font::from_bytes(buffer) {
-        Ok(font) => font,
-        Err(reason) => {
-            return Err(format!("Unable to open font \"{}\": {}", filename, reason));
+fn parse_avatar_region(value: &Yaml) -> Option<AvatarRegion> {
+    let hash = match value {
+        Yaml::Hash(hash) => hash,
+        _ => {
+            warn!("Config contains invalid avatar region \"{:?}\"", value);
+            return None;
         }
     };
 
-    Ok(font)
-}
+    let mut coordinates = [None; 4];
+    let mut label: Option<String> = None;
+
+    for (key, value) in hash {
+        let index = match key.as_str() {
+            Some("left") => 0,
+            Some("top") => 1,
+            Some("right") => 2,
+            Some("bottom") => 3,
+            Some("label") => {
+                if let Yaml::String(value) = value {
+                    label = Some(value.clone());
+                } else {
+                    warn!(
+                        "Config contains invalid value for avatar label \"{:?}\"",
+                        value
+                    );
+                }
+                continue;
+            }
+            _ => {
+                warn!("Config contains unknown avatar region key {:?}", key);
+                continue;
+            }
+        };
 
-fn load_image(filename: &str) -> Result<RgbaImage, String> {
-    let image = match image::open(filename) {
-        Ok(image) => image.to_rgba(),
-        Err(reason) => {
-            return Err(format!("Unable to open image {}: {}", filename, reason));
+        match value {
+            Yaml::Integer(coordinate) if *coordinate >= 0 => {
+                coordinates[index] = Some(*coordinate as u32);
+            }
+            _ => {
+                warn!(
+                    "Config contains invalid avatar region coordinate \"{:?}\"",
+                    value
+                );
+            }
         }
-    };
+    }
 
-    return Ok(image);
+    match coordinates {
+        [Some(left), Some(top), Some(right), Some(bottom)] if left < right && top < bottom => {
+            Some(AvatarRegion {
+                region: Region {
+                    left,
+                    top,
+                    right,
+                    bottom,
+                },
+                label,
+            })
+        }
+        _ => {
+            warn!("Config contains an avatar region without a valid left, top, right, and bottom; skipping");
+            None
+        }
+    }
 }
 
-fn load_memes(filename: &str) -> (HashMap<String, Font<'static>>, Vec<Meme>) {
-    let mut fonts = HashMap::<String, Font<'static>>::new();
+fn load_memes(
+    store: &dyn TemplateStore,
+    filename: &str,
+    added_memes: &AddedMemesFile,
+    font_cache_dir: &str,
+) -> Result<(Fonts, Vec<Meme>), String> {
+    let mut font_loader = FontLoader::new(store, font_cache_dir);
+    let mut fonts = Fonts::new();
     let mut memes = Vec::<Meme>::new();
 
-    let config = match read_to_string(&filename) {
-        Ok(contents) => contents,
-        Err(reason) => {
-            error!("Unable to read config file \"{}\": {}", filename, reason);
-            process::exit(1);
+    let config = match store.read(filename).map(String::from_utf8) {
+        Ok(Ok(contents)) => contents,
+        Ok(Err(reason)) => {
+            return Err(format!(
+                "Unable to read config file \"{}\": {}",
+                filename, reason
+            ));
         }
+        Err(reason) => return Err(format!("Unable to read config file: {}", reason)),
     };
 
     let yaml = match YamlLoader::load_from_str(&config) {
         Ok(yaml) => yaml,
         Err(reason) => {
-            error!("Unable to parse config file \"{}\": {}", filename, reason);
-            process::exit(1);
+            return Err(format!(
+                "Unable to parse config file \"{}\": {}",
+                filename, reason
+            ));
         }
     };
 
     let yaml = match yaml.first() {
         Some(yaml) => yaml,
-        _ => {
-            error!("Empty config file");
-            process::exit(1);
-        }
+        _ => return Err("Empty config file".into()),
     };
 
+    let added_sections = added_memes.read_sections()?;
+    let local_store = LocalStore;
+
     if let Yaml::Array(meme_sections) = yaml {
-        for meme_section in meme_sections {
+        let sections = meme_sections.iter().map(|section| (store, section)).chain(
+            added_sections
+                .iter()
+                .map(|section| (&local_store as &dyn TemplateStore, section)),
+        );
+
+        for (section_store, meme_section) in sections {
             if let Yaml::Hash(hash) = meme_section {
                 let mut read_image_filename: Option<&str> = None;
                 let mut read_font_filename: Option<String> = None;
@@ -299,8 +564,39 @@ fn load_memes(filename: &str) -> (HashMap<String, Font<'static>>, Vec<Meme>) {
                 let mut read_bottom: Option<u32> = None;
                 let mut read_text_prefix: Option<&str> = None;
                 let mut read_text_suffix: Option<&str> = None;
+                let mut read_transform: Option<Transform> = None;
+                let mut read_transform_seed: Option<u64> = None;
                 let mut read_command: Option<&str> = None;
                 let mut read_is_default: Option<bool> = None;
+                let mut read_category: Option<&str> = None;
+                let mut read_description: Option<&str> = None;
+                let mut read_license: Option<&str> = None;
+                let mut read_source_url: Option<&str> = None;
+                let mut read_hyphenate: Option<bool> = None;
+                let mut read_allow_zalgo: Option<bool> = None;
+                let mut read_direction: Option<Direction> = None;
+                let mut read_alignment: Option<Alignment> = None;
+                let mut read_text_color: Option<Rgba<u8>> = None;
+                let mut read_stroke_width: Option<u32> = None;
+                let mut read_stroke_color: Option<Rgba<u8>> = None;
+                let mut read_stroke_join: Option<StrokeJoin> = None;
+                let mut read_effect: Option<TextEffectKind> = None;
+                let mut read_code_font: Option<String> = None;
+                let mut read_code_color: Option<Rgba<u8>> = None;
+                let mut read_code_background: Option<Rgba<u8>> = None;
+                let mut read_effect_amplitude: Option<f32> = None;
+                let mut read_effect_frequency: Option<f32> = None;
+                let mut read_animate: Option<Option<AnimationKind>> = None;
+                let mut read_animate_duration_ms: Option<u32> = None;
+                let mut read_animate_fps: Option<u32> = None;
+                let mut read_plugin: Option<String> = None;
+                let mut read_avatar_regions = Vec::<AvatarRegion>::new();
+                let mut read_remove_avatar_background: Option<bool> = None;
+                let mut read_extends: Option<&str> = None;
+                let mut read_output_max_width: Option<u32> = None;
+                let mut read_weight: Option<u32> = None;
+                let mut read_active_from: Option<&str> = None;
+                let mut read_active_until: Option<&str> = None;
 
                 for (key, value) in hash {
                     let key = match key {
@@ -335,6 +631,45 @@ fn load_memes(filename: &str) -> (HashMap<String, Font<'static>>, Vec<Meme>) {
                                 );
                             }
                         }
+                        "code_font" => {
+                            if let Yaml::String(code_font) = value {
+                                read_code_font = Some(code_font.trim().into());
+                            } else {
+                                warn!(
+                                    "Config contains invalid value for code font filename \"{:?}\"",
+                                    value
+                                );
+                            }
+                        }
+                        "code_color" => match value.as_str().map(parse_color) {
+                            Some(Ok(code_color)) => read_code_color = Some(code_color),
+                            Some(Err(reason)) => {
+                                warn!("Config contains invalid value for code_color: {}", reason);
+                            }
+                            None => {
+                                warn!(
+                                    "Config contains invalid value for code_color \"{:?}\"",
+                                    value
+                                );
+                            }
+                        },
+                        "code_background" => match value.as_str().map(parse_color) {
+                            Some(Ok(code_background)) => {
+                                read_code_background = Some(code_background)
+                            }
+                            Some(Err(reason)) => {
+                                warn!(
+                                    "Config contains invalid value for code_background: {}",
+                                    reason
+                                );
+                            }
+                            None => {
+                                warn!(
+                                    "Config contains invalid value for code_background \"{:?}\"",
+                                    value
+                                );
+                            }
+                        },
                         "font_size" => {
                             let mut valid_value_found = false;
 
@@ -355,8 +690,9 @@ fn load_memes(filename: &str) -> (HashMap<String, Font<'static>>, Vec<Meme>) {
                         "left" => {
                             let mut valid_value_found = false;
 
+                            // The text can start at the very edge of the image
                             if let Yaml::Integer(left) = value {
-                                if *left > 0 {
+                                if *left >= 0 {
                                     read_left = Some(*left as u32);
                                     valid_value_found = true;
                                 }
@@ -370,7 +706,7 @@ fn load_memes(filename: &str) -> (HashMap<String, Font<'static>>, Vec<Meme>) {
                             let mut valid_value_found = false;
 
                             if let Yaml::Integer(top) = value {
-                                if *top > 0 {
+                                if *top >= 0 {
                                     read_top = Some(*top as u32);
                                     valid_value_found = true;
                                 }
@@ -442,398 +778,4166 @@ fn load_memes(filename: &str) -> (HashMap<String, Font<'static>>, Vec<Meme>) {
                                 warn!("Config contains invalid value for default \"{:?}\"", value);
                             }
                         }
-                        unknown_key => {
-                            warn!("Config contains unknown key {}", unknown_key);
+                        "category" => {
+                            if let Yaml::String(category) = value {
+                                read_category = Some(category);
+                            } else {
+                                warn!("Config contains invalid value for category \"{:?}\"", value);
+                            }
                         }
-                    }
-                }
+                        "description" => {
+                            if let Yaml::String(description) = value {
+                                read_description = Some(description);
+                            } else {
+                                warn!(
+                                    "Config contains invalid value for description \"{:?}\"",
+                                    value
+                                );
+                            }
+                        }
+                        "license" => {
+                            if let Yaml::String(license) = value {
+                                read_license = Some(license);
+                            } else {
+                                warn!("Config contains invalid value for license \"{:?}\"", value);
+                            }
+                        }
+                        "source_url" => {
+                            if let Yaml::String(source_url) = value {
+                                read_source_url = Some(source_url);
+                            } else {
+                                warn!(
+                                    "Config contains invalid value for source_url \"{:?}\"",
+                                    value
+                                );
+                            }
+                        }
+                        "allow_zalgo" => {
+                            if let Yaml::Boolean(allow_zalgo) = value {
+                                read_allow_zalgo = Some(*allow_zalgo);
+                            } else {
+                                warn!(
+                                    "Config contains invalid value for allow_zalgo \"{:?}\"",
+                                    value
+                                );
+                            }
+                        }
+                        "hyphenate" => {
+                            if let Yaml::Boolean(hyphenate) = value {
+                                read_hyphenate = Some(*hyphenate);
+                            } else {
+                                warn!(
+                                    "Config contains invalid value for hyphenate \"{:?}\"",
+                                    value
+                                );
+                            }
+                        }
+                        "direction" => {
+                            let mut valid_value_found = false;
 
-                if read_image_filename.is_none() {
-                    warn!("Config file is missing an image filename for a meme; skipping");
-                    continue;
-                }
+                            if let Yaml::String(direction) = value {
+                                if let Some(direction) = Direction::from_name(direction) {
+                                    read_direction = Some(direction);
+                                    valid_value_found = true;
+                                }
+                            }
 
-                if read_font_filename.is_none() {
-                    if fonts.is_empty() {
-                        warn!("Config file is missing a font for an image; skipping");
-                    } else {
-                        warn!("Config file is missing a font for an image; using a random font");
-                        read_font_filename = Some(fonts.keys().next().unwrap().trim().into());
-                    }
-                }
+                            if !valid_value_found {
+                                warn!(
+                                    "Config contains invalid value for direction \"{:?}\"",
+                                    value
+                                );
+                            }
+                        }
+                        "align" => {
+                            let mut valid_value_found = false;
 
-                let image_filename = read_image_filename.unwrap().trim();
+                            if let Yaml::String(alignment) = value {
+                                if let Some(alignment) = Alignment::from_name(alignment) {
+                                    read_alignment = Some(alignment);
+                                    valid_value_found = true;
+                                }
+                            }
 
-                let image = match load_image(&image_filename) {
-                    Ok(image) => image,
-                    Err(reason) => {
-                        warn!("Unable to load image \"{}\": {}", image_filename, reason);
-                        continue;
-                    }
-                };
+                            if !valid_value_found {
+                                warn!("Config contains invalid value for align \"{:?}\"", value);
+                            }
+                        }
+                        "stroke_width" => {
+                            let mut valid_value_found = false;
 
-                let font_name = read_font_filename.unwrap();
+                            if let Yaml::Integer(stroke_width) = value {
+                                if *stroke_width >= 0 {
+                                    read_stroke_width = Some(*stroke_width as u32);
+                                    valid_value_found = true;
+                                }
+                            }
 
-                if !fonts.contains_key(&font_name) {
-                    match load_font(&font_name) {
-                        Ok(font) => {
-                            fonts.insert(font_name.clone(), font);
-                        }
-                        Err(reason) => {
-                            warn!("Unable to load font \"{}\": {}", font_name, reason);
+                            if !valid_value_found {
+                                warn!(
+                                    "Config contains invalid value for stroke_width: \"{:?}\"",
+                                    value
+                                );
+                            }
                         }
-                    }
-                }
-
-                let font_size = read_font_size.unwrap_or(12);
-                let scale = Scale {
-                    x: font_size as f32,
-                    y: font_size as f32,
-                };
-                let left = read_left.unwrap_or(0);
-                let top = read_top.unwrap_or(0);
-                let right = read_right.unwrap_or(image.width());
-                let bottom = read_bottom.unwrap_or(image.height());
-                let center = Point {
-                    x: (left + right) / 2,
-                    y: (top + bottom) / 2,
-                };
-                let text_prefix = read_text_prefix.clone().unwrap_or("").into();
-                let text_suffix = read_text_suffix.clone().unwrap_or("").into();
-                let command = read_command.clone().unwrap_or("_default").into();
-                let is_default = read_is_default.unwrap_or(false);
+                        "stroke_join" => {
+                            let mut valid_value_found = false;
 
-                memes.push(Meme {
-                    image,
-                    font: font_name.into(),
-                    scale,
-                    left,
-                    top,
-                    right,
-                    bottom,
-                    center,
-                    text_prefix,
-                    text_suffix,
-                    command,
-                    is_default,
-                });
-            } else {
-                warn!("Config contains invalid content");
-            }
-        }
-    } else {
-        error!("Config file does not appear to contain any meme data or is malformed");
-        process::exit(1);
-    }
+                            if let Yaml::String(stroke_join) = value {
+                                if let Some(stroke_join) = StrokeJoin::from_name(stroke_join) {
+                                    read_stroke_join = Some(stroke_join);
+                                    valid_value_found = true;
+                                }
+                            }
 
-    (fonts, memes)
-}
+                            if !valid_value_found {
+                                warn!(
+                                    "Config contains invalid value for stroke_join \"{:?}\"",
+                                    value
+                                );
+                            }
+                        }
+                        "transform" => {
+                            let mut valid_value_found = false;
 
-fn get_line_height(font: &Font, scale: Scale) -> u32 {
-    let v_metrics = font.v_metrics(scale);
+                            if let Yaml::String(transform) = value {
+                                if let Some(transform) = Transform::from_name(transform) {
+                                    read_transform = Some(transform);
+                                    valid_value_found = true;
+                                }
+                            }
 
-    (v_metrics.line_gap / 2f32 + v_metrics.ascent - v_metrics.descent) as u32
-}
+                            if !valid_value_found {
+                                warn!(
+                                    "Config contains invalid value for transform \"{:?}\"",
+                                    value
+                                );
+                            }
+                        }
+                        "transform_seed" => {
+                            let mut valid_value_found = false;
 
-fn get_text_width(font: &Font, text: &str, scale: Scale) -> u32 {
-    // Return the rightmost edge of the last glyph in the text
-    let point = Point { x: 0f32, y: 0f32 };
+                            if let Yaml::Integer(transform_seed) = value {
+                                if *transform_seed >= 0 {
+                                    read_transform_seed = Some(*transform_seed as u64);
+                                    valid_value_found = true;
+                                }
+                            }
 
-    let glyph = match font.layout(text, scale, point).last() {
-        Some(glyph) => glyph,
-        None => return 0,
-    };
+                            if !valid_value_found {
+                                warn!(
+                                    "Config contains invalid value for transform_seed: \"{:?}\"",
+                                    value
+                                );
+                            }
+                        }
+                        "effect" => {
+                            let mut valid_value_found = false;
 
-    match glyph.pixel_bounding_box() {
-        Some(point) => point.max.x as u32,
-        None => return 0,
-    }
-}
+                            if let Yaml::String(effect) = value {
+                                if let Some(effect) = TextEffectKind::from_name(effect) {
+                                    read_effect = Some(effect);
+                                    valid_value_found = true;
+                                }
+                            }
+
+                            if !valid_value_found {
+                                warn!("Config contains invalid value for effect \"{:?}\"", value);
+                            }
+                        }
+                        "effect_amplitude" => {
+                            // Amplitudes can be fractional, as `dumpconfig` writes them
+                            let effect_amplitude = match value {
+                                Yaml::Integer(effect_amplitude) => Some(*effect_amplitude as f32),
+                                Yaml::Real(_) => value.as_f64().map(|amplitude| amplitude as f32),
+                                _ => None,
+                            };
+
+                            match effect_amplitude {
+                                Some(effect_amplitude) if effect_amplitude.is_finite() => {
+                                    read_effect_amplitude = Some(effect_amplitude);
+                                }
+                                _ => {
+                                    warn!(
+                                        "Config contains invalid value for effect_amplitude: \"{:?}\"",
+                                        value
+                                    );
+                                }
+                            }
+                        }
+                        "effect_frequency" => {
+                            let effect_frequency = match value {
+                                Yaml::Integer(effect_frequency) => Some(*effect_frequency as f32),
+                                Yaml::Real(_) => value.as_f64().map(|frequency| frequency as f32),
+                                _ => None,
+                            };
+
+                            match effect_frequency {
+                                Some(effect_frequency) if effect_frequency > 0f32 => {
+                                    read_effect_frequency = Some(effect_frequency);
+                                }
+                                _ => {
+                                    warn!(
+                                        "Config contains invalid value for effect_frequency: \"{:?}\"",
+                                        value
+                                    );
+                                }
+                            }
+                        }
+                        "animate" => match value.as_str().map(AnimationKind::from_name) {
+                            Some(Ok(animate)) => read_animate = Some(animate),
+                            _ => {
+                                warn!("Config contains invalid value for animate \"{:?}\"", value);
+                            }
+                        },
+                        "animate_duration_ms" => {
+                            let mut valid_value_found = false;
+
+                            if let Yaml::Integer(animate_duration_ms) = value {
+                                if (100..=10_000).contains(animate_duration_ms) {
+                                    read_animate_duration_ms = Some(*animate_duration_ms as u32);
+                                    valid_value_found = true;
+                                }
+                            }
+
+                            if !valid_value_found {
+                                warn!(
+                                    "Config contains invalid value for animate_duration_ms: \"{:?}\"",
+                                    value
+                                );
+                            }
+                        }
+                        "animate_fps" => {
+                            let mut valid_value_found = false;
+
+                            if let Yaml::Integer(animate_fps) = value {
+                                if (1..=50).contains(animate_fps) {
+                                    read_animate_fps = Some(*animate_fps as u32);
+                                    valid_value_found = true;
+                                }
+                            }
+
+                            if !valid_value_found {
+                                warn!(
+                                    "Config contains invalid value for animate_fps: \"{:?}\"",
+                                    value
+                                );
+                            }
+                        }
+                        "avatar_regions" => {
+                            if let Yaml::Array(avatar_regions) = value {
+                                read_avatar_regions = avatar_regions
+                                    .iter()
+                                    .filter_map(parse_avatar_region)
+                                    .collect();
+                            } else {
+                                warn!(
+                                    "Config contains invalid value for avatar_regions \"{:?}\"",
+                                    value
+                                );
+                            }
+                        }
+                        "remove_avatar_background" => {
+                            if let Yaml::Boolean(remove_avatar_background) = value {
+                                read_remove_avatar_background = Some(*remove_avatar_background);
+                            } else {
+                                warn!(
+                                    "Config contains invalid value for remove_avatar_background \"{:?}\"",
+                                    value
+                                );
+                            }
+                        }
+                        "extends" => {
+                            if let Yaml::String(extends) = value {
+                                read_extends = Some(extends);
+                            } else {
+                                warn!("Config contains invalid value for extends \"{:?}\"", value);
+                            }
+                        }
+                        "plugin" => match value.as_str().map(str::trim) {
+                            Some(plugin) if plugin.ends_with(".wasm") => {
+                                warn!(
+                                    "Config contains WASM plugin \"{}\", but only commands are supported",
+                                    plugin
+                                );
+                            }
+                            Some(plugin) if !plugin.is_empty() => {
+                                read_plugin = Some(plugin.into());
+                            }
+                            _ => {
+                                warn!("Config contains invalid value for plugin \"{:?}\"", value);
+                            }
+                        },
+                        "output_max_width" => {
+                            let mut valid_value_found = false;
+
+                            if let Yaml::Integer(output_max_width) = value {
+                                if *output_max_width > 0 {
+                                    read_output_max_width = Some(*output_max_width as u32);
+                                    valid_value_found = true;
+                                }
+                            }
+
+                            if !valid_value_found {
+                                warn!(
+                                    "Config contains invalid value for output_max_width: \"{:?}\"",
+                                    value
+                                );
+                            }
+                        }
+                        "active_from" => {
+                            if let Yaml::String(active_from) = value {
+                                read_active_from = Some(active_from);
+                            } else {
+                                warn!(
+                                    "Config contains invalid value for active_from \"{:?}\"",
+                                    value
+                                );
+                            }
+                        }
+                        "active_until" => {
+                            if let Yaml::String(active_until) = value {
+                                read_active_until = Some(active_until);
+                            } else {
+                                warn!(
+                                    "Config contains invalid value for active_until \"{:?}\"",
+                                    value
+                                );
+                            }
+                        }
+                        "text_color" => match value.as_str().map(parse_color) {
+                            Some(Ok(text_color)) => read_text_color = Some(text_color),
+                            Some(Err(reason)) => {
+                                warn!("Config contains invalid value for text_color: {}", reason);
+                            }
+                            None => {
+                                warn!(
+                                    "Config contains invalid value for text_color \"{:?}\"",
+                                    value
+                                );
+                            }
+                        },
+                        "stroke_color" => match value.as_str().map(parse_color) {
+                            Some(Ok(stroke_color)) => read_stroke_color = Some(stroke_color),
+                            Some(Err(reason)) => {
+                                warn!("Config contains invalid value for stroke_color: {}", reason);
+                            }
+                            None => {
+                                warn!(
+                                    "Config contains invalid value for stroke_color \"{:?}\"",
+                                    value
+                                );
+                            }
+                        },
+                        "weight" => {
+                            let mut valid_value_found = false;
+
+                            if let Yaml::Integer(weight) = value {
+                                if *weight > 0 {
+                                    read_weight = Some(*weight as u32);
+                                    valid_value_found = true;
+                                }
+                            }
+
+                            if !valid_value_found {
+                                warn!("Config contains invalid value for weight: \"{:?}\"", value);
+                            }
+                        }
+                        // Only used by `sync`, which reads it from the added memes
+                        GALLERY_URL_KEY => {}
+                        unknown_key => {
+                            warn!("Config contains unknown key {}", unknown_key);
+                        }
+                    }
+                }
+
+                // Memes can only extend memes defined before them, so that the
+                // parent is already fully loaded
+                let parent = match read_extends.map(|extends| extends.trim()) {
+                    Some(extends) => match memes
+                        .iter()
+                        .find(|meme| fold_command(&meme.command) == fold_command(extends))
+                    {
+                        Some(parent) => Some(parent),
+                        None => {
+                            warn!(
+                                "Config file has a meme extending \"{}\", which is not defined before it; skipping",
+                                extends
+                            );
+                            continue;
+                        }
+                    },
+                    None => None,
+                };
+
+                if read_image_filename.is_none() && parent.is_none() {
+                    warn!("Config file is missing an image filename for a meme; skipping");
+                    continue;
+                }
+
+                if read_font_filename.is_none() {
+                    read_font_filename = parent.map(|parent| parent.font.clone());
+                }
+
+                if read_font_filename.is_none() {
+                    if fonts.is_empty() {
+                        warn!("Config file is missing a font for an image; skipping");
+                    } else {
+                        warn!("Config file is missing a font for an image; using a random font");
+                        read_font_filename = Some(fonts.keys().next().unwrap().trim().into());
+                    }
+                }
+
+                let (image, thumbnail, version, image_filename) =
+                    match (read_image_filename, parent) {
+                        (Some(image_filename), _) => {
+                            let image_filename = image_filename.trim();
+
+                            let image = match load_image(section_store, image_filename) {
+                                Ok(image) => image,
+                                Err(reason) => {
+                                    warn!(
+                                        "Unable to load image \"{}\": {}",
+                                        image_filename, reason
+                                    );
+                                    continue;
+                                }
+                            };
+
+                            let thumbnail = match make_thumbnail(&image) {
+                                Ok(thumbnail) => thumbnail,
+                                Err(reason) => {
+                                    warn!(
+                                        "Unable to create thumbnail for \"{}\": {}",
+                                        image_filename, reason
+                                    );
+                                    Vec::new()
+                                }
+                            };
+
+                            let version = template_version(&image);
+
+                            (image, thumbnail, version, image_filename.to_string())
+                        }
+                        (None, Some(parent)) => (
+                            parent.image.clone(),
+                            parent.thumbnail.clone(),
+                            parent.version.clone(),
+                            parent.filename.clone(),
+                        ),
+                        // Memes without an image or a parent were skipped above
+                        (None, None) => continue,
+                    };
+
+                let font_name = read_font_filename.unwrap();
+
+                if !fonts.contains_key(&font_name) {
+                    match font_loader.load(&font_name) {
+                        Ok(font) => {
+                            fonts.insert(font_name.clone(), Arc::new(font));
+                        }
+                        Err(reason) => {
+                            warn!("Unable to load font \"{}\": {}", font_name, reason);
+                        }
+                    }
+                }
+
+                let code_font =
+                    read_code_font.or(parent.and_then(|parent| parent.code_font.clone()));
+
+                if let Some(code_font) = &code_font {
+                    if !fonts.contains_key(code_font) {
+                        match font_loader.load(code_font) {
+                            Ok(font) => {
+                                fonts.insert(code_font.clone(), Arc::new(font));
+                            }
+                            Err(reason) => {
+                                warn!("Unable to load code font \"{}\": {}", code_font, reason);
+                            }
+                        }
+                    }
+                }
+
+                // Anything not set for a meme extending another is inherited
+                // from the parent, except for what identifies the meme itself:
+                // its command, description, and whether it is the default
+                let scale = match (read_font_size, parent) {
+                    (Some(font_size), _) => Scale {
+                        x: font_size as f32,
+                        y: font_size as f32,
+                    },
+                    (None, Some(parent)) => parent.scale,
+                    (None, None) => Scale { x: 12f32, y: 12f32 },
+                };
+                let left = read_left.or(parent.map(|parent| parent.left)).unwrap_or(0);
+                let top = read_top.or(parent.map(|parent| parent.top)).unwrap_or(0);
+                let right = read_right
+                    .or(parent.map(|parent| parent.right))
+                    .unwrap_or(image.width());
+                let bottom = read_bottom
+                    .or(parent.map(|parent| parent.bottom))
+                    .unwrap_or(image.height());
+                let text_prefix = match (read_text_prefix, parent) {
+                    (Some(text_prefix), _) => text_prefix.into(),
+                    (None, Some(parent)) => parent.text_prefix.clone(),
+                    (None, None) => String::new(),
+                };
+                let text_suffix = match (read_text_suffix, parent) {
+                    (Some(text_suffix), _) => text_suffix.into(),
+                    (None, Some(parent)) => parent.text_suffix.clone(),
+                    (None, None) => String::new(),
+                };
+                let transform = read_transform
+                    .or(parent.map(|parent| parent.transform))
+                    .unwrap_or(Transform::Upper);
+                let transform_seed =
+                    read_transform_seed.or(parent.and_then(|parent| parent.transform_seed));
+                let command: String = read_command.unwrap_or("_default").into();
+
+                // Memes without a command are all defaults, which can share it
+                if read_command.is_some() {
+                    let folded = fold_command(&command);
+
+                    if let Some(existing) = memes
+                        .iter()
+                        .find(|meme| fold_command(&meme.command) == folded)
+                    {
+                        warn!(
+                            "Config file has a meme \"{}\", which matches the same messages as \"{}\" before it; skipping",
+                            command, existing.command
+                        );
+                        continue;
+                    }
+                }
+
+                let is_default = read_is_default.unwrap_or(false);
+                let weight = read_weight.unwrap_or(1);
+                let schedule = if read_active_from.is_some() || read_active_until.is_some() {
+                    match Schedule::parse(read_active_from, read_active_until) {
+                        Ok(schedule) => schedule,
+                        Err(reason) => {
+                            warn!(
+                                "Config contains an invalid schedule for meme \"{}\": {}; skipping",
+                                command, reason
+                            );
+                            continue;
+                        }
+                    }
+                } else {
+                    parent.and_then(|parent| parent.schedule)
+                };
+                let category = match (read_category, parent) {
+                    (Some(category), _) => Some(category.trim().to_string()),
+                    (None, Some(parent)) => parent.category.clone(),
+                    (None, None) => None,
+                }
+                .filter(|category| !category.is_empty());
+                let description = read_description
+                    .map(|description| description.trim().to_string())
+                    .filter(|description| !description.is_empty());
+                // The template's attribution only carries over to memes that
+                // use the same image
+                let template_parent = parent.filter(|_| read_image_filename.is_none());
+                let license = read_license
+                    .map(|license| license.trim().to_string())
+                    .or(template_parent.and_then(|parent| parent.license.clone()))
+                    .filter(|license| !license.is_empty());
+                let source_url = read_source_url
+                    .map(|source_url| source_url.trim().to_string())
+                    .or(template_parent.and_then(|parent| parent.source_url.clone()))
+                    .filter(|source_url| !source_url.is_empty());
+                let allow_zalgo = read_allow_zalgo
+                    .or(parent.map(|parent| parent.allow_zalgo))
+                    .unwrap_or(false);
+                let hyphenate = read_hyphenate
+                    .or(parent.map(|parent| parent.hyphenate))
+                    .unwrap_or(false);
+                let direction = read_direction
+                    .or(parent.map(|parent| parent.direction))
+                    .unwrap_or(Direction::LeftToRight);
+                let alignment = read_alignment
+                    .or(parent.map(|parent| parent.alignment))
+                    .unwrap_or(Alignment::Center);
+                let text_color = read_text_color
+                    .or(parent.map(|parent| parent.text_color))
+                    .unwrap_or(Rgba([0, 0, 0, 255]));
+                let stroke_width = read_stroke_width
+                    .or(parent.map(|parent| parent.stroke_width))
+                    .unwrap_or(0);
+                let stroke_color = read_stroke_color
+                    .or(parent.map(|parent| parent.stroke_color))
+                    .unwrap_or(Rgba([255, 255, 255, 255]));
+                let stroke_join = read_stroke_join
+                    .or(parent.map(|parent| parent.stroke_join))
+                    .unwrap_or(StrokeJoin::Round);
+                let effect = TextEffect {
+                    kind: read_effect
+                        .or(parent.map(|parent| parent.effect.kind))
+                        .unwrap_or(TextEffectKind::None),
+                    amplitude: read_effect_amplitude
+                        .or(parent.map(|parent| parent.effect.amplitude))
+                        .unwrap_or(scale.y / 8f32),
+                    frequency: read_effect_frequency
+                        .or(parent.map(|parent| parent.effect.frequency))
+                        .unwrap_or(1f32),
+                    seed: 0,
+                };
+                let parent_animation = parent.and_then(|parent| parent.animation);
+                let animation = match read_animate {
+                    Some(animate) => animate,
+                    None => parent_animation.map(|animation| animation.kind),
+                }
+                .map(|kind| Animation {
+                    kind,
+                    duration_ms: read_animate_duration_ms
+                        .or(parent_animation.map(|animation| animation.duration_ms))
+                        .unwrap_or(2000),
+                    fps: read_animate_fps
+                        .or(parent_animation.map(|animation| animation.fps))
+                        .unwrap_or(10),
+                });
+                let plugin = read_plugin.or(parent.and_then(|parent| parent.plugin.clone()));
+                let code_color = read_code_color
+                    .or(parent.map(|parent| parent.code_color))
+                    .unwrap_or(text_color);
+                let code_background =
+                    read_code_background.or(parent.and_then(|parent| parent.code_background));
+                let avatar_regions = match parent {
+                    Some(parent) if read_avatar_regions.is_empty() => parent.avatar_regions.clone(),
+                    _ => read_avatar_regions,
+                };
+                let remove_avatar_background = read_remove_avatar_background
+                    .or(parent.map(|parent| parent.remove_avatar_background))
+                    .unwrap_or(false);
+                let output_max_width =
+                    read_output_max_width.or(parent.and_then(|parent| parent.output_max_width));
+
+                memes.push(Meme {
+                    image,
+                    thumbnail,
+                    version,
+                    filename: image_filename,
+                    loaded_at: SystemTime::now(),
+                    font: font_name,
+                    scale,
+                    left,
+                    top,
+                    right,
+                    bottom,
+                    text_prefix,
+                    text_suffix,
+                    transform,
+                    transform_seed,
+                    command,
+                    is_default,
+                    category,
+                    description,
+                    license,
+                    source_url,
+                    hyphenate,
+                    allow_zalgo,
+                    direction,
+                    alignment,
+                    text_color,
+                    stroke_width,
+                    stroke_color,
+                    stroke_join,
+                    effect,
+                    animation,
+                    plugin,
+                    code_font,
+                    code_color,
+                    code_background,
+                    avatar_regions,
+                    remove_avatar_background,
+                    output_max_width,
+                    weight,
+                    schedule,
+                });
+            } else {
+                warn!("Config contains invalid content");
+            }
+        }
+    } else {
+        return Err("Config file does not appear to contain any meme data or is malformed".into());
+    }
+
+    Ok((fonts, memes))
+}
+
+fn help_lines() -> Vec<String> {
+    let mut lines: Vec<String> = vec![
+        "**Commands**".into(),
+        "`help` - Show this message".into(),
+        "`memes [<category>]` - List the available memes, optionally only those in a category".into(),
+        "`search <terms>` - Find memes by name or description".into(),
+        "`about <meme>` - Show how a meme is set up".into(),
+        "`preset save <name> <text>` - Save text to reuse later".into(),
+        "`preset use [<meme>] <name>` - Create a meme with a saved preset".into(),
+        "`preset list`, `preset delete <name>` - Manage your presets".into(),
+        "`forgetme` - Delete everything the bot has stored about you, like your presets and events".into(),
+        "`random [<category>] <text>` - Create a random meme, optionally one from a category".into(),
+        "`locale [<locale>|default]` - Show or set the locale used for this server's memes (managers only)".into(),
+        "`makeemoji <meme> <text>` - Add a meme to this server as an emoji (managers only)".into(),
+        "`emojistrip <emoji>... [caption]` - Put custom emoji side by side in one image, with an optional caption below".into(),
+        "`factcheck <meme> [<message link>]` - Quote the message replied to or linked in a meme, with its length, reading time, and ratio of reactions".into(),
+        "`pollmeme <meme> [<message link>]` - Draw the votes on the poll replied to or linked into a meme".into(),
+        "`voicememes [here <meme> [text]|add @user|remove @user|off]` - Show or choose the meme posted when certain users join a voice channel (managers only)".into(),
+        "`welcome [here <meme> [text]|off]` - Show or choose the meme that welcomes new members (managers only)".into(),
+        "`event [list|add [@user] <MM-DD> [name]|remove [@user] [name]]` - Show or change this server's birthdays and other yearly events".into(),
+        "`event [here <meme> [text]|off]` - Choose the meme posted on the day of each event (managers only)".into(),
+        "`managers [add|remove <user>]` - Show or choose this server's managers (requires Administrator)".into(),
+        "`announcements [here|off]` - Show or choose the channel that the bot's announcements are posted in (managers only)".into(),
+        "`aliases [add <alias> <command>|remove <alias>]` - Show or choose this server's own names for memes and commands (managers only)".into(),
+        "`hotkeys [add <emoji> <meme>|remove <emoji>]` - Show or choose the reactions that turn messages into memes (managers only)".into(),
+        "`features [enable|disable|reset <feature>]` - Show or choose which of the bot's features this server uses (managers only)".into(),
+        "`<meme> <text>` - Create a meme with the given text".into(),
+        "`<text>` - Create the default meme with the given text".into(),
+        "`\\<text>` - Create the default meme even if the text starts with a meme's name, like `\\drake is cool`".into(),
+        "`<meme> <text> --color <color>` - Use a different text color, like `#ff0066` or `red`".into(),
+        "`<meme> <text> --nowrap` - Only break lines where the text does, for ASCII art".into(),
+        "`<meme> <text> --seed <number>` - Make random picks, cases, and jitter come out the same way again".into(),
+        "`<meme> {red}some{/} text` - Draw parts of the text in other colors".into(),
+        "`<meme> <text> | <effect> [<text>]` - Apply effects to the meme, one after another:".into(),
+    ];
+
+    for stage in STAGES {
+        lines.push(format!("  `{}` - {}", stage.name, stage.description));
+    }
+
+    lines
+}
+
+fn meme_list_lines(memes: &MemeRegistry, category: Option<&str>) -> Vec<String> {
+    let (mut lines, listed) = match category {
+        Some(category) => match memes.in_category(category) {
+            Some(listed) => (vec![format!("**Memes in {}**", category)], listed),
+            None => return vec![format!("There is no category called \"{}\".", category)],
+        },
+        None => (vec!["**Memes**".to_string()], memes.all()),
+    };
+
+    for meme in listed {
+        let mut line = format!("`{}`", meme.command);
+
+        if let (None, Some(category)) = (category, &meme.category) {
+            line += &format!(" - {}", category);
+        }
+
+        if meme.is_default {
+            line += " (default)";
+        }
+
+        lines.push(line);
+    }
+
+    if category.is_none() && memes.categories().next().is_some() {
+        let categories: Vec<&str> = memes.categories().map(|c| c.as_str()).collect();
+
+        lines.push(String::new());
+        lines.push(format!("Categories: {}", categories.join(", ")));
+    }
+
+    lines
+}
+
+fn get_guild_locale(ctx: &Context, msg: &Message) -> Option<String> {
+    let guild_id = msg.guild_id?;
+
+    let data = ctx.data.read();
+    let guild_settings = data
+        .get::<GuildSettingsKey>()
+        .expect("get_guild_locale(): Unable to retrieve guild settings");
+
+    guild_settings.get(guild_id.0)?.locale.clone()
+}
+
+fn set_guild_locale(ctx: &Context, msg: &Message, locale: &str) {
+    let guild_id = match msg.guild_id {
+        Some(guild_id) => guild_id,
+        None => {
+            msg.channel_id
+                .say(ctx, "Locales can only be set in a server.")
+                .ok();
+            return;
+        }
+    };
+
+    let locale = locale.trim();
+
+    if locale.is_empty() {
+        let reply = match get_guild_locale(ctx, msg) {
+            Some(locale) => format!("This server's locale is `{}`.", locale),
+            None => "This server uses the default locale.".into(),
+        };
+
+        msg.channel_id.say(ctx, reply).ok();
+        return;
+    }
+
+    if !is_manager(ctx, msg) {
+        msg.channel_id
+            .say(
+                ctx,
+                "You need to be a manager of this server (or have the Manage Server permission) to do that.",
+            )
+            .ok();
+        return;
+    }
+
+    let re_locale = Regex::new(r"^[a-zA-Z]{2,3}([-_][a-zA-Z0-9]{2,8})*$")
+        .expect("Unable to create locale matching pattern");
+
+    let new_locale = if locale.eq_ignore_ascii_case("default") {
+        None
+    } else if re_locale.is_match(locale) {
+        Some(locale.to_string())
+    } else {
+        msg.channel_id
+            .say(
+                ctx,
+                "That doesn't look like a locale. Try something like `en`, `tr`, or `el-GR`.",
+            )
+            .ok();
+        return;
+    };
+
+    let mut data = ctx.data.write();
+    let guild_settings = data
+        .get_mut::<GuildSettingsKey>()
+        .expect("Command locale: Unable to retrieve guild settings");
+
+    guild_settings.get_mut(guild_id.0).locale = new_locale;
+
+    if let Err(reason) = guild_settings.save() {
+        warn!("Command locale: {}", reason);
+    }
+
+    drop(data);
+
+    msg.channel_id.say(ctx, "Locale updated.").ok();
+}
+
+fn announcements_command(ctx: &Context, msg: &Message, rest: &str) {
+    let guild_id = match msg.guild_id {
+        Some(guild_id) => guild_id.0,
+        None => {
+            msg.channel_id
+                .say(ctx, "Announcements can only be set up in a server.")
+                .ok();
+            return;
+        }
+    };
+
+    let action = rest.trim().to_lowercase();
+
+    if action.is_empty() {
+        let channel_id = ctx
+            .data
+            .read()
+            .get::<GuildSettingsKey>()
+            .expect("Command announcements: Unable to retrieve guild settings")
+            .get(guild_id)
+            .and_then(|settings| settings.announcements_channel);
+
+        let reply = match channel_id {
+            Some(channel_id) => format!("Announcements are posted in <#{}>.", channel_id),
+            None => "This server doesn't receive announcements. (A manager can turn them on with `announcements here`.)".into(),
+        };
+
+        msg.channel_id.say(ctx, reply).ok();
+        return;
+    }
+
+    if !is_manager(ctx, msg) {
+        msg.channel_id
+            .say(
+                ctx,
+                "You need to be a manager of this server (or have the Manage Server permission) to do that.",
+            )
+            .ok();
+        return;
+    }
+
+    let (channel_id, reply) = match action.as_str() {
+        "here" => (
+            Some(msg.channel_id.0),
+            "Announcements will be posted in this channel.",
+        ),
+        "off" => (None, "This server won't receive announcements anymore."),
+        _ => {
+            msg.channel_id
+                .say(ctx, "Usage: `announcements [here|off]`")
+                .ok();
+            return;
+        }
+    };
+
+    let mut data = ctx.data.write();
+    let guild_settings = data
+        .get_mut::<GuildSettingsKey>()
+        .expect("Command announcements: Unable to retrieve guild settings");
+
+    guild_settings.get_mut(guild_id).announcements_channel = channel_id;
+
+    if let Err(reason) = guild_settings.save() {
+        warn!("Command announcements: {}", reason);
+    }
+
+    drop(data);
+
+    msg.channel_id.say(ctx, reply).ok();
+}
+
+/// Posts `text` to the announcements channel of every guild that has one, a
+/// message at a time so that the bot stays within Discord's rate limits, and
+/// tells the admin how it went once it's done
+fn broadcast(ctx: &Context, msg: &Message, text: &str) {
+    let text = text.trim();
+
+    if text.is_empty() {
+        msg.channel_id.say(ctx, "Usage: `broadcast <text>`").ok();
+        return;
+    }
+
+    let channels = ctx
+        .data
+        .read()
+        .get::<GuildSettingsKey>()
+        .expect("Command broadcast: Unable to retrieve guild settings")
+        .announcements_channels();
+
+    if channels.is_empty() {
+        msg.channel_id
+            .say(ctx, "No servers have opted in to announcements.")
+            .ok();
+        return;
+    }
+
+    info!(
+        "Broadcast to {} servers requested by {}",
+        channels.len(),
+        redact::user(&msg.author)
+    );
+
+    msg.channel_id
+        .say(
+            ctx,
+            format!("Sending the announcement to {} servers...", channels.len()),
+        )
+        .ok();
+
+    let ctx = ctx.clone();
+    let reply_channel = msg.channel_id;
+    let text = text.to_string();
+
+    thread::spawn(move || {
+        let mut sent = 0;
+
+        for (index, (guild_id, channel_id)) in channels.iter().enumerate() {
+            if index > 0 {
+                thread::sleep(BROADCAST_INTERVAL);
+            }
+
+            match ChannelId(*channel_id).say(&ctx, &text) {
+                Ok(_) => sent += 1,
+                Err(reason) => warn!(
+                    "Broadcast: Unable to post in channel {} of guild {}: {}",
+                    channel_id, guild_id, reason
+                ),
+            }
+        }
+
+        reply_channel
+            .say(
+                &ctx,
+                format!(
+                    "Sent the announcement to {} of {} servers.",
+                    sent,
+                    channels.len()
+                ),
+            )
+            .ok();
+    });
+}
+
+/// Splits `text` into the parts for each avatar region, separated by `|`.
+/// Each part can mention a user, whose avatar goes into the region, and the
+/// rest of the part becomes the region's label, cased with `seed` for
+/// `Transform::MockRandom`.
+fn collect_avatars(
+    ctx: &Context,
+    msg: &Message,
+    meme: &Meme,
+    text: &str,
+    locale: Option<&str>,
+    seed: Option<u64>,
+) -> Vec<(Option<RgbaImage>, Option<String>)> {
+    let re_user = Regex::new(r"<@!?(\d{1,32})>").expect("Unable to create user matching pattern");
+
+    // Parts beyond the meme's avatar regions would be thrown away, so don't
+    // fetch their avatars
+    text.split('|')
+        .take(meme.avatar_regions.len())
+        .map(|part| {
+            let avatar = re_user
+                .captures(part)
+                .and_then(|mention| mention.get(1))
+                .and_then(|id| id.as_str().parse::<u64>().ok())
+                .and_then(|id| msg.mentions.iter().find(|user| user.id.0 == id))
+                .and_then(|user| match fetch_avatar(user) {
+                    Ok(avatar) => Some(avatar),
+                    Err(reason) => {
+                        warn!("Create meme: {}", reason);
+                        None
+                    }
+                });
+
+            let label = re_user.replace(part, "").trim().to_string();
+            let label = if label.is_empty() {
+                None
+            } else {
+                Some(expand_mentions(
+                    ctx,
+                    msg,
+                    casing::apply_transform(
+                        &strip_zalgo(&label, meme.allow_zalgo),
+                        meme.transform,
+                        locale,
+                        seed,
+                    ),
+                ))
+            };
+
+            (avatar, label)
+        })
+        .collect()
+}
+
+/// Picks the meme's font, or any loaded font if the meme's own font could not
+/// be loaded
+fn select_font<'a>(fonts: &'a Fonts, meme: &Meme) -> Option<&'a Arc<Font<'static>>> {
+    fonts.get(&meme.font).or_else(|| fonts.values().next())
+}
+
+/// Picks the font for code in the meme, which is the meme's own font unless
+/// it has a `code_font` that could be loaded
+fn select_code_font<'a>(fonts: &'a Fonts, meme: &Meme) -> Option<&'a Arc<Font<'static>>> {
+    meme.code_font
+        .as_ref()
+        .and_then(|code_font| fonts.get(code_font))
+        .or_else(|| select_font(fonts, meme))
+}
+
+/// Returns the text to draw into a meme for the given user text. `seed` is
+/// the seed of `Transform::MockRandom`.
+fn meme_text(meme: &Meme, text: &str, locale: Option<&str>, seed: Option<u64>) -> String {
+    let text = strip_zalgo(text, meme.allow_zalgo);
+    let text = match &meme.plugin {
+        Some(plugin) => apply_text_plugin(plugin, &text),
+        None => text,
+    };
+
+    meme.text_prefix.clone()
+        + &casing::apply_transform(&text, meme.transform, locale, seed)
+        + &meme.text_suffix
+}
+
+/// The largest file that can be attached to a message in the message's server,
+/// which boosts raise. Animations are squeezed to fit it.
+fn upload_limit(ctx: &Context, msg: &Message) -> usize {
+    let tier = msg.guild(ctx).map(|guild| guild.read().premium_tier);
+
+    match tier {
+        Some(PremiumTier::Tier2) => 50 * 1024 * 1024,
+        Some(PremiumTier::Tier3) => 100 * 1024 * 1024,
+        _ => MAX_ANIMATION_BYTES,
+    }
+}
+
+/// Posts a link to a meme that's too large to attach, after uploading it to
+/// `OVERFLOW_UPLOAD_URL`. Returns whether it was posted.
+fn send_oversized_meme(
+    ctx: &Context,
+    msg: &Message,
+    settings: &MemeSettings,
+    output: Vec<u8>,
+    filename: &str,
+    extension: &str,
+) -> bool {
+    let store = match &settings.overflow_store {
+        Some(store) => store,
+        None => {
+            say_meme_error(
+                ctx,
+                msg,
+                settings,
+                "This meme came out too large to upload. (Maybe try a shorter text, or no animation?)",
+            );
+            return false;
+        }
+    };
+
+    let content_type = format!("image/{}", extension);
+
+    match store.upload(filename, output, &content_type) {
+        Ok(url) => msg.channel_id.say(ctx, url).is_ok(),
+        Err(reason) => {
+            warn!("Create meme: Unable to upload oversized meme: {}", reason);
+
+            say_meme_error(
+                ctx,
+                msg,
+                settings,
+                "This meme came out too large to upload, and couldn't be put anywhere else.",
+            );
+            false
+        }
+    }
+}
+
+/// Replies to a meme that couldn't be made, deleting the reply again after
+/// `ERROR_DELETE_SECS` if that's set, so that mistakes don't clutter the
+/// channel
+fn say_meme_error(
+    ctx: &Context,
+    msg: &Message,
+    settings: &MemeSettings,
+    content: impl std::fmt::Display,
+) {
+    let reply = match msg.channel_id.say(ctx, content) {
+        Ok(reply) => reply,
+        Err(reason) => {
+            warn!("Create meme: Unable to send error: {}", reason);
+            return;
+        }
+    };
+
+    if let Some(delay) = settings.error_delete_delay {
+        let http = ctx.http.clone();
+
+        thread::spawn(move || {
+            thread::sleep(delay);
+
+            if let Err(reason) = http.delete_message(reply.channel_id.0, reply.id.0) {
+                warn!("Create meme: Unable to delete error: {}", reason);
+            }
+        });
+    }
+}
+
+/// What making a meme needs from the shared data, copied out of it up front so
+/// that other commands aren't kept waiting while the meme is rendered and
+/// uploaded
+struct MemeSettings {
+    locale: Option<String>,
+    font: Option<Arc<Font<'static>>>,
+    code_font: Option<Arc<Font<'static>>>,
+    // The features that are turned off where the meme is being made
+    disabled_features: Vec<&'static str>,
+    output_settings: OutputSettings,
+    slow_request_budget: Duration,
+    error_delete_delay: Option<Duration>,
+    overflow_store: Option<Arc<UploadStore>>,
+}
+
+impl MemeSettings {
+    fn read(data: &ShareMap, guild_id: Option<u64>, meme: &Meme) -> MemeSettings {
+        let locale = guild_id.and_then(|guild_id| {
+            data.get::<GuildSettingsKey>()
+                .expect("Create meme: Unable to retrieve guild settings")
+                .get(guild_id)
+                .and_then(|settings| settings.locale.clone())
+        });
+
+        let fonts = data
+            .get::<FontsKey>()
+            .expect("Create meme: Unable to retrieve fonts");
+
+        let settings = data
+            .get::<BotSettingsKey>()
+            .expect("Create meme: Unable to retrieve bot settings");
+
+        MemeSettings {
+            locale,
+            font: select_font(fonts, meme).cloned(),
+            code_font: select_code_font(fonts, meme).cloned(),
+            disabled_features: FEATURES
+                .iter()
+                .filter(|feature| !is_feature_enabled(data, guild_id, feature.name))
+                .map(|feature| feature.name)
+                .collect(),
+            output_settings: settings.output_settings,
+            slow_request_budget: settings.slow_request_budget,
+            error_delete_delay: settings.error_delete_delay,
+            overflow_store: settings.overflow_store.clone(),
+        }
+    }
+
+    fn is_enabled(&self, feature: &str) -> bool {
+        !self.disabled_features.contains(&feature)
+    }
+}
+
+fn send_meme(ctx: &Context, msg: &Message, meme: &Meme, text: &str) {
+    let mut timings = Timings::start();
+
+    let guild_id = msg.guild_id.map(|guild_id| guild_id.0);
+    let settings = MemeSettings::read(&ctx.data.read(), guild_id, meme);
+
+    let font = match &settings.font {
+        Some(font) => font,
+        None => {
+            say_meme_error(
+                ctx,
+                msg,
+                &settings,
+                "I don't know how to say this...Literally. (No fonts loaded.)",
+            );
+            return;
+        }
+    };
+
+    let (text, flags) = match take_flags(text) {
+        Ok((text, flags)) => (text, flags),
+        Err(reason) => {
+            say_meme_error(ctx, msg, &settings, reason);
+            return;
+        }
+    };
+
+    let (text, stages) = split_pipeline(&text);
+    let style = flags.text_style(meme);
+
+    if let Some((stage, _)) = stages
+        .iter()
+        .find(|(stage, _)| !settings.is_enabled(stage.name))
+    {
+        say_meme_error(
+            ctx,
+            msg,
+            &settings,
+            format!("`{}` is turned off in this server.", stage.name),
+        );
+        return;
+    }
+
+    // Random cases get a seed of their own unless one was given, which is
+    // logged so that the meme can be made again with `--seed`
+    let seed = match flags.transform_seed(meme) {
+        Some(seed) => Some(seed),
+        None if meme.transform == Transform::MockRandom => {
+            let seed = rand::random();
+
+            info!("Casing meme \"{}\" with seed {}", meme.command, seed);
+
+            Some(seed)
+        }
+        None => None,
+    };
+
+    timings.end_phase("parse");
+
+    let code = find_code_block(text)
+        .filter(|_| meme.avatar_regions.is_empty() && settings.is_enabled("code"));
+
+    let frames = if let Some(code) = code {
+        debug!("Creating meme \"{}\" with code", meme.command);
+
+        vec![AnimationFrame::still(render_code(
+            meme,
+            settings.code_font.as_ref().unwrap_or(font),
+            code,
+            flags.color.unwrap_or(meme.code_color),
+            meme.code_background,
+        ))]
+    } else if meme.avatar_regions.is_empty() {
+        let text = expand_mentions(
+            ctx,
+            msg,
+            meme_text(meme, text, settings.locale.as_deref(), seed),
+        );
+
+        timings.end_phase("mentions");
+
+        debug!(
+            "Creating meme \"{}\" with text \"{}\"",
+            meme.command,
+            redact::text(&text)
+        );
+
+        match meme.animation.filter(|_| settings.is_enabled("animations")) {
+            Some(animation) => render_animation(meme, font, &text, style, animation),
+            None => vec![AnimationFrame::still(render_meme(meme, font, &text, style))],
+        }
+    } else {
+        if !settings.is_enabled("avatars") {
+            say_meme_error(
+                ctx,
+                msg,
+                &settings,
+                "Memes with avatars are turned off in this server.",
+            );
+            return;
+        }
+
+        let avatars = collect_avatars(ctx, msg, meme, text, settings.locale.as_deref(), seed);
+
+        timings.end_phase("avatars");
+
+        if avatars.iter().all(|(avatar, _)| avatar.is_none()) {
+            say_meme_error(
+                ctx,
+                msg,
+                &settings,
+                "Mention the users to put in this meme, separated by `|`. (Like `@user1 | @user2`.)",
+            );
+            return;
+        }
+
+        debug!("Creating avatar meme \"{}\"", meme.command);
+
+        vec![AnimationFrame::still(render_avatar_meme(
+            meme, font, &avatars, style,
+        ))]
+    };
+
+    timings.end_phase("render");
+
+    let mut staged_frames = Vec::<AnimationFrame>::new();
+
+    for frame in frames {
+        match apply_stages(frame.image, meme, font, &stages) {
+            Ok(image) => staged_frames.push(AnimationFrame { image, ..frame }),
+            Err(reason) => {
+                say_meme_error(ctx, msg, &settings, reason);
+                return;
+            }
+        }
+    }
+
+    if !stages.is_empty() {
+        timings.end_phase("effects");
+    }
+
+    let mut frames: Vec<AnimationFrame> = staged_frames
+        .into_iter()
+        .map(|frame| AnimationFrame {
+            image: fit_to_width(
+                frame.image,
+                meme.output_max_width.or(settings.output_settings.max_width),
+            ),
+            ..frame
+        })
+        .collect();
+
+    let encoded = if frames.len() == 1 {
+        encode_meme_png(&frames.remove(0).image, meme, settings.output_settings)
+            .map(|png| (png, "png"))
+    } else {
+        match encode_animation(frames, upload_limit(ctx, msg)) {
+            Ok(gif) => Ok((gif, "gif")),
+            Err(reason) => {
+                say_meme_error(ctx, msg, &settings, reason);
+                return;
+            }
+        }
+    };
+
+    match encoded {
+        Ok((output, extension)) => {
+            timings.end_phase("encode");
+
+            let generated_image_filename = format!("{}.{}", meme.command, extension);
+
+            let sent = if output.len() > upload_limit(ctx, msg) {
+                send_oversized_meme(
+                    ctx,
+                    msg,
+                    &settings,
+                    output,
+                    &generated_image_filename,
+                    extension,
+                )
+            } else {
+                msg.channel_id
+                    .send_files(
+                        ctx,
+                        vec![(&output[..], generated_image_filename.as_str())],
+                        |m| m,
+                    )
+                    .is_ok()
+            };
+
+            timings.end_phase("upload");
+
+            if sent {
+                ctx.data
+                    .read()
+                    .get::<UsageCountsKey>()
+                    .expect("Create meme: Unable to retrieve usage counts")
+                    .record(&meme.command);
+            }
+
+            timings.report(
+                &format!("Meme \"{}\"", meme.command),
+                settings.slow_request_budget,
+            );
+        }
+        Err(reason) => {
+            say_meme_error(
+                ctx,
+                msg,
+                &settings,
+                "Sorry, something went wrong! Maybe try again?",
+            );
+
+            warn!("Create meme: {}", reason);
+        }
+    }
+}
+
+/// Parses a user given as a mention or an ID
+fn parse_user_id(text: &str) -> Option<u64> {
+    let re_user = Regex::new(r"^(?:<@!?(\d{1,32})>|(\d{1,32}))$")
+        .expect("Unable to create user matching pattern");
+
+    re_user
+        .captures(text.trim())
+        .and_then(|captures| captures.get(1).or_else(|| captures.get(2)))
+        .and_then(|id| id.as_str().parse::<u64>().ok())
+}
+
+fn managers_command(ctx: &Context, msg: &Message, rest: &str) {
+    let guild_id = match msg.guild_id {
+        Some(guild_id) => guild_id.0,
+        None => {
+            msg.channel_id
+                .say(ctx, "Managers can only be chosen in a server.")
+                .ok();
+            return;
+        }
+    };
+
+    let (subcommand, rest) = split_first_word(rest);
+    let subcommand = subcommand.to_lowercase();
+
+    if subcommand.is_empty() {
+        let data = ctx.data.read();
+        let managers = data
+            .get::<GuildSettingsKey>()
+            .expect("Command managers: Unable to retrieve guild settings")
+            .get(guild_id)
+            .map(|settings| settings.managers.clone())
+            .unwrap_or_default();
+        drop(data);
+
+        let mut lines =
+            vec!["**Managers** (besides everyone with the Manage Server permission)".to_string()];
+
+        // Names are looked up rather than mentioning the managers, which
+        // would notify them
+        for manager in &managers {
+            match UserId(*manager).to_user_cached(&ctx.cache) {
+                Some(user) => {
+                    let user = user.read();
+                    lines.push(format!(
+                        "{}#{} (`{}`)",
+                        user.name, user.discriminator, manager
+                    ));
+                }
+                None => lines.push(format!("`{}`", manager)),
+            }
+        }
+
+        if managers.is_empty() {
+            lines.push("None".into());
+        }
+
+        send_paginated(ctx, msg.channel_id, msg.author.id, &lines);
+        return;
+    }
+
+    if subcommand != "add" && subcommand != "remove" {
+        msg.channel_id
+            .say(
+                ctx,
+                "Usage: `managers`, `managers add <user>`, or `managers remove <user>`",
+            )
+            .ok();
+        return;
+    }
+
+    if !is_guild_admin(ctx, msg) && !is_bot_admin(ctx, msg) {
+        msg.channel_id
+            .say(ctx, "You need the Administrator permission to do that.")
+            .ok();
+        return;
+    }
+
+    let user_id = match parse_user_id(rest) {
+        Some(user_id) => user_id,
+        None => {
+            msg.channel_id
+                .say(ctx, "Mention the user or give their ID.")
+                .ok();
+            return;
+        }
+    };
+
+    let mut data = ctx.data.write();
+    let guild_settings = data
+        .get_mut::<GuildSettingsKey>()
+        .expect("Command managers: Unable to retrieve guild settings");
+
+    let managers = &mut guild_settings.get_mut(guild_id).managers;
+
+    let reply = if subcommand == "add" {
+        if managers.contains(&user_id) {
+            "That user is already a manager."
+        } else {
+            managers.push(user_id);
+            "Added manager."
+        }
+    } else if let Some(index) = managers.iter().position(|manager| *manager == user_id) {
+        managers.remove(index);
+        "Removed manager."
+    } else {
+        "That user isn't a manager."
+    };
+
+    if let Err(reason) = guild_settings.save() {
+        warn!("Command managers: {}", reason);
+    }
+
+    drop(data);
+
+    msg.channel_id.say(ctx, reply).ok();
+}
+
+/// Commands that only admins can use, and only in DMs
+const ADMIN_COMMANDS: &[&str] = &[
+    "addmeme",
+    "admins",
+    "broadcast",
+    "calibrate",
+    "dumpconfig",
+    "import",
+    "purgeuser",
+    "quit",
+    "reload",
+    "removememe",
+    "revoke",
+    "setregion",
+    "suggestregion",
+    "sync",
+    "undo",
+];
+
+/// Where a command goes once the checks that apply to every command are done
+#[derive(Debug, PartialEq)]
+enum Route {
+    /// The command has been answered, or gets no answer
+    Handled,
+    /// One of `ADMIN_COMMANDS`, from an admin in a DM
+    Admin,
+    /// Any other command, or a meme
+    Everyone,
+}
+
+/// Checks whether a command can be used where and by whom it was sent: the
+/// terms have to be accepted in a server before anything else, `auth` logs
+/// admins in, and admin commands need an admin in a DM
+fn route_command(chat: &dyn Chat, msg: &Message, first_word: &str, rest: &str) -> Route {
+    let data = chat.data().read();
+
+    let is_admin = data
+        .get::<AdminSessionsKey>()
+        .expect("Command: Unable to retrieve admin sessions")
+        .lock()
+        .is_admin(msg.author.id.0);
+
+    let terms = data
+        .get::<BotSettingsKey>()
+        .expect("Command: Unable to retrieve bot settings")
+        .terms
+        .clone();
+
+    if let (Some(terms), Some(guild_id)) = (terms, msg.guild_id) {
+        let terms_accepted = data
+            .get::<GuildSettingsKey>()
+            .expect("Command: Unable to retrieve guild settings")
+            .get(guild_id.0)
+            .is_some_and(|settings| settings.terms_accepted);
+
+        if !terms_accepted {
+            drop(data);
+
+            if first_word == "accept-terms" {
+                accept_terms(chat, msg);
+            } else {
+                chat.say(
+                    msg.channel_id,
+                    &format!(
+                        "Before I can be used here, an administrator of this server has to accept these terms by sending `accept-terms`:\n{}",
+                        terms
+                    ),
+                );
+            }
+
+            return Route::Handled;
+        }
+    }
+
+    if !msg.is_private() {
+        return Route::Everyone;
+    }
+
+    if first_word == "auth" {
+        if is_admin {
+            chat.say(msg.channel_id, "You are already authorized.");
+            return Route::Handled;
+        }
+
+        let admin_password = data
+            .get::<BotSettingsKey>()
+            .expect("Command auth: Unable to retrieve bot settings")
+            .admin_password
+            .clone();
+
+        if let Some(admin_password) = admin_password {
+            if admin_password == rest {
+                info!(
+                    "User sucessfully authorized as admin: {}",
+                    redact::user(&msg.author)
+                );
+
+                let mut sessions = data
+                    .get::<AdminSessionsKey>()
+                    .expect("Command auth: Unable to retrieve admin sessions")
+                    .lock();
+
+                sessions.start(
+                    msg.author.id.0,
+                    &format!("{}#{}", msg.author.name, msg.author.discriminator),
+                );
+
+                if let Err(reason) = sessions.save() {
+                    warn!("Command auth: {}", reason);
+                }
+
+                chat.say(msg.channel_id, "Successfully authorized.");
+            } else {
+                info!(
+                    "User failed attempt to authorize as admin: {}",
+                    redact::user(&msg.author)
+                );
+            }
+        }
+
+        return Route::Handled;
+    }
+
+    if !is_admin || !ADMIN_COMMANDS.contains(&first_word) {
+        return Route::Everyone;
+    }
+
+    if matches!(first_word, "addmeme" | "import" | "sync")
+        && !capabilities::is_enabled(Capability::AddingMemes)
+    {
+        chat.say(msg.channel_id, Capability::AddingMemes.disabled_message());
+        return Route::Handled;
+    }
+
+    Route::Admin
+}
+
+fn accept_terms(chat: &dyn Chat, msg: &Message) {
+    let guild_id = match msg.guild_id {
+        Some(guild_id) => guild_id.0,
+        None => return,
+    };
+
+    if !is_guild_admin(chat, msg) {
+        chat.say(
+            msg.channel_id,
+            "You need the Administrator permission to do that.",
+        );
+        return;
+    }
+
+    let mut data = chat.data().write();
+    let guild_settings = data
+        .get_mut::<GuildSettingsKey>()
+        .expect("Command accept-terms: Unable to retrieve guild settings");
+
+    guild_settings.get_mut(guild_id).terms_accepted = true;
+
+    if let Err(reason) = guild_settings.save() {
+        warn!("Command accept-terms: {}", reason);
+    }
+
+    drop(data);
+
+    info!(
+        "Terms accepted for guild {} by {}",
+        guild_id,
+        redact::user(&msg.author)
+    );
+
+    chat.say(msg.channel_id, "Thanks! I'm ready to go. (Try `help`.)");
+}
+
+/// Lists the memes that best match `rest`, with their thumbnails
+fn search_command(chat: &dyn Chat, msg: &Message, rest: &str) {
+    let memes = chat
+        .data()
+        .read()
+        .get::<MemesKey>()
+        .expect("Command search: Unable to retrieve memes")
+        .load_full();
+
+    if rest.trim().is_empty() {
+        chat.say(
+            msg.channel_id,
+            "What should I search for? (Try `search <terms>`.)",
+        );
+        return;
+    }
+
+    let results = search(&memes, rest);
+
+    if results.is_empty() {
+        chat.say(msg.channel_id, "No memes match that.");
+        return;
+    }
+
+    let mut content = "**Best matches**".to_string();
+
+    for meme in &results {
+        content += &format!("\n`{}`", meme.command);
+
+        if let Some(description) = &meme.description {
+            let description: String = description.chars().take(100).collect();
+
+            content += &format!(" - {}", description);
+        }
+    }
+
+    let filenames: Vec<String> = results
+        .iter()
+        .map(|meme| format!("{}-{}.png", meme.command, meme.version))
+        .collect();
+
+    let thumbnails: Vec<(&str, &[u8])> = results
+        .iter()
+        .zip(&filenames)
+        .filter(|(meme, _)| !meme.thumbnail.is_empty())
+        .map(|(meme, filename)| (filename.as_str(), &meme.thumbnail[..]))
+        .collect();
+
+    if thumbnails.is_empty() {
+        chat.say(msg.channel_id, &content);
+    } else {
+        chat.send_files(msg.channel_id, &thumbnails, &content);
+    }
+}
+
+/// Suggests where the text of the meme named in `rest` could go, with a
+/// preview of the region drawn on its template
+fn suggest_region_command(chat: &dyn Chat, msg: &Message, rest: &str) {
+    let memes = chat
+        .data()
+        .read()
+        .get::<MemesKey>()
+        .expect("Command suggestregion: Unable to retrieve memes")
+        .load_full();
+
+    let meme = match memes.find(&rest.trim().to_lowercase()) {
+        Some(meme) => meme,
+        None => {
+            chat.say(msg.channel_id, "Usage: `suggestregion <meme>`");
+            return;
+        }
+    };
+
+    let region = match suggest_region(&meme.image) {
+        Some(region) => region,
+        None => {
+            chat.say(
+                msg.channel_id,
+                "I couldn't find any blank area in that template.",
+            );
+            return;
+        }
+    };
+
+    let content = format!(
+        "Suggested region for `{}`:\n```yml\n  left: {}\n  top: {}\n  right: {}\n  bottom: {}\n```",
+        meme.command, region.left, region.top, region.right, region.bottom
+    );
+
+    match encode_png(
+        &draw_region_preview(&meme.image, region),
+        OutputSettings::default(),
+    ) {
+        Ok(preview) => {
+            chat.send_files(
+                msg.channel_id,
+                &[("suggestion.png", &preview[..])],
+                &content,
+            );
+        }
+        Err(reason) => {
+            warn!("Command suggestregion: {}", reason);
+
+            chat.say(msg.channel_id, &content);
+        }
+    }
+}
+
+/// Lists who has an admin session and when each one expires
+fn admin_session_lines(chat: &dyn Chat) -> Vec<String> {
+    let mut lines = vec!["**Admin sessions**".to_string()];
+
+    for session in chat
+        .data()
+        .read()
+        .get::<AdminSessionsKey>()
+        .expect("Command admins: Unable to retrieve admin sessions")
+        .lock()
+        .list()
+    {
+        let remaining = session.remaining().as_secs();
+
+        lines.push(format!(
+            "{} (`{}`) - expires in {}h {}m",
+            session.name,
+            session.user_id,
+            remaining / 3600,
+            remaining % 3600 / 60
+        ));
+    }
+
+    lines
+}
+
+/// Ends the admin session of the user given in `rest`
+fn revoke_command(chat: &dyn Chat, msg: &Message, rest: &str) {
+    let user_id = match parse_user_id(rest) {
+        Some(user_id) => user_id,
+        None => {
+            chat.say(msg.channel_id, "Usage: `revoke <user ID or mention>`");
+            return;
+        }
+    };
+
+    let data = chat.data().read();
+    let mut sessions = data
+        .get::<AdminSessionsKey>()
+        .expect("Command revoke: Unable to retrieve admin sessions")
+        .lock();
+
+    if !sessions.revoke(user_id) {
+        chat.say(msg.channel_id, "That user doesn't have an admin session.");
+        return;
+    }
+
+    if let Err(reason) = sessions.save() {
+        warn!("Command revoke: {}", reason);
+    }
+
+    drop(sessions);
+    drop(data);
+
+    info!(
+        "User revoked admin session of {}: {}",
+        redact::user_id(user_id),
+        redact::user(&msg.author)
+    );
+
+    chat.say(msg.channel_id, "Revoked.");
+}
+
+/// Works out which meme `command` asks for and what goes on it, and passes
+/// them to `send`: the longest meme command the text starts with, or
+/// otherwise the default meme. `alias` is the command the first word stands
+/// for in this server, if it's an alias.
+fn meme_command(
+    chat: &dyn Chat,
+    msg: &Message,
+    command: &Command,
+    alias: Option<&str>,
+    send: &mut dyn FnMut(&Meme, &str),
+) {
+    if command.entire.is_empty() {
+        return;
+    }
+
+    let guild_id = msg.guild_id.map(|guild_id| guild_id.0);
+
+    let is_default_alias = alias == Some(aliases::DEFAULT_MEME);
+
+    // A leading backslash makes the whole text go on the default meme,
+    // even if it starts with a command or an easter egg
+    let escaped = command.entire.strip_prefix('\\');
+
+    // Everything needed from the shared data is taken out of it before the
+    // attached or replied-to text is downloaded, so that other commands
+    // aren't kept waiting, even by a command waiting to change the data
+    let data = chat.data().read();
+
+    let memes = data
+        .get::<MemesKey>()
+        .expect("Create meme: Unable to retrieve memes")
+        .load_full();
+
+    let easter_egg = data
+        .get::<GreetingsKey>()
+        .expect("Create meme: Unable to retrieve greetings")
+        .find_easter_egg(command.entire)
+        .filter(|_| escaped.is_none())
+        .cloned();
+
+    let read_attachments = is_feature_enabled(&data, guild_id, "text-attachments");
+    let read_replies = is_feature_enabled(&data, guild_id, "replies");
+
+    drop(data);
+
+    match &easter_egg {
+        Some(EasterEggResponse::Reply(reply)) => {
+            chat.say(msg.channel_id, reply);
+            return;
+        }
+        Some(EasterEggResponse::Meme(meme_command)) => match memes.find(meme_command) {
+            Some(meme) => {
+                send(meme, command.entire);
+                return;
+            }
+            None => warn!(
+                "Easter egg refers to unknown meme \"{}\"; ignoring it",
+                meme_command
+            ),
+        },
+        None => {}
+    }
+
+    let text: &str;
+    let meme: &Meme;
+
+    let reply_text: String;
+
+    // Commands can be several words long, so the longest one the message
+    // starts with wins, unless the first word is an alias
+    let matching_command = match alias {
+        _ if is_default_alias || escaped.is_some() => None,
+        Some(alias) => memes.find(alias).map(|meme| (meme, command.rest)),
+        None => memes.find_prefix(command.entire),
+    };
+
+    if let Some((matching_command, rest)) = matching_command {
+        meme = matching_command;
+
+        // Without any text of its own, the meme takes its text from an
+        // attached text file, or else from the message it replies to
+        text = if rest.trim().is_empty() {
+            let attached = read_attachments.then(|| attachment_text(msg)).flatten();
+            let replied = || {
+                read_replies
+                    .then(|| replied_text(chat, msg, read_attachments))
+                    .flatten()
+            };
+
+            match attached.or_else(replied) {
+                Some(replied) => {
+                    reply_text = replied;
+                    &reply_text
+                }
+                None => rest,
+            }
+        } else {
+            rest
+        };
+    } else if let Some(default_command) = memes.default_meme(peek_seed(command.entire)) {
+        meme = default_command;
+        text = match escaped {
+            Some(escaped) => escaped.trim_start(),
+            None if is_default_alias => command.rest,
+            None => command.entire,
+        };
+    } else {
+        chat.say(
+            msg.channel_id,
+            "I have no idea what's going on. (No memes loaded.)",
+        );
+        return;
+    }
+
+    send(meme, text);
+}
+
+/// Reads the config and greetings files again and swaps them in, leaving the
+/// old ones in place if either can't be loaded. Returns a summary of what was
+/// loaded.
+fn reload_memes(ctx: &Context) -> Result<String, String> {
+    let source = ctx
+        .data
+        .read()
+        .get::<BotSettingsKey>()
+        .expect("Reload: Unable to retrieve bot settings")
+        .config_source
+        .clone();
+
+    // Loading may download fonts, so it's done without holding the lock
+    let (fonts, mut memes) = load_memes(
+        source.store.as_ref(),
+        &source.config_filename,
+        &source.added_memes,
+        &source.font_cache_dir,
+    )?;
+    let greetings = Greetings::load(source.store.as_ref(), &source.greetings_filename)?;
+
+    let mut data = ctx.data.write();
+
+    let old_memes = data
+        .get::<MemesKey>()
+        .expect("Reload: Unable to retrieve memes")
+        .load();
+
+    // Templates replaced under the same command get a new version
+    let mut changed_templates = 0;
+
+    for meme in &mut memes {
+        match old_memes.find(&meme.command) {
+            Some(old_meme) if old_meme.version == meme.version => {
+                meme.loaded_at = old_meme.loaded_at;
+            }
+            Some(_) => {
+                info!(
+                    "Template of \"{}\" changed to version {}",
+                    meme.command, meme.version
+                );
+
+                changed_templates += 1;
+            }
+            None => {}
+        }
+    }
+
+    let summary = format!(
+        "{} memes ({} with new templates), {} fonts, and {} easter eggs",
+        memes.len(),
+        changed_templates,
+        fonts.len(),
+        greetings.easter_eggs().len()
+    );
+
+    data.insert::<FontsKey>(fonts);
+    data.insert::<GreetingsKey>(greetings);
+
+    // The old fonts are freed once the memes being made with them are done
+    clear_glyph_cache();
+
+    // Memes that are being made hold on to the registry they started with
+    data.get::<MemesKey>()
+        .expect("Reload: Unable to retrieve memes")
+        .store(Arc::new(MemeRegistry::new(memes)));
+
+    Ok(summary)
+}
+
+/// Sends the report on the config's warnings to each admin other than the one
+/// who reloaded it, since admins rarely watch the log
+fn send_warnings_report(ctx: &Context, reloaded_by: u64, report: &str) {
+    let admins: Vec<u64> = ctx
+        .data
+        .read()
+        .get::<AdminSessionsKey>()
+        .expect("Command reload: Unable to retrieve admin sessions")
+        .lock()
+        .list()
+        .iter()
+        .map(|session| session.user_id)
+        .filter(|user_id| *user_id != reloaded_by)
+        .collect();
+
+    for user_id in admins {
+        let result = UserId(user_id)
+            .create_dm_channel(ctx)
+            .and_then(|channel| channel.say(ctx, format!("The config was reloaded. {}", report)));
+
+        if let Err(reason) = result {
+            warn!(
+                "Command reload: Unable to send the warnings to admin {}: {}",
+                redact::user_id(user_id),
+                reason
+            );
+        }
+    }
+}
+
+fn reload_config(ctx: &Context, msg: &Message) {
+    let (result, warnings) = config_warnings::collect(|| reload_memes(ctx));
+
+    let reply = match result {
+        Ok(summary) => {
+            info!("Config reloaded by {}", redact::user(&msg.author));
+
+            format!("Reloaded {}.", summary)
+        }
+        Err(reason) => {
+            warn!("Command reload: {}", reason);
+            format!(
+                "Unable to reload, so the old config is still in use: {}",
+                reason
+            )
+        }
+    };
+
+    let report = config_warnings::report(&warnings);
+
+    let reply = match &report {
+        Some(report) => format!("{}\n{}", reply, report),
+        None => reply,
+    };
+
+    msg.channel_id.say(ctx, reply).ok();
+
+    if let Some(report) = report {
+        send_warnings_report(ctx, msg.author.id.0, &report);
+    }
+}
+
+/// Parses a comma-separated list of guild IDs from the environment variable
+/// `name`, exiting if any of them is invalid
+fn parse_guild_list(name: &str, list: &str) -> Vec<u64> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|guild_id| !guild_id.is_empty())
+        .map(|guild_id| match guild_id.parse::<u64>() {
+            Ok(guild_id) => guild_id,
+            Err(_) => {
+                error!("{} contains invalid guild ID \"{}\"", name, guild_id);
+                process::exit(1);
+            }
+        })
+        .collect()
+}
+
+fn split_first_word(text: &str) -> (&str, &str) {
+    let text = text.trim_start();
+
+    match text.find(char::is_whitespace) {
+        Some(index) => (&text[..index], text[index..].trim_start()),
+        None => (text, ""),
+    }
+}
+
+fn preset_command(ctx: &Context, msg: &Message, rest: &str) {
+    let (subcommand, rest) = split_first_word(rest);
+    let user_id = msg.author.id.0;
+
+    let mut data = ctx.data.write();
+
+    let presets = data
+        .get_mut::<PresetsKey>()
+        .expect("Command preset: Unable to retrieve presets");
+
+    match subcommand.to_lowercase().as_str() {
+        "save" => {
+            let (name, text) = split_first_word(rest);
+
+            if name.is_empty() || text.is_empty() {
+                msg.channel_id
+                    .say(ctx, "Usage: `preset save <name> <text>`")
+                    .ok();
+                return;
+            }
+
+            if let Err(reason) = presets.insert(user_id, name, text) {
+                msg.channel_id.say(ctx, reason).ok();
+                return;
+            }
+
+            if let Err(reason) = presets.save() {
+                warn!("Command preset: {}", reason);
+            }
+
+            msg.channel_id
+                .say(ctx, format!("Saved preset `{}`.", name.to_lowercase()))
+                .ok();
+        }
+        "use" => {
+            let (first, second) = split_first_word(rest);
+
+            // Either `preset use <meme> <name>` or `preset use <name>` for
+            // the default meme
+            let (command, name) = if second.is_empty() {
+                (None, first)
+            } else {
+                (Some(first.to_lowercase()), second)
+            };
+
+            let text = match presets.get(user_id, name) {
+                Some(text) => text.clone(),
+                None => {
+                    msg.channel_id
+                        .say(ctx, format!("You don't have a preset called `{}`.", name))
+                        .ok();
+                    return;
+                }
+            };
+
+            let memes = data
+                .get::<MemesKey>()
+                .expect("Command preset: Unable to retrieve memes")
+                .load();
+
+            let meme = match &command {
+                Some(command) => memes.find(command),
+                None => memes.default_meme(peek_seed(&text)),
+            };
+
+            match meme {
+                Some(meme) => {
+                    drop(data);
+
+                    send_meme(ctx, msg, meme, &text);
+                }
+                None => {
+                    msg.channel_id
+                        .say(ctx, "I don't know that meme. (Try `memes`.)")
+                        .ok();
+                }
+            }
+        }
+        "list" => {
+            let mut lines = vec!["**Your presets**".to_string()];
+
+            for (name, text) in presets.list(user_id) {
+                lines.push(format!("`{}` - {}", name, text));
+            }
+
+            if lines.len() == 1 {
+                msg.channel_id
+                    .say(
+                        ctx,
+                        "You don't have any presets. (Try `preset save <name> <text>`.)",
+                    )
+                    .ok();
+                return;
+            }
+
+            lines.push(format!(
+                "({}/{} used)",
+                lines.len() - 1,
+                MAX_PRESETS_PER_USER
+            ));
+
+            send_paginated(ctx, msg.channel_id, msg.author.id, &lines);
+        }
+        "delete" => {
+            if !presets.remove(user_id, rest.trim()) {
+                msg.channel_id
+                    .say(
+                        ctx,
+                        format!("You don't have a preset called `{}`.", rest.trim()),
+                    )
+                    .ok();
+                return;
+            }
+
+            if let Err(reason) = presets.save() {
+                warn!("Command preset: {}", reason);
+            }
+
+            msg.channel_id.say(ctx, "Deleted preset.").ok();
+        }
+        _ => {
+            msg.channel_id
+                .say(
+                    ctx,
+                    "Usage: `preset save <name> <text>`, `preset use [<meme>] <name>`, `preset list`, or `preset delete <name>`",
+                )
+                .ok();
+        }
+    }
+}
 
 struct Handler;
 
-impl EventHandler for Handler {
-    fn ready(&self, ctx: Context, ready: Ready) {
-        info!(
-            "Connected as {}#{}",
-            ready.user.name, ready.user.discriminator
-        );
+impl EventHandler for Handler {
+    fn ready(&self, ctx: Context, ready: Ready) {
+        info!(
+            "Connected as {}#{}",
+            ready.user.name, ready.user.discriminator
+        );
+
+        let mut data = ctx.data.write();
+        let settings = data
+            .get_mut::<BotSettingsKey>()
+            .expect("ready(): Unable to retrieve bot settings");
+        settings.id = Some(ready.user.id.0);
+
+        if !settings.tasks_started {
+            settings.tasks_started = true;
+
+            let activities = settings.activities.clone();
+            let interval = settings.activity_interval;
+            let heartbeat_interval = settings.heartbeat_interval;
+            let retention = settings.retention.clone();
+
+            drop(data);
+
+            if let Some(heartbeat_interval) = heartbeat_interval {
+                start_heartbeat(ctx.clone(), heartbeat_interval);
+            }
+
+            start_janitor(ctx.clone(), retention);
+
+            start_events(ctx.clone());
+            start_presence(ctx, activities, interval);
+        }
+    }
+
+    fn guild_create(&self, ctx: Context, guild: Guild, is_new: bool) {
+        let data = ctx.data.read();
+        let settings = data
+            .get::<BotSettingsKey>()
+            .expect("guild_create(): Unable to retrieve bot settings");
+
+        let is_allowed = match &settings.allowed_guilds {
+            Some(allowed_guilds) => allowed_guilds.contains(&guild.id.0),
+            None => true,
+        } && !settings.denied_guilds.contains(&guild.id.0);
+
+        drop(data);
+
+        if is_allowed {
+            return;
+        }
+
+        warn!(
+            "Leaving {} guild \"{}\" ({}, owned by {}), which isn't allowed",
+            if is_new { "newly joined" } else { "existing" },
+            guild.name,
+            guild.id,
+            guild.owner_id
+        );
+
+        if let Err(reason) = guild.leave(&ctx) {
+            error!("Unable to leave guild {}: {}", guild.id, reason);
+        }
+    }
+
+    fn reaction_add(&self, ctx: Context, reaction: Reaction) {
+        handle_reaction(&ctx, &reaction);
+        handle_hotkey(&ctx, &reaction);
+    }
+
+    fn reaction_remove(&self, ctx: Context, reaction: Reaction) {
+        handle_reaction(&ctx, &reaction);
+    }
+
+    fn guild_member_addition(&self, ctx: Context, guild_id: GuildId, member: Member) {
+        handle_member_addition(&ctx, guild_id, &member);
+    }
+
+    fn voice_state_update(
+        &self,
+        ctx: Context,
+        guild_id: Option<GuildId>,
+        old: Option<VoiceState>,
+        new: VoiceState,
+    ) {
+        handle_voice_state(&ctx, guild_id, old, &new);
+    }
+
+    fn message(&self, ctx: Context, mut msg: Message) {
+        if msg.author.bot {
+            return;
+        }
+
+        // Discord turns messages that are too long into a message.txt
+        // attachment, command and all
+        let bot_user_id = bot_user_id(&ctx);
+        let is_private = || {
+            msg.channel(&ctx)
+                .is_some_and(|channel| channel.private().is_some())
+        };
+        let read_attachment = |msg: &Message| {
+            let read_attachments = is_feature_enabled(
+                &ctx.data.read(),
+                msg.guild_id.map(|guild_id| guild_id.0),
+                "text-attachments",
+            );
+
+            read_attachments.then(|| attachment_text(msg)).flatten()
+        };
+
+        if let Some(content) = long_message_content(&msg, bot_user_id, is_private, read_attachment)
+        {
+            msg.content = content;
+        }
+
+        if msg.is_private()
+            && (handle_wizard_message(&ctx, &msg)
+                || handle_calibration_message(&ctx, &msg)
+                || handle_sync_message(&ctx, &msg))
+        {
+            return;
+        }
+
+        let command = match is_command(&ctx, &msg) {
+            Some(command) => command,
+            None => return,
+        };
+
+        if command.entire.is_empty() {
+            let response = ctx
+                .data
+                .read()
+                .get::<GreetingsKey>()
+                .expect("Mention: Unable to retrieve greetings")
+                .mention_response()
+                .to_string();
+
+            msg.channel_id.say(&ctx, response).ok();
+            return;
+        }
+
+        debug!(
+            "Received command; first word: \"{}\", rest: \"{}\"",
+            redact::text(command.first_word),
+            redact::text(command.rest)
+        );
+
+        let data = ctx.data.read();
+
+        let first_word = command.first_word.to_lowercase();
+        let guild_id = msg.guild_id.map(|guild_id| guild_id.0);
+
+        // Servers can give memes and commands names of their own
+        let alias = guild_id.and_then(|guild_id| resolve_alias(&data, guild_id, &first_word));
+
+        let first_word = match &alias {
+            Some(command) if command != aliases::DEFAULT_MEME => command.clone(),
+            _ => first_word,
+        };
+
+        drop(data);
+
+        let is_admin_command = match route_command(&ctx, &msg, &first_word, command.rest) {
+            Route::Handled => return,
+            Route::Admin => true,
+            Route::Everyone => false,
+        };
+
+        let data = ctx.data.read();
+
+        if is_admin_command && first_word == "quit" {
+            info!("User requested quit: {}", redact::user(&msg.author));
+
+            let shard_manager = match data.get::<ShardManagerKey>() {
+                Some(shard_manager) => shard_manager,
+                None => {
+                    process::exit(1);
+                }
+            };
+
+            shard_manager.lock().shutdown_all();
+        } else if is_admin_command && first_word == "reload" {
+            drop(data);
+
+            reload_config(&ctx, &msg);
+        } else if is_admin_command && first_word == "dumpconfig" {
+            drop(data);
+
+            dump_config(&ctx, &msg);
+        } else if is_admin_command && first_word == "addmeme" {
+            drop(data);
+
+            start_wizard(&ctx, &msg);
+        } else if is_admin_command && first_word == "import" {
+            drop(data);
+
+            import(&ctx, &msg, command.rest);
+        } else if is_admin_command && first_word == "sync" {
+            drop(data);
+
+            sync(&ctx, &msg, command.rest);
+        } else if is_admin_command && first_word == "removememe" {
+            drop(data);
+
+            remove_meme(&ctx, &msg, command.rest);
+        } else if is_admin_command && first_word == "setregion" {
+            drop(data);
+
+            set_region(&ctx, &msg, command.rest);
+        } else if is_admin_command && first_word == "undo" {
+            drop(data);
+
+            undo(&ctx, &msg);
+        } else if is_admin_command && first_word == "broadcast" {
+            drop(data);
+
+            broadcast(&ctx, &msg, command.rest);
+        } else if is_admin_command && first_word == "calibrate" {
+            drop(data);
+
+            start_calibration(&ctx, &msg, command.rest);
+        } else if is_admin_command && first_word == "suggestregion" {
+            drop(data);
+
+            suggest_region_command(&ctx, &msg, command.rest);
+        } else if is_admin_command && first_word == "admins" {
+            drop(data);
+
+            let lines = admin_session_lines(&ctx);
+
+            send_paginated(&ctx, msg.channel_id, msg.author.id, &lines);
+        } else if is_admin_command && first_word == "revoke" {
+            drop(data);
+
+            revoke_command(&ctx, &msg, command.rest);
+        } else if is_admin_command && first_word == "purgeuser" {
+            drop(data);
+
+            purge_user(&ctx, &msg, command.rest);
+        } else if first_word == "forgetme" {
+            drop(data);
+
+            forget_me(&ctx, &msg, command.rest);
+        } else if first_word == "help" {
+            drop(data);
+
+            send_paginated(&ctx, msg.channel_id, msg.author.id, &help_lines());
+        } else if first_word == "memes" {
+            let lines = meme_list_lines(
+                &data
+                    .get::<MemesKey>()
+                    .expect("Command memes: Unable to retrieve memes")
+                    .load(),
+                command.rest.split_whitespace().next(),
+            );
+
+            drop(data);
+
+            send_paginated(&ctx, msg.channel_id, msg.author.id, &lines);
+        } else if first_word == "managers" {
+            drop(data);
+
+            managers_command(&ctx, &msg, command.rest);
+        } else if first_word == "announcements" {
+            drop(data);
+
+            announcements_command(&ctx, &msg, command.rest);
+        } else if first_word == "hotkeys" {
+            drop(data);
+
+            hotkeys_command(&ctx, &msg, command.rest);
+        } else if first_word == "about" {
+            drop(data);
+
+            about_command(&ctx, &msg, command.rest);
+        } else if first_word == "aliases" {
+            drop(data);
+
+            aliases_command(&ctx, &msg, command.rest);
+        } else if first_word == "features" {
+            drop(data);
+
+            features_command(&ctx, &msg, command.rest);
+        } else if first_word == "emojistrip" {
+            drop(data);
+
+            emoji_strip(&ctx, &msg, command.rest);
+        } else if first_word == "factcheck" {
+            drop(data);
+
+            fact_check(&ctx, &msg, command.rest);
+        } else if first_word == "pollmeme" {
+            drop(data);
+
+            poll_meme(&ctx, &msg, command.rest);
+        } else if first_word == "makeemoji" {
+            drop(data);
+
+            make_emoji(&ctx, &msg, command.rest);
+        } else if first_word == "event" {
+            drop(data);
+
+            event_command(&ctx, &msg, command.rest);
+        } else if first_word == "welcome" {
+            drop(data);
+
+            welcome_command(&ctx, &msg, command.rest);
+        } else if first_word == "voicememes" {
+            drop(data);
+
+            voice_memes_command(&ctx, &msg, command.rest);
+        } else if first_word == "locale" {
+            drop(data);
+
+            set_guild_locale(&ctx, &msg, command.rest);
+        } else if first_word == "search" {
+            drop(data);
+
+            search_command(&ctx, &msg, command.rest);
+        } else if (first_word == "preset" && !is_feature_enabled(&data, guild_id, "presets"))
+            || (first_word == "random" && !is_feature_enabled(&data, guild_id, "random"))
+        {
+            drop(data);
+
+            msg.channel_id
+                .say(
+                    &ctx,
+                    format!("`{}` is turned off in this server.", first_word),
+                )
+                .ok();
+        } else if first_word == "preset" {
+            drop(data);
+
+            preset_command(&ctx, &msg, command.rest);
+        } else if first_word == "random" {
+            let memes = data
+                .get::<MemesKey>()
+                .expect("Command random: Unable to retrieve memes")
+                .load();
+
+            let first_word_of_rest = command.rest.split_whitespace().next().unwrap_or("");
+
+            // The text may start with the name of a category to pick from;
+            // otherwise, any meme will do
+            let (pool, text) = match memes.in_category(first_word_of_rest) {
+                Some(pool) => (pool, command.rest[first_word_of_rest.len()..].trim_start()),
+                None => (memes.all(), command.rest),
+            };
+
+            let meme = match choose_weighted(&pool, peek_seed(text)) {
+                Some(meme) => meme,
+                None => {
+                    msg.channel_id
+                        .say(&ctx, "I have no idea what's going on. (No memes loaded.)")
+                        .ok();
+                    return;
+                }
+            };
+
+            drop(data);
+
+            send_meme(&ctx, &msg, meme, text);
+        } else {
+            drop(data);
+
+            meme_command(&ctx, &msg, &command, alias.as_deref(), &mut |meme, text| {
+                send_meme(&ctx, &msg, meme, text)
+            });
+        }
+    }
+}
+
+fn main() {
+    if env::args().skip(1).any(|arg| arg == "--print-schema") {
+        println!("{}", schema::CONFIG_SCHEMA);
+        return;
+    }
+
+    dotenv().ok();
+    config_warnings::init();
+
+    // Collect basic config
+    let bot_admin_password = match env::var("BOT_ADMIN_PASSWORD") {
+        Ok(mut password) => {
+            password = password.trim().to_string();
+
+            if password.is_empty() {
+                None
+            } else {
+                Some(password)
+            }
+        }
+        Err(_) => None,
+    };
+
+    if bot_admin_password.is_none() {
+        warn!("No bot admin password specified");
+    }
+
+    let slow_request_budget = match env::var("SLOW_REQUEST_MS") {
+        Ok(milliseconds) => match milliseconds.trim().parse::<u64>() {
+            Ok(milliseconds) => Duration::from_millis(milliseconds),
+            Err(_) => {
+                error!("SLOW_REQUEST_MS must be a number of milliseconds");
+                process::exit(1);
+            }
+        },
+        Err(_) => Duration::from_millis(2000),
+    };
+
+    // An empty list is treated as no list, rather than leaving every guild
+    let allowed_guilds = env::var("ALLOWED_GUILDS")
+        .ok()
+        .filter(|guilds| !guilds.trim().is_empty())
+        .map(|guilds| parse_guild_list("ALLOWED_GUILDS", &guilds));
+    let denied_guilds = env::var("DENIED_GUILDS")
+        .map(|guilds| parse_guild_list("DENIED_GUILDS", &guilds))
+        .unwrap_or_default();
+
+    let gallery_url = env::var("GALLERY_URL")
+        .ok()
+        .map(|url| url.trim().to_string())
+        .filter(|url| !url.is_empty());
+
+    let terms = env::var("TERMS")
+        .ok()
+        .map(|terms| terms.trim().to_string())
+        .filter(|terms| !terms.is_empty());
+
+    let mut activities = Vec::<BotActivity>::new();
+
+    if let Ok(activity_list) = env::var("BOT_ACTIVITIES") {
+        for activity in activity_list.split(';').filter(|a| !a.trim().is_empty()) {
+            match BotActivity::parse(activity) {
+                Some(activity) => activities.push(activity),
+                None => warn!(
+                    "BOT_ACTIVITIES contains \"{}\", which doesn't start with \"playing\" or \"listening to\"",
+                    activity.trim()
+                ),
+            }
+        }
+    }
+
+    let activity_interval = match env::var("BOT_ACTIVITY_INTERVAL_SECS") {
+        Ok(seconds) => match seconds.trim().parse::<u64>() {
+            Ok(seconds) if seconds > 0 => Duration::from_secs(seconds),
+            _ => {
+                error!("BOT_ACTIVITY_INTERVAL_SECS must be a positive number of seconds");
+                process::exit(1);
+            }
+        },
+        Err(_) => Duration::from_secs(300),
+    };
+
+    let heartbeat_interval = match env::var("HEARTBEAT_INTERVAL_MINS") {
+        Ok(minutes) if minutes.trim().is_empty() => None,
+        Ok(minutes) => match minutes.trim().parse::<u64>() {
+            Ok(minutes) if minutes > 0 => Some(Duration::from_secs(minutes * 60)),
+            _ => {
+                error!("HEARTBEAT_INTERVAL_MINS must be a positive number of minutes");
+                process::exit(1);
+            }
+        },
+        Err(_) => None,
+    };
+
+    // Usage counts and cached downloads are kept forever unless these are set
+    let usage_retention = match env::var("USAGE_RETENTION_DAYS") {
+        Ok(days) if days.trim().is_empty() => None,
+        Ok(days) => match days
+            .trim()
+            .parse::<u64>()
+            .ok()
+            .filter(|days| *days > 0)
+            .and_then(|days| days.checked_mul(24 * 60 * 60))
+        {
+            Some(seconds) => Some(Duration::from_secs(seconds)),
+            None => {
+                error!("USAGE_RETENTION_DAYS must be a positive number of days");
+                process::exit(1);
+            }
+        },
+        Err(_) => None,
+    };
+
+    let cache_retention = match env::var("CACHE_RETENTION_DAYS") {
+        Ok(days) if days.trim().is_empty() => None,
+        Ok(days) => match days
+            .trim()
+            .parse::<u64>()
+            .ok()
+            .filter(|days| *days > 0)
+            .and_then(|days| days.checked_mul(24 * 60 * 60))
+        {
+            Some(seconds) => Some(Duration::from_secs(seconds)),
+            None => {
+                error!("CACHE_RETENTION_DAYS must be a positive number of days");
+                process::exit(1);
+            }
+        },
+        Err(_) => None,
+    };
+
+    let error_delete_delay = match env::var("ERROR_DELETE_SECS") {
+        Ok(seconds) if seconds.trim().is_empty() => None,
+        Ok(seconds) => match seconds.trim().parse::<u64>() {
+            Ok(seconds) if seconds > 0 => Some(Duration::from_secs(seconds)),
+            _ => {
+                error!("ERROR_DELETE_SECS must be a positive number of seconds");
+                process::exit(1);
+            }
+        },
+        Err(_) => None,
+    };
+
+    let mut output_settings = OutputSettings::default();
+
+    if let Ok(compression) = env::var("PNG_COMPRESSION") {
+        match OutputSettings::compression_from_name(compression.trim()) {
+            Some(compression) => output_settings.compression = compression,
+            None => {
+                error!("PNG_COMPRESSION must be fast, default, best, huffman, or rle");
+                process::exit(1);
+            }
+        }
+    }
+
+    if let Ok(filter) = env::var("PNG_FILTER") {
+        match OutputSettings::filter_from_name(filter.trim()) {
+            Some(filter) => output_settings.filter = filter,
+            None => {
+                error!("PNG_FILTER must be none, sub, up, avg, or paeth");
+                process::exit(1);
+            }
+        }
+    }
+
+    if let Ok(max_width) = env::var("OUTPUT_MAX_WIDTH") {
+        match max_width.trim().parse::<u32>() {
+            Ok(max_width) if max_width > 0 => output_settings.max_width = Some(max_width),
+            _ => {
+                error!("OUTPUT_MAX_WIDTH must be a positive number of pixels");
+                process::exit(1);
+            }
+        }
+    }
+
+    output_settings.embed_attribution = env::var("EMBED_ATTRIBUTION")
+        .map(|embed| embed.trim() == "true")
+        .unwrap_or(false);
+
+    let font_cache_dir = env::var("FONT_CACHE_DIR").unwrap_or("font_cache".into());
+
+    let mut fetch_settings = FetchSettings::default();
+
+    if let Ok(timeout) = env::var("FETCH_TIMEOUT_SECS") {
+        match timeout.trim().parse::<u64>() {
+            Ok(timeout) if timeout > 0 => fetch_settings.timeout = Duration::from_secs(timeout),
+            _ => {
+                error!("FETCH_TIMEOUT_SECS must be a positive number of seconds");
+                process::exit(1);
+            }
+        }
+    }
+
+    if let Ok(max_redirects) = env::var("FETCH_MAX_REDIRECTS") {
+        match max_redirects.trim().parse::<usize>() {
+            Ok(max_redirects) => fetch_settings.max_redirects = max_redirects,
+            Err(_) => {
+                error!("FETCH_MAX_REDIRECTS must be a number");
+                process::exit(1);
+            }
+        }
+    }
+
+    fetch_settings.proxy = env::var("FETCH_PROXY")
+        .ok()
+        .map(|proxy| proxy.trim().to_string())
+        .filter(|proxy| !proxy.is_empty());
+    fetch_settings.allow_private_addresses = env::var("FETCH_ALLOW_PRIVATE_ADDRESSES")
+        .map(|allow| allow.trim() == "true")
+        .unwrap_or(false);
+
+    if let Err(reason) = fetch::configure(fetch_settings) {
+        error!("{}", reason);
+        process::exit(1);
+    }
+
+    let safe_mode = env::var("SAFE_MODE")
+        .map(|safe_mode| safe_mode.trim() == "true")
+        .unwrap_or(false);
+
+    if safe_mode {
+        if env::var("TEMPLATE_STORE_URL").is_ok() {
+            error!("TEMPLATE_STORE_URL can't be used in safe mode, which turns off downloads");
+            process::exit(1);
+        }
+
+        info!("Safe mode is on; downloads, text attachments, avatars, and adding memes are turned off");
+    }
+
+    let overflow_store = match env::var("OVERFLOW_UPLOAD_URL") {
+        Ok(url) if url.trim().is_empty() => None,
+        Ok(url) => {
+            let public_url = env::var("OVERFLOW_PUBLIC_URL")
+                .ok()
+                .filter(|public_url| !public_url.trim().is_empty());
+
+            if !fetch::is_url(url.trim())
+                || !public_url
+                    .as_deref()
+                    .is_none_or(|public_url| fetch::is_url(public_url.trim()))
+            {
+                error!(
+                    "OVERFLOW_UPLOAD_URL and OVERFLOW_PUBLIC_URL must be http:// or https:// URLs"
+                );
+                process::exit(1);
+            }
+
+            if safe_mode {
+                error!("OVERFLOW_UPLOAD_URL can't be used in safe mode, which turns off downloads and uploads");
+                process::exit(1);
+            }
+
+            Some(Arc::new(UploadStore::new(
+                url.trim(),
+                public_url.as_deref().map(str::trim),
+            )))
+        }
+        Err(_) => None,
+    };
+
+    // Images from users can be checked before they're used, by a service
+    // that flags the ones that shouldn't be
+    match env::var("MODERATION_URL") {
+        Ok(url) if url.trim().is_empty() => {}
+        Ok(url) => {
+            if !fetch::is_url(url.trim()) {
+                error!("MODERATION_URL must be an http:// or https:// URL");
+                process::exit(1);
+            }
+
+            if safe_mode {
+                error!("MODERATION_URL can't be used in safe mode, which turns off downloads and uploads");
+                process::exit(1);
+            }
+
+            if let Err(reason) = moderation::configure(Box::new(WebhookModerator::new(url.trim())))
+            {
+                error!("{}", reason);
+                process::exit(1);
+            }
+        }
+        Err(_) => {}
+    }
+
+    // Features can be turned off unless a server turns them back on
+    let disabled_features: Vec<String> = env::var("DISABLED_FEATURES")
+        .unwrap_or_default()
+        .split(',')
+        .map(|feature| feature.trim().to_lowercase())
+        .filter(|feature| !feature.is_empty())
+        .collect();
+
+    if let Some(feature) = disabled_features
+        .iter()
+        .find(|feature| !is_feature(feature))
+    {
+        error!(
+            "DISABLED_FEATURES: There's no feature called \"{}\"",
+            feature
+        );
+        process::exit(1);
+    }
+
+    if let Err(reason) = capabilities::configure(CapabilitySettings {
+        safe_mode,
+        disabled_features,
+    }) {
+        error!("{}", reason);
+        process::exit(1);
+    }
+
+    let fold_accents = env::var("FOLD_ACCENTS")
+        .map(|fold_accents| fold_accents.trim() == "true")
+        .unwrap_or(false);
+
+    if let Err(reason) = configure_accent_folding(fold_accents) {
+        error!("{}", reason);
+        process::exit(1);
+    }
+
+    // Who users are and what they send can be kept out of the log, for
+    // deployments that ship their logs somewhere else
+    let redact_logs = env::var("REDACT_LOGS")
+        .map(|redact_logs| redact_logs.trim() == "true")
+        .unwrap_or(false);
+    let text_chars = match env::var("REDACT_TEXT_CHARS") {
+        Ok(text_chars) => match text_chars.trim().parse::<usize>() {
+            Ok(text_chars) => text_chars,
+            Err(_) => {
+                error!("REDACT_TEXT_CHARS must be a number");
+                process::exit(1);
+            }
+        },
+        Err(_) => 0,
+    };
+
+    if let Err(reason) = redact::configure(RedactionSettings {
+        redact: redact_logs,
+        text_chars,
+    }) {
+        error!("{}", reason);
+        process::exit(1);
+    }
+
+    // Templates and fonts are read from object storage if a URL is given, so
+    // that deployments don't need a copy of them on disk
+    let store: Arc<dyn TemplateStore + Send + Sync> = match env::var("TEMPLATE_STORE_URL") {
+        Ok(url) => {
+            if !fetch::is_url(&url) {
+                error!("TEMPLATE_STORE_URL must be an http:// or https:// URL");
+                process::exit(1);
+            }
+
+            Arc::new(HttpStore::new(&url))
+        }
+        Err(_) => Arc::new(LocalStore),
+    };
+
+    let config_source = ConfigSource {
+        store,
+        config_filename: env::var("CONFIG_FILE").unwrap_or("config.yml".into()),
+        greetings_filename: env::var("GREETINGS_FILE").unwrap_or("greetings.yml".into()),
+        added_memes: AddedMemesFile {
+            filename: env::var("ADDED_MEMES_FILE").unwrap_or("added_memes.yml".into()),
+            images_dir: env::var("ADDED_MEMES_DIR").unwrap_or("added_memes".into()),
+        },
+        font_cache_dir,
+    };
+
+    let (fonts, memes) = match load_memes(
+        config_source.store.as_ref(),
+        &config_source.config_filename,
+        &config_source.added_memes,
+        &config_source.font_cache_dir,
+    ) {
+        Ok((fonts, memes)) => (fonts, memes),
+        Err(reason) => {
+            error!("{}", reason);
+            process::exit(1);
+        }
+    };
+
+    if fonts.is_empty() {
+        warn!("No fonts were loaded");
+    }
+
+    let greetings = match Greetings::load(
+        config_source.store.as_ref(),
+        &config_source.greetings_filename,
+    ) {
+        Ok(greetings) => greetings,
+        Err(reason) => {
+            error!("{}", reason);
+            process::exit(1);
+        }
+    };
+
+    if memes.is_empty() {
+        warn!("No memes were loaded");
+    }
+
+    // Serve the HTTP API instead of connecting to Discord
+    #[cfg(feature = "http-api")]
+    {
+        if let Ok(address) = env::var("HTTP_API_ADDRESS") {
+            if let Err(reason) =
+                http_api::serve(&address, fonts, MemeRegistry::new(memes), output_settings)
+            {
+                error!("{}", reason);
+                process::exit(1);
+            }
+
+            return;
+        }
+    }
+
+    let discord_bot_token = match env::var("DISCORD_BOT_TOKEN") {
+        Ok(token) => token,
+        Err(_) => {
+            error!("DISCORD_BOT_TOKEN is missing");
+            process::exit(1);
+        }
+    };
+
+    let guild_settings_filename =
+        env::var("GUILD_SETTINGS_FILE").unwrap_or("guild_settings.yml".into());
+
+    let guild_settings = match GuildSettingsStore::load(&guild_settings_filename) {
+        Ok(guild_settings) => guild_settings,
+        Err(reason) => {
+            error!("{}", reason);
+            process::exit(1);
+        }
+    };
+
+    let admin_session_lifetime = match env::var("ADMIN_SESSION_HOURS") {
+        // Sessions that end as soon as they start would lock every admin out,
+        // and so would a number of hours that overflows
+        Ok(hours) => match hours
+            .trim()
+            .parse::<u64>()
+            .ok()
+            .filter(|hours| *hours > 0)
+            .and_then(|hours| hours.checked_mul(60 * 60))
+        {
+            Some(seconds) => Duration::from_secs(seconds),
+            None => {
+                error!("ADMIN_SESSION_HOURS must be a positive number of hours");
+                process::exit(1);
+            }
+        },
+        Err(_) => Duration::from_secs(24 * 60 * 60),
+    };
+
+    let admin_sessions = match AdminSessionStore::load(
+        &env::var("ADMIN_SESSIONS_FILE").unwrap_or("admin_sessions.yml".into()),
+        admin_session_lifetime,
+    ) {
+        Ok(admin_sessions) => admin_sessions,
+        Err(reason) => {
+            error!("{}", reason);
+            process::exit(1);
+        }
+    };
+
+    let presets_filename = env::var("PRESETS_FILE").unwrap_or("presets.yml".into());
+
+    let presets = match PresetStore::load(&presets_filename) {
+        Ok(presets) => presets,
+        Err(reason) => {
+            error!("{}", reason);
+            process::exit(1);
+        }
+    };
+
+    let meme_history = match MemeHistory::load(
+        &env::var("MEME_HISTORY_FILE").unwrap_or("meme_history.yml".into()),
+    ) {
+        Ok(meme_history) => meme_history,
+        Err(reason) => {
+            error!("{}", reason);
+            process::exit(1);
+        }
+    };
+
+    info!("Connecting");
+
+    let mut client = match Client::new(&discord_bot_token, Handler) {
+        Ok(client) => client,
+        Err(reason) => {
+            error!("Unable to create client: {}", reason);
+            process::exit(1);
+        }
+    };
+
+    // Store the ShardManager in the client's data in order to allow event
+    // handler methods to access it
+    {
+        let mut data = client.data.write();
+        data.insert::<ShardManagerKey>(Arc::clone(&client.shard_manager));
+        data.insert::<BotSettingsKey>(BotSettings {
+            id: None,
+            admin_password: bot_admin_password,
+            slow_request_budget,
+            output_settings,
+            terms,
+            activities,
+            activity_interval,
+            heartbeat_interval,
+            retention: RetentionSettings {
+                usage: usage_retention,
+                cache: cache_retention,
+                cache_dir: config_source.font_cache_dir.clone(),
+            },
+            error_delete_delay,
+            overflow_store,
+            tasks_started: false,
+            config_source,
+            allowed_guilds,
+            denied_guilds,
+            gallery_url,
+        });
+        data.insert::<FontsKey>(fonts);
+        data.insert::<MemesKey>(ArcSwap::from_pointee(MemeRegistry::new(memes)));
+        data.insert::<GreetingsKey>(greetings);
+        data.insert::<PaginationsKey>(Paginations::default());
+        data.insert::<GuildSettingsKey>(guild_settings);
+        data.insert::<PresetsKey>(presets);
+        data.insert::<AdminSessionsKey>(Mutex::new(admin_sessions));
+        data.insert::<WizardsKey>(HashMap::new());
+        data.insert::<CalibrationsKey>(HashMap::new());
+        data.insert::<GallerySyncsKey>(HashMap::new());
+        data.insert::<VoiceJoinsKey>(HashMap::new());
+        data.insert::<MemeHistoryKey>(meme_history);
+        data.insert::<UsageCountsKey>(UsageCounts::default());
+    }
+
+    // Seasonal memes turn on and off as the date changes
+    let data = Arc::clone(&client.data);
+
+    thread::spawn(move || loop {
+        thread::sleep(SCHEDULE_REFRESH_INTERVAL);
+
+        data.read()
+            .get::<MemesKey>()
+            .expect("Schedule: Unable to retrieve memes")
+            .load()
+            .refresh_schedule(Date::today());
+    });
 
-        let mut data = ctx.data.write();
-        let settings = data
-            .get_mut::<BotSettingsKey>()
-            .expect("ready(): Unable to retrieve bot settings");
-        settings.id = Some(ready.user.id.0);
+    if let Err(reason) = client.start() {
+        error!("Unable to start client: {}", reason);
+        process::exit(1);
     }
+}
 
-    fn message(&self, ctx: Context, msg: Message) {
-        if msg.author.bot {
-            return;
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chat::mock::{message, reply, with_attachment, MockChat};
+    use code_block::code_block_range;
+    use layout::trim_blank_lines;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use registry::mock::meme;
+    use std::sync::{mpsc, Barrier};
+
+    const BOT_USER_ID: u64 = 1234;
+
+    // Pieces that random input is built from: the prefix and mentions they
+    // can be mistaken for, unusual whitespace, backslashes, and multi-byte
+    // and right-to-left text
+    const PIECES: &[&str] = &[
+        "<@1234>",
+        "<@!1234>",
+        "<@",
+        "<@!",
+        "<#",
+        "<@&",
+        "<a:",
+        ">",
+        ":",
+        "1234",
+        "1",
+        "2",
+        "3",
+        "99999999999999999999",
+        "\\",
+        " ",
+        "  ",
+        "\t",
+        "\n",
+        "\u{a0}",
+        "\u{3000}",
+        "\u{200f}",
+        "drake",
+        "é",
+        "ß",
+        "🐸",
+        "עברית",
+        "مرحبا",
+        "日本語",
+        "\u{301}",
+    ];
+
+    // Pieces of meme text with flags, flag values, and code in it
+    const FLAG_PIECES: &[&str] = &[
+        "--color",
+        "--colour",
+        "--COLOR",
+        "--seed",
+        "--nowrap",
+        "--",
+        "-",
+        "a--nowrap",
+        "rgb(",
+        "255 0 102",
+        ")",
+        "#ff0066",
+        "red",
+        "42",
+        "18446744073709551616",
+        "```",
+        "`",
+        " ",
+        "\t",
+        "\n",
+        "\u{3000}",
+        "é",
+        "🐸",
+        "עברית",
+    ];
+
+    // Pieces of meme text with stages, and `|` where it isn't one
+    const PIPELINE_PIECES: &[&str] = &[
+        "|",
+        " | ",
+        "||",
+        "deepfry",
+        "Mirror",
+        "mirrored",
+        "caption",
+        "\"bottom text\"",
+        "@user1",
+        " ",
+        "\n",
+        "🐸",
+        "עברית",
+    ];
+
+    fn random_text(rng: &mut StdRng, pieces: &[&str]) -> String {
+        (0..rng.gen_range(0, 12))
+            .map(|_| pieces[rng.gen_range(0, pieces.len())])
+            .collect()
+    }
 
-        let command = match is_command(&ctx, &msg) {
-            Some(command) => command,
-            None => return,
-        };
+    fn names(kind: MentionKind, id: u64) -> Option<String> {
+        match (kind, id) {
+            (MentionKind::User, 1) => Some("alice".into()),
+            (MentionKind::Channel, 2) => Some("general".into()),
+            (MentionKind::Role, 3) => Some("mods".into()),
+            _ => None,
+        }
+    }
 
-        if command.entire.is_empty() {
-            msg.channel_id.say(&ctx, "Yes?").ok();
-            return;
+    #[test]
+    fn splits_the_first_word() {
+        let cases = [
+            ("", ("", "")),
+            ("   ", ("", "")),
+            ("drake", ("drake", "")),
+            ("drake top text", ("drake", "top text")),
+            ("  drake \t top  text ", ("drake", "top  text ")),
+            ("drake\nline one\nline two", ("drake", "line one\nline two")),
+            ("drake\u{3000}全角", ("drake", "全角")),
+            ("מם שלום עולם", ("מם", "שלום עולם")),
+            ("\\drake \\n", ("\\drake", "\\n")),
+        ];
+
+        for (text, expected) in cases.iter() {
+            assert_eq!(split_first_word(text), *expected, "{:?}", text);
         }
+    }
 
-        debug!(
-            "Received command; first word: \"{}\", rest: \"{}\"",
-            command.first_word, command.rest
-        );
+    #[test]
+    fn parses_commands() {
+        let cases = [
+            ("", "", "", ""),
+            (" \n ", "", "", ""),
+            ("help", "help", "help", ""),
+            (" drake  a b ", "drake  a b", "drake", "a b"),
+            ("🐸 frog", "🐸 frog", "🐸", "frog"),
+            ("مرحبا بالعالم", "مرحبا بالعالم", "مرحبا", "بالعالم"),
+        ];
+
+        for (text, entire, first_word, rest) in cases.iter() {
+            let command = Command::parse(text);
+
+            assert_eq!(command.entire, *entire, "{:?}", text);
+            assert_eq!(command.first_word, *first_word, "{:?}", text);
+            assert_eq!(command.rest, *rest, "{:?}", text);
+        }
+    }
 
-        let mut data = ctx.data.write();
-        let settings = data
-            .get_mut::<BotSettingsKey>()
-            .expect("Command quit: Unable to retrieve bot settings");
+    #[test]
+    fn finds_the_bot_mention() {
+        let cases = [
+            ("", None),
+            ("<@1234>", Some("")),
+            ("<@!1234> drake", Some(" drake")),
+            ("  <@1234>drake", Some("drake")),
+            ("<@1234", None),
+            ("<@12345> drake", None),
+            ("<@123> drake", None),
+            ("drake <@1234>", None),
+            ("\\<@1234> drake", None),
+            ("<@99999999999999999999999999> drake", None),
+            ("<@1234> עברית", Some(" עברית")),
+        ];
+
+        for (content, expected) in cases.iter() {
+            assert_eq!(
+                after_bot_mention(content, BOT_USER_ID),
+                *expected,
+                "{:?}",
+                content
+            );
+        }
+    }
 
-        let first_word = command.first_word.to_lowercase();
+    #[test]
+    fn replaces_mentions() {
+        let cases = [
+            ("", ""),
+            ("<@1> and <@!1>", "@alice and @alice"),
+            ("<@5>", "@5"),
+            ("<#2> <#5>", "#general #deleted-channel"),
+            ("<@&3> <@&5>", "@mods @deleted-role"),
+            ("<:pepe:12345> <a:dance:6>", ":pepe: :dance:"),
+            ("<@", "<@"),
+            ("<@1", "<@1"),
+            ("<@<@1>>", "<@@alice>"),
+            ("<@99999999999999999999>", "@99999999999999999999"),
+            ("<#99999999999999999999>", "#deleted-channel"),
+            (
+                "<@123456789012345678901234567890123>",
+                "<@123456789012345678901234567890123>",
+            ),
+            ("\\<@1>", "\\@alice"),
+            ("שלום <@1>", "שלום @alice"),
+        ];
+
+        for (text, expected) in cases.iter() {
+            assert_eq!(replace_mentions(text, names), *expected, "{:?}", text);
+        }
+    }
 
-        let is_private_channel = match msg.channel(&ctx) {
-            Some(channel) => channel.private().is_some(),
-            None => false,
+    #[test]
+    fn replaces_mentions_once() {
+        // A role named like a mention isn't replaced again
+        let name_of = |kind, _| match kind {
+            MentionKind::Role => Some("<@&3>".to_string()),
+            _ => None,
         };
 
-        if is_private_channel && first_word == "auth" {
-            if settings.admin_ids.contains(msg.author.id.as_u64()) {
-                msg.channel_id.say(&ctx, "You are already authorized.").ok();
-                return;
-            }
+        assert_eq!(replace_mentions("<@&3>", name_of), "@<@&3>");
+    }
 
-            if let Some(admin_password) = &settings.admin_password {
-                if admin_password == command.rest {
-                    info!(
-                        "User sucessfully authorized as admin: {}#{}",
-                        msg.author.name, msg.author.discriminator
-                    );
+    #[test]
+    fn splits_random_text_into_words() {
+        let mut rng = StdRng::seed_from_u64(1413);
 
-                    settings.admin_ids.push(msg.author.id.0);
+        for _ in 0..10_000 {
+            let text = random_text(&mut rng, PIECES);
+            let (first_word, rest) = split_first_word(&text);
 
-                    msg.channel_id.say(&ctx, "Successfully authorized.").ok();
-                } else {
-                    info!(
-                        "User failed attempt to authorize as admin: {}#{}",
-                        msg.author.name, msg.author.discriminator
-                    );
+            assert!(!first_word.contains(char::is_whitespace), "{:?}", text);
+            assert!(!rest.starts_with(char::is_whitespace), "{:?}", text);
+            assert_eq!(first_word.is_empty(), text.trim().is_empty(), "{:?}", text);
+            assert!(text.trim_start().starts_with(first_word), "{:?}", text);
+            assert!(text.ends_with(rest), "{:?}", text);
+
+            let command = Command::parse(&text);
+
+            assert_eq!(command.entire, text.trim(), "{:?}", text);
+            assert_eq!(command.first_word, first_word, "{:?}", text);
+            assert_eq!(command.rest, rest.trim_end(), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn finds_the_bot_mention_in_random_text() {
+        let mut rng = StdRng::seed_from_u64(1413);
+
+        for _ in 0..10_000 {
+            let text = random_text(&mut rng, PIECES);
+            let mentioned = text.trim_start().starts_with("<@1234>")
+                || text.trim_start().starts_with("<@!1234>");
+
+            match after_bot_mention(&text, BOT_USER_ID) {
+                Some(rest) => {
+                    assert!(mentioned, "{:?}", text);
+                    assert!(text.ends_with(rest), "{:?}", text);
                 }
+                None => assert!(!mentioned, "{:?}", text),
             }
-        } else if is_private_channel
-            && first_word == "quit"
-            && settings.admin_ids.contains(msg.author.id.as_u64())
-        {
-            info!(
-                "User requested quit: {}#{}",
-                msg.author.name, msg.author.discriminator
+        }
+    }
+
+    #[test]
+    fn replaces_every_mention_in_random_text() {
+        let mut rng = StdRng::seed_from_u64(1413);
+        let re_mention = Regex::new(r"<(?:@!?|#|@&)\d{1,32}>|<a?:[a-zA-Z0-9~_]+:\d{1,32}>")
+            .expect("Unable to create mention matching pattern");
+
+        for _ in 0..10_000 {
+            let text = random_text(&mut rng, PIECES);
+            let replaced = replace_mentions(&text, names);
+
+            assert!(
+                !re_mention.is_match(&replaced),
+                "{:?} -> {:?}",
+                text,
+                replaced
             );
+        }
+    }
 
-            let shard_manager = match data.get::<ShardManagerKey>() {
-                Some(shard_manager) => shard_manager,
-                None => {
-                    process::exit(1);
-                }
-            };
+    const GUILD: u64 = 100;
+    const ADMIN: u64 = 1;
+    const USER: u64 = 2;
+
+    fn bot_settings(admin_password: &str, terms: Option<&str>) -> BotSettings {
+        BotSettings {
+            id: Some(BOT_USER_ID),
+            admin_password: Some(admin_password.into()),
+            slow_request_budget: Duration::from_secs(1),
+            output_settings: OutputSettings::default(),
+            terms: terms.map(String::from),
+            activities: Vec::new(),
+            activity_interval: Duration::from_secs(60),
+            heartbeat_interval: None,
+            retention: RetentionSettings {
+                usage: None,
+                cache: None,
+                cache_dir: String::new(),
+            },
+            error_delete_delay: None,
+            overflow_store: None,
+            tasks_started: false,
+            config_source: ConfigSource {
+                store: Arc::new(LocalStore),
+                config_filename: String::new(),
+                greetings_filename: String::new(),
+                added_memes: AddedMemesFile {
+                    filename: String::new(),
+                    images_dir: String::new(),
+                },
+                font_cache_dir: String::new(),
+            },
+            allowed_guilds: None,
+            denied_guilds: Vec::new(),
+            gallery_url: None,
+        }
+    }
 
-            shard_manager.lock().shutdown_all();
-        } else {
-            if command.entire.is_empty() {
-                return;
-            }
+    /// A chat where `ADMIN` is already authenticated
+    fn chat(terms: Option<&str>) -> MockChat {
+        let chat = MockChat::new();
+        let mut data = chat.data().write();
 
-            let memes = data
-                .get::<MemesKey>()
-                .expect("Create meme: Unable to retrieve memes");
+        data.insert::<BotSettingsKey>(bot_settings("hunter2", terms));
+        data.get::<AdminSessionsKey>()
+            .expect("Unable to retrieve admin sessions")
+            .lock()
+            .start(ADMIN, "admin#0001");
 
-            let matches = memes
-                .iter()
-                .filter(|meme| meme.command == first_word)
-                .collect::<Vec<&Meme>>();
+        drop(data);
 
-            let matching_command = matches.first();
+        chat
+    }
 
-            let text: &str;
-            let meme: &Meme;
+    fn route(chat: &MockChat, guild_id: Option<u64>, author_id: u64, text: &str) -> Route {
+        let command = Command::parse(text);
 
-            if matching_command.is_some() {
-                meme = matching_command.unwrap();
-                text = command.rest;
-            } else {
-                let matches = memes
-                    .iter()
-                    .filter(|meme| meme.is_default)
-                    .collect::<Vec<&Meme>>();
+        route_command(
+            chat,
+            &message(guild_id, author_id, text),
+            command.first_word,
+            command.rest,
+        )
+    }
 
-                let default_command = matches.first();
+    #[test]
+    fn routes_admin_commands_from_admins_in_dms() {
+        let chat = chat(None);
 
-                if default_command.is_some() {
-                    meme = default_command.unwrap();
-                    text = command.entire;
-                } else {
-                    msg.channel_id
-                        .say(&ctx, "I have no idea what's going on. (No memes loaded.)")
-                        .ok();
-                    return;
-                }
-            }
+        for command in ADMIN_COMMANDS {
+            assert_eq!(
+                route(&chat, None, ADMIN, command),
+                Route::Admin,
+                "{}",
+                command
+            );
+        }
 
-            let text = meme.text_prefix.clone() + &text.to_uppercase() + &meme.text_suffix;
+        assert!(chat.take_said().is_empty());
+    }
 
-            let text = expand_mentions(&ctx, &msg, text);
+    #[test]
+    fn routes_admin_commands_from_anyone_else_like_memes() {
+        let chat = chat(None);
+        let cases = [
+            (None, USER, "quit"),
+            (None, USER, "reload"),
+            (Some(GUILD), ADMIN, "quit"),
+            (Some(GUILD), USER, "purgeuser 1"),
+            (None, ADMIN, "drake quit"),
+            (None, ADMIN, "help"),
+        ];
+
+        for (guild_id, author_id, text) in cases.iter() {
+            assert_eq!(
+                route(&chat, *guild_id, *author_id, text),
+                Route::Everyone,
+                "{:?}",
+                text
+            );
+        }
 
-            debug!("Creating meme \"{}\" with text \"{}\"", meme.command, text);
+        assert!(chat.take_said().is_empty());
+    }
 
-            let generated_image_filename = meme.command.clone() + ".png";
+    #[test]
+    fn authorizes_admins_with_the_password() {
+        let chat = chat(None);
 
-            let mut image = meme.image.clone();
+        assert_eq!(route(&chat, None, USER, "auth hunter3"), Route::Handled);
+        assert!(chat.take_said().is_empty());
+        assert_eq!(route(&chat, None, USER, "quit"), Route::Everyone);
 
-            let temp_dir =
-                tempdir().expect("Command create_image: Failed to create temporary directory");
+        assert_eq!(route(&chat, None, USER, "auth hunter2"), Route::Handled);
+        assert_eq!(chat.take_said(), vec!["Successfully authorized."]);
+        assert_eq!(route(&chat, None, USER, "quit"), Route::Admin);
 
-            let file_path = format!(
-                "{}/{}",
-                temp_dir
-                    .path()
-                    .to_str()
-                    .expect("Command create_image: Failed to retrieve temporary directory path"),
-                generated_image_filename
-            );
+        assert_eq!(route(&chat, None, USER, "auth hunter2"), Route::Handled);
+        assert_eq!(chat.take_said(), vec!["You are already authorized."]);
+    }
 
-            let fonts = data
-                .get::<FontsKey>()
-                .expect("Create meme: Unable to retrieve fonts");
-
-            let font = match fonts.get(&meme.font) {
-                Some(font) => font,
-                None => match fonts.values().next() {
-                    Some(font) => font,
-                    None => {
-                        msg.channel_id
-                            .say(
-                                &ctx,
-                                "I don't know how to say this...Literally. (No fonts loaded.)",
-                            )
-                            .ok();
-                        return;
-                    }
-                },
-            };
+    #[test]
+    fn only_authorizes_in_dms() {
+        let chat = chat(None);
 
-            let color = Pixel::from_channels(0, 0, 0, 255);
-            let scale = meme.scale;
+        assert_eq!(
+            route(&chat, Some(GUILD), USER, "auth hunter2"),
+            Route::Everyone
+        );
+        assert!(chat.take_said().is_empty());
+        assert_eq!(route(&chat, None, USER, "quit"), Route::Everyone);
+    }
 
-            let line_height = get_line_height(&font, scale);
+    #[test]
+    fn waits_for_the_terms_to_be_accepted() {
+        let mut chat = chat(Some("Be nice."));
 
-            // TODO: Word wrap
+        assert_eq!(route(&chat, Some(GUILD), USER, "drake hi"), Route::Handled);
+        let said = chat.take_said();
+        assert_eq!(said.len(), 1);
+        assert!(said[0].ends_with("`accept-terms`:\nBe nice."));
 
-            let lines: Vec<&str> = text.lines().collect();
-            let mut curr_y = meme.center.y - (line_height * (lines.len() as u32) / 2);
+        // Even admins' commands wait
+        assert_eq!(route(&chat, Some(GUILD), ADMIN, "help"), Route::Handled);
+        chat.take_said();
 
-            for line in lines {
-                let line = line.trim();
+        // DMs don't belong to any server
+        assert_eq!(route(&chat, None, USER, "drake hi"), Route::Everyone);
 
-                let x = meme.center.x - get_text_width(&font, &line, scale) / 2;
+        assert_eq!(
+            route(&chat, Some(GUILD), USER, "accept-terms"),
+            Route::Handled
+        );
+        assert_eq!(
+            chat.take_said(),
+            vec!["You need the Administrator permission to do that."]
+        );
 
-                debug!("Drawing text at ({}, {})", x, curr_y);
+        chat.guild_admins.push((GuildId(GUILD), UserId(USER)));
 
-                image = drawing::draw_text(&mut image, color, x, curr_y, scale, &font, &line);
+        assert_eq!(
+            route(&chat, Some(GUILD), USER, "accept-terms"),
+            Route::Handled
+        );
+        assert_eq!(
+            chat.take_said(),
+            vec!["Thanks! I'm ready to go. (Try `help`.)"]
+        );
 
-                curr_y += line_height;
-            }
+        assert_eq!(route(&chat, Some(GUILD), USER, "drake hi"), Route::Everyone);
+        assert_eq!(route(&chat, Some(200), USER, "drake hi"), Route::Handled);
+    }
 
-            match image.save(&file_path) {
-                Ok(_) => {
-                    msg.channel_id
-                        .send_files(&ctx, vec![file_path.as_str()], |m| m)
-                        .ok();
+    fn tokens(content: &str, is_private: bool) -> Option<(&str, &str, &str)> {
+        parse_command(content, BOT_USER_ID, || is_private)
+            .map(|command| (command.entire, command.first_word, command.rest))
+    }
 
-                    if let Err(reason) = remove_file(&file_path) {
-                        warn!("Command create_image: Temporary file \"{}\" could not be deleted: {:?}", file_path, reason);
-                    }
-                }
-                Err(reason) => {
-                    msg.channel_id
-                        .say(&ctx, "Sorry, something went wrong! Maybe try again?")
-                        .ok();
+    #[test]
+    fn reads_long_messages_only_when_meant_for_the_bot() {
+        let read = |content: &str, is_private: bool| {
+            let msg = with_attachment(message(Some(GUILD), USER, content), "message.txt");
+            let mut reads = 0;
+
+            let content = long_message_content(
+                &msg,
+                BOT_USER_ID,
+                || is_private,
+                |_| {
+                    reads += 1;
+                    Some("drake hi".into())
+                },
+            );
 
-                    warn!(
-                        "Command create_image: Failed to save image to \"{}\": {:?}",
-                        file_path, reason
-                    );
-                }
-            }
+            (content, reads)
+        };
+
+        // Messages that aren't for the bot are never downloaded
+        assert_eq!(read("", false), (None, 0));
+        assert_eq!(read("look at this", false), (None, 0));
+        assert_eq!(read("<@5678>", false), (None, 0));
+        assert_eq!(read("<@1234> drake", false), (None, 0));
+        assert_eq!(read("look at this", true), (None, 0));
+
+        assert_eq!(read("", true), (Some("drake hi".into()), 1));
+        assert_eq!(
+            read(" <@!1234> ", false),
+            (Some("<@!1234> drake hi".into()), 1)
+        );
+
+        // Other text files are left to the commands that read them
+        let msg = with_attachment(message(Some(GUILD), USER, "<@1234>"), "notes.txt");
+        let content = long_message_content(
+            &msg,
+            BOT_USER_ID,
+            || true,
+            |_| panic!("notes.txt was downloaded"),
+        );
+
+        assert_eq!(content, None);
+    }
+
+    #[test]
+    fn tokenizes_mentions_and_dms_the_same() {
+        let cases = [
+            (
+                "<@1234> drake  hi  ",
+                false,
+                Some(("drake  hi", "drake", "hi")),
+            ),
+            (
+                "<@!1234> drake  hi  ",
+                false,
+                Some(("drake  hi", "drake", "hi")),
+            ),
+            (
+                " <@!1234>\n\tdrake\thi\n",
+                false,
+                Some(("drake\thi", "drake", "hi")),
+            ),
+            ("drake  hi  ", true, Some(("drake  hi", "drake", "hi"))),
+            ("\n drake  hi  ", true, Some(("drake  hi", "drake", "hi"))),
+            (
+                "<@1234> drake  hi  ",
+                true,
+                Some(("drake  hi", "drake", "hi")),
+            ),
+            ("<@1234>", false, Some(("", "", ""))),
+            ("<@!1234>  \n", false, Some(("", "", ""))),
+            ("  ", true, Some(("", "", ""))),
+            ("drake hi", false, None),
+            ("hi <@1234>", false, None),
+            ("<@1234 drake", false, None),
+        ];
+
+        for (content, is_private, expected) in cases.iter() {
+            assert_eq!(tokens(content, *is_private), *expected, "{:?}", content);
         }
     }
-}
 
-fn main() {
-    dotenv().ok();
-    env_logger::init();
+    #[test]
+    fn tokenizes_random_mentions_and_dms_the_same() {
+        let mut rng = StdRng::seed_from_u64(1475);
 
-    // Collect basic config
-    let discord_bot_token = match env::var("DISCORD_BOT_TOKEN") {
-        Ok(token) => token,
-        Err(_) => {
-            error!("DISCORD_BOT_TOKEN is missing");
-            process::exit(1);
+        for _ in 0..10_000 {
+            let text = random_text(&mut rng, PIECES);
+
+            // A DM that starts with a mention has it taken off
+            if after_bot_mention(&text, BOT_USER_ID).is_some() {
+                continue;
+            }
+
+            let mentioned = format!("<@!1234>{}", text);
+
+            assert_eq!(tokens(&mentioned, false), tokens(&text, true), "{:?}", text);
         }
-    };
+    }
 
-    let bot_admin_password = match env::var("BOT_ADMIN_PASSWORD") {
-        Ok(mut password) => {
-            password = password.trim().to_string();
+    /// A chat like `chat(None)` with `drake`, `drake two`, and the default meme
+    /// loaded
+    fn meme_chat() -> MockChat {
+        let chat = chat(None);
+        let mut data = chat.data().write();
 
-            if password.is_empty() {
-                None
-            } else {
-                Some(password)
-            }
+        data.insert::<MemesKey>(ArcSwap::from_pointee(MemeRegistry::new(vec![
+            meme("drake", false),
+            meme("drake two", false),
+            meme("default", true),
+        ])));
+        data.insert::<GreetingsKey>(Greetings::default());
+
+        drop(data);
+
+        chat
+    }
+
+    /// The command of the meme that `msg` makes, and the text that goes on it
+    fn chosen_meme(
+        chat: &MockChat,
+        msg: &Message,
+        alias: Option<&str>,
+    ) -> Option<(String, String)> {
+        let command = Command::parse(&msg.content);
+        let mut chosen = None;
+
+        meme_command(chat, msg, &command, alias, &mut |meme, text| {
+            chosen = Some((meme.command.clone(), text.to_string()))
+        });
+
+        chosen
+    }
+
+    fn chosen(command: &str, text: &str) -> Option<(String, String)> {
+        Some((command.into(), text.into()))
+    }
+
+    #[test]
+    fn makes_the_longest_matching_meme() {
+        let chat = meme_chat();
+        let cases = [
+            ("drake hi", chosen("drake", "hi")),
+            ("Drake  hi there", chosen("drake", "hi there")),
+            ("drake two hi", chosen("drake two", "hi")),
+            ("drake twohi", chosen("drake", "twohi")),
+            ("hello there", chosen("default", "hello there")),
+            ("\\drake hi", chosen("default", "drake hi")),
+        ];
+
+        for (text, expected) in cases.iter() {
+            assert_eq!(
+                chosen_meme(&chat, &message(None, USER, text), None),
+                *expected,
+                "{:?}",
+                text
+            );
         }
-        Err(_) => None,
-    };
 
-    if bot_admin_password.is_none() {
-        warn!("No bot admin password specified");
+        assert!(chat.take_said().is_empty());
     }
 
-    let (fonts, memes) = load_memes(&env::var("CONFIG_FILE").unwrap_or("config.yml".into()));
+    #[test]
+    fn makes_memes_from_aliases() {
+        let chat = meme_chat();
 
-    if fonts.is_empty() {
-        warn!("No fonts were loaded");
+        assert_eq!(
+            chosen_meme(&chat, &message(Some(GUILD), USER, "d hi"), Some("drake")),
+            chosen("drake", "hi")
+        );
+        assert_eq!(
+            chosen_meme(
+                &chat,
+                &message(Some(GUILD), USER, "say drake hi"),
+                Some(aliases::DEFAULT_MEME)
+            ),
+            chosen("default", "drake hi")
+        );
     }
 
-    if memes.is_empty() {
-        warn!("No memes were loaded");
+    #[test]
+    fn takes_text_from_the_replied_to_message() {
+        let mut chat = meme_chat();
+        let replied = message(Some(GUILD), ADMIN, "what they said");
+
+        chat.messages.push(replied.clone());
+
+        assert_eq!(
+            chosen_meme(&chat, &reply(Some(GUILD), USER, "drake", &replied), None),
+            chosen("drake", "what they said")
+        );
+        assert_eq!(
+            chosen_meme(&chat, &reply(Some(GUILD), USER, "drake hi", &replied), None),
+            chosen("drake", "hi")
+        );
+        assert_eq!(
+            chosen_meme(&chat, &message(Some(GUILD), USER, "drake"), None),
+            chosen("drake", "")
+        );
+
+        // A reply to a message that's gone makes the meme without text
+        chat.messages.clear();
+
+        assert_eq!(
+            chosen_meme(&chat, &reply(Some(GUILD), USER, "drake", &replied), None),
+            chosen("drake", "")
+        );
     }
 
-    info!("Connecting");
+    #[test]
+    fn says_when_no_memes_are_loaded() {
+        let chat = chat(None);
+        let mut data = chat.data().write();
 
-    let mut client = match Client::new(&discord_bot_token, Handler) {
-        Ok(client) => client,
-        Err(reason) => {
-            error!("Unable to create client: {}", reason);
-            process::exit(1);
-        }
-    };
+        data.insert::<MemesKey>(ArcSwap::from_pointee(MemeRegistry::new(Vec::new())));
+        data.insert::<GreetingsKey>(Greetings::default());
 
-    // Store the ShardManager in the client's data in order to allow event
-    // handler methods to access it
-    {
-        let mut data = client.data.write();
-        data.insert::<ShardManagerKey>(Arc::clone(&client.shard_manager));
-        data.insert::<BotSettingsKey>(BotSettings {
-            id: None,
-            admin_password: bot_admin_password,
-            admin_ids: Vec::<u64>::new(),
+        drop(data);
+
+        assert_eq!(
+            chosen_meme(&chat, &message(None, USER, "hello"), None),
+            None
+        );
+        assert_eq!(
+            chat.take_said(),
+            vec!["I have no idea what's going on. (No memes loaded.)"]
+        );
+    }
+
+    #[test]
+    fn searches_memes_with_their_thumbnails() {
+        let chat = meme_chat();
+        let msg = message(Some(GUILD), USER, "search");
+
+        search_command(&chat, &msg, "two");
+        assert_eq!(
+            chat.take_said(),
+            vec!["**Best matches**\n`drake two` - The drake two meme"]
+        );
+        assert_eq!(chat.take_sent_files(), vec!["drake two-v1.png"]);
+
+        search_command(&chat, &msg, "pigeon");
+        assert_eq!(chat.take_said(), vec!["No memes match that."]);
+
+        search_command(&chat, &msg, " ");
+        assert_eq!(
+            chat.take_said(),
+            vec!["What should I search for? (Try `search <terms>`.)"]
+        );
+        assert!(chat.take_sent_files().is_empty());
+    }
+
+    #[test]
+    fn suggests_regions_with_a_preview() {
+        let chat = meme_chat();
+        let msg = message(None, ADMIN, "suggestregion");
+
+        suggest_region_command(&chat, &msg, "Drake");
+        let said = chat.take_said();
+        assert_eq!(said.len(), 1);
+        assert!(said[0].starts_with("Suggested region for `drake`:"));
+        assert_eq!(chat.take_sent_files(), vec!["suggestion.png"]);
+
+        suggest_region_command(&chat, &msg, "pigeon");
+        assert_eq!(chat.take_said(), vec!["Usage: `suggestregion <meme>`"]);
+    }
+
+    #[test]
+    fn lists_and_revokes_admin_sessions() {
+        let chat = chat(None);
+        let msg = message(None, ADMIN, "revoke");
+
+        let lines = admin_session_lines(&chat);
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].starts_with("admin#0001 (`1`) - expires in "));
+
+        revoke_command(&chat, &msg, "nobody");
+        assert_eq!(
+            chat.take_said(),
+            vec!["Usage: `revoke <user ID or mention>`"]
+        );
+
+        revoke_command(&chat, &msg, "<@2>");
+        assert_eq!(
+            chat.take_said(),
+            vec!["That user doesn't have an admin session."]
+        );
+
+        revoke_command(&chat, &msg, "<@1>");
+        assert_eq!(chat.take_said(), vec!["Revoked."]);
+        assert_eq!(admin_session_lines(&chat).len(), 1);
+        assert_eq!(route(&chat, None, ADMIN, "quit"), Route::Everyone);
+    }
+
+    #[test]
+    fn runs_other_commands_while_a_meme_waits_for_its_reply() {
+        let mut chat = meme_chat();
+        let replied = message(Some(GUILD), ADMIN, "what they said");
+
+        chat.messages.push(replied.clone());
+        chat.lookup_gate = Some(Barrier::new(2));
+
+        let chat = &chat;
+        let gate = chat.lookup_gate.as_ref().expect("Unable to retrieve gate");
+        let (done, finished) = mpsc::channel();
+
+        thread::scope(|scope| {
+            let waiting = scope
+                .spawn(|| chosen_meme(chat, &reply(Some(GUILD), USER, "drake", &replied), None));
+
+            // The meme is now looking up the message it replies to
+            gate.wait();
+
+            // A command that changes the shared data, like `reload`, and one
+            // that reads it after that command has started waiting for it
+            let writer_done = done.clone();
+            scope.spawn(move || {
+                drop(chat.data().write());
+                writer_done.send("write").ok();
+            });
+            scope.spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                search_command(chat, &message(Some(GUILD), USER, "search"), "two");
+                done.send("search").ok();
+            });
+
+            let mut finished_first: Vec<&str> = (0..2)
+                .filter_map(|_| finished.recv_timeout(Duration::from_secs(5)).ok())
+                .collect();
+            finished_first.sort();
+
+            // Let the lookup finish either way, so that nothing is left waiting
+            gate.wait();
+
+            assert_eq!(finished_first, vec!["search", "write"]);
+            assert_eq!(
+                waiting.join().expect("Meme thread panicked"),
+                chosen("drake", "what they said")
+            );
         });
-        data.insert::<FontsKey>(fonts);
-        data.insert::<MemesKey>(memes);
     }
 
-    if let Err(reason) = client.start() {
-        error!("Unable to start client: {}", reason);
-        process::exit(1);
+    #[test]
+    fn takes_flags() {
+        let cases = [
+            ("hi", "hi", None, false, None),
+            ("--nowrap hi", "hi", None, true, None),
+            ("hi --seed 42\nthere", "hi \nthere", None, false, Some(42)),
+            (
+                "--color rgb(255 0 102) hi",
+                "hi",
+                Some(Rgba([255, 0, 102, 255])),
+                false,
+                None,
+            ),
+            (
+                "--COLOUR red hi",
+                "hi",
+                Some(Rgba([255, 0, 0, 255])),
+                false,
+                None,
+            ),
+            ("--\u{3000}--nowrap", "--", None, true, None),
+        ];
+
+        for (text, remaining, color, nowrap, seed) in cases.iter() {
+            let (text_left, flags) = take_flags(text).expect(text);
+
+            assert_eq!(text_left, *remaining, "{:?}", text);
+            assert_eq!(flags.color, *color, "{:?}", text);
+            assert_eq!(flags.nowrap, *nowrap, "{:?}", text);
+            assert_eq!(flags.seed, *seed, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn leaves_dashes_in_code_and_words_alone() {
+        let cases = [
+            "```\nls --color --nowrap\n```",
+            "```sh\nsort --seed 1```",
+            "well--nowrap",
+            "em--dash",
+            "-- and --unknown flags",
+            "🐸--seed 42",
+        ];
+
+        for text in cases.iter() {
+            let (text_left, flags) = take_flags(text).expect(text);
+
+            assert_eq!(text_left, *text, "{:?}", text);
+            assert!(!flags.nowrap && flags.seed.is_none(), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn refuses_flags_without_values() {
+        let cases = [
+            "--seed",
+            "--seed x",
+            "--seed 18446744073709551616",
+            "--color",
+        ];
+
+        for text in cases.iter() {
+            assert!(take_flags(text).is_err(), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn takes_flags_from_random_text() {
+        let mut rng = StdRng::seed_from_u64(1413);
+
+        for _ in 0..10_000 {
+            let text = random_text(&mut rng, FLAG_PIECES);
+
+            // Anything may be refused, as long as nothing panics
+            let (text_left, flags) = match take_flags(&text) {
+                Ok(taken) => taken,
+                Err(_) => continue,
+            };
+
+            if !text.contains("--") {
+                assert_eq!(text_left, trim_blank_lines(&text), "{:?}", text);
+                assert!(flags.color.is_none() && flags.seed.is_none() && !flags.nowrap);
+            }
+
+            assert_eq!(
+                text_left.matches("a--nowrap").count(),
+                text.matches("a--nowrap").count(),
+                "{:?}",
+                text
+            );
+
+            if let Some(code) = code_block_range(&text) {
+                assert!(text_left.contains(&text[code]), "{:?}", text);
+            }
+        }
+    }
+
+    /// Splits `text` like `split_pipeline`, naming the stages
+    fn stage_names(text: &str) -> (&str, Vec<(&str, &str)>) {
+        let (base, stages) = split_pipeline(text);
+
+        (
+            base,
+            stages
+                .into_iter()
+                .map(|(stage, args)| (stage.name, args))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn splits_stages_off_the_end() {
+        let cases = [
+            ("hi", ("hi", vec![])),
+            ("@a | @b", ("@a | @b", vec![])),
+            ("a | b | deepfry", ("a | b", vec![("deepfry", "")])),
+            (
+                "a|Mirror | caption \"bottom text\"",
+                ("a", vec![("mirror", ""), ("caption", "\"bottom text\"")]),
+            ),
+            ("x | mirror | y", ("x | mirror | y", vec![])),
+            ("| deepfry", ("", vec![("deepfry", "")])),
+            ("a || deepfry", ("a |", vec![("deepfry", "")])),
+        ];
+
+        for (text, expected) in cases.iter() {
+            assert_eq!(stage_names(text), *expected, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn splits_stages_off_random_text() {
+        let mut rng = StdRng::seed_from_u64(1413);
+        let stages = [" | deepfry", "|mirror", " | caption hi", " |GRAYSCALE "];
+
+        for _ in 0..10_000 {
+            let text = random_text(&mut rng, PIPELINE_PIECES);
+
+            // Text that already ends in a stage isn't what's being tested
+            if !stage_names(&text).1.is_empty() {
+                continue;
+            }
+
+            // Adding stages to text with `|` in it splits off exactly those
+            // stages and gives back the text
+            let added: Vec<&str> = (0..rng.gen_range(0, 4))
+                .map(|_| stages[rng.gen_range(0, stages.len())])
+                .collect();
+            let piped = text.clone() + &added.concat();
+            let (base, found) = stage_names(&piped);
+
+            assert_eq!(base, trim_blank_lines(&text), "{:?}", piped);
+            assert_eq!(found.len(), added.len(), "{:?}", piped);
+        }
     }
 }
@@ -0,0 +1,114 @@
+use image::Rgba;
+
+use crate::color::parse_color;
+
+// Braces with more than this many characters between them aren't tags, so
+// that stray braces don't make long texts slow to parse
+const MAX_TAG_LENGTH: usize = 40;
+
+/// Text with its color markup taken out, along with the color of each of its
+/// characters
+pub struct ColoredText {
+    pub text: String,
+    // One for each character of `text`; `None` for the default text color
+    pub colors: Vec<Option<Rgba<u8>>>,
+}
+
+/// Parses the color in a tag, which may have been cased along with the rest
+/// of the text
+fn parse_tag_color(tag: &str) -> Option<Rgba<u8>> {
+    // Turkish casing turns "i" into "İ", which lowercases to "i" followed by a
+    // combining dot
+    let tag = tag.to_lowercase().replace('\u{307}', "").replace('ı', "i");
+
+    parse_color(&tag).ok()
+}
+
+/// Takes color markup like `{red}some text{/}` out of `text`. The colors are
+/// written like in the config, tags can be nested, and `\{` is a brace that
+/// doesn't start a tag. Braces around anything that isn't a color are kept as
+/// they are.
+pub fn parse_color_markup(text: &str) -> ColoredText {
+    let mut plain = String::with_capacity(text.len());
+    let mut colors = Vec::<Option<Rgba<u8>>>::new();
+    let mut open_colors = Vec::<Rgba<u8>>::new();
+
+    let mut rest = text;
+
+    while let Some(c) = rest.chars().next() {
+        if c == '\\' && rest[1..].starts_with('{') {
+            plain.push('{');
+            colors.push(open_colors.last().copied());
+            rest = &rest[2..];
+            continue;
+        }
+
+        if c == '{' {
+            let end = rest
+                .char_indices()
+                .take(MAX_TAG_LENGTH + 2)
+                .find(|(_, c)| *c == '}')
+                .map(|(index, _)| index);
+
+            if let Some(end) = end {
+                let tag = rest[1..end].trim();
+
+                if tag == "/" {
+                    open_colors.pop();
+                    rest = &rest[end + 1..];
+                    continue;
+                }
+
+                if let Some(color) = parse_tag_color(tag) {
+                    open_colors.push(color);
+                    rest = &rest[end + 1..];
+                    continue;
+                }
+            }
+        }
+
+        plain.push(c);
+        colors.push(open_colors.last().copied());
+        rest = &rest[c.len_utf8()..];
+    }
+
+    ColoredText {
+        text: plain,
+        colors,
+    }
+}
+
+impl ColoredText {
+    pub fn has_colors(&self) -> bool {
+        self.colors.iter().any(Option::is_some)
+    }
+
+    /// Returns the colors of the characters of each of `lines`, which `text`
+    /// was broken into. Lines are matched up with the text by their visible
+    /// characters, since breaking lines changes the spaces between words and
+    /// can add hyphens, which take the color of the character before them.
+    pub fn line_colors(&self, lines: &[String]) -> Vec<Vec<Option<Rgba<u8>>>> {
+        let mut visible = self
+            .text
+            .chars()
+            .zip(self.colors.iter().copied())
+            .filter(|(c, _)| !c.is_whitespace())
+            .peekable();
+        let mut last_color = None;
+
+        lines
+            .iter()
+            .map(|line| {
+                line.chars()
+                    .map(|c| {
+                        if let Some((_, color)) = visible.next_if(|(next, _)| *next == c) {
+                            last_color = color;
+                        }
+
+                        last_color
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,373 @@
+use image::RgbaImage;
+use log::{info, warn};
+use yaml_rust::yaml::{Hash, Yaml};
+
+use serenity::model::prelude::Message;
+use serenity::prelude::Context;
+
+use crate::added_memes::AddedMemesFile;
+use crate::meme_history::{Change, MemeHistoryKey};
+use crate::redact;
+use crate::registry::{MemesKey, Region};
+use crate::render::{encode_png, OutputSettings};
+use crate::wizard::parse_region;
+use crate::{reload_memes, split_first_word, BotSettingsKey};
+
+pub fn added_memes_file(ctx: &Context) -> AddedMemesFile {
+    ctx.data
+        .read()
+        .get::<BotSettingsKey>()
+        .expect("Edit memes: Unable to retrieve bot settings")
+        .config_source
+        .added_memes
+        .clone()
+}
+
+pub fn section_index(sections: &[Yaml], command: &str) -> Option<usize> {
+    sections
+        .iter()
+        .position(|section| section["command"].as_str() == Some(command))
+}
+
+/// Explains why `command` can't be changed, since it isn't an added meme
+fn not_added(ctx: &Context, command: &str) -> String {
+    let in_config = ctx
+        .data
+        .read()
+        .get::<MemesKey>()
+        .expect("Edit memes: Unable to retrieve memes")
+        .load()
+        .contains(command);
+
+    if in_config {
+        format!(
+            "`{}` is defined in the config file, so it can only be changed there.",
+            command
+        )
+    } else {
+        format!("There's no meme called `{}`.", command)
+    }
+}
+
+/// Records a change to the added memes so that it can be undone
+pub fn record_change(ctx: &Context, change: Change) {
+    let mut data = ctx.data.write();
+    let history = data
+        .get_mut::<MemeHistoryKey>()
+        .expect("Edit memes: Unable to retrieve meme history");
+
+    history.record(change);
+
+    if let Err(reason) = history.save() {
+        warn!("Edit memes: {}", reason);
+    }
+}
+
+/// Returns the meme whose style a new meme takes, which is `parent` or, if
+/// it's empty, the first meme that can be used
+pub fn style_parent(ctx: &Context, parent: &str) -> Result<String, String> {
+    let data = ctx.data.read();
+    let memes = data
+        .get::<MemesKey>()
+        .expect("Edit memes: Unable to retrieve memes")
+        .load();
+
+    if parent.is_empty() {
+        match memes
+            .all()
+            .into_iter()
+            .find(|meme| meme.avatar_regions.is_empty())
+        {
+            Some(meme) => Ok(meme.command.clone()),
+            None => Err("There are no memes whose style could be used.".into()),
+        }
+    } else {
+        match memes.find(parent) {
+            Some(meme) if meme.avatar_regions.is_empty() => Ok(parent.to_string()),
+            Some(_) => Err("Pick a meme without avatars for the style.".into()),
+            None => Err(format!("There's no meme called `{}`.", parent)),
+        }
+    }
+}
+
+/// Sets the text area in the section of an added meme
+pub fn set_section_region(section: &mut Hash, region: Region) {
+    for (key, value) in &[
+        ("left", region.left),
+        ("top", region.top),
+        ("right", region.right),
+        ("bottom", region.bottom),
+    ] {
+        section.insert(Yaml::String((*key).into()), Yaml::Integer(*value as i64));
+    }
+}
+
+/// Saves `image` as the template of the meme `command` and returns its
+/// section for the added memes file, using the font and text style of the
+/// meme `parent`. Images are saved under `image_name`, which is usually the
+/// command.
+pub fn new_section(
+    added_memes: &AddedMemesFile,
+    command: &str,
+    parent: &str,
+    image_name: &str,
+    image: &RgbaImage,
+    region: Region,
+) -> Result<Hash, String> {
+    let filename =
+        added_memes.save_image(image_name, &encode_png(image, OutputSettings::default())?)?;
+
+    let mut section = Hash::new();
+    section.insert(Yaml::String("filename".into()), Yaml::String(filename));
+    section.insert(Yaml::String("extends".into()), Yaml::String(parent.into()));
+    section.insert(Yaml::String("command".into()), Yaml::String(command.into()));
+    set_section_region(&mut section, region);
+
+    Ok(section)
+}
+
+/// Adds the meme `command` with the template `image`, using the font and text
+/// style of the meme `parent`
+pub fn add_meme(
+    ctx: &Context,
+    command: &str,
+    parent: &str,
+    image: &RgbaImage,
+    region: Region,
+) -> Result<(), String> {
+    let added_memes = added_memes_file(ctx);
+
+    let section = new_section(&added_memes, command, parent, command, image, region)?;
+
+    added_memes.append(section)?;
+
+    record_change(
+        ctx,
+        Change::Added {
+            command: command.into(),
+        },
+    );
+
+    reload_memes(ctx)?;
+
+    Ok(())
+}
+
+fn remove(ctx: &Context, command: &str) -> Result<String, String> {
+    let added_memes = added_memes_file(ctx);
+    let mut sections = added_memes.read_sections()?;
+
+    let index = match section_index(&sections, command) {
+        Some(index) => index,
+        None => return Err(not_added(ctx, command)),
+    };
+
+    if let Some(child) = sections
+        .iter()
+        .find(|section| section["extends"].as_str() == Some(command))
+    {
+        return Err(format!(
+            "`{}` extends `{}`, so remove it first.",
+            child["command"].as_str().unwrap_or("?"),
+            command
+        ));
+    }
+
+    // The image is kept, so that the removal can be undone
+    let section = sections.remove(index);
+    added_memes.write_sections(sections)?;
+
+    record_change(ctx, Change::Removed { index, section });
+
+    reload_memes(ctx)?;
+
+    Ok(format!(
+        "Removed `{}`. (Send `undo` to bring it back.)",
+        command
+    ))
+}
+
+pub fn remove_meme(ctx: &Context, msg: &Message, rest: &str) {
+    let command = rest.trim().to_lowercase();
+
+    if command.is_empty() {
+        msg.channel_id.say(ctx, "Usage: `removememe <meme>`").ok();
+        return;
+    }
+
+    let reply = match remove(ctx, &command) {
+        Ok(reply) => {
+            info!(
+                "Meme \"{}\" removed by {}",
+                command,
+                redact::user(&msg.author)
+            );
+
+            reply
+        }
+        Err(reason) => reason,
+    };
+
+    msg.channel_id.say(ctx, reply).ok();
+}
+
+fn move_region(ctx: &Context, command: &str, coordinates: &str) -> Result<String, String> {
+    let added_memes = added_memes_file(ctx);
+    let mut sections = added_memes.read_sections()?;
+
+    let index = match section_index(&sections, command) {
+        Some(index) => index,
+        None => return Err(not_added(ctx, command)),
+    };
+
+    let memes = ctx
+        .data
+        .read()
+        .get::<MemesKey>()
+        .expect("Edit memes: Unable to retrieve memes")
+        .load();
+
+    let region = match memes.find(command) {
+        Some(meme) => parse_region(coordinates, &meme.image)?,
+        None => return Err(format!("`{}` isn't loaded right now.", command)),
+    };
+
+    let previous = sections[index].clone();
+
+    if let Yaml::Hash(section) = &mut sections[index] {
+        set_section_region(section, region);
+    }
+
+    added_memes.write_sections(sections)?;
+
+    record_change(
+        ctx,
+        Change::Edited {
+            command: command.into(),
+            section: previous,
+        },
+    );
+
+    reload_memes(ctx)?;
+
+    Ok(format!("Moved the text of `{}`.", command))
+}
+
+pub fn set_region(ctx: &Context, msg: &Message, rest: &str) {
+    let (command, coordinates) = split_first_word(rest);
+    let command = command.to_lowercase();
+
+    if command.is_empty() || coordinates.trim().is_empty() {
+        msg.channel_id
+            .say(
+                ctx,
+                "Usage: `setregion <meme> <left> <top> <right> <bottom>`",
+            )
+            .ok();
+        return;
+    }
+
+    let reply = match move_region(ctx, &command, coordinates) {
+        Ok(reply) => {
+            info!(
+                "Region of meme \"{}\" changed by {}",
+                command,
+                redact::user(&msg.author)
+            );
+
+            reply
+        }
+        Err(reason) => reason,
+    };
+
+    msg.channel_id.say(ctx, reply).ok();
+}
+
+/// Reverts `change` in the sections of the added memes file
+fn revert(sections: &mut Vec<Yaml>, change: &Change) -> Result<(), String> {
+    match change {
+        Change::Added { command } => match section_index(sections, command) {
+            Some(index) => {
+                sections.remove(index);
+            }
+            None => return Err(format!("`{}` has already been removed.", command)),
+        },
+        Change::Removed { index, section } => {
+            let command = section["command"].as_str().unwrap_or("");
+
+            if section_index(sections, command).is_some() {
+                return Err(format!(
+                    "There's a meme called `{}` again, so the old one can't be restored.",
+                    command
+                ));
+            }
+
+            sections.insert((*index).min(sections.len()), section.clone());
+        }
+        Change::Edited { command, section } => match section_index(sections, command) {
+            Some(index) => sections[index] = section.clone(),
+            None => return Err(format!("`{}` has been removed since.", command)),
+        },
+    }
+
+    Ok(())
+}
+
+fn undo_last(ctx: &Context) -> Result<String, String> {
+    let change = ctx
+        .data
+        .write()
+        .get_mut::<MemeHistoryKey>()
+        .expect("Command undo: Unable to retrieve meme history")
+        .pop();
+
+    let change = match change {
+        Some(change) => change,
+        None => return Err("There's nothing to undo.".into()),
+    };
+
+    let added_memes = added_memes_file(ctx);
+
+    let result = added_memes.read_sections().and_then(|mut sections| {
+        revert(&mut sections, &change)?;
+        added_memes.write_sections(sections)
+    });
+
+    let mut data = ctx.data.write();
+    let history = data
+        .get_mut::<MemeHistoryKey>()
+        .expect("Command undo: Unable to retrieve meme history");
+
+    let description = change.describe();
+
+    // Keep the change if it couldn't be reverted, so that it can be tried
+    // again once the problem is fixed
+    if result.is_err() {
+        history.record(change);
+    }
+
+    if let Err(reason) = history.save() {
+        warn!("Command undo: {}", reason);
+    }
+
+    drop(data);
+
+    result?;
+
+    reload_memes(ctx)?;
+
+    Ok(format!("Undid {}.", description))
+}
+
+pub fn undo(ctx: &Context, msg: &Message) {
+    let reply = match undo_last(ctx) {
+        Ok(reply) => {
+            info!("{} requested by {}", reply, redact::user(&msg.author));
+
+            reply
+        }
+        Err(reason) => reason,
+    };
+
+    msg.channel_id.say(ctx, reply).ok();
+}
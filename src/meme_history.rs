@@ -0,0 +1,169 @@
+use log::warn;
+use std::collections::VecDeque;
+use std::fs::{read_to_string, write};
+use yaml_rust::yaml::{Hash, Yaml};
+use yaml_rust::{YamlEmitter, YamlLoader};
+
+use serenity::prelude::TypeMapKey;
+
+// Only this many changes can be undone
+pub const MAX_CHANGES: usize = 20;
+
+/// A change to the added memes, with what's needed to revert it
+pub enum Change {
+    Added {
+        command: String,
+    },
+    Removed {
+        // Where the meme's section was in the added memes file
+        index: usize,
+        section: Yaml,
+    },
+    Edited {
+        command: String,
+        // The meme's section from before the edit
+        section: Yaml,
+    },
+}
+
+impl Change {
+    fn from_yaml(yaml: &Yaml) -> Option<Change> {
+        match yaml["change"].as_str()? {
+            "added" => Some(Change::Added {
+                command: yaml["command"].as_str()?.into(),
+            }),
+            "removed" => Some(Change::Removed {
+                index: yaml["index"].as_i64().filter(|index| *index >= 0)? as usize,
+                section: yaml["meme"].clone(),
+            }),
+            "edited" => Some(Change::Edited {
+                command: yaml["command"].as_str()?.into(),
+                section: yaml["meme"].clone(),
+            }),
+            _ => None,
+        }
+    }
+
+    fn to_yaml(&self) -> Yaml {
+        let mut hash = Hash::new();
+
+        let (change, command, index, section) = match self {
+            Change::Added { command } => ("added", Some(command), None, None),
+            Change::Removed { index, section } => ("removed", None, Some(*index), Some(section)),
+            Change::Edited { command, section } => ("edited", Some(command), None, Some(section)),
+        };
+
+        hash.insert(Yaml::String("change".into()), Yaml::String(change.into()));
+
+        if let Some(command) = command {
+            hash.insert(
+                Yaml::String("command".into()),
+                Yaml::String(command.clone()),
+            );
+        }
+
+        if let Some(index) = index {
+            hash.insert(Yaml::String("index".into()), Yaml::Integer(index as i64));
+        }
+
+        if let Some(section) = section {
+            hash.insert(Yaml::String("meme".into()), section.clone());
+        }
+
+        Yaml::Hash(hash)
+    }
+
+    /// Describes the change, like "adding `example`"
+    pub fn describe(&self) -> String {
+        match self {
+            Change::Added { command } => format!("adding `{}`", command),
+            Change::Removed { section, .. } => {
+                format!("removing `{}`", section["command"].as_str().unwrap_or("?"))
+            }
+            Change::Edited { command, .. } => format!("editing `{}`", command),
+        }
+    }
+}
+
+/// The most recent changes to the added memes, newest last, persisted to a
+/// YAML file so that they can still be undone after a restart
+pub struct MemeHistory {
+    filename: String,
+    changes: VecDeque<Change>,
+}
+
+pub struct MemeHistoryKey;
+
+impl TypeMapKey for MemeHistoryKey {
+    type Value = MemeHistory;
+}
+
+impl MemeHistory {
+    /// Loads the history from `filename`. A missing file is not an error,
+    /// since nothing has been changed yet.
+    pub fn load(filename: &str) -> Result<MemeHistory, String> {
+        let mut history = MemeHistory {
+            filename: filename.into(),
+            changes: VecDeque::new(),
+        };
+
+        let contents = match read_to_string(filename) {
+            Ok(contents) => contents,
+            Err(_) => return Ok(history),
+        };
+
+        let yaml = match YamlLoader::load_from_str(&contents) {
+            Ok(yaml) => yaml,
+            Err(reason) => {
+                return Err(format!(
+                    "Unable to parse meme history file \"{}\": {}",
+                    filename, reason
+                ));
+            }
+        };
+
+        if let Some(Yaml::Array(changes)) = yaml.first() {
+            for change in changes {
+                match Change::from_yaml(change) {
+                    Some(change) => history.changes.push_back(change),
+                    None => warn!("Meme history contains invalid change \"{:?}\"", change),
+                }
+            }
+        }
+
+        Ok(history)
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let changes = self.changes.iter().map(Change::to_yaml).collect();
+
+        let mut contents = String::new();
+
+        if let Err(reason) = YamlEmitter::new(&mut contents).dump(&Yaml::Array(changes)) {
+            return Err(format!("Unable to serialize meme history: {:?}", reason));
+        }
+
+        if let Err(reason) = write(&self.filename, contents + "\n") {
+            return Err(format!(
+                "Unable to write meme history file \"{}\": {}",
+                self.filename, reason
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Records `change`, forgetting the oldest change if there are too many
+    pub fn record(&mut self, change: Change) {
+        self.changes.push_back(change);
+
+        while self.changes.len() > MAX_CHANGES {
+            self.changes.pop_front();
+        }
+    }
+
+    /// Removes and returns the most recent change
+    pub fn pop(&mut self) -> Option<Change> {
+        self.changes.pop_back()
+    }
+}
@@ -0,0 +1,289 @@
+use log::warn;
+use regex::Regex;
+use serde_json::Value;
+
+use serenity::model::id::{ChannelId, GuildId, MessageId};
+use serenity::model::prelude::{Channel, Embed, Message};
+use serenity::prelude::Context;
+
+use crate::capabilities::{self, Capability};
+use crate::chat::Chat;
+use crate::fetch;
+
+// Discord turns messages that are too long into a message.txt attachment of
+// a few KB at most, and longer text wouldn't be readable in a meme anyway
+const MAX_TEXT_ATTACHMENT_BYTES: u64 = 16 * 1024;
+
+const ONLY_HERE: &str = "Only messages in this server can be used.";
+
+/// Returns the text in the first text file attached to `message`, unless it's
+/// too large
+pub fn attachment_text(message: &Message) -> Option<String> {
+    if !capabilities::is_enabled(Capability::TextAttachments) {
+        return None;
+    }
+
+    let attachment = message
+        .attachments
+        .iter()
+        .find(|attachment| attachment.filename.to_lowercase().ends_with(".txt"))?;
+
+    if attachment.size > MAX_TEXT_ATTACHMENT_BYTES {
+        return None;
+    }
+
+    match fetch::download(&attachment.url, MAX_TEXT_ATTACHMENT_BYTES as usize) {
+        Ok(contents) => Some(String::from_utf8_lossy(&contents).into_owned()),
+        Err(reason) => {
+            warn!("Message text: {}", reason);
+            None
+        }
+    }
+}
+
+fn embeds_text(embeds: &[Embed]) -> String {
+    embeds
+        .iter()
+        .flat_map(|embed| vec![&embed.title, &embed.description])
+        .flatten()
+        .map(|text| text.trim())
+        .filter(|text| !text.is_empty())
+        .collect::<Vec<&str>>()
+        .join("\n")
+}
+
+/// The same as `embeds_text`, for embeds that are still JSON
+fn json_embeds_text(embeds: &Value) -> Vec<String> {
+    embeds
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+        .iter()
+        .flat_map(|embed| vec![&embed["title"], &embed["description"]])
+        .filter_map(Value::as_str)
+        .map(|text| text.trim().to_string())
+        .filter(|text| !text.is_empty())
+        .collect()
+}
+
+/// Returns the text of the messages forwarded in `message`
+fn forwarded_text(chat: &dyn Chat, message: &Message) -> String {
+    let snapshots = match chat.get_forwarded_snapshots(message.channel_id, message.id) {
+        Ok(snapshots) => snapshots,
+        Err(reason) => {
+            warn!("Message text: {}", reason);
+            return String::new();
+        }
+    };
+
+    let mut lines = Vec::<String>::new();
+
+    for snapshot in &snapshots {
+        if let Some(content) = snapshot["message"]["content"].as_str() {
+            if !content.trim().is_empty() {
+                lines.push(content.trim().to_string());
+            }
+        }
+
+        lines.extend(json_embeds_text(&snapshot["message"]["embeds"]));
+    }
+
+    lines.join("\n")
+}
+
+/// Returns the text to put in a meme made from `message`: its content, or
+/// for messages without any, the text file attached to it, the titles and
+/// descriptions of its embeds, or the text of the messages it forwards. The
+/// attached text file is only read if `read_attachments` is set.
+pub fn message_text(chat: &dyn Chat, message: &Message, read_attachments: bool) -> String {
+    if !message.content.trim().is_empty() {
+        return message.content.clone();
+    }
+
+    if let Some(text) = read_attachments
+        .then(|| attachment_text(message))
+        .flatten()
+        .filter(|text| !text.trim().is_empty())
+    {
+        return text;
+    }
+
+    let text = embeds_text(&message.embeds);
+
+    if !text.is_empty() {
+        return text;
+    }
+
+    // Forwarded messages refer to the original, but so do replies, which
+    // usually have content of their own
+    if message.message_reference.is_some() {
+        return forwarded_text(chat, message);
+    }
+
+    String::new()
+}
+
+/// Returns the text of the message that `msg` replies to, if it's a reply
+pub fn replied_text(chat: &dyn Chat, msg: &Message, read_attachments: bool) -> Option<String> {
+    let reference = msg.message_reference.as_ref()?;
+    let message_id = reference.message_id?;
+
+    match chat.get_message(reference.channel_id, message_id) {
+        Ok(message) => Some(message_text(chat, &message, read_attachments)),
+        Err(reason) => {
+            warn!(
+                "Message text: Unable to read replied-to message: {}",
+                reason
+            );
+            None
+        }
+    }
+}
+
+/// Returns the server that `channel_id` is in, or `None` for DMs and
+/// channels that can't hold messages
+fn channel_guild_id(ctx: &Context, channel_id: ChannelId) -> Result<Option<GuildId>, String> {
+    match channel_id.to_channel(ctx) {
+        Ok(Channel::Guild(channel)) => Ok(Some(channel.read().guild_id)),
+        Ok(_) => Ok(None),
+        Err(reason) => Err(format!(
+            "Unable to retrieve channel {}: {}",
+            channel_id, reason
+        )),
+    }
+}
+
+/// Returns whether a message in `channel_id`, which is in the server
+/// `channel_guild_id`, is in the same server (or DM) as a command sent in
+/// `msg_channel_id` of `msg_guild_id`. The server written in a link is never
+/// trusted, as any channel can be put after it.
+fn is_same_place(
+    msg_guild_id: Option<GuildId>,
+    msg_channel_id: ChannelId,
+    channel_id: ChannelId,
+    channel_guild_id: Option<GuildId>,
+) -> bool {
+    match (msg_guild_id, channel_guild_id) {
+        (Some(msg_guild_id), Some(channel_guild_id)) => msg_guild_id == channel_guild_id,
+        (None, None) => channel_id == msg_channel_id,
+        _ => false,
+    }
+}
+
+/// Looks up which server `channel_id` is in, returning the reply for when
+/// it isn't the same server (or DM) as `msg`
+fn check_same_place(ctx: &Context, msg: &Message, channel_id: ChannelId) -> Result<(), String> {
+    let channel_guild_id = match channel_guild_id(ctx, channel_id) {
+        Ok(channel_guild_id) => channel_guild_id,
+        Err(reason) => {
+            warn!("Message text: {}", reason);
+            return Err("Unable to find the channel of that message.".into());
+        }
+    };
+
+    if !is_same_place(msg.guild_id, msg.channel_id, channel_id, channel_guild_id) {
+        return Err(ONLY_HERE.into());
+    }
+
+    Ok(())
+}
+
+/// Works out which message a command is about: the one linked to or whose ID
+/// is given in `rest`, or otherwise the one `msg` replies to. Either way, the
+/// message has to be in the server (or DM) the command was sent in. `usage`
+/// is the reply for when there's no message.
+pub fn linked_message_ids(
+    ctx: &Context,
+    msg: &Message,
+    rest: &str,
+    usage: &str,
+) -> Result<(ChannelId, MessageId), String> {
+    let rest = rest.trim();
+
+    if rest.is_empty() {
+        // References can point to other servers too, as forwards do
+        return match &msg.message_reference {
+            Some(reference) => match reference.message_id {
+                Some(message_id) => {
+                    check_same_place(ctx, msg, reference.channel_id)?;
+                    Ok((reference.channel_id, message_id))
+                }
+                None => Err(usage.into()),
+            },
+            None => Err(usage.into()),
+        };
+    }
+
+    if let Ok(message_id) = rest.parse::<u64>() {
+        return Ok((msg.channel_id, MessageId(message_id)));
+    }
+
+    let re_message_link = Regex::new(
+        r"^<?https://(?:(?:ptb|canary)\.)?discord(?:app)?\.com/channels/(\d+|@me)/(\d+)/(\d+)>?$",
+    )
+    .expect("Unable to create message link matching pattern");
+
+    let captures = match re_message_link.captures(rest) {
+        Some(captures) => captures,
+        None => return Err(usage.into()),
+    };
+
+    let channel_id = ChannelId(captures[2].parse::<u64>().map_err(|_| usage)?);
+    let message_id = MessageId(captures[3].parse::<u64>().map_err(|_| usage)?);
+
+    // Links that don't even claim to be from here are turned away without
+    // looking up their channel
+    let is_linked_here = match (&captures[1], msg.guild_id) {
+        ("@me", None) => true,
+        (guild_id, Some(msg_guild_id)) => guild_id == msg_guild_id.0.to_string(),
+        _ => false,
+    };
+
+    if !is_linked_here {
+        return Err(ONLY_HERE.into());
+    }
+
+    check_same_place(ctx, msg, channel_id)?;
+
+    Ok((channel_id, message_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GUILD: GuildId = GuildId(100);
+    const OTHER_GUILD: GuildId = GuildId(200);
+    const CHANNEL: ChannelId = ChannelId(10);
+    const OTHER_CHANNEL: ChannelId = ChannelId(20);
+
+    #[test]
+    fn accepts_channels_in_the_same_server() {
+        assert!(is_same_place(Some(GUILD), CHANNEL, CHANNEL, Some(GUILD)));
+        assert!(is_same_place(
+            Some(GUILD),
+            CHANNEL,
+            OTHER_CHANNEL,
+            Some(GUILD)
+        ));
+    }
+
+    #[test]
+    fn rejects_channels_from_other_servers_linked_as_this_one() {
+        // A link like /channels/100/20/... whose channel 20 is in server 200
+        assert!(!is_same_place(
+            Some(GUILD),
+            CHANNEL,
+            OTHER_CHANNEL,
+            Some(OTHER_GUILD)
+        ));
+    }
+
+    #[test]
+    fn keeps_dms_to_their_own_channel() {
+        assert!(is_same_place(None, CHANNEL, CHANNEL, None));
+        assert!(!is_same_place(None, CHANNEL, OTHER_CHANNEL, None));
+        assert!(!is_same_place(None, CHANNEL, CHANNEL, Some(GUILD)));
+        assert!(!is_same_place(Some(GUILD), CHANNEL, OTHER_CHANNEL, None));
+    }
+}
@@ -0,0 +1,124 @@
+use log::warn;
+use serde_json::Value;
+use std::sync::OnceLock;
+
+use crate::fetch;
+
+// Replies from moderation webhooks are only read up to this size
+const MAX_REPLY_BYTES: usize = 64 * 1024;
+
+/// Something that looks at the images users supply before the bot pastes them
+/// into memes or keeps them as templates
+pub trait ImageModerator {
+    /// Returns why the image shouldn't be used, if it shouldn't
+    fn check(&self, contents: &[u8]) -> Result<Option<String>, String>;
+}
+
+/// Posts each image to a webhook, which replies with JSON like
+/// `{"flagged": true, "reason": "nudity"}`. The webhook comes from the bot's
+/// configuration, so it may be on a private address, like a classifier
+/// running next to the bot.
+pub struct WebhookModerator {
+    url: String,
+}
+
+impl WebhookModerator {
+    pub fn new(url: &str) -> WebhookModerator {
+        WebhookModerator { url: url.into() }
+    }
+}
+
+impl ImageModerator for WebhookModerator {
+    fn check(&self, contents: &[u8]) -> Result<Option<String>, String> {
+        let reply = fetch::post(
+            &self.url,
+            contents,
+            "application/octet-stream",
+            MAX_REPLY_BYTES,
+            true,
+        )?;
+
+        let verdict: Value = match serde_json::from_str(&reply) {
+            Ok(verdict) => verdict,
+            Err(reason) => {
+                return Err(format!(
+                    "Unable to parse the reply from \"{}\": {}",
+                    self.url, reason
+                ));
+            }
+        };
+
+        match verdict["flagged"].as_bool() {
+            Some(true) => Ok(Some(
+                verdict["reason"]
+                    .as_str()
+                    .unwrap_or("no reason given")
+                    .to_string(),
+            )),
+            Some(false) => Ok(None),
+            None => Err(format!(
+                "The reply from \"{}\" doesn't say whether the image was flagged",
+                self.url
+            )),
+        }
+    }
+}
+
+static MODERATOR: OnceLock<Box<dyn ImageModerator + Send + Sync>> = OnceLock::new();
+
+/// Sets what checks users' images, once when the bot starts. Without a
+/// moderator, images aren't checked.
+pub fn configure(moderator: Box<dyn ImageModerator + Send + Sync>) -> Result<(), String> {
+    if MODERATOR.set(moderator).is_err() {
+        return Err("Image moderation has already been configured".into());
+    }
+
+    Ok(())
+}
+
+/// Checks an image that a user supplied before it's used, logging any image
+/// that's rejected along with `source`, which says where it came from, like
+/// "avatar of name#1234". Images that can't be checked are rejected too.
+/// Returns an explanation for the user if the image is rejected.
+pub fn check(contents: &[u8], source: &str) -> Result<(), String> {
+    let moderator = match MODERATOR.get() {
+        Some(moderator) => moderator,
+        None => return Ok(()),
+    };
+
+    match moderator.check(contents) {
+        Ok(None) => Ok(()),
+        Ok(Some(reason)) => {
+            warn!("Moderation: Rejected {}: {}", source, reason);
+
+            Err("That image was flagged by moderation, so it can't be used.".into())
+        }
+        Err(reason) => {
+            warn!(
+                "Moderation: Rejected {}, which couldn't be checked: {}",
+                source, reason
+            );
+
+            Err("That image couldn't be checked by moderation, so it can't be used.".into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetch::mock::serve;
+
+    #[test]
+    fn posts_to_webhooks_on_private_addresses() {
+        let (port, server) = serve(vec![
+            "HTTP/1.1 200 OK\r\nContent-Length: 37\r\nConnection: close\r\n\r\n{\"flagged\": true, \"reason\": \"nudity\"}",
+            "HTTP/1.1 200 OK\r\nContent-Length: 18\r\nConnection: close\r\n\r\n{\"flagged\": false}",
+        ]);
+        let moderator = WebhookModerator::new(&format!("http://127.0.0.1:{}/check", port));
+
+        assert_eq!(moderator.check(b"image"), Ok(Some("nudity".into())));
+        assert_eq!(moderator.check(b"image"), Ok(None));
+        assert_eq!(server.join().unwrap(), vec!["POST /check HTTP/1.1"; 2]);
+    }
+}
@@ -0,0 +1,65 @@
+use image::png::{CompressionType, FilterType as PngFilterType, PNGEncoder};
+use image::{ColorType, RgbaImage};
+
+/// How finished memes are prepared for uploading. Encoding large templates
+/// with the higher PNG compression settings is noticeably slower.
+#[derive(Clone, Copy)]
+pub struct OutputSettings {
+    pub compression: CompressionType,
+    pub filter: PngFilterType,
+    // Wider memes are scaled down to this width, unless the meme sets its own
+    pub max_width: Option<u32>,
+    // Whether PNGs say what the license and source of their template are
+    pub embed_attribution: bool,
+}
+
+impl Default for OutputSettings {
+    fn default() -> OutputSettings {
+        OutputSettings {
+            compression: CompressionType::Fast,
+            filter: PngFilterType::Sub,
+            max_width: None,
+            embed_attribution: false,
+        }
+    }
+}
+
+impl OutputSettings {
+    pub fn compression_from_name(name: &str) -> Option<CompressionType> {
+        match name.to_lowercase().as_str() {
+            "fast" => Some(CompressionType::Fast),
+            "default" => Some(CompressionType::Default),
+            "best" => Some(CompressionType::Best),
+            "huffman" => Some(CompressionType::Huffman),
+            "rle" => Some(CompressionType::Rle),
+            _ => None,
+        }
+    }
+
+    pub fn filter_from_name(name: &str) -> Option<PngFilterType> {
+        match name.to_lowercase().as_str() {
+            "none" => Some(PngFilterType::NoFilter),
+            "sub" => Some(PngFilterType::Sub),
+            "up" => Some(PngFilterType::Up),
+            "avg" => Some(PngFilterType::Avg),
+            "paeth" => Some(PngFilterType::Paeth),
+            _ => None,
+        }
+    }
+}
+
+pub fn encode_png(image: &RgbaImage, settings: OutputSettings) -> Result<Vec<u8>, String> {
+    let mut buffer = Vec::new();
+
+    if let Err(reason) = PNGEncoder::new_with_quality(
+        &mut buffer,
+        settings.compression,
+        settings.filter,
+    )
+    .encode(image, image.width(), image.height(), ColorType::Rgba8)
+    {
+        return Err(format!("Unable to encode image: {}", reason));
+    }
+
+    Ok(buffer)
+}
@@ -0,0 +1,163 @@
+use log::warn;
+use std::collections::{HashMap, VecDeque};
+
+use serenity::model::prelude::{ChannelId, MessageId, Reaction, ReactionType, UserId};
+use serenity::prelude::{Context, TypeMapKey};
+
+// Discord rejects messages longer than this
+const MAX_MESSAGE_LENGTH: usize = 2000;
+
+// Leave room for the page footer that is appended to every page
+const FOOTER_LENGTH: usize = 32;
+
+// Only the most recent paginated messages can be flipped through
+const MAX_TRACKED_MESSAGES: usize = 64;
+
+const PREVIOUS_PAGE: &str = "◀";
+const NEXT_PAGE: &str = "▶";
+
+struct Pages {
+    pages: Vec<String>,
+    current: usize,
+    requester_id: UserId,
+}
+
+#[derive(Default)]
+pub struct Paginations {
+    pages: HashMap<MessageId, Pages>,
+    order: VecDeque<MessageId>,
+}
+
+pub struct PaginationsKey;
+
+impl TypeMapKey for PaginationsKey {
+    type Value = Paginations;
+}
+
+fn split_into_pages(lines: &[String]) -> Vec<String> {
+    let max_length = MAX_MESSAGE_LENGTH - FOOTER_LENGTH;
+
+    let mut pages = Vec::<String>::new();
+    let mut page = String::new();
+
+    for line in lines {
+        // A single line that doesn't fit on a page by itself is cut short
+        let line: String = if line.chars().count() > max_length {
+            line.chars().take(max_length - 1).collect::<String>() + "…"
+        } else {
+            line.clone()
+        };
+
+        if !page.is_empty() && page.chars().count() + line.chars().count() + 1 > max_length {
+            pages.push(page);
+            page = String::new();
+        }
+
+        if !page.is_empty() {
+            page.push('\n');
+        }
+
+        page.push_str(&line);
+    }
+
+    if !page.is_empty() || pages.is_empty() {
+        pages.push(page);
+    }
+
+    pages
+}
+
+fn render_page(pages: &[String], index: usize) -> String {
+    if pages.len() == 1 {
+        return pages[0].clone();
+    }
+
+    format!("{}\n\n*Page {}/{}*", pages[index], index + 1, pages.len())
+}
+
+/// Sends `lines` to the channel, splitting them into pages that fit into a
+/// single message. Multi-page replies get ◀ ▶ reactions that the requester
+/// can use to flip between pages.
+pub fn send_paginated(
+    ctx: &Context,
+    channel_id: ChannelId,
+    requester_id: UserId,
+    lines: &[String],
+) {
+    let pages = split_into_pages(lines);
+
+    let message = match channel_id.say(ctx, render_page(&pages, 0)) {
+        Ok(message) => message,
+        Err(reason) => {
+            warn!("send_paginated(): Unable to send message: {:?}", reason);
+            return;
+        }
+    };
+
+    if pages.len() == 1 {
+        return;
+    }
+
+    message.react(ctx, PREVIOUS_PAGE).ok();
+    message.react(ctx, NEXT_PAGE).ok();
+
+    let mut data = ctx.data.write();
+    let paginations = data
+        .get_mut::<PaginationsKey>()
+        .expect("send_paginated(): Unable to retrieve paginations");
+
+    paginations.pages.insert(
+        message.id,
+        Pages {
+            pages,
+            current: 0,
+            requester_id,
+        },
+    );
+    paginations.order.push_back(message.id);
+
+    while paginations.order.len() > MAX_TRACKED_MESSAGES {
+        if let Some(message_id) = paginations.order.pop_front() {
+            paginations.pages.remove(&message_id);
+        }
+    }
+}
+
+/// Flips the page of a paginated message in response to the requester adding
+/// or removing one of the page reactions. Reactions can't be removed by the bot
+/// in DMs, so removing a reaction turns the page just like adding one does.
+pub fn handle_reaction(ctx: &Context, reaction: &Reaction) {
+    let step: isize = match &reaction.emoji {
+        ReactionType::Unicode(emoji) if emoji == PREVIOUS_PAGE => -1,
+        ReactionType::Unicode(emoji) if emoji == NEXT_PAGE => 1,
+        _ => return,
+    };
+
+    let content = {
+        let mut data = ctx.data.write();
+        let paginations = data
+            .get_mut::<PaginationsKey>()
+            .expect("handle_reaction(): Unable to retrieve paginations");
+
+        let pages = match paginations.pages.get_mut(&reaction.message_id) {
+            Some(pages) => pages,
+            None => return,
+        };
+
+        if pages.requester_id != reaction.user_id {
+            return;
+        }
+
+        let page_count = pages.pages.len() as isize;
+        pages.current = ((pages.current as isize + step).rem_euclid(page_count)) as usize;
+
+        render_page(&pages.pages, pages.current)
+    };
+
+    if let Err(reason) = reaction
+        .channel_id
+        .edit_message(ctx, reaction.message_id, |m| m.content(content))
+    {
+        warn!("handle_reaction(): Unable to edit message: {:?}", reason);
+    }
+}
@@ -0,0 +1,131 @@
+use serenity::model::prelude::Message;
+
+use crate::admin_sessions::AdminSessionsKey;
+use crate::chat::Chat;
+use crate::guild_settings::GuildSettingsKey;
+
+// There are two tiers of permissions. Bot admins authenticate with the admin
+// password and can do anything, including shutting down the bot. Managers can
+// change the settings of their own guild: everyone with the Manage Server
+// permission is a manager, and guild administrators can make other users
+// managers with the `managers` command.
+
+pub fn is_bot_admin(chat: &dyn Chat, msg: &Message) -> bool {
+    let data = chat.data().read();
+    let is_admin = data
+        .get::<AdminSessionsKey>()
+        .expect("is_bot_admin(): Unable to retrieve admin sessions")
+        .lock()
+        .is_admin(msg.author.id.0);
+
+    is_admin
+}
+
+/// Returns whether the author of `msg` has the Administrator permission in the
+/// guild it was sent in, which is needed to choose the guild's managers
+pub fn is_guild_admin(chat: &dyn Chat, msg: &Message) -> bool {
+    match msg.guild_id {
+        Some(guild_id) => chat.is_guild_admin(guild_id, msg.author.id),
+        None => false,
+    }
+}
+
+/// Returns whether the author of `msg` may change the settings of the guild it
+/// was sent in
+pub fn is_manager(chat: &dyn Chat, msg: &Message) -> bool {
+    let guild_id = match msg.guild_id {
+        Some(guild_id) => guild_id,
+        None => return false,
+    };
+
+    if is_bot_admin(chat, msg) {
+        return true;
+    }
+
+    if chat.can_manage_guild(guild_id, msg.author.id) {
+        return true;
+    }
+
+    let data = chat.data().read();
+
+    data.get::<GuildSettingsKey>()
+        .expect("is_manager(): Unable to retrieve guild settings")
+        .get(guild_id.0)
+        .is_some_and(|settings| settings.managers.contains(&msg.author.id.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::mock::{message, MockChat};
+    use serenity::model::id::{GuildId, UserId};
+
+    const GUILD: u64 = 100;
+
+    fn start_admin_session(chat: &MockChat, user_id: u64) {
+        chat.data()
+            .read()
+            .get::<AdminSessionsKey>()
+            .expect("Unable to retrieve admin sessions")
+            .lock()
+            .start(user_id, "admin#0001");
+    }
+
+    #[test]
+    fn makes_bot_admins_managers_everywhere() {
+        let chat = MockChat::new();
+        start_admin_session(&chat, 1);
+
+        assert!(is_bot_admin(&chat, &message(None, 1, "")));
+        assert!(is_manager(&chat, &message(Some(GUILD), 1, "")));
+        assert!(!is_bot_admin(&chat, &message(None, 2, "")));
+    }
+
+    #[test]
+    fn makes_members_who_can_manage_the_server_managers() {
+        let mut chat = MockChat::new();
+        chat.guild_managers.push((GuildId(GUILD), UserId(1)));
+
+        assert!(is_manager(&chat, &message(Some(GUILD), 1, "")));
+        assert!(!is_manager(&chat, &message(Some(200), 1, "")));
+        assert!(!is_manager(&chat, &message(Some(GUILD), 2, "")));
+    }
+
+    #[test]
+    fn makes_chosen_users_managers_of_their_server_only() {
+        let chat = MockChat::new();
+        chat.data()
+            .write()
+            .get_mut::<GuildSettingsKey>()
+            .expect("Unable to retrieve guild settings")
+            .get_mut(GUILD)
+            .managers
+            .push(1);
+
+        assert!(is_manager(&chat, &message(Some(GUILD), 1, "")));
+        assert!(!is_manager(&chat, &message(Some(200), 1, "")));
+    }
+
+    #[test]
+    fn has_no_managers_in_dms() {
+        let mut chat = MockChat::new();
+        start_admin_session(&chat, 1);
+        chat.guild_managers.push((GuildId(GUILD), UserId(1)));
+
+        assert!(!is_manager(&chat, &message(None, 1, "")));
+        assert!(!is_guild_admin(&chat, &message(None, 1, "")));
+    }
+
+    #[test]
+    fn asks_discord_who_administers_the_server() {
+        let mut chat = MockChat::new();
+        chat.guild_admins.push((GuildId(GUILD), UserId(1)));
+
+        assert!(is_guild_admin(&chat, &message(Some(GUILD), 1, "")));
+        assert!(!is_guild_admin(&chat, &message(Some(GUILD), 2, "")));
+
+        // Being a bot admin doesn't make anyone a server's administrator
+        start_admin_session(&chat, 2);
+        assert!(!is_guild_admin(&chat, &message(Some(GUILD), 2, "")));
+    }
+}
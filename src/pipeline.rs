@@ -0,0 +1,124 @@
+use image::RgbaImage;
+use log::debug;
+use rusttype::Font;
+use std::sync::Arc;
+
+use crate::filters;
+use crate::layout::trim_blank_lines;
+use crate::registry::Meme;
+use crate::render::add_caption;
+
+// Effects can make the image larger (like `caption`) or be slow (like
+// `deepfry`), so only this many can be chained
+pub const MAX_STAGES: usize = 8;
+
+/// A step that can be applied to a generated meme, like `deepfry` in
+/// `drake some text | deepfry`
+pub struct Stage {
+    pub name: &'static str,
+    pub description: &'static str,
+    apply: fn(&RgbaImage, &Meme, &Arc<Font<'static>>, &str) -> Result<RgbaImage, String>,
+}
+
+impl Stage {
+    pub fn apply(
+        &self,
+        image: &RgbaImage,
+        meme: &Meme,
+        font: &Arc<Font<'static>>,
+        args: &str,
+    ) -> Result<RgbaImage, String> {
+        (self.apply)(image, meme, font, args)
+    }
+}
+
+fn caption(
+    image: &RgbaImage,
+    meme: &Meme,
+    font: &Arc<Font<'static>>,
+    args: &str,
+) -> Result<RgbaImage, String> {
+    let text = args.trim().trim_matches('"').trim();
+
+    if text.is_empty() {
+        return Err("Give `caption` some text. (Like `caption \"bottom text\"`.)".into());
+    }
+
+    Ok(add_caption(image, meme, font, text))
+}
+
+pub const STAGES: &[Stage] = &[
+    Stage {
+        name: "caption",
+        description: "adds a line of text below the meme",
+        apply: caption,
+    },
+    Stage {
+        name: "deepfry",
+        description: "makes the meme look like it was reposted too many times",
+        apply: |image, _, _, _| filters::deep_fry(image),
+    },
+    Stage {
+        name: "grayscale",
+        description: "removes the colors",
+        apply: |image, _, _, _| Ok(filters::grayscale(image)),
+    },
+    Stage {
+        name: "mirror",
+        description: "flips the meme horizontally",
+        apply: |image, _, _, _| Ok(filters::mirror(image)),
+    },
+];
+
+pub fn find_stage(name: &str) -> Option<&'static Stage> {
+    let name = name.to_lowercase();
+
+    STAGES.iter().find(|stage| stage.name == name)
+}
+
+/// Splits trailing `| stage args` segments off of `text`. Only segments that
+/// start with the name of a stage are split off, so `|` can still be used in
+/// the meme text itself, like for separating avatars.
+pub fn split_pipeline(text: &str) -> (&str, Vec<(&'static Stage, &str)>) {
+    let mut base = text;
+    let mut stages = Vec::new();
+
+    while let Some(index) = base.rfind('|') {
+        let segment = base[index + 1..].trim();
+        let (name, args) = match segment.find(char::is_whitespace) {
+            Some(space) => (&segment[..space], segment[space..].trim()),
+            None => (segment, ""),
+        };
+
+        match find_stage(name) {
+            Some(stage) => stages.push((stage, args)),
+            None => break,
+        }
+
+        base = &base[..index];
+    }
+
+    stages.reverse();
+
+    (trim_blank_lines(base), stages)
+}
+
+/// Applies each stage to `image` in turn
+pub fn apply_stages(
+    mut image: RgbaImage,
+    meme: &Meme,
+    font: &Arc<Font<'static>>,
+    stages: &[(&Stage, &str)],
+) -> Result<RgbaImage, String> {
+    if stages.len() > MAX_STAGES {
+        return Err(format!("You can apply at most {} effects.", MAX_STAGES));
+    }
+
+    for (stage, args) in stages {
+        debug!("Applying \"{}\" to meme \"{}\"", stage.name, meme.command);
+
+        image = stage.apply(&image, meme, font, args)?;
+    }
+
+    Ok(image)
+}
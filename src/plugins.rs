@@ -0,0 +1,106 @@
+use log::warn;
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Plugins that take longer than this are stopped, and the text is used as is
+const PLUGIN_TIMEOUT: Duration = Duration::from_secs(2);
+
+// More output than this isn't read, since it wouldn't fit in a meme anyway
+const MAX_PLUGIN_OUTPUT_BYTES: u64 = 16 * 1024;
+
+/// Runs `command`, a program followed by its arguments, with `text` as its
+/// input, returning what it prints. The command is run directly rather than
+/// by a shell.
+fn run_plugin(command: &str, text: &str) -> Result<String, String> {
+    let mut words = command.split_whitespace();
+
+    let program = match words.next() {
+        Some(program) => program,
+        None => return Err("Plugin has no command".into()),
+    };
+
+    let mut child = Command::new(program)
+        .args(words)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|reason| format!("Unable to run plugin \"{}\": {}", command, reason))?;
+
+    // Input and output are handled on their own threads, so that a plugin
+    // that prints before reading all of its input can't get stuck
+    let mut stdin = child.stdin.take().expect("Plugin: Unable to open stdin");
+    let input = text.to_string();
+    let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+
+    let stdout = child.stdout.take().expect("Plugin: Unable to open stdout");
+    let reader = thread::spawn(move || {
+        let mut output = Vec::new();
+
+        stdout
+            .take(MAX_PLUGIN_OUTPUT_BYTES)
+            .read_to_end(&mut output)
+            .map(|_| output)
+    });
+
+    let started = Instant::now();
+
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if started.elapsed() < PLUGIN_TIMEOUT => {
+                thread::sleep(Duration::from_millis(10))
+            }
+            Ok(None) => {
+                child.kill().ok();
+                child.wait().ok();
+
+                return Err(format!(
+                    "Plugin \"{}\" took longer than {} seconds",
+                    command,
+                    PLUGIN_TIMEOUT.as_secs()
+                ));
+            }
+            Err(reason) => {
+                return Err(format!(
+                    "Unable to wait for plugin \"{}\": {}",
+                    command, reason
+                ))
+            }
+        }
+    };
+
+    // A plugin that exits without reading its input isn't an error
+    writer.join().ok();
+
+    let output = match reader.join() {
+        Ok(Ok(output)) => output,
+        _ => return Err(format!("Unable to read output of plugin \"{}\"", command)),
+    };
+
+    if !status.success() {
+        return Err(format!("Plugin \"{}\" failed ({})", command, status));
+    }
+
+    let output = String::from_utf8_lossy(&output);
+
+    Ok(output
+        .strip_suffix('\n')
+        .map(|output| output.strip_suffix('\r').unwrap_or(output))
+        .unwrap_or(&output)
+        .to_string())
+}
+
+/// Transforms `text` with the plugin `command`, or leaves it as it is if the
+/// plugin doesn't work
+pub fn apply_text_plugin(command: &str, text: &str) -> String {
+    match run_plugin(command, text) {
+        Ok(transformed) => transformed,
+        Err(reason) => {
+            warn!("{}; using the text as it is", reason);
+            text.to_string()
+        }
+    }
+}
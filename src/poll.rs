@@ -0,0 +1,227 @@
+use log::{info, warn};
+use serde_json::Value;
+use std::collections::HashMap;
+
+use serenity::http::request::RequestBuilder;
+use serenity::http::routing::RouteInfo;
+use serenity::model::id::{ChannelId, MessageId};
+use serenity::model::prelude::{Message, ReactionType};
+use serenity::prelude::Context;
+
+use crate::aliases::resolve_alias;
+use crate::flags::Flags;
+use crate::hotkeys::{emoji_key, parse_emoji};
+use crate::message_text::linked_message_ids;
+use crate::redact;
+use crate::registry::MemesKey;
+use crate::render::{encode_meme_png, render_poll};
+use crate::{split_first_word, MemeSettings};
+
+// Only the options with the most votes are drawn, so that each still has room
+const MAX_OPTIONS: usize = 10;
+
+const USAGE: &str =
+    "Usage: `pollmeme <meme> [<message link>]`, replying to the poll or linking to it";
+
+/// Returns the answers of a Discord poll and their votes, if the message is one
+/// and its votes have been counted. The library doesn't know about polls, so
+/// they're read from the message's JSON.
+fn poll_results(json: &Value) -> Option<Vec<(String, u64)>> {
+    let counts: HashMap<u64, u64> = json["poll"]["results"]["answer_counts"]
+        .as_array()?
+        .iter()
+        .filter_map(|count| Some((count["id"].as_u64()?, count["count"].as_u64()?)))
+        .collect();
+
+    Some(
+        json["poll"]["answers"]
+            .as_array()?
+            .iter()
+            .filter_map(|answer| {
+                let votes = counts
+                    .get(&answer["answer_id"].as_u64()?)
+                    .copied()
+                    .unwrap_or(0);
+
+                Some((answer["poll_media"]["text"].as_str()?.to_string(), votes))
+            })
+            .collect(),
+    )
+}
+
+/// Counts the votes on a message used as a poll, as its reactions, leaving out
+/// the bot's own. Lines of the message that start with one of the emoji, like
+/// "👍 Pizza", give the names of the options.
+fn tally_reactions(poll: &Message) -> Vec<(String, u64)> {
+    let mut names = HashMap::new();
+
+    for line in poll.content.lines() {
+        let (emoji, name) = split_first_word(line.trim());
+
+        if let Some(key) = parse_emoji(emoji) {
+            if !name.trim().is_empty() {
+                names.insert(key, name.trim().to_string());
+            }
+        }
+    }
+
+    poll.reactions
+        .iter()
+        .map(|reaction| {
+            let name = emoji_key(&reaction.reaction_type)
+                .and_then(|key| names.get(&key).cloned())
+                .unwrap_or_else(|| match &reaction.reaction_type {
+                    ReactionType::Custom {
+                        name: Some(name), ..
+                    } => name.clone(),
+                    reaction_type => reaction_type.as_data(),
+                });
+
+            let votes = reaction.count - if reaction.me { 1 } else { 0 };
+
+            (name, votes)
+        })
+        .collect()
+}
+
+/// Fetches the poll and counts its votes: those of a Discord poll, or else
+/// the reactions on the message
+fn tally(
+    ctx: &Context,
+    channel_id: ChannelId,
+    message_id: MessageId,
+) -> Result<Vec<(String, u64)>, String> {
+    let route = RouteInfo::GetMessage {
+        channel_id: channel_id.0,
+        message_id: message_id.0,
+    };
+
+    let json: Value = ctx
+        .http
+        .fire(RequestBuilder::new(route).build())
+        .map_err(|reason| format!("Unable to retrieve message {}: {}", message_id, reason))?;
+
+    let mut results = match poll_results(&json) {
+        Some(results) => results,
+        None => {
+            let poll: Message = serde_json::from_value(json)
+                .map_err(|reason| format!("Unable to parse message {}: {}", message_id, reason))?;
+
+            tally_reactions(&poll)
+        }
+    };
+
+    results.sort_by(|(_, a), (_, b)| b.cmp(a));
+    results.truncate(MAX_OPTIONS);
+
+    Ok(results)
+}
+
+/// Draws the results of a poll into a chart-style meme
+pub fn poll_meme(ctx: &Context, msg: &Message, rest: &str) {
+    let (command, rest) = split_first_word(rest);
+    let command = command.to_lowercase();
+
+    if command.is_empty() {
+        msg.channel_id.say(ctx, USAGE).ok();
+        return;
+    }
+
+    let (channel_id, message_id) = match linked_message_ids(ctx, msg, rest, USAGE) {
+        Ok(ids) => ids,
+        Err(reason) => {
+            msg.channel_id.say(ctx, reason).ok();
+            return;
+        }
+    };
+
+    let results = match tally(ctx, channel_id, message_id) {
+        Ok(results) => results,
+        Err(reason) => {
+            warn!("Command pollmeme: {}", reason);
+
+            msg.channel_id
+                .say(
+                    ctx,
+                    "Unable to find that message. (The bot needs to be able to read its channel.)",
+                )
+                .ok();
+            return;
+        }
+    };
+
+    if results.is_empty() {
+        msg.channel_id
+            .say(
+                ctx,
+                "That message has no votes to count, as a poll or as reactions.",
+            )
+            .ok();
+        return;
+    }
+
+    let data = ctx.data.read();
+
+    let command = msg
+        .guild_id
+        .and_then(|guild_id| resolve_alias(&data, guild_id.0, &command))
+        .unwrap_or(command);
+
+    let memes = data
+        .get::<MemesKey>()
+        .expect("Command pollmeme: Unable to retrieve memes")
+        .load();
+
+    let meme = match memes.find(&command) {
+        Some(meme) => meme,
+        None => {
+            drop(data);
+
+            msg.channel_id
+                .say(ctx, format!("There's no meme called `{}`.", command))
+                .ok();
+            return;
+        }
+    };
+
+    // Rendering can take a while, so it's done without holding on to the data
+    let settings = MemeSettings::read(&data, msg.guild_id.map(|guild_id| guild_id.0), meme);
+    drop(data);
+
+    let font = match &settings.font {
+        Some(font) => font,
+        None => {
+            msg.channel_id.say(ctx, "No fonts are loaded.").ok();
+            return;
+        }
+    };
+
+    let image = render_poll(meme, font, &results, Flags::default().text_style(meme));
+    let png = encode_meme_png(&image, meme, settings.output_settings);
+
+    let png = match png {
+        Ok(png) => png,
+        Err(reason) => {
+            warn!("Command pollmeme: {}", reason);
+
+            msg.channel_id.say(ctx, "Unable to create the meme.").ok();
+            return;
+        }
+    };
+
+    info!(
+        "Poll meme \"{}\" made from message {} by {}",
+        command,
+        message_id,
+        redact::user(&msg.author)
+    );
+
+    let filename = format!("{}.png", command);
+
+    if let Err(reason) = msg
+        .channel_id
+        .send_files(ctx, vec![(&png[..], filename.as_str())], |m| m)
+    {
+        warn!("Command pollmeme: Unable to send meme: {}", reason);
+    }
+}
@@ -0,0 +1,55 @@
+use std::thread;
+use std::time::Duration;
+
+use serenity::model::gateway::Activity;
+use serenity::prelude::Context;
+
+/// An activity shown as the bot's status, like "Playing with memes"
+#[derive(Clone)]
+pub enum BotActivity {
+    Playing(String),
+    Listening(String),
+}
+
+impl BotActivity {
+    /// Parses an activity written the way Discord shows it, like `playing with
+    /// memes` or `listening to hot takes`
+    pub fn parse(text: &str) -> Option<BotActivity> {
+        let text = text.trim();
+        let lowercase = text.to_lowercase();
+
+        if lowercase.starts_with("playing ") {
+            Some(BotActivity::Playing(text["playing ".len()..].trim().into()))
+        } else if lowercase.starts_with("listening to ") {
+            Some(BotActivity::Listening(
+                text["listening to ".len()..].trim().into(),
+            ))
+        } else {
+            None
+        }
+    }
+
+    fn to_activity(&self) -> Activity {
+        match self {
+            BotActivity::Playing(name) => Activity::playing(name),
+            BotActivity::Listening(name) => Activity::listening(name),
+        }
+    }
+}
+
+/// Sets the bot's activity, cycling through `activities` every `interval` if
+/// there is more than one
+pub fn start_presence(ctx: Context, activities: Vec<BotActivity>, interval: Duration) {
+    match activities.len() {
+        0 => {}
+        1 => ctx.set_activity(activities[0].to_activity()),
+        _ => {
+            thread::spawn(move || {
+                for activity in activities.iter().cycle() {
+                    ctx.set_activity(activity.to_activity());
+                    thread::sleep(interval);
+                }
+            });
+        }
+    }
+}
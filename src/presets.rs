@@ -0,0 +1,186 @@
+use log::warn;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{read_to_string, write};
+use yaml_rust::yaml::{Hash, Yaml};
+use yaml_rust::{YamlEmitter, YamlLoader};
+
+use serenity::prelude::TypeMapKey;
+
+use crate::redact;
+
+// Limits on what each user can store
+pub const MAX_PRESETS_PER_USER: usize = 25;
+pub const MAX_PRESET_NAME_LENGTH: usize = 32;
+pub const MAX_PRESET_TEXT_LENGTH: usize = 500;
+
+/// Snippets of meme text saved by users under names of their choosing,
+/// persisted to a YAML file so that they survive restarts
+pub struct PresetStore {
+    filename: String,
+    users: HashMap<u64, BTreeMap<String, String>>,
+}
+
+pub struct PresetsKey;
+
+impl TypeMapKey for PresetsKey {
+    type Value = PresetStore;
+}
+
+impl PresetStore {
+    /// Loads the presets from `filename`. A missing file is not an error,
+    /// since nobody has saved a preset yet.
+    pub fn load(filename: &str) -> Result<PresetStore, String> {
+        let mut store = PresetStore {
+            filename: filename.into(),
+            users: HashMap::new(),
+        };
+
+        let contents = match read_to_string(filename) {
+            Ok(contents) => contents,
+            Err(_) => return Ok(store),
+        };
+
+        let yaml = match YamlLoader::load_from_str(&contents) {
+            Ok(yaml) => yaml,
+            Err(reason) => {
+                return Err(format!(
+                    "Unable to parse presets file \"{}\": {}",
+                    filename, reason
+                ));
+            }
+        };
+
+        if let Some(Yaml::Hash(users)) = yaml.first() {
+            for (user_id, presets) in users {
+                let user_id = match user_id.as_str().and_then(|id| id.parse::<u64>().ok()) {
+                    Some(user_id) => user_id,
+                    None => {
+                        warn!("Presets file contains invalid user ID \"{:?}\"", user_id);
+                        continue;
+                    }
+                };
+
+                let presets = match presets {
+                    Yaml::Hash(presets) => presets,
+                    _ => {
+                        warn!(
+                            "Presets for user {} are malformed",
+                            redact::user_id(user_id)
+                        );
+                        continue;
+                    }
+                };
+
+                let user_presets = store.users.entry(user_id).or_default();
+
+                for (name, text) in presets {
+                    match (name.as_str(), text.as_str()) {
+                        (Some(name), Some(text)) => {
+                            user_presets.insert(name.into(), text.into());
+                        }
+                        _ => {
+                            warn!(
+                                "Presets for user {} contain an invalid entry",
+                                redact::user_id(user_id)
+                            );
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(store)
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let mut users = Hash::new();
+
+        for (user_id, presets) in &self.users {
+            if presets.is_empty() {
+                continue;
+            }
+
+            let mut user_presets = Hash::new();
+
+            for (name, text) in presets {
+                user_presets.insert(Yaml::String(name.clone()), Yaml::String(text.clone()));
+            }
+
+            users.insert(Yaml::String(user_id.to_string()), Yaml::Hash(user_presets));
+        }
+
+        let mut contents = String::new();
+
+        if let Err(reason) = YamlEmitter::new(&mut contents).dump(&Yaml::Hash(users)) {
+            return Err(format!("Unable to serialize presets: {:?}", reason));
+        }
+
+        if let Err(reason) = write(&self.filename, contents + "\n") {
+            return Err(format!(
+                "Unable to write presets file \"{}\": {}",
+                self.filename, reason
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn get(&self, user_id: u64, name: &str) -> Option<&String> {
+        self.users.get(&user_id)?.get(&name.to_lowercase())
+    }
+
+    pub fn list(&self, user_id: u64) -> Vec<(&String, &String)> {
+        match self.users.get(&user_id) {
+            Some(presets) => presets.iter().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Saves a preset, replacing any existing preset with the same name.
+    /// Returns an explanation for the user if the preset breaks the limits.
+    pub fn insert(&mut self, user_id: u64, name: &str, text: &str) -> Result<(), String> {
+        let name = name.to_lowercase();
+
+        if name.chars().count() > MAX_PRESET_NAME_LENGTH {
+            return Err(format!(
+                "Preset names can be at most {} characters long.",
+                MAX_PRESET_NAME_LENGTH
+            ));
+        }
+
+        if text.chars().count() > MAX_PRESET_TEXT_LENGTH {
+            return Err(format!(
+                "Presets can be at most {} characters long.",
+                MAX_PRESET_TEXT_LENGTH
+            ));
+        }
+
+        let presets = self.users.entry(user_id).or_default();
+
+        if !presets.contains_key(&name) && presets.len() >= MAX_PRESETS_PER_USER {
+            return Err(format!(
+                "You can have at most {} presets. Delete one first.",
+                MAX_PRESETS_PER_USER
+            ));
+        }
+
+        presets.insert(name, text.into());
+
+        Ok(())
+    }
+
+    /// Removes all of the user's presets, returning how many there were
+    pub fn forget(&mut self, user_id: u64) -> usize {
+        self.users
+            .remove(&user_id)
+            .map_or(0, |presets| presets.len())
+    }
+
+    /// Returns whether there was a preset to remove
+    pub fn remove(&mut self, user_id: u64, name: &str) -> bool {
+        match self.users.get_mut(&user_id) {
+            Some(presets) => presets.remove(&name.to_lowercase()).is_some(),
+            None => false,
+        }
+    }
+}
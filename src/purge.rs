@@ -0,0 +1,378 @@
+use log::{info, warn};
+
+use serenity::model::prelude::Message;
+use serenity::prelude::{Context, ShareMap};
+
+use crate::admin_sessions::AdminSessionsKey;
+use crate::calibrate::CalibrationsKey;
+use crate::gallery::GallerySyncsKey;
+use crate::guild_settings::GuildSettingsKey;
+use crate::presets::PresetsKey;
+use crate::redact;
+use crate::voice_memes::VoiceJoinsKey;
+use crate::wizard::WizardsKey;
+use crate::{parse_user_id, split_first_word};
+
+fn plural(count: usize, noun: &str) -> String {
+    format!("{} {}{}", count, noun, if count == 1 { "" } else { "s" })
+}
+
+/// What the bot has stored about a user
+struct StoredData {
+    presets: usize,
+    // Events, manager roles, and voice meme entries in servers' settings
+    guild_entries: usize,
+    admin_session: bool,
+    // Unfinished `addmeme`, `calibrate`, and `sync` conversations
+    conversations: usize,
+    // Recent voice memes, which keep the next ones from being posted too soon
+    voice_joins: usize,
+}
+
+impl StoredData {
+    fn is_empty(&self) -> bool {
+        self.presets == 0
+            && self.guild_entries == 0
+            && !self.admin_session
+            && self.conversations == 0
+            && self.voice_joins == 0
+    }
+
+    /// Lists the data, like "3 presets, 1 admin session"
+    fn describe(&self) -> String {
+        let mut parts = Vec::new();
+
+        if self.presets > 0 {
+            parts.push(plural(self.presets, "preset"));
+        }
+
+        if self.guild_entries > 0 {
+            parts.push(plural(self.guild_entries, "server setting"));
+        }
+
+        if self.admin_session {
+            parts.push("1 admin session".into());
+        }
+
+        if self.conversations > 0 {
+            parts.push(plural(self.conversations, "unfinished conversation"));
+        }
+
+        if self.voice_joins > 0 {
+            parts.push(plural(self.voice_joins, "voice meme cooldown"));
+        }
+
+        parts.join(", ")
+    }
+}
+
+fn find(data: &ShareMap, user_id: u64) -> StoredData {
+    let presets = data
+        .get::<PresetsKey>()
+        .expect("Purge: Unable to retrieve presets")
+        .list(user_id)
+        .len();
+
+    let guild_entries = data
+        .get::<GuildSettingsKey>()
+        .expect("Purge: Unable to retrieve guild settings")
+        .user_entries(user_id);
+
+    let admin_session = data
+        .get::<AdminSessionsKey>()
+        .expect("Purge: Unable to retrieve admin sessions")
+        .lock()
+        .is_admin(user_id);
+
+    let conversations = [
+        data.get::<WizardsKey>()
+            .expect("Purge: Unable to retrieve wizards")
+            .contains_key(&user_id),
+        data.get::<CalibrationsKey>()
+            .expect("Purge: Unable to retrieve calibrations")
+            .contains_key(&user_id),
+        data.get::<GallerySyncsKey>()
+            .expect("Purge: Unable to retrieve gallery syncs")
+            .contains_key(&user_id),
+    ]
+    .iter()
+    .filter(|started| **started)
+    .count();
+
+    let voice_joins = data
+        .get::<VoiceJoinsKey>()
+        .expect("Purge: Unable to retrieve voice joins")
+        .keys()
+        .filter(|(_, id)| *id == user_id)
+        .count();
+
+    StoredData {
+        presets,
+        guild_entries,
+        admin_session,
+        conversations,
+        voice_joins,
+    }
+}
+
+/// Deletes everything stored about `user_id`, saving the files that changed
+fn purge(data: &mut ShareMap, user_id: u64) {
+    let presets = data
+        .get_mut::<PresetsKey>()
+        .expect("Purge: Unable to retrieve presets");
+
+    if presets.forget(user_id) > 0 {
+        if let Err(reason) = presets.save() {
+            warn!("Purge: {}", reason);
+        }
+    }
+
+    let guild_settings = data
+        .get_mut::<GuildSettingsKey>()
+        .expect("Purge: Unable to retrieve guild settings");
+
+    if guild_settings.forget_user(user_id) > 0 {
+        if let Err(reason) = guild_settings.save() {
+            warn!("Purge: {}", reason);
+        }
+    }
+
+    let mut sessions = data
+        .get::<AdminSessionsKey>()
+        .expect("Purge: Unable to retrieve admin sessions")
+        .lock();
+
+    if sessions.revoke(user_id) {
+        if let Err(reason) = sessions.save() {
+            warn!("Purge: {}", reason);
+        }
+    }
+
+    drop(sessions);
+
+    data.get_mut::<WizardsKey>()
+        .expect("Purge: Unable to retrieve wizards")
+        .remove(&user_id);
+    data.get_mut::<CalibrationsKey>()
+        .expect("Purge: Unable to retrieve calibrations")
+        .remove(&user_id);
+    data.get_mut::<GallerySyncsKey>()
+        .expect("Purge: Unable to retrieve gallery syncs")
+        .remove(&user_id);
+    data.get_mut::<VoiceJoinsKey>()
+        .expect("Purge: Unable to retrieve voice joins")
+        .retain(|(_, id), _| *id != user_id);
+}
+
+/// Shows what's stored about `user_id` and how to confirm deleting it, or
+/// deletes it once confirmed. Returns the reply, and whether anything was
+/// deleted.
+fn forget(
+    data: &mut ShareMap,
+    user_id: u64,
+    confirmed: bool,
+    confirm_command: &str,
+) -> (String, bool) {
+    let stored = find(data, user_id);
+
+    if stored.is_empty() {
+        return ("Nothing is stored.".into(), false);
+    }
+
+    if !confirmed {
+        let reply = format!(
+            "Stored: {}. Send `{}` to delete all of it. This can't be undone.",
+            stored.describe(),
+            confirm_command
+        );
+
+        return (reply, false);
+    }
+
+    purge(data, user_id);
+
+    (format!("Deleted {}.", stored.describe()), true)
+}
+
+/// Deletes what the bot has stored about the author of `msg`, once they
+/// confirm with `forgetme confirm`
+pub fn forget_me(ctx: &Context, msg: &Message, rest: &str) {
+    let confirmed = rest.trim().eq_ignore_ascii_case("confirm");
+    let (reply, deleted) = forget(
+        &mut ctx.data.write(),
+        msg.author.id.0,
+        confirmed,
+        "forgetme confirm",
+    );
+
+    if deleted {
+        info!("User had their data deleted: {}", redact::user(&msg.author));
+    }
+
+    msg.channel_id.say(ctx, reply).ok();
+}
+
+/// Deletes what the bot has stored about the user given in `rest`, once the
+/// admin confirms with `purgeuser <user> confirm`
+pub fn purge_user(ctx: &Context, msg: &Message, rest: &str) {
+    let (user, confirm) = split_first_word(rest.trim());
+
+    let user_id = match parse_user_id(user) {
+        Some(user_id) => user_id,
+        None => {
+            msg.channel_id
+                .say(ctx, "Usage: `purgeuser <user ID or mention> [confirm]`")
+                .ok();
+            return;
+        }
+    };
+
+    let confirmed = confirm.trim().eq_ignore_ascii_case("confirm");
+    let (reply, deleted) = forget(
+        &mut ctx.data.write(),
+        user_id,
+        confirmed,
+        &format!("purgeuser {} confirm", user_id),
+    );
+
+    if deleted {
+        info!(
+            "User deleted the data of {}: {}",
+            redact::user_id(user_id),
+            redact::user(&msg.author)
+        );
+    }
+
+    msg.channel_id.say(ctx, reply).ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serenity::prelude::Mutex;
+    use std::collections::HashMap;
+    use std::env::temp_dir;
+    use std::fs::remove_file;
+    use std::time::{Duration, Instant};
+
+    use crate::admin_sessions::AdminSessionStore;
+    use crate::guild_settings::{Event, GuildSettingsStore};
+    use crate::presets::PresetStore;
+    use crate::{calibrate, gallery, wizard};
+
+    const USER: u64 = 1;
+    const OTHER_USER: u64 = 2;
+
+    fn filename(store: &str) -> String {
+        temp_dir()
+            .join(format!("purge-{}-{}.yml", store, std::process::id()))
+            .to_string_lossy()
+            .to_string()
+    }
+
+    /// Stores one of everything about `USER`, and a preset of `OTHER_USER`
+    fn populated_data() -> ShareMap {
+        let mut presets = PresetStore::load(&filename("presets")).unwrap();
+        presets.insert(USER, "greeting", "hello").unwrap();
+        presets.insert(USER, "farewell", "goodbye").unwrap();
+        presets.insert(OTHER_USER, "greeting", "hi").unwrap();
+
+        let mut guild_settings = GuildSettingsStore::load(&filename("guild-settings")).unwrap();
+        let settings = guild_settings.get_mut(10);
+        settings.managers.push(USER);
+        settings.events.push(Event {
+            user: USER,
+            name: "birthday".into(),
+            month: 1,
+            day: 1,
+            last_posted: None,
+        });
+
+        let mut sessions =
+            AdminSessionStore::load(&filename("admin-sessions"), Duration::from_secs(60)).unwrap();
+        sessions.start(USER, "admin");
+
+        let mut wizards = HashMap::new();
+        wizards.insert(USER, wizard::mock::wizard());
+        let mut calibrations = HashMap::new();
+        calibrations.insert(USER, calibrate::mock::calibration("drake"));
+        let mut syncs = HashMap::new();
+        syncs.insert(USER, gallery::mock::sync("drake"));
+
+        let mut voice_joins = HashMap::new();
+        voice_joins.insert((10, USER), Instant::now());
+        voice_joins.insert((11, USER), Instant::now());
+
+        let mut data = ShareMap::custom();
+        data.insert::<PresetsKey>(presets);
+        data.insert::<GuildSettingsKey>(guild_settings);
+        data.insert::<AdminSessionsKey>(Mutex::new(sessions));
+        data.insert::<WizardsKey>(wizards);
+        data.insert::<CalibrationsKey>(calibrations);
+        data.insert::<GallerySyncsKey>(syncs);
+        data.insert::<VoiceJoinsKey>(voice_joins);
+        data
+    }
+
+    fn remove_files() {
+        for store in &["presets", "guild-settings", "admin-sessions"] {
+            remove_file(filename(store)).ok();
+        }
+    }
+
+    #[test]
+    fn describes_the_data_with_plurals() {
+        let stored = StoredData {
+            presets: 1,
+            guild_entries: 2,
+            admin_session: true,
+            conversations: 1,
+            voice_joins: 3,
+        };
+
+        assert_eq!(
+            stored.describe(),
+            "1 preset, 2 server settings, 1 admin session, 1 unfinished conversation, 3 voice meme cooldowns"
+        );
+
+        let stored = StoredData {
+            presets: 2,
+            guild_entries: 0,
+            admin_session: false,
+            conversations: 3,
+            voice_joins: 0,
+        };
+
+        assert_eq!(stored.describe(), "2 presets, 3 unfinished conversations");
+    }
+
+    #[test]
+    fn forgets_nothing_until_confirmed_and_then_everything() {
+        let mut data = populated_data();
+
+        let (reply, deleted) = forget(&mut data, USER, false, "forgetme confirm");
+
+        assert!(!deleted);
+        assert_eq!(
+            reply,
+            "Stored: 2 presets, 2 server settings, 1 admin session, 3 unfinished conversations, 2 voice meme cooldowns. Send `forgetme confirm` to delete all of it. This can't be undone."
+        );
+        assert_eq!(find(&data, USER).describe(), "2 presets, 2 server settings, 1 admin session, 3 unfinished conversations, 2 voice meme cooldowns");
+
+        let (reply, deleted) = forget(&mut data, USER, true, "forgetme confirm");
+        remove_files();
+
+        assert!(deleted);
+        assert_eq!(
+            reply,
+            "Deleted 2 presets, 2 server settings, 1 admin session, 3 unfinished conversations, 2 voice meme cooldowns."
+        );
+        assert!(find(&data, USER).is_empty());
+        assert_eq!(find(&data, OTHER_USER).describe(), "1 preset");
+
+        let (reply, deleted) = forget(&mut data, USER, true, "forgetme confirm");
+
+        assert!(!deleted);
+        assert_eq!(reply, "Nothing is stored.");
+    }
+}
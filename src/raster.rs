@@ -0,0 +1,690 @@
+use image::{Rgba, RgbaImage};
+use rusttype::{point, Font, PositionedGlyph, Scale};
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use std::ops::Range;
+use std::sync::{Arc, Mutex, OnceLock};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum StrokeJoin {
+    Round,
+    Miter,
+    Bevel,
+}
+
+impl StrokeJoin {
+    pub fn from_name(name: &str) -> Option<StrokeJoin> {
+        match name.to_lowercase().as_str() {
+            "round" => Some(StrokeJoin::Round),
+            "miter" => Some(StrokeJoin::Miter),
+            "bevel" => Some(StrokeJoin::Bevel),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            StrokeJoin::Round => "round",
+            StrokeJoin::Miter => "miter",
+            StrokeJoin::Bevel => "bevel",
+        }
+    }
+
+    // How far an offset is from the center of the pen for this kind of join:
+    // a round pen traces round corners, a square pen keeps corners sharp, and
+    // a diamond-shaped pen cuts them off
+    fn distance(self, dx: i32, dy: i32) -> f32 {
+        let (dx, dy) = (dx.abs() as f32, dy.abs() as f32);
+
+        match self {
+            StrokeJoin::Round => (dx * dx + dy * dy).sqrt(),
+            StrokeJoin::Miter => dx.max(dy),
+            StrokeJoin::Bevel => dx + dy,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum TextEffectKind {
+    None,
+    // Glyphs bob up and down along a sine wave
+    Wave,
+    // The line curves, bulging upward in the middle
+    Arc,
+    // Glyphs are nudged around by a random-looking amount
+    Jitter,
+}
+
+impl TextEffectKind {
+    pub fn from_name(name: &str) -> Option<TextEffectKind> {
+        match name.to_lowercase().as_str() {
+            "none" => Some(TextEffectKind::None),
+            "wave" => Some(TextEffectKind::Wave),
+            "arc" => Some(TextEffectKind::Arc),
+            "jitter" => Some(TextEffectKind::Jitter),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            TextEffectKind::None => "none",
+            TextEffectKind::Wave => "wave",
+            TextEffectKind::Arc => "arc",
+            TextEffectKind::Jitter => "jitter",
+        }
+    }
+}
+
+/// Displaces the glyphs of a line of text as they're placed
+#[derive(Clone, Copy)]
+pub struct TextEffect {
+    pub kind: TextEffectKind,
+    // How far glyphs are moved at most, in pixels
+    pub amplitude: f32,
+    // How many waves fit in a line, for `Wave`
+    pub frequency: f32,
+    // Changes which way glyphs are nudged, for `Jitter`
+    pub seed: u64,
+}
+
+impl TextEffect {
+    pub const NONE: TextEffect = TextEffect {
+        kind: TextEffectKind::None,
+        amplitude: 0f32,
+        frequency: 0f32,
+        seed: 0,
+    };
+
+    /// Returns how far to move glyph number `index`, whose center is at
+    /// `position` from 0 (the start of the line) to 1 (the end of the line)
+    fn offset(self, index: usize, position: f32) -> (f32, f32) {
+        match self.kind {
+            TextEffectKind::None => (0f32, 0f32),
+            TextEffectKind::Wave => (
+                0f32,
+                -self.amplitude * (2f32 * PI * self.frequency * position).sin(),
+            ),
+            TextEffectKind::Arc => {
+                let distance_from_center = 2f32 * position - 1f32;
+
+                (
+                    0f32,
+                    -self.amplitude * (1f32 - distance_from_center * distance_from_center),
+                )
+            }
+            TextEffectKind::Jitter => {
+                // The same text always jitters the same way for the same seed,
+                // so that a meme looks the same each time it's made
+                let mut state = (index as u32).wrapping_add(1).wrapping_mul(2_654_435_761)
+                    ^ (self.seed as u32 ^ (self.seed >> 32) as u32);
+                let mut next = || {
+                    state ^= state << 13;
+                    state ^= state >> 17;
+                    state ^= state << 5;
+
+                    (state % 2001) as f32 / 1000f32 - 1f32
+                };
+
+                (self.amplitude * next(), self.amplitude * next())
+            }
+        }
+    }
+}
+
+/// Coverage of the pixels around a piece of text, from 0 (untouched) to 1
+/// (completely covered)
+pub struct Mask {
+    // Position of the mask's top-left corner relative to the point the text
+    // was drawn at
+    left: i32,
+    top: i32,
+    width: u32,
+    height: u32,
+    coverage: Vec<f32>,
+}
+
+impl Mask {
+    fn empty(left: i32, top: i32, width: u32, height: u32) -> Mask {
+        Mask {
+            left,
+            top,
+            width,
+            height,
+            coverage: vec![0f32; (width * height) as usize],
+        }
+    }
+
+    fn get(&self, x: i32, y: i32) -> f32 {
+        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+            return 0f32;
+        }
+
+        self.coverage[(y as u32 * self.width + x as u32) as usize]
+    }
+
+    /// Returns the outline of this mask, `width` pixels wide, as traced by a
+    /// pen of the shape matching `join`. The outline includes the area covered
+    /// by the original mask, so it is meant to be drawn underneath it.
+    pub fn stroke(&self, width: u32, join: StrokeJoin) -> Mask {
+        let radius = width as i32;
+        let padding = radius + 1;
+
+        let mut stroke = Mask::empty(
+            self.left - padding,
+            self.top - padding,
+            self.width + 2 * padding as u32,
+            self.height + 2 * padding as u32,
+        );
+
+        // Weights of the pen at each offset, antialiased along its edge
+        let mut pen = Vec::<(i32, i32, f32)>::new();
+
+        for dy in -padding..=padding {
+            for dx in -padding..=padding {
+                let weight = (radius as f32 + 0.5 - join.distance(dx, dy)).clamp(0f32, 1f32);
+
+                if weight > 0f32 {
+                    pen.push((dx, dy, weight));
+                }
+            }
+        }
+
+        for y in 0..stroke.height as i32 {
+            for x in 0..stroke.width as i32 {
+                let mut coverage = 0f32;
+
+                for (dx, dy, weight) in &pen {
+                    coverage = coverage.max(self.get(x - padding + dx, y - padding + dy) * weight);
+
+                    if coverage >= 1f32 {
+                        break;
+                    }
+                }
+
+                stroke.coverage[(y as u32 * stroke.width + x as u32) as usize] = coverage;
+            }
+        }
+
+        stroke
+    }
+
+    /// Blends `color` into `image` according to this mask, with the point the
+    /// text was drawn at placed at (`x`, `y`)
+    pub fn draw(&self, image: &mut RgbaImage, x: i32, y: i32, color: Rgba<u8>) {
+        for mask_y in 0..self.height as i32 {
+            for mask_x in 0..self.width as i32 {
+                // Partly transparent colors cover less of what's underneath
+                let coverage = self.get(mask_x, mask_y) * color[3] as f32 / 255f32;
+
+                if coverage <= 0f32 {
+                    continue;
+                }
+
+                let image_x = x + self.left + mask_x;
+                let image_y = y + self.top + mask_y;
+
+                if image_x < 0
+                    || image_y < 0
+                    || image_x >= image.width() as i32
+                    || image_y >= image.height() as i32
+                {
+                    continue;
+                }
+
+                let pixel = image.get_pixel_mut(image_x as u32, image_y as u32);
+
+                for channel in 0..3 {
+                    pixel[channel] = (pixel[channel] as f32 * (1f32 - coverage)
+                        + color[channel] as f32 * coverage)
+                        .round() as u8;
+                }
+
+                pixel[3] = (pixel[3] as f32 + (255f32 - pixel[3] as f32) * coverage).round() as u8;
+            }
+        }
+    }
+}
+
+// Glyphs are rasterized at this many horizontal and vertical offsets within a
+// pixel, so that cached glyphs still line up closely with where they belong
+const SUBPIXEL_STEPS: f32 = 4f32;
+
+// The cache is emptied once it holds this many glyphs, which bounds its memory
+// use when many fonts and sizes are in use
+const MAX_CACHED_GLYPHS: usize = 16384;
+
+#[derive(PartialEq, Eq, Hash)]
+struct GlyphKey {
+    // The address of the font, which the cache holds on to so that no other
+    // font can be loaded at the same address while its glyphs are cached
+    font: usize,
+    glyph: u32,
+    scale_x: u32,
+    scale_y: u32,
+    subpixel_x: u8,
+    subpixel_y: u8,
+}
+
+/// Coverage of a single glyph, relative to the whole pixel it was drawn at
+struct GlyphBitmap {
+    left: i32,
+    top: i32,
+    width: u32,
+    coverage: Vec<f32>,
+}
+
+/// The glyphs rasterized so far, along with the fonts they came from
+#[derive(Default)]
+struct GlyphCache {
+    fonts: HashMap<usize, Arc<Font<'static>>>,
+    glyphs: HashMap<GlyphKey, Arc<GlyphBitmap>>,
+}
+
+fn glyph_cache() -> &'static Mutex<GlyphCache> {
+    static GLYPH_CACHE: OnceLock<Mutex<GlyphCache>> = OnceLock::new();
+
+    GLYPH_CACHE.get_or_init(|| Mutex::new(GlyphCache::default()))
+}
+
+/// Forgets every cached glyph, letting go of fonts that are no longer loaded,
+/// like after the config is reloaded
+pub fn clear_glyph_cache() {
+    let mut cache = glyph_cache()
+        .lock()
+        .expect("clear_glyph_cache(): Unable to lock glyph cache");
+
+    cache.fonts.clear();
+    cache.glyphs.clear();
+}
+
+/// Splits a coordinate into a whole pixel and a step within it
+fn split_subpixel(coordinate: f32) -> (i32, u8) {
+    let steps = (coordinate * SUBPIXEL_STEPS).round() as i32;
+
+    (
+        steps.div_euclid(SUBPIXEL_STEPS as i32),
+        steps.rem_euclid(SUBPIXEL_STEPS as i32) as u8,
+    )
+}
+
+/// Returns the bitmap of `glyph` along with the whole pixel it is placed at,
+/// rasterizing it only if the same glyph hasn't been rasterized before
+fn rasterize_glyph(
+    font: &Arc<Font<'static>>,
+    scale: Scale,
+    glyph: &PositionedGlyph,
+) -> (i32, i32, Arc<GlyphBitmap>) {
+    let position = glyph.position();
+    let (x, subpixel_x) = split_subpixel(position.x);
+    let (y, subpixel_y) = split_subpixel(position.y);
+
+    let key = GlyphKey {
+        font: Arc::as_ptr(font) as usize,
+        glyph: glyph.id().0,
+        scale_x: scale.x.to_bits(),
+        scale_y: scale.y.to_bits(),
+        subpixel_x,
+        subpixel_y,
+    };
+
+    let cached = glyph_cache()
+        .lock()
+        .expect("rasterize_glyph(): Unable to lock glyph cache")
+        .glyphs
+        .get(&key)
+        .cloned();
+
+    if let Some(bitmap) = cached {
+        return (x, y, bitmap);
+    }
+
+    // The cache isn't locked while the glyph is rasterized, so that other
+    // threads can still use it in the meantime. The font can't be dropped
+    // until this returns, so its address stays its own.
+    let glyph = glyph.unpositioned().clone().positioned(point(
+        subpixel_x as f32 / SUBPIXEL_STEPS,
+        subpixel_y as f32 / SUBPIXEL_STEPS,
+    ));
+
+    let bitmap = match glyph.pixel_bounding_box() {
+        Some(bb) => {
+            let width = bb.width() as u32;
+            let mut coverage = vec![0f32; (width * bb.height() as u32) as usize];
+
+            glyph.draw(|glyph_x, glyph_y, glyph_coverage| {
+                coverage[(glyph_y * width + glyph_x) as usize] = glyph_coverage;
+            });
+
+            GlyphBitmap {
+                left: bb.min.x,
+                top: bb.min.y,
+                width,
+                coverage,
+            }
+        }
+        None => GlyphBitmap {
+            left: 0,
+            top: 0,
+            width: 0,
+            coverage: Vec::new(),
+        },
+    };
+
+    let mut cache = glyph_cache()
+        .lock()
+        .expect("rasterize_glyph(): Unable to lock glyph cache");
+
+    if cache.glyphs.len() >= MAX_CACHED_GLYPHS {
+        cache.fonts.clear();
+        cache.glyphs.clear();
+    }
+
+    cache
+        .fonts
+        .entry(key.font)
+        .or_insert_with(|| Arc::clone(font));
+
+    // Another thread may have rasterized the same glyph in the meantime, in
+    // which case its bitmap is kept
+    let bitmap = cache.glyphs.entry(key).or_insert_with(|| Arc::new(bitmap));
+
+    (x, y, Arc::clone(bitmap))
+}
+
+/// Rasterizes a line of text into a mask, with its glyphs moved around by
+/// `effect`. The point the text is drawn at is the top-left corner of the
+/// line, like `imageproc::drawing::draw_text`.
+pub fn rasterize_text(
+    font: &Arc<Font<'static>>,
+    scale: Scale,
+    text: &str,
+    effect: TextEffect,
+) -> Mask {
+    rasterize_text_range(font, scale, text, effect, 0..usize::MAX)
+}
+
+/// The same as `rasterize_text`, with only the characters in `range` of the
+/// line rasterized, where they'd be in the whole line
+pub fn rasterize_text_range(
+    font: &Arc<Font<'static>>,
+    scale: Scale,
+    text: &str,
+    effect: TextEffect,
+    range: Range<usize>,
+) -> Mask {
+    let v_metrics = font.v_metrics(scale);
+    let positioned: Vec<_> = font
+        .layout(text, scale, point(0f32, v_metrics.ascent))
+        .collect();
+
+    let width = positioned
+        .last()
+        .map(|glyph| glyph.position().x + glyph.unpositioned().h_metrics().advance_width)
+        .unwrap_or(0f32)
+        .max(1f32);
+
+    let glyphs: Vec<_> = positioned
+        .into_iter()
+        .enumerate()
+        .map(|(index, glyph)| {
+            if effect.kind == TextEffectKind::None {
+                return glyph;
+            }
+
+            let position = glyph.position();
+            let center = position.x + glyph.unpositioned().h_metrics().advance_width / 2f32;
+            let (dx, dy) = effect.offset(index, center / width);
+
+            glyph
+                .into_unpositioned()
+                .positioned(point(position.x + dx, position.y + dy))
+        })
+        .enumerate()
+        .filter(|(index, _)| range.contains(index))
+        .map(|(_, glyph)| rasterize_glyph(font, scale, &glyph))
+        .filter(|(_, _, bitmap)| bitmap.width > 0)
+        .collect();
+
+    if glyphs.is_empty() {
+        return Mask::empty(0, 0, 0, 0);
+    }
+
+    let bounds = |(x, y, bitmap): &(i32, i32, Arc<GlyphBitmap>)| {
+        let height = (bitmap.coverage.len() as u32 / bitmap.width) as i32;
+        let left = x + bitmap.left;
+        let top = y + bitmap.top;
+
+        (left, top, left + bitmap.width as i32, top + height)
+    };
+
+    let left = glyphs
+        .iter()
+        .map(|glyph| bounds(glyph).0)
+        .min()
+        .unwrap_or(0);
+    let top = glyphs
+        .iter()
+        .map(|glyph| bounds(glyph).1)
+        .min()
+        .unwrap_or(0);
+    let right = glyphs
+        .iter()
+        .map(|glyph| bounds(glyph).2)
+        .max()
+        .unwrap_or(0);
+    let bottom = glyphs
+        .iter()
+        .map(|glyph| bounds(glyph).3)
+        .max()
+        .unwrap_or(0);
+
+    let mut mask = Mask::empty(left, top, (right - left) as u32, (bottom - top) as u32);
+
+    for glyph in &glyphs {
+        let (glyph_left, glyph_top, _, _) = bounds(glyph);
+        let bitmap = &glyph.2;
+
+        for (index, coverage) in bitmap.coverage.iter().enumerate() {
+            let x = (glyph_left - left) as u32 + index as u32 % bitmap.width;
+            let y = (glyph_top - top) as u32 + index as u32 / bitmap.width;
+            let index = (y * mask.width + x) as usize;
+
+            // Overlapping glyphs shouldn't darken each other's edges
+            mask.coverage[index] = mask.coverage[index].max(*coverage);
+        }
+    }
+
+    mask
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusttype::FontCollection;
+
+    /// Two installed fonts from different families, if there are that many
+    fn two_system_fonts() -> Option<(Font<'static>, Font<'static>)> {
+        let mut system_fonts = fontdb::Database::new();
+        system_fonts.load_system_fonts();
+
+        let mut fonts = Vec::new();
+        let mut families = Vec::new();
+
+        for face in system_fonts.faces() {
+            let family = face.families.first().map(|(family, _)| family.clone());
+
+            if fonts.len() == 2 || families.contains(&family) {
+                continue;
+            }
+
+            let font = system_fonts.with_face_data(face.id, |data, index| {
+                FontCollection::from_bytes(data.to_vec())
+                    .ok()?
+                    .font_at(index as usize)
+                    .ok()
+            });
+
+            if let Some(Some(font)) = font {
+                fonts.push(font);
+                families.push(family);
+            }
+        }
+
+        let second = fonts.pop()?;
+        let first = fonts.pop()?;
+
+        Some((first, second))
+    }
+
+    fn coverage(mask: &Mask) -> (i32, i32, u32, u32, Vec<f32>) {
+        (
+            mask.left,
+            mask.top,
+            mask.width,
+            mask.height,
+            mask.coverage.clone(),
+        )
+    }
+
+    fn rasterize(font: &Arc<Font<'static>>) -> (i32, i32, u32, u32, Vec<f32>) {
+        coverage(&rasterize_text(
+            font,
+            Scale::uniform(32f32),
+            "Rag",
+            TextEffect::NONE,
+        ))
+    }
+
+    #[test]
+    fn keeps_the_glyphs_of_fonts_apart() {
+        let (first, second) = match two_system_fonts() {
+            Some(fonts) => fonts,
+            None => {
+                eprintln!("Skipping: fewer than two fonts are installed");
+                return;
+            }
+        };
+
+        // Loaded the way the bot loads them, and then dropped before the
+        // second font, whose allocation could then take the first's address
+        let first = Arc::new(first);
+        let first_mask = rasterize(&first);
+        assert_eq!(rasterize(&first), first_mask);
+        drop(first);
+
+        let second = Arc::new(second);
+        let second_mask = rasterize(&second);
+
+        clear_glyph_cache();
+
+        assert_eq!(rasterize(&second), second_mask);
+        assert_ne!(second_mask, first_mask);
+    }
+
+    #[test]
+    fn renders_fonts_in_turn() {
+        let (first, second) = match two_system_fonts() {
+            Some(fonts) => fonts,
+            None => {
+                eprintln!("Skipping: fewer than two fonts are installed");
+                return;
+            }
+        };
+
+        let (first, second) = (Arc::new(first), Arc::new(second));
+        let (first_mask, second_mask) = (rasterize(&first), rasterize(&second));
+
+        for _ in 0..3 {
+            assert_eq!(rasterize(&first), first_mask);
+            assert_eq!(rasterize(&second), second_mask);
+        }
+
+        assert_ne!(first_mask, second_mask);
+    }
+
+    #[test]
+    fn renders_from_many_threads_at_once() {
+        let (first, second) = match two_system_fonts() {
+            Some(fonts) => fonts,
+            None => {
+                eprintln!("Skipping: fewer than two fonts are installed");
+                return;
+            }
+        };
+
+        let (first, second) = (Arc::new(first), Arc::new(second));
+        let (first_mask, second_mask) = (rasterize(&first), rasterize(&second));
+
+        // Memes are rendered without any lock held, so requests share the
+        // glyph cache as they please
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let (first, second) = (first.clone(), second.clone());
+                let (first_mask, second_mask) = (first_mask.clone(), second_mask.clone());
+
+                std::thread::spawn(move || {
+                    for _ in 0..20 {
+                        assert_eq!(rasterize(&first), first_mask);
+                        assert_eq!(rasterize(&second), second_mask);
+                    }
+                })
+            })
+            .collect();
+
+        for thread in threads {
+            thread.join().expect("A rendering thread panicked");
+        }
+    }
+
+    #[test]
+    fn rasterizes_missing_glyphs_from_many_threads_at_once() {
+        let (font, _) = match two_system_fonts() {
+            Some(fonts) => fonts,
+            None => {
+                eprintln!("Skipping: fewer than two fonts are installed");
+                return;
+            }
+        };
+
+        let font = Arc::new(font);
+        let scales = [17f32, 23f32, 29f32, 31f32];
+        let expected: Vec<_> = scales
+            .iter()
+            .map(|size| {
+                coverage(&rasterize_text(
+                    &font,
+                    Scale::uniform(*size),
+                    "Rag",
+                    TextEffect::NONE,
+                ))
+            })
+            .collect();
+
+        // Every thread misses the cache for the same glyphs at once, and
+        // rasterizes them while the others do too
+        clear_glyph_cache();
+
+        let barrier = Arc::new(std::sync::Barrier::new(8));
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let (font, expected, barrier) = (font.clone(), expected.clone(), barrier.clone());
+
+                std::thread::spawn(move || {
+                    barrier.wait();
+
+                    for (size, expected) in scales.iter().zip(&expected) {
+                        let mask =
+                            rasterize_text(&font, Scale::uniform(*size), "Rag", TextEffect::NONE);
+
+                        assert_eq!(coverage(&mask), *expected);
+                    }
+                })
+            })
+            .collect();
+
+        for thread in threads {
+            thread.join().expect("A rendering thread panicked");
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::OnceLock;
+
+use serenity::model::prelude::User;
+
+/// How much of what users send and who they are makes it into the log
+pub struct RedactionSettings {
+    // Whether users are logged by a hash of their ID instead of their name,
+    // and texts by their length instead of their contents
+    pub redact: bool,
+    // How many characters of each text are still logged when redacting
+    pub text_chars: usize,
+}
+
+struct Redaction {
+    settings: RedactionSettings,
+    // Mixed into the hashes of user IDs, so that the same user can be followed
+    // through the log without the ID being recoverable from it
+    salt: u64,
+}
+
+static REDACTION: OnceLock<Redaction> = OnceLock::new();
+
+/// Sets how user content is logged, once when the bot starts. Before this is
+/// called, nothing is redacted.
+pub fn configure(settings: RedactionSettings) -> Result<(), String> {
+    let redaction = Redaction {
+        settings,
+        salt: rand::random(),
+    };
+
+    if REDACTION.set(redaction).is_err() {
+        return Err("Log redaction has already been configured".into());
+    }
+
+    Ok(())
+}
+
+fn redaction() -> Option<&'static Redaction> {
+    REDACTION
+        .get()
+        .filter(|redaction| redaction.settings.redact)
+}
+
+/// A user as they should appear in the log, like `name#1234`, or `user:<hash>`
+/// when redacting
+pub fn user(user: &User) -> String {
+    match redaction() {
+        Some(redaction) => hashed_user_id(redaction, user.id.0),
+        None => format!("{}#{}", user.name, user.discriminator),
+    }
+}
+
+/// A user ID as it should appear in the log
+pub fn user_id(user_id: u64) -> String {
+    match redaction() {
+        Some(redaction) => hashed_user_id(redaction, user_id),
+        None => user_id.to_string(),
+    }
+}
+
+fn hashed_user_id(redaction: &Redaction, user_id: u64) -> String {
+    let mut hasher = DefaultHasher::new();
+    redaction.salt.hash(&mut hasher);
+    user_id.hash(&mut hasher);
+
+    format!("user:{:016x}", hasher.finish())
+}
+
+/// Text that a user sent, as it should appear in the log: all of it, or only
+/// its start and length when redacting
+pub fn text(text: &str) -> String {
+    match redaction() {
+        Some(redaction) => redacted_text(text, redaction.settings.text_chars),
+        None => text.into(),
+    }
+}
+
+/// `text` cut down to its first `text_chars` characters, with its length
+fn redacted_text(text: &str, text_chars: usize) -> String {
+    let length = text.chars().count();
+    let kept: String = text.chars().take(text_chars).collect();
+
+    if kept.is_empty() {
+        format!("({} characters)", length)
+    } else if length > text_chars {
+        format!("{}… ({} characters)", kept, length)
+    } else {
+        kept
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_start_of_long_texts() {
+        assert_eq!(
+            redacted_text("drake hello there", 5),
+            "drake… (17 characters)"
+        );
+        assert_eq!(redacted_text("drake", 5), "drake");
+        assert_eq!(redacted_text("hi", 5), "hi");
+    }
+
+    #[test]
+    fn counts_characters_rather_than_bytes() {
+        assert_eq!(redacted_text("héllo wörld", 3), "hél… (11 characters)");
+        assert_eq!(redacted_text("🎉🎉🎉", 2), "🎉🎉… (3 characters)");
+    }
+
+    #[test]
+    fn keeps_only_the_length_without_any_characters() {
+        assert_eq!(redacted_text("drake hello", 0), "(11 characters)");
+        assert_eq!(redacted_text("", 0), "(0 characters)");
+        assert_eq!(redacted_text("", 5), "(0 characters)");
+    }
+}
@@ -0,0 +1,457 @@
+use image::{Rgba, RgbaImage};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use rusttype::{Point, Scale};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::time::SystemTime;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+use arc_swap::ArcSwap;
+use crc32fast::Hasher;
+use log::info;
+use serenity::prelude::TypeMapKey;
+
+use crate::animation::Animation;
+use crate::casing::Transform;
+use crate::layout::{Alignment, Direction};
+use crate::raster::{StrokeJoin, TextEffect};
+use crate::schedule::{Date, Schedule};
+use crate::split_first_word;
+
+#[derive(Clone)]
+pub struct Meme {
+    pub image: RgbaImage,
+    // PNG-encoded preview of the image
+    pub thumbnail: Vec<u8>,
+    // Changes whenever the image is replaced, so that anything made from the
+    // old one can be told apart
+    pub version: String,
+    // Where the image was loaded from, which is the parent's image for memes
+    // that extend another without one of their own
+    pub filename: String,
+    // When this version of the image was loaded; reloads keep the time of
+    // images that didn't change
+    pub loaded_at: SystemTime,
+    pub font: String,
+    pub scale: Scale,
+    pub left: u32,
+    pub top: u32,
+    pub right: u32,
+    pub bottom: u32,
+    pub text_prefix: String,
+    pub text_suffix: String,
+    pub transform: Transform,
+    // Makes the random cases of `Transform::MockRandom` the same every time
+    pub transform_seed: Option<u64>,
+    pub command: String,
+    pub is_default: bool,
+    pub category: Option<String>,
+    pub description: Option<String>,
+    // Where the template image came from and under what terms, for instances
+    // that have to show their templates' provenance
+    pub license: Option<String>,
+    pub source_url: Option<String>,
+    pub hyphenate: bool,
+    pub allow_zalgo: bool,
+    pub direction: Direction,
+    pub alignment: Alignment,
+    pub text_color: Rgba<u8>,
+    pub stroke_width: u32,
+    pub stroke_color: Rgba<u8>,
+    pub stroke_join: StrokeJoin,
+    pub effect: TextEffect,
+    // Makes the meme a GIF with its text animated
+    pub animation: Option<Animation>,
+    // A command that the text is passed through before it's drawn
+    pub plugin: Option<String>,
+    // The font of fenced code blocks, instead of the meme's own font
+    pub code_font: Option<String>,
+    pub code_color: Rgba<u8>,
+    pub code_background: Option<Rgba<u8>>,
+    pub avatar_regions: Vec<AvatarRegion>,
+    pub remove_avatar_background: bool,
+    pub output_max_width: Option<u32>,
+    // How likely the meme is to be picked, relative to the others, when one is
+    // chosen at random
+    pub weight: u32,
+    // When the meme is available, for seasonal memes
+    pub schedule: Option<Schedule>,
+}
+
+impl Meme {
+    pub fn text_region(&self) -> Region {
+        Region {
+            left: self.left,
+            top: self.top,
+            right: self.right,
+            bottom: self.bottom,
+        }
+    }
+}
+
+/// Returns a checksum of the size and pixels of `image`, used as the version
+/// of a template. It stays the same across restarts and Rust versions, since
+/// it's shown to users and sent to browsers.
+pub fn template_version(image: &RgbaImage) -> String {
+    let (width, height) = image.dimensions();
+
+    let mut crc = Hasher::new();
+    crc.update(&width.to_le_bytes());
+    crc.update(&height.to_le_bytes());
+    crc.update(image);
+
+    format!("{:08x}", crc.finalize())
+}
+
+#[derive(Clone, Copy)]
+pub struct Region {
+    pub left: u32,
+    pub top: u32,
+    pub right: u32,
+    pub bottom: u32,
+}
+
+impl Region {
+    pub fn width(&self) -> u32 {
+        self.right.saturating_sub(self.left)
+    }
+
+    pub fn height(&self) -> u32 {
+        self.bottom.saturating_sub(self.top)
+    }
+
+    pub fn center(&self) -> Point<u32> {
+        Point {
+            x: (self.left + self.right) / 2,
+            y: (self.top + self.bottom) / 2,
+        }
+    }
+}
+
+/// A box that a user's avatar is pasted into, with an optional label drawn
+/// along its bottom edge
+#[derive(Clone)]
+pub struct AvatarRegion {
+    pub region: Region,
+    pub label: Option<String>,
+}
+
+static FOLD_ACCENTS: OnceLock<bool> = OnceLock::new();
+
+/// Sets whether commands match whatever accents they're written with, once
+/// when the bot starts. Before this is called, accents have to match.
+pub fn configure_accent_folding(fold_accents: bool) -> Result<(), String> {
+    if FOLD_ACCENTS.set(fold_accents).is_err() {
+        return Err("Accent folding has already been configured".into());
+    }
+
+    Ok(())
+}
+
+/// The form of `command` that commands are matched by, so that `Drake` finds
+/// `drake` and, with accent folding, `cafe` finds `café`. Lowercasing stands
+/// in for full casefolding, apart from `ß`, which folds to `ss`. The words of
+/// multi-word commands are matched with any whitespace between them.
+pub fn fold_command(command: &str) -> String {
+    let folded = command
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .to_lowercase()
+        .replace('ß', "ss");
+
+    if FOLD_ACCENTS.get().copied().unwrap_or(false) {
+        folded
+            .nfd()
+            .filter(|c| !is_combining_mark(*c))
+            .nfc()
+            .collect()
+    } else {
+        folded.nfc().collect()
+    }
+}
+
+/// All of the memes that were loaded, along with indices for looking them up.
+/// Memes that are out of season are left out of every lookup. Reloading the
+/// config swaps in a new registry, while memes that are being made keep using
+/// the one they started with.
+pub struct MemeRegistry {
+    memes: Vec<Meme>,
+    // Folded commands to the index of the meme with that command
+    commands: HashMap<String, usize>,
+    // The most words in any command
+    max_words: usize,
+    // Category names (lowercase) to the indices of the memes in them
+    categories: BTreeMap<String, Vec<usize>>,
+    // Whether each meme is in season, as of the last refresh, which doesn't
+    // need a new registry
+    active: Vec<AtomicBool>,
+}
+
+pub struct MemesKey;
+
+impl TypeMapKey for MemesKey {
+    type Value = ArcSwap<MemeRegistry>;
+}
+
+impl MemeRegistry {
+    pub fn new(memes: Vec<Meme>) -> MemeRegistry {
+        let mut commands = HashMap::<String, usize>::new();
+        let mut categories = BTreeMap::<String, Vec<usize>>::new();
+
+        for (index, meme) in memes.iter().enumerate() {
+            // The first meme with a command wins, as it did before commands
+            // were folded; the config loader warns about the others
+            commands.entry(fold_command(&meme.command)).or_insert(index);
+
+            if let Some(category) = &meme.category {
+                categories
+                    .entry(category.to_lowercase())
+                    .or_default()
+                    .push(index);
+            }
+        }
+
+        let max_words = memes
+            .iter()
+            .map(|meme| meme.command.split_whitespace().count())
+            .max()
+            .unwrap_or(0);
+        let active = memes.iter().map(|_| AtomicBool::new(true)).collect();
+
+        let registry = MemeRegistry {
+            memes,
+            commands,
+            max_words,
+            categories,
+            active,
+        };
+
+        registry.refresh_schedule(Date::today());
+
+        registry
+    }
+
+    /// Works out which memes are in season on `today`, logging the ones that
+    /// started or stopped being available
+    pub fn refresh_schedule(&self, today: Date) {
+        for (meme, active) in self.memes.iter().zip(&self.active) {
+            let is_active = match &meme.schedule {
+                Some(schedule) => schedule.is_active(today),
+                None => true,
+            };
+
+            if is_active != active.swap(is_active, Ordering::Relaxed) {
+                info!(
+                    "Meme \"{}\" is now {}",
+                    meme.command,
+                    if is_active {
+                        "in season"
+                    } else {
+                        "out of season"
+                    }
+                );
+            }
+        }
+    }
+
+    /// Returns the memes that are currently available
+    pub fn all(&self) -> Vec<&Meme> {
+        self.memes
+            .iter()
+            .zip(&self.active)
+            .filter(|(_, active)| active.load(Ordering::Relaxed))
+            .map(|(meme, _)| meme)
+            .collect()
+    }
+
+    /// Returns every meme that was loaded, even those out of season
+    pub fn loaded(&self) -> &[Meme] {
+        &self.memes
+    }
+
+    /// Returns whether there's a meme with `command`, even if it's out of
+    /// season
+    pub fn contains(&self, command: &str) -> bool {
+        self.commands.contains_key(&fold_command(command))
+    }
+
+    /// Returns the available meme with `command`, ignoring its case and, with
+    /// accent folding, its accents
+    pub fn find(&self, command: &str) -> Option<&Meme> {
+        let index = *self.commands.get(&fold_command(command))?;
+
+        if self.active[index].load(Ordering::Relaxed) {
+            Some(&self.memes[index])
+        } else {
+            None
+        }
+    }
+
+    /// Returns the available meme whose command is the longest run of words
+    /// at the start of `text`, so that `is this a pigeon` finds `is this`
+    /// before `is`, along with the text after the command
+    pub fn find_prefix<'a>(&self, text: &'a str) -> Option<(&Meme, &'a str)> {
+        let mut words = Vec::new();
+        let mut rests = Vec::new();
+        let mut rest = text;
+
+        while words.len() < self.max_words {
+            let (word, after) = split_first_word(rest);
+
+            if word.is_empty() {
+                break;
+            }
+
+            words.push(word);
+            rests.push(after);
+            rest = after;
+        }
+
+        (1..=words.len())
+            .rev()
+            .find_map(|count| Some((self.find(&words[..count].join(" "))?, rests[count - 1])))
+    }
+
+    /// Returns one of the default memes, picked at random by weight, or the
+    /// same one every time for the same `seed`
+    pub fn default_meme(&self, seed: Option<u64>) -> Option<&Meme> {
+        let defaults: Vec<&Meme> = self
+            .all()
+            .into_iter()
+            .filter(|meme| meme.is_default)
+            .collect();
+
+        choose_weighted(&defaults, seed)
+    }
+
+    /// Returns the categories that have at least one available meme
+    pub fn categories(&self) -> impl Iterator<Item = &String> {
+        let active = &self.active;
+
+        self.categories
+            .iter()
+            .filter(move |(_, indices)| {
+                indices
+                    .iter()
+                    .any(|index| active[*index].load(Ordering::Relaxed))
+            })
+            .map(|(category, _)| category)
+    }
+
+    /// Returns the available memes in `category`, or `None` if there is no
+    /// such category
+    pub fn in_category(&self, category: &str) -> Option<Vec<&Meme>> {
+        let indices = self.categories.get(&category.to_lowercase())?;
+
+        Some(
+            indices
+                .iter()
+                .filter(|index| self.active[**index].load(Ordering::Relaxed))
+                .map(|index| &self.memes[*index])
+                .collect(),
+        )
+    }
+}
+
+/// Picks one of `memes` at random, with each meme's chance proportional to its
+/// weight. The same `seed` picks the same meme from the same memes; without
+/// one, a random seed is used and logged, so that the pick can be repeated.
+pub fn choose_weighted<'a>(memes: &[&'a Meme], seed: Option<u64>) -> Option<&'a Meme> {
+    let seed = seed.unwrap_or_else(rand::random);
+
+    let meme = memes
+        .choose_weighted(&mut StdRng::seed_from_u64(seed), |meme| meme.weight)
+        .ok()
+        .copied()?;
+
+    if memes.len() > 1 {
+        info!(
+            "Picked meme \"{}\" out of {} with seed {}",
+            meme.command,
+            memes.len(),
+            seed
+        );
+    }
+
+    Some(meme)
+}
+
+#[cfg(test)]
+pub mod mock {
+    use super::*;
+
+    /// A meme for tests with a plain white 100x100 image, whose text fills
+    /// the whole image
+    pub fn meme(command: &str, is_default: bool) -> Meme {
+        Meme {
+            image: RgbaImage::from_pixel(100, 100, Rgba([255, 255, 255, 255])),
+            thumbnail: vec![1, 2, 3],
+            version: "v1".into(),
+            filename: format!("{}.png", command),
+            loaded_at: SystemTime::now(),
+            font: String::new(),
+            scale: Scale::uniform(20f32),
+            left: 0,
+            top: 0,
+            right: 100,
+            bottom: 100,
+            text_prefix: String::new(),
+            text_suffix: String::new(),
+            transform: Transform::Upper,
+            transform_seed: None,
+            command: command.into(),
+            is_default,
+            category: None,
+            description: Some(format!("The {} meme", command)),
+            license: None,
+            source_url: None,
+            hyphenate: false,
+            allow_zalgo: false,
+            direction: Direction::LeftToRight,
+            alignment: Alignment::Center,
+            text_color: Rgba([0, 0, 0, 255]),
+            stroke_width: 0,
+            stroke_color: Rgba([255, 255, 255, 255]),
+            stroke_join: StrokeJoin::Round,
+            effect: TextEffect::NONE,
+            animation: None,
+            plugin: None,
+            code_font: None,
+            code_color: Rgba([0, 0, 0, 255]),
+            code_background: None,
+            avatar_regions: Vec::new(),
+            remove_avatar_background: false,
+            output_max_width: None,
+            weight: 1,
+            schedule: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions_templates_by_their_size_and_pixels() {
+        let image = RgbaImage::from_pixel(100, 100, Rgba([255, 255, 255, 255]));
+        let version = template_version(&image);
+
+        assert_eq!(version.len(), 8);
+        assert_eq!(template_version(&image.clone()), version);
+
+        let mut changed = image.clone();
+        changed.put_pixel(50, 50, Rgba([254, 255, 255, 255]));
+
+        assert_ne!(template_version(&changed), version);
+
+        let resized = RgbaImage::from_pixel(50, 200, Rgba([255, 255, 255, 255]));
+
+        assert_ne!(template_version(&resized), version);
+    }
+}
@@ -0,0 +1,781 @@
+use crc32fast::Hasher;
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, Rgba, RgbaImage};
+use log::debug;
+use rusttype::{Font, Scale};
+use std::sync::Arc;
+
+pub use did_you_just_say_bot::output::{encode_png, OutputSettings};
+
+use crate::filters::remove_background;
+use crate::layout::{get_line_height, get_line_x, measure_text, visual_char_order, TextLayout};
+use crate::markup::parse_color_markup;
+use crate::raster::{rasterize_text, rasterize_text_range, TextEffect};
+use crate::registry::{Meme, Region};
+
+// Code that doesn't fit is shrunk, but not below this size
+const MIN_CODE_FONT_SIZE: f32 = 8f32;
+
+/// The parts of how text is drawn that can change from one meme to the next,
+/// like with flags
+#[derive(Clone, Copy)]
+pub struct TextStyle {
+    pub color: Rgba<u8>,
+    // Whether lines that are too long are wrapped to fit
+    pub wrap: bool,
+    // Seeds the meme's text effect, for effects that look random
+    pub seed: u64,
+}
+
+/// How the text is shown in one frame of an animated meme
+#[derive(Clone, Copy)]
+pub struct TextFrame {
+    // How much of the text has been typed out, from 0 (none) to 1 (all)
+    pub reveal: f32,
+    // How opaque the text is, from 0 (invisible) to 1 (as configured)
+    pub opacity: f32,
+    // How far the text is moved from where it belongs, in pixels
+    pub offset: (i32, i32),
+}
+
+impl TextFrame {
+    pub const STILL: TextFrame = TextFrame {
+        reveal: 1f32,
+        opacity: 1f32,
+        offset: (0, 0),
+    };
+}
+
+/// Returns `color` with its opacity multiplied by `opacity`
+fn fade(color: Rgba<u8>, opacity: f32) -> Rgba<u8> {
+    let mut faded = color;
+    faded[3] = (color[3] as f32 * opacity.clamp(0f32, 1f32)).round() as u8;
+
+    faded
+}
+
+/// Draws `text` into `region` of `image` in the style given, using the rest
+/// of the meme's text style, as it's shown in `frame`
+fn draw_text(
+    image: &mut RgbaImage,
+    meme: &Meme,
+    font: &Arc<Font<'static>>,
+    text: &str,
+    region: Region,
+    style: TextStyle,
+    frame: TextFrame,
+) {
+    let scale = meme.scale;
+
+    let center = region.center();
+
+    let markup = parse_color_markup(text);
+
+    let layout = measure_text(
+        font,
+        scale,
+        &markup.text,
+        Some(region.width().saturating_sub(2 * meme.stroke_width)).filter(|_| style.wrap),
+        meme.hyphenate,
+    );
+    let mut curr_y = center.y.saturating_sub(layout.height / 2);
+
+    // Lines are typed out one after another, each staying where it ends up
+    // once the text is complete
+    let total_chars: usize = layout.lines.iter().map(|line| line.chars().count()).sum();
+    let mut chars_left = (total_chars as f32 * frame.reveal.clamp(0f32, 1f32)).round() as usize;
+
+    let (offset_x, offset_y) = frame.offset;
+
+    let line_colors = if markup.has_colors() {
+        markup.line_colors(&layout.lines)
+    } else {
+        vec![Vec::new(); layout.lines.len()]
+    };
+
+    for ((line, width), colors) in layout
+        .lines
+        .iter()
+        .zip(&layout.line_widths)
+        .zip(&line_colors)
+    {
+        let line_chars = line.chars().count().min(chars_left);
+        chars_left -= line_chars;
+
+        let typed: Vec<char> = line.chars().take(line_chars).collect();
+        let order = visual_char_order(&typed.iter().collect::<String>(), meme.direction);
+
+        let line: String = order.iter().map(|index| typed[*index]).collect();
+        let colors: Vec<Option<Rgba<u8>>> = if colors.is_empty() {
+            Vec::new()
+        } else {
+            order.iter().map(|index| colors[*index]).collect()
+        };
+
+        // Unwrapped text is aligned as a block, so that ASCII art stays in
+        // one piece
+        let width = if style.wrap { *width } else { layout.width };
+
+        let x = get_line_x(
+            region.left,
+            region.right,
+            width,
+            meme.alignment,
+            meme.direction,
+        );
+
+        let x = x as i32 + offset_x;
+        let y = curr_y as i32 + offset_y;
+
+        debug!("Drawing text at ({}, {})", x, y);
+
+        let effect = TextEffect {
+            seed: style.seed,
+            ..meme.effect
+        };
+        let mask = rasterize_text(font, scale, &line, effect);
+
+        if meme.stroke_width > 0 {
+            mask.stroke(meme.stroke_width, meme.stroke_join).draw(
+                image,
+                x,
+                y,
+                fade(meme.stroke_color, frame.opacity),
+            );
+        }
+
+        if colors.iter().all(Option::is_none) {
+            mask.draw(image, x, y, fade(style.color, frame.opacity));
+        } else {
+            // Each run of characters of the same color is drawn on its own,
+            // in the place it has in the whole line
+            let mut start = 0;
+
+            while start < colors.len() {
+                let color = colors[start];
+                let end = colors[start..]
+                    .iter()
+                    .position(|other| *other != color)
+                    .map_or(colors.len(), |length| start + length);
+
+                rasterize_text_range(font, scale, &line, effect, start..end).draw(
+                    image,
+                    x,
+                    y,
+                    fade(color.unwrap_or(style.color), frame.opacity),
+                );
+
+                start = end;
+            }
+        }
+
+        curr_y += layout.line_height;
+    }
+}
+
+/// Draws `text` into a copy of the meme's image in the style given. The text
+/// is used as-is, so the meme's prefix, suffix, and casing have to be applied
+/// beforehand.
+pub fn render_meme(
+    meme: &Meme,
+    font: &Arc<Font<'static>>,
+    text: &str,
+    style: TextStyle,
+) -> RgbaImage {
+    render_meme_frame(meme, font, text, style, TextFrame::STILL)
+}
+
+/// The same as `render_meme`, with the text shown as it is in one frame of an
+/// animation
+pub fn render_meme_frame(
+    meme: &Meme,
+    font: &Arc<Font<'static>>,
+    text: &str,
+    style: TextStyle,
+    frame: TextFrame,
+) -> RgbaImage {
+    let mut image = meme.image.clone();
+
+    draw_text(
+        &mut image,
+        meme,
+        font,
+        text,
+        meme.text_region(),
+        style,
+        frame,
+    );
+
+    image
+}
+
+/// Fills `region` of `image` with `color`, rounding its corners off with
+/// `radius`
+fn fill_rounded_rect(image: &mut RgbaImage, region: Region, radius: u32, color: Rgba<u8>) {
+    let alpha = color[3] as u32;
+
+    for y in region.top..region.bottom.min(image.height()) {
+        for x in region.left..region.right.min(image.width()) {
+            // Distance into the corner's square, if the pixel is in one
+            let dx = (region.left + radius).saturating_sub(x + 1)
+                + (x + radius + 1).saturating_sub(region.right);
+            let dy = (region.top + radius).saturating_sub(y + 1)
+                + (y + radius + 1).saturating_sub(region.bottom);
+
+            if dx * dx + dy * dy > radius * radius && dx > 0 && dy > 0 {
+                continue;
+            }
+
+            let pixel = image.get_pixel_mut(x, y);
+
+            for channel in 0..3 {
+                pixel[channel] = ((pixel[channel] as u32 * (255 - alpha)
+                    + color[channel] as u32 * alpha)
+                    / 255) as u8;
+            }
+
+            pixel[3] = pixel[3].max(color[3]);
+        }
+    }
+}
+
+/// Draws `code` into a copy of the meme's image in the font given, keeping its
+/// spacing and line breaks, and shrinking it if it doesn't fit. The code is
+/// drawn over a box of `background` if there is one.
+pub fn render_code(
+    meme: &Meme,
+    font: &Arc<Font<'static>>,
+    code: &str,
+    color: Rgba<u8>,
+    background: Option<Rgba<u8>>,
+) -> RgbaImage {
+    let mut image = meme.image.clone();
+    let region = meme.text_region();
+    let code = code.replace('\t', "    ");
+
+    let padding_for = |layout: &TextLayout| match background {
+        Some(_) => layout.line_height / 2,
+        None => 0,
+    };
+
+    let mut scale = meme.scale;
+    let mut layout = measure_text(font, scale, &code, None, false);
+    let padding = padding_for(&layout);
+
+    let fit = f32::min(
+        region.width() as f32 / (layout.width + 2 * padding).max(1) as f32,
+        region.height() as f32 / (layout.height + 2 * padding).max(1) as f32,
+    );
+
+    if fit < 1f32 {
+        scale = Scale::uniform((scale.y * fit).max(MIN_CODE_FONT_SIZE));
+        layout = measure_text(font, scale, &code, None, false);
+    }
+
+    let padding = padding_for(&layout);
+    let center = region.center();
+    let left = center.x.saturating_sub(layout.width / 2);
+    let top = center.y.saturating_sub(layout.height / 2);
+
+    if let Some(background) = background {
+        fill_rounded_rect(
+            &mut image,
+            Region {
+                left: left.saturating_sub(padding),
+                top: top.saturating_sub(padding),
+                right: left + layout.width + padding,
+                bottom: top + layout.height + padding,
+            },
+            padding,
+            background,
+        );
+    }
+
+    let mut curr_y = top;
+
+    for line in &layout.lines {
+        let mask = rasterize_text(font, scale, line, TextEffect::NONE);
+
+        // An outline only gets in the way on top of a background
+        if meme.stroke_width > 0 && background.is_none() {
+            mask.stroke(meme.stroke_width, meme.stroke_join).draw(
+                &mut image,
+                left as i32,
+                curr_y as i32,
+                meme.stroke_color,
+            );
+        }
+
+        mask.draw(&mut image, left as i32, curr_y as i32, color);
+
+        curr_y += layout.line_height;
+    }
+
+    image
+}
+
+/// Pastes avatars into a copy of the meme's image, one per avatar region, and
+/// labels them. A label given for a region overrides the configured one.
+pub fn render_avatar_meme(
+    meme: &Meme,
+    font: &Arc<Font<'static>>,
+    avatars: &[(Option<RgbaImage>, Option<String>)],
+    style: TextStyle,
+) -> RgbaImage {
+    let mut image = meme.image.clone();
+    let line_height = get_line_height(font, meme.scale);
+
+    for (avatar_region, (avatar, label)) in meme.avatar_regions.iter().zip(avatars) {
+        let region = avatar_region.region;
+
+        if let Some(avatar) = avatar {
+            let mut avatar = avatar.clone();
+
+            if meme.remove_avatar_background {
+                remove_background(&mut avatar);
+            }
+
+            let avatar = DynamicImage::ImageRgba8(avatar)
+                .resize_to_fill(
+                    region.width().max(1),
+                    region.height().max(1),
+                    FilterType::Lanczos3,
+                )
+                .to_rgba();
+
+            imageops::overlay(&mut image, &avatar, region.left, region.top);
+        }
+
+        if let Some(label) = label.as_ref().or(avatar_region.label.as_ref()) {
+            let label_region = Region {
+                top: region.bottom.saturating_sub(line_height),
+                ..region
+            };
+
+            draw_text(
+                &mut image,
+                meme,
+                font,
+                label,
+                label_region,
+                style,
+                TextFrame::STILL,
+            );
+        }
+    }
+
+    image
+}
+
+// How opaque the bars of poll results are, relative to the text color, so that
+// their labels can be read on top of them
+const POLL_BAR_OPACITY: f32 = 0.4;
+
+/// Draws the results of a poll, as options and their votes, into a copy of
+/// the meme's image. Memes with avatar regions get an option as the caption of
+/// each region, as panels of a chart; the others get a bar for each option in
+/// their text region, as long as its share of the votes.
+pub fn render_poll(
+    meme: &Meme,
+    font: &Arc<Font<'static>>,
+    results: &[(String, u64)],
+    style: TextStyle,
+) -> RgbaImage {
+    let total: u64 = results.iter().map(|(_, votes)| votes).sum();
+
+    let share = |votes: u64, of: u64| (votes * of + total / 2).checked_div(total).unwrap_or(0);
+    let labels: Vec<String> = results
+        .iter()
+        .map(|(option, votes)| format!("{}: {} ({}%)", option, votes, share(*votes, 100)))
+        .collect();
+
+    if !meme.avatar_regions.is_empty() {
+        let captions: Vec<(Option<RgbaImage>, Option<String>)> = labels
+            .into_iter()
+            .map(|label| (None, Some(label)))
+            .collect();
+
+        return render_avatar_meme(meme, font, &captions, style);
+    }
+
+    let mut image = meme.image.clone();
+    let region = meme.text_region();
+    let row_height = region.height() / results.len().max(1) as u32;
+    let padding = row_height / 8;
+
+    for (index, ((_, votes), label)) in results.iter().zip(&labels).enumerate() {
+        let top = region.top + index as u32 * row_height;
+        let row = Region {
+            top,
+            bottom: top + row_height,
+            ..region
+        };
+
+        let width = share(*votes, region.width() as u64) as u32;
+
+        if width > 0 {
+            fill_rounded_rect(
+                &mut image,
+                Region {
+                    top: row.top + padding,
+                    right: region.left + width,
+                    bottom: row.bottom.saturating_sub(padding),
+                    ..region
+                },
+                padding,
+                fade(style.color, POLL_BAR_OPACITY),
+            );
+        }
+
+        draw_text(
+            &mut image,
+            meme,
+            font,
+            label,
+            row,
+            TextStyle {
+                wrap: false,
+                ..style
+            },
+            TextFrame::STILL,
+        );
+    }
+
+    image
+}
+
+/// Returns a copy of `image` with a white band added below it, holding
+/// `text` in black in the meme's font
+pub fn add_caption(
+    image: &RgbaImage,
+    meme: &Meme,
+    font: &Arc<Font<'static>>,
+    text: &str,
+) -> RgbaImage {
+    let line_height = get_line_height(font, meme.scale);
+    let padding = line_height / 2;
+
+    let layout = measure_text(
+        font,
+        meme.scale,
+        text,
+        Some(image.width().saturating_sub(2 * padding)),
+        meme.hyphenate,
+    );
+    let band_height = layout.height + 2 * padding;
+
+    let mut captioned = RgbaImage::from_pixel(
+        image.width(),
+        image.height() + band_height,
+        Rgba([255, 255, 255, 255]),
+    );
+
+    imageops::overlay(&mut captioned, image, 0, 0);
+
+    let region = Region {
+        left: padding,
+        top: image.height(),
+        right: image.width().saturating_sub(padding),
+        bottom: image.height() + band_height,
+    };
+
+    draw_text(
+        &mut captioned,
+        meme,
+        font,
+        text,
+        region,
+        TextStyle {
+            color: Rgba([0, 0, 0, 255]),
+            wrap: true,
+            seed: 0,
+        },
+        TextFrame::STILL,
+    );
+
+    captioned
+}
+
+/// Scales `image` down to `max_width` if it's wider, keeping its aspect ratio
+pub fn fit_to_width(image: RgbaImage, max_width: Option<u32>) -> RgbaImage {
+    match max_width {
+        Some(max_width) if image.width() > max_width => {
+            let height = ((image.height() as u64 * max_width as u64) / image.width() as u64).max(1);
+
+            imageops::resize(&image, max_width, height as u32, FilterType::Lanczos3)
+        }
+        _ => image,
+    }
+}
+
+/// Adds each of `entries`, a keyword and its text, to `png` as an
+/// international text chunk, right after the header
+fn add_png_text(png: Vec<u8>, entries: &[(&str, &str)]) -> Vec<u8> {
+    // The signature and the header chunk, which always has 13 bytes of data
+    const HEADER_END: usize = 8 + 4 + 4 + 13 + 4;
+
+    if png.len() < HEADER_END {
+        return png;
+    }
+
+    let mut chunks = Vec::new();
+
+    for (keyword, text) in entries {
+        // The keyword is followed by no compression, no language tag, and no
+        // translated keyword
+        let mut data = b"iTXt".to_vec();
+        data.extend_from_slice(keyword.as_bytes());
+        data.extend_from_slice(&[0, 0, 0, 0, 0]);
+        data.extend_from_slice(text.as_bytes());
+
+        chunks.extend_from_slice(&((data.len() - 4) as u32).to_be_bytes());
+        chunks.extend_from_slice(&data);
+        let mut crc = Hasher::new();
+        crc.update(&data);
+
+        chunks.extend_from_slice(&crc.finalize().to_be_bytes());
+    }
+
+    let mut output = png;
+    output.splice(HEADER_END..HEADER_END, chunks);
+
+    output
+}
+
+/// Encodes a finished meme, naming its template's license and source in the
+/// PNG if `settings` says to
+pub fn encode_meme_png(
+    image: &RgbaImage,
+    meme: &Meme,
+    settings: OutputSettings,
+) -> Result<Vec<u8>, String> {
+    let png = encode_png(image, settings)?;
+
+    if !settings.embed_attribution {
+        return Ok(png);
+    }
+
+    let mut entries = Vec::new();
+
+    if let Some(license) = &meme.license {
+        entries.push(("Copyright", license.as_str()));
+    }
+
+    if let Some(source_url) = &meme.source_url {
+        entries.push(("Source URL", source_url.as_str()));
+    }
+
+    Ok(add_png_text(png, &entries))
+}
+
+// Thumbnails fit into a square of this size
+const THUMBNAIL_SIZE: u32 = 128;
+
+/// Returns a small PNG preview of `image`, which is quick to upload in listings
+pub fn make_thumbnail(image: &RgbaImage) -> Result<Vec<u8>, String> {
+    let scale = (THUMBNAIL_SIZE as f32 / image.width().max(image.height()) as f32).min(1f32);
+
+    let width = ((image.width() as f32 * scale).round() as u32).max(1);
+    let height = ((image.height() as f32 * scale).round() as u32).max(1);
+
+    encode_png(
+        &imageops::thumbnail(image, width, height),
+        OutputSettings::default(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::Alignment;
+    use crate::registry::mock::meme;
+    use rusttype::FontCollection;
+
+    const WRAPPED: TextStyle = TextStyle {
+        color: Rgba([0, 0, 0, 255]),
+        wrap: true,
+        seed: 0,
+    };
+
+    const UNWRAPPED: TextStyle = TextStyle {
+        wrap: false,
+        ..WRAPPED
+    };
+
+    /// The first installed font that can be opened
+    fn system_font() -> Option<Arc<Font<'static>>> {
+        let mut system_fonts = fontdb::Database::new();
+        system_fonts.load_system_fonts();
+
+        let font = system_fonts.faces().find_map(|face| {
+            system_fonts
+                .with_face_data(face.id, |data, index| {
+                    FontCollection::from_bytes(data.to_vec())
+                        .ok()?
+                        .font_at(index as usize)
+                        .ok()
+                })
+                .flatten()
+        });
+
+        font.map(Arc::new)
+    }
+
+    /// A white meme `width` by `height` pixels, with its text drawn within
+    /// a 20 pixel margin
+    fn blank_meme(width: u32, height: u32, alignment: Alignment) -> Meme {
+        Meme {
+            image: RgbaImage::from_pixel(width, height, Rgba([255, 255, 255, 255])),
+            scale: Scale::uniform(32f32),
+            left: 20,
+            top: 20,
+            right: width - 20,
+            bottom: height - 20,
+            alignment,
+            ..meme("blank", false)
+        }
+    }
+
+    /// The left, top, right, and bottom edges of what was drawn in `rows` of
+    /// `image`, with the right and bottom edges just past the last pixel
+    fn ink_bounds(image: &RgbaImage, rows: std::ops::Range<u32>) -> Option<(u32, u32, u32, u32)> {
+        let mut bounds: Option<(u32, u32, u32, u32)> = None;
+
+        for (x, y, pixel) in image.enumerate_pixels() {
+            if !rows.contains(&y) || *pixel == Rgba([255, 255, 255, 255]) {
+                continue;
+            }
+
+            bounds = Some(match bounds {
+                Some((left, top, right, bottom)) => {
+                    (left.min(x), top.min(y), right.max(x + 1), bottom.max(y + 1))
+                }
+                None => (x, y, x + 1, y + 1),
+            });
+        }
+
+        bounds
+    }
+
+    /// Checks that `text` is drawn where `measure_text` says it is: each line
+    /// in its own band of the measured height, starting at the left of the
+    /// text region and ending at the line's measured width
+    fn assert_drawn_as_measured(font: &Arc<Font<'static>>, text: &str, style: TextStyle) {
+        let meme = blank_meme(400, 400, Alignment::Left);
+        let region = meme.text_region();
+        let layout = measure_text(
+            font,
+            meme.scale,
+            text,
+            Some(region.width()).filter(|_| style.wrap),
+            meme.hyphenate,
+        );
+        let image = render_meme(&meme, font, text, style);
+
+        let top = region.center().y - layout.height / 2;
+        let (_, ink_top, _, ink_bottom) = ink_bounds(&image, 0..image.height()).unwrap();
+
+        assert!(ink_top >= top, "{:?}", text);
+        assert!(ink_bottom <= top + layout.height, "{:?}", text);
+
+        for (index, (line, width)) in layout.lines.iter().zip(&layout.line_widths).enumerate() {
+            let line_top = top + index as u32 * layout.line_height;
+            let bounds = ink_bounds(&image, line_top..line_top + layout.line_height);
+
+            if line.is_empty() {
+                assert_eq!(bounds, None, "{:?}", line);
+                continue;
+            }
+
+            // Antialiasing can leave the outermost column of a glyph blank
+            let (left, _, right, _) = bounds.unwrap();
+            // Unwrapped lines can run off the edge of the image
+            let measured_right = (region.left + width).min(image.width());
+
+            assert!(left >= region.left, "{:?}", line);
+            assert!(right <= measured_right + 1, "{:?}", line);
+            assert!(right + 2 >= measured_right, "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn draws_text_as_measured() {
+        let font = match system_font() {
+            Some(font) => font,
+            None => {
+                eprintln!("Skipping: no fonts are installed");
+                return;
+            }
+        };
+
+        let texts = [
+            "did you just say",
+            "top text\nbottom",
+            "top text\n\nbottom text",
+            "did you just say that the build is green when nobody ran the tests",
+        ];
+
+        for text in texts.iter() {
+            assert_drawn_as_measured(&font, text, WRAPPED);
+            assert_drawn_as_measured(&font, text, UNWRAPPED);
+        }
+    }
+
+    #[test]
+    fn aligns_unwrapped_text_as_a_block() {
+        let font = match system_font() {
+            Some(font) => font,
+            None => {
+                eprintln!("Skipping: no fonts are installed");
+                return;
+            }
+        };
+
+        let meme = blank_meme(600, 300, Alignment::Center);
+        let text = "did\ndid you just say";
+        let line_height = get_line_height(&font, meme.scale);
+        let top = meme.text_region().center().y - line_height;
+        let first_line = top..top + line_height;
+
+        // Wrapped lines are each centered, so the short one starts further in
+        let wrapped = render_meme(&meme, &font, text, WRAPPED);
+        let (wrapped_left, ..) = ink_bounds(&wrapped, first_line.clone()).unwrap();
+        let (block_left, ..) = ink_bounds(&wrapped, 0..wrapped.height()).unwrap();
+
+        assert!(wrapped_left > block_left + 10);
+
+        // Unwrapped lines all start where the widest one does
+        let unwrapped = render_meme(&meme, &font, text, UNWRAPPED);
+        let (unwrapped_left, ..) = ink_bounds(&unwrapped, first_line).unwrap();
+
+        assert!(unwrapped_left <= block_left + 2);
+    }
+
+    #[test]
+    fn leaves_long_lines_unwrapped() {
+        let font = match system_font() {
+            Some(font) => font,
+            None => {
+                eprintln!("Skipping: no fonts are installed");
+                return;
+            }
+        };
+
+        let meme = blank_meme(300, 300, Alignment::Left);
+        let text = "did you just say that the build is green";
+        let line_height = get_line_height(&font, meme.scale);
+
+        let wrapped = render_meme(&meme, &font, text, WRAPPED);
+        let (_, top, right, bottom) = ink_bounds(&wrapped, 0..wrapped.height()).unwrap();
+
+        assert!(right <= meme.right);
+        assert!(bottom - top > line_height);
+
+        // Without wrapping, the line runs off the right edge of the image
+        let unwrapped = render_meme(&meme, &font, text, UNWRAPPED);
+        let (_, top, right, bottom) = ink_bounds(&unwrapped, 0..unwrapped.height()).unwrap();
+
+        assert_eq!(right, unwrapped.width());
+        assert!(bottom - top <= line_height);
+    }
+}
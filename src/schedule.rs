@@ -0,0 +1,192 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A calendar date in UTC
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct Date {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+}
+
+impl Date {
+    pub fn today() -> Date {
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|now| now.as_secs())
+            .unwrap_or(0);
+
+        Date::from_days_since_epoch((seconds / 86_400) as i64)
+    }
+
+    // Converts days since 1970-01-01 to a date in the proleptic Gregorian
+    // calendar; see http://howardhinnant.github.io/date_algorithms.html
+    fn from_days_since_epoch(days: i64) -> Date {
+        let days = days + 719_468;
+        let era = days.div_euclid(146_097);
+        let day_of_era = days.rem_euclid(146_097);
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+        let month = if shifted_month < 10 {
+            shifted_month + 3
+        } else {
+            shifted_month - 9
+        } as u32;
+        let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        Date { year, month, day }
+    }
+
+    /// Whether this is the yearly anniversary of `(month, day)`. February 29
+    /// falls on February 28 in years without one.
+    pub fn is_anniversary(&self, (month, day): (u32, u32)) -> bool {
+        let is_leap_year = self.year % 4 == 0 && (self.year % 100 != 0 || self.year % 400 == 0);
+
+        if (month, day) == (2, 29) && !is_leap_year {
+            return (self.month, self.day) == (2, 28);
+        }
+
+        (self.month, self.day) == (month, day)
+    }
+}
+
+/// When a seasonal meme is available
+#[derive(Clone, Copy)]
+pub enum Schedule {
+    // Between two dates, inclusive; either end may be open
+    Dates {
+        from: Option<Date>,
+        until: Option<Date>,
+    },
+    // Between two days of every year, inclusive, as (month, day); the range
+    // may wrap around the new year
+    Yearly {
+        from: (u32, u32),
+        until: (u32, u32),
+    },
+}
+
+enum DateSpec {
+    Date(Date),
+    MonthDay(u32, u32),
+}
+
+fn days_in_month(month: u32) -> u32 {
+    match month {
+        2 => 29,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Parses `YYYY-MM-DD` as a date, or `MM-DD` as a day of every year
+fn parse_date(text: &str) -> Option<DateSpec> {
+    let parts: Vec<&str> = text.trim().split('-').collect();
+
+    let (year, month, day) = match parts.as_slice() {
+        [year, month, day] if year.len() == 4 => (Some(year.parse::<i64>().ok()?), month, day),
+        [month, day] => (None, month, day),
+        _ => return None,
+    };
+
+    let month = month.parse::<u32>().ok()?;
+    let day = day.parse::<u32>().ok()?;
+
+    if !(1..=12).contains(&month) || day < 1 || day > days_in_month(month) {
+        return None;
+    }
+
+    match year {
+        Some(year) => Some(DateSpec::Date(Date { year, month, day })),
+        None => Some(DateSpec::MonthDay(month, day)),
+    }
+}
+
+/// Parses `MM-DD` as a day of the year, or takes it from a date like
+/// `YYYY-MM-DD`
+pub fn parse_month_day(text: &str) -> Option<(u32, u32)> {
+    match parse_date(text)? {
+        DateSpec::Date(date) => Some((date.month, date.day)),
+        DateSpec::MonthDay(month, day) => Some((month, day)),
+    }
+}
+
+impl Schedule {
+    /// Builds a schedule from a meme's `active_from` and `active_until`, which
+    /// have to be either both dates or both days of the year
+    pub fn parse(from: Option<&str>, until: Option<&str>) -> Result<Option<Schedule>, String> {
+        let parse = |text: Option<&str>| match text {
+            Some(text) => match parse_date(text) {
+                Some(date) => Ok(Some(date)),
+                None => Err(format!(
+                    "\"{}\" is not a date like 2020-10-31 or a day of the year like 10-31",
+                    text
+                )),
+            },
+            None => Ok(None),
+        };
+
+        match (parse(from)?, parse(until)?) {
+            (None, None) => Ok(None),
+            (
+                Some(DateSpec::MonthDay(from_month, from_day)),
+                Some(DateSpec::MonthDay(until_month, until_day)),
+            ) => Ok(Some(Schedule::Yearly {
+                from: (from_month, from_day),
+                until: (until_month, until_day),
+            })),
+            (Some(DateSpec::MonthDay(_, _)), _) | (_, Some(DateSpec::MonthDay(_, _))) => Err(
+                "A day of the year needs to be used for both active_from and active_until".into(),
+            ),
+            (from, until) => {
+                let date = |spec: Option<DateSpec>| match spec {
+                    Some(DateSpec::Date(date)) => Some(date),
+                    _ => None,
+                };
+
+                Ok(Some(Schedule::Dates {
+                    from: date(from),
+                    until: date(until),
+                }))
+            }
+        }
+    }
+
+    /// Returns the `active_from` and `active_until` that the schedule is
+    /// parsed from
+    pub fn bounds(&self) -> (Option<String>, Option<String>) {
+        match *self {
+            Schedule::Dates { from, until } => {
+                let format =
+                    |date: Date| format!("{:04}-{:02}-{:02}", date.year, date.month, date.day);
+
+                (from.map(format), until.map(format))
+            }
+            Schedule::Yearly { from, until } => {
+                let format = |(month, day): (u32, u32)| format!("{:02}-{:02}", month, day);
+
+                (Some(format(from)), Some(format(until)))
+            }
+        }
+    }
+
+    pub fn is_active(&self, today: Date) -> bool {
+        match *self {
+            Schedule::Dates { from, until } => {
+                !matches!(from, Some(from) if today < from)
+                    && !matches!(until, Some(until) if today > until)
+            }
+            Schedule::Yearly { from, until } => {
+                let today = (today.month, today.day);
+
+                if from <= until {
+                    from <= today && today <= until
+                } else {
+                    today >= from || today <= until
+                }
+            }
+        }
+    }
+}
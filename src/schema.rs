@@ -0,0 +1,72 @@
+/// JSON Schema for `config.yml`, printed by `--print-schema` so that editors
+/// and CI can validate config files. Keep it in step with `load_memes` when
+/// adding or changing keys.
+pub const CONFIG_SCHEMA: &str = r##"{
+  "$schema": "http://json-schema.org/draft-07/schema#",
+  "title": "did_you_just_say config",
+  "type": "array",
+  "items": {
+    "type": "object",
+    "properties": {
+      "filename": { "type": "string", "description": "Path of the template image" },
+      "font": { "type": "string", "description": "Path or URL of a font file, or the family name of an installed font" },
+      "font_size": { "type": "integer", "minimum": 1, "default": 12 },
+      "left": { "type": "integer", "minimum": 0, "default": 0 },
+      "top": { "type": "integer", "minimum": 0, "default": 0 },
+      "right": { "type": "integer", "minimum": 1, "description": "Defaults to the width of the image" },
+      "bottom": { "type": "integer", "minimum": 1, "description": "Defaults to the height of the image" },
+      "text_prefix": { "type": "string", "default": "" },
+      "text_suffix": { "type": "string", "default": "" },
+      "transform": { "enum": ["upper", "mock", "mock_random"], "default": "upper" },
+      "transform_seed": { "type": "integer", "minimum": 0, "description": "Makes mock_random pick the same cases every time" },
+      "plugin": { "type": "string", "minLength": 1, "description": "A command that the text is passed through on stdin and stdout" },
+      "command": { "type": "string" },
+      "is_default": { "type": "boolean", "default": false },
+      "gallery_url": { "type": "string", "description": "The gallery image the meme was installed from by sync" },
+      "weight": { "type": "integer", "minimum": 1, "default": 1, "description": "How likely the meme is to be picked among the default memes or by random" },
+      "category": { "type": "string" },
+      "description": { "type": "string" },
+      "license": { "type": "string", "description": "The license of the template image, like CC BY-SA 4.0" },
+      "source_url": { "type": "string", "description": "Where the template image came from" },
+      "hyphenate": { "type": "boolean", "default": false },
+      "allow_zalgo": { "type": "boolean", "default": false },
+      "direction": { "enum": ["ltr", "rtl"], "default": "ltr" },
+      "align": { "enum": ["left", "center", "right", "start", "end"], "default": "center" },
+      "text_color": { "type": "string", "default": "black", "description": "Hex code like #ff0066, rgb()/rgba(), or a CSS color name" },
+      "stroke_color": { "type": "string", "default": "white", "description": "Hex code like #ff0066, rgb()/rgba(), or a CSS color name" },
+      "stroke_width": { "type": "integer", "minimum": 0, "default": 0 },
+      "stroke_join": { "enum": ["round", "miter", "bevel"], "default": "round" },
+      "code_font": { "type": "string", "description": "Font for fenced code blocks, like a monospace font (default: font)" },
+      "code_color": { "type": "string", "description": "Color of code, written like text_color (default: text_color)" },
+      "code_background": { "type": "string", "description": "Color of a rounded box drawn behind code, written like text_color (default: none)" },
+      "effect": { "enum": ["none", "wave", "arc", "jitter"], "default": "none" },
+      "effect_amplitude": { "type": "number", "description": "How far glyphs are moved, in pixels (default: an eighth of font_size)" },
+      "effect_frequency": { "type": "number", "exclusiveMinimum": 0, "default": 1, "description": "How many waves fit in a line, for wave" },
+      "animate": { "enum": ["none", "typewriter", "fade", "shake"], "default": "none", "description": "Animates the text, making the meme a GIF" },
+      "animate_duration_ms": { "type": "integer", "minimum": 100, "maximum": 10000, "default": 2000 },
+      "animate_fps": { "type": "integer", "minimum": 1, "maximum": 50, "default": 10 },
+      "avatar_regions": {
+        "type": "array",
+        "items": {
+          "type": "object",
+          "properties": {
+            "left": { "type": "integer", "minimum": 0 },
+            "top": { "type": "integer", "minimum": 0 },
+            "right": { "type": "integer", "minimum": 0 },
+            "bottom": { "type": "integer", "minimum": 0 },
+            "label": { "type": "string" }
+          },
+          "required": ["left", "top", "right", "bottom"],
+          "additionalProperties": false
+        }
+      },
+      "remove_avatar_background": { "type": "boolean", "default": false },
+      "output_max_width": { "type": "integer", "minimum": 1, "description": "Wider memes are scaled down to this width" },
+      "active_from": { "type": "string", "pattern": "^([0-9]{4}-)?[0-9]{2}-[0-9]{2}$", "description": "First day the meme is available, as YYYY-MM-DD or MM-DD for every year" },
+      "active_until": { "type": "string", "pattern": "^([0-9]{4}-)?[0-9]{2}-[0-9]{2}$", "description": "Last day the meme is available, as YYYY-MM-DD or MM-DD for every year" },
+      "extends": { "type": "string", "description": "Command of an earlier meme to inherit settings from" }
+    },
+    "anyOf": [{ "required": ["filename"] }, { "required": ["extends"] }],
+    "additionalProperties": false
+  }
+}"##;
@@ -0,0 +1,86 @@
+use crate::registry::{Meme, MemeRegistry};
+
+// Only this many of the best matches are returned
+const MAX_RESULTS: usize = 10;
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+
+    for i in 1..=a.len() {
+        current[0] = i;
+
+        for j in 1..=b.len() {
+            let substitution = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+
+            current[j] = (previous[j] + 1)
+                .min(current[j - 1] + 1)
+                .min(previous[j - 1] + substitution);
+        }
+
+        std::mem::swap(&mut previous, &mut current);
+    }
+
+    previous[b.len()]
+}
+
+fn score_term(meme: &Meme, term: &str) -> u32 {
+    let command = meme.command.to_lowercase();
+
+    if command == term {
+        return 100;
+    }
+
+    if command.starts_with(term) {
+        return 50;
+    }
+
+    if command.contains(term) {
+        return 30;
+    }
+
+    // Allow for typos, more of them the longer the term is
+    let distance = edit_distance(&command, term);
+
+    if distance <= (term.chars().count() / 3).max(1) {
+        return 20 - 5 * distance as u32;
+    }
+
+    if let Some(description) = &meme.description {
+        if description.to_lowercase().contains(term) {
+            return 10;
+        }
+    }
+
+    0
+}
+
+/// Returns the memes whose commands or descriptions best match `query`, best
+/// matches first. Every word of the query counts towards a meme's score, so
+/// memes matching more of the words rank higher.
+pub fn search<'a>(memes: &'a MemeRegistry, query: &str) -> Vec<&'a Meme> {
+    let terms: Vec<String> = query
+        .split_whitespace()
+        .map(|term| term.to_lowercase())
+        .collect();
+
+    let mut results: Vec<(u32, &Meme)> = memes
+        .all()
+        .into_iter()
+        .map(|meme| (terms.iter().map(|term| score_term(meme, term)).sum(), meme))
+        .filter(|(score, _)| *score > 0)
+        .collect();
+
+    results.sort_by(|(a_score, a), (b_score, b)| {
+        b_score.cmp(a_score).then_with(|| a.command.cmp(&b.command))
+    });
+
+    results
+        .into_iter()
+        .take(MAX_RESULTS)
+        .map(|(_, meme)| meme)
+        .collect()
+}
@@ -0,0 +1,162 @@
+use crc32fast::Hasher;
+use std::fs::read;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::fetch;
+
+/// Where the config file and the templates and fonts it names are read from
+pub trait TemplateStore {
+    /// Returns the contents of the file at `path`
+    fn read(&self, path: &str) -> Result<Vec<u8>, String>;
+
+    fn exists(&self, path: &str) -> bool;
+}
+
+/// Reads files from the local filesystem, with paths relative to the working
+/// directory
+pub struct LocalStore;
+
+impl TemplateStore for LocalStore {
+    fn read(&self, path: &str) -> Result<Vec<u8>, String> {
+        match read(path) {
+            Ok(contents) => Ok(contents),
+            Err(reason) => Err(format!("Unable to read file \"{}\": {}", path, reason)),
+        }
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        Path::new(path).exists()
+    }
+}
+
+/// Reads files over HTTP, with paths relative to a base URL. This works with
+/// object storage buckets that are publicly readable or served through a
+/// gateway, such as `https://storage.googleapis.com/<bucket>/` or
+/// `https://<bucket>.s3.amazonaws.com/`.
+pub struct HttpStore {
+    base_url: String,
+}
+
+impl HttpStore {
+    pub fn new(base_url: &str) -> HttpStore {
+        HttpStore {
+            base_url: base_url.trim_end_matches('/').into(),
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/{}", self.base_url, path.trim_start_matches('/'))
+    }
+}
+
+impl TemplateStore for HttpStore {
+    fn read(&self, path: &str) -> Result<Vec<u8>, String> {
+        fetch::download(&self.url(path), fetch::MAX_FILE_BYTES)
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        fetch::exists(&self.url(path))
+    }
+}
+
+/// Turns `filename`, which comes from a meme's command and can hold anything,
+/// into a name that needs no escaping in a URL: lowercase ASCII letters,
+/// digits, `.`, `_`, and `-`
+fn url_safe_name(filename: &str) -> String {
+    let name: String = filename
+        .chars()
+        .map(|c| match c.to_ascii_lowercase() {
+            c @ ('a'..='z' | '0'..='9' | '.' | '_' | '-') => c,
+            _ => '-',
+        })
+        .collect();
+
+    // A name made only of dots would be read as a relative path
+    if name.chars().all(|c| c == '.') {
+        format!("meme{}", name)
+    } else {
+        name
+    }
+}
+
+/// Where memes too large to attach to a message are uploaded instead, so that
+/// a link to them can be posted. Files are sent with a PUT request to
+/// `<upload URL>/<name>` and linked to at `<public URL>/<name>`, which works
+/// with object storage buckets that accept uploads and with simple file
+/// servers.
+pub struct UploadStore {
+    upload_url: String,
+    public_url: String,
+}
+
+impl UploadStore {
+    pub fn new(upload_url: &str, public_url: Option<&str>) -> UploadStore {
+        UploadStore {
+            upload_url: upload_url.trim_end_matches('/').into(),
+            public_url: public_url
+                .unwrap_or(upload_url)
+                .trim_end_matches('/')
+                .into(),
+        }
+    }
+
+    /// Uploads `contents` under a name made unique from `filename`, returning
+    /// the URL it can be seen at
+    pub fn upload(
+        &self,
+        filename: &str,
+        contents: Vec<u8>,
+        content_type: &str,
+    ) -> Result<String, String> {
+        let mut crc = Hasher::new();
+        crc.update(&contents);
+
+        let uploaded_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|now| now.as_nanos())
+            .unwrap_or(0);
+
+        let name = format!(
+            "{:x}-{:08x}-{}",
+            uploaded_at,
+            crc.finalize(),
+            url_safe_name(filename)
+        );
+
+        fetch::upload(
+            &format!("{}/{}", self.upload_url, name),
+            contents,
+            content_type,
+        )?;
+
+        Ok(format!("{}/{}", self.public_url, name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_safe_names() {
+        assert_eq!(url_safe_name("drake.png"), "drake.png");
+        assert_eq!(url_safe_name("two_buttons-2.gif"), "two_buttons-2.gif");
+    }
+
+    #[test]
+    fn replaces_characters_that_need_escaping() {
+        assert_eq!(
+            url_safe_name("Is This A Pigeon?.png"),
+            "is-this-a-pigeon-.png"
+        );
+        assert_eq!(url_safe_name("a/b#c%d.png"), "a-b-c-d.png");
+        assert_eq!(url_safe_name("café ☕.png"), "caf---.png");
+    }
+
+    #[test]
+    fn doesnt_make_relative_paths() {
+        assert_eq!(url_safe_name(".."), "meme..");
+        assert_eq!(url_safe_name(""), "meme");
+    }
+}
@@ -0,0 +1,149 @@
+use image::{Rgba, RgbaImage};
+use imageproc::drawing::draw_hollow_rect_mut;
+use imageproc::rect::Rect;
+
+use crate::registry::Region;
+
+// The image is examined in square cells of this many pixels
+const CELL_SIZE: u32 = 8;
+
+// Cells whose brightness varies less than this (as a standard deviation, on a
+// scale of 0 to 255) count as blank
+const MAX_BLANK_DEVIATION: f32 = 12f32;
+
+fn is_blank_cell(image: &RgbaImage, cell_x: u32, cell_y: u32) -> bool {
+    let mut sum = 0f32;
+    let mut sum_of_squares = 0f32;
+    let mut count = 0f32;
+
+    for y in cell_y * CELL_SIZE..((cell_y + 1) * CELL_SIZE).min(image.height()) {
+        for x in cell_x * CELL_SIZE..((cell_x + 1) * CELL_SIZE).min(image.width()) {
+            let pixel = image.get_pixel(x, y);
+            let alpha = pixel[3] as f32 / 255f32;
+
+            let luma =
+                (0.299 * pixel[0] as f32 + 0.587 * pixel[1] as f32 + 0.114 * pixel[2] as f32)
+                    * alpha;
+
+            sum += luma;
+            sum_of_squares += luma * luma;
+            count += 1f32;
+        }
+    }
+
+    if count == 0f32 {
+        return false;
+    }
+
+    let mean = sum / count;
+    let variance = (sum_of_squares / count - mean * mean).max(0f32);
+
+    variance.sqrt() < MAX_BLANK_DEVIATION
+}
+
+/// Finds the largest rectangle of blank cells, returning its column, row,
+/// width, and height in cells
+fn largest_blank_rectangle(
+    blank: &[Vec<bool>],
+    columns: usize,
+) -> Option<(usize, usize, usize, usize)> {
+    // For each row, treat the number of blank cells stacked above each column
+    // as a histogram and find the largest rectangle under it
+    let mut heights = vec![0usize; columns];
+    let mut best: Option<(usize, usize, usize, usize)> = None;
+    let mut best_area = 0;
+
+    for (row, cells) in blank.iter().enumerate() {
+        for column in 0..columns {
+            heights[column] = if cells[column] {
+                heights[column] + 1
+            } else {
+                0
+            };
+        }
+
+        let mut stack = Vec::<usize>::new();
+
+        for column in 0..=columns {
+            let height = if column < columns { heights[column] } else { 0 };
+
+            while let Some(&top) = stack.last() {
+                if heights[top] < height {
+                    break;
+                }
+
+                stack.pop();
+
+                let left = stack.last().map_or(0, |&left| left + 1);
+                let width = column - left;
+                let area = width * heights[top];
+
+                if area > best_area {
+                    best_area = area;
+                    best = Some((left, row + 1 - heights[top], width, heights[top]));
+                }
+            }
+
+            stack.push(column);
+        }
+    }
+
+    best
+}
+
+/// Suggests a region for the text of a template: the largest rectangle of the
+/// image that is close to a single flat color
+pub fn suggest_region(image: &RgbaImage) -> Option<Region> {
+    let columns = (image.width() / CELL_SIZE) as usize;
+    let rows = (image.height() / CELL_SIZE) as usize;
+
+    let blank: Vec<Vec<bool>> = (0..rows)
+        .map(|row| {
+            (0..columns)
+                .map(|column| is_blank_cell(image, column as u32, row as u32))
+                .collect()
+        })
+        .collect();
+
+    let (column, row, width, height) = largest_blank_rectangle(&blank, columns)?;
+
+    Some(Region {
+        left: column as u32 * CELL_SIZE,
+        top: row as u32 * CELL_SIZE,
+        right: (column + width) as u32 * CELL_SIZE,
+        bottom: (row + height) as u32 * CELL_SIZE,
+    })
+}
+
+/// Returns a copy of `image` with `region` highlighted
+pub fn draw_region_preview(image: &RgbaImage, region: Region) -> RgbaImage {
+    let mut preview = image.clone();
+    let highlight = Rgba([255, 0, 128, 255]);
+
+    for y in region.top..region.bottom.min(preview.height()) {
+        for x in region.left..region.right.min(preview.width()) {
+            let pixel = preview.get_pixel_mut(x, y);
+
+            for channel in 0..3 {
+                pixel[channel] =
+                    ((pixel[channel] as u32 * 3 + highlight[channel] as u32) / 4) as u8;
+            }
+        }
+    }
+
+    // Draw a thick border so that the region stands out on large templates
+    for inset in 0..3 {
+        if region.width() <= 2 * inset || region.height() <= 2 * inset {
+            break;
+        }
+
+        draw_hollow_rect_mut(
+            &mut preview,
+            Rect::at((region.left + inset) as i32, (region.top + inset) as i32)
+                .of_size(region.width() - 2 * inset, region.height() - 2 * inset),
+            highlight,
+        );
+    }
+
+    preview
+}
@@ -0,0 +1,61 @@
+use log::{debug, warn};
+use std::time::{Duration, Instant};
+
+/// Durations of the phases of handling a request, like rendering and
+/// uploading a meme
+pub struct Timings {
+    started: Instant,
+    phase_started: Instant,
+    phases: Vec<(&'static str, Duration)>,
+}
+
+impl Timings {
+    pub fn start() -> Timings {
+        let now = Instant::now();
+
+        Timings {
+            started: now,
+            phase_started: now,
+            phases: Vec::new(),
+        }
+    }
+
+    /// Records the time since the previous phase ended as the duration of
+    /// `phase`
+    pub fn end_phase(&mut self, phase: &'static str) {
+        let now = Instant::now();
+
+        self.phases.push((phase, now - self.phase_started));
+        self.phase_started = now;
+    }
+
+    fn breakdown(&self) -> String {
+        self.phases
+            .iter()
+            .map(|(phase, duration)| format!("{} {}ms", phase, duration.as_millis()))
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+
+    /// Logs the breakdown, as a warning if the whole request took longer than
+    /// `budget`
+    pub fn report(&self, request: &str, budget: Duration) {
+        let total = self.started.elapsed();
+
+        if total > budget {
+            warn!(
+                "{} took {}ms ({})",
+                request,
+                total.as_millis(),
+                self.breakdown()
+            );
+        } else {
+            debug!(
+                "{} took {}ms ({})",
+                request,
+                total.as_millis(),
+                self.breakdown()
+            );
+        }
+    }
+}
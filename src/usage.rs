@@ -0,0 +1,72 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serenity::prelude::TypeMapKey;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// How many times each meme has been created since the bot started, by day, so
+/// that days past the retention window can be forgotten. Memes are created
+/// while the data is only borrowed, so the counts have a lock of their own.
+#[derive(Default)]
+pub struct UsageCounts {
+    // Commands to their counts on each day since the Unix epoch
+    counts: Mutex<HashMap<String, BTreeMap<u64, u64>>>,
+}
+
+pub struct UsageCountsKey;
+
+impl TypeMapKey for UsageCountsKey {
+    type Value = UsageCounts;
+}
+
+fn today() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs() / SECONDS_PER_DAY)
+        .unwrap_or(0)
+}
+
+impl UsageCounts {
+    pub fn record(&self, command: &str) {
+        *self
+            .counts
+            .lock()
+            .expect("Usage counts: Unable to lock counts")
+            .entry(command.into())
+            .or_default()
+            .entry(today())
+            .or_default() += 1;
+    }
+
+    pub fn get(&self, command: &str) -> u64 {
+        self.counts
+            .lock()
+            .expect("Usage counts: Unable to lock counts")
+            .get(command)
+            .map_or(0, |days| days.values().sum())
+    }
+
+    /// Forgets the counts from days that ended more than `retention` ago,
+    /// returning how many days' counts were forgotten
+    pub fn forget_older_than(&self, retention: Duration) -> usize {
+        let oldest_kept = today().saturating_sub(retention.as_secs() / SECONDS_PER_DAY);
+        let mut forgotten = 0;
+
+        let mut counts = self
+            .counts
+            .lock()
+            .expect("Usage counts: Unable to lock counts");
+
+        for days in counts.values_mut() {
+            let before = days.len();
+            *days = days.split_off(&oldest_kept);
+            forgotten += before - days.len();
+        }
+
+        counts.retain(|_, days| !days.is_empty());
+
+        forgotten
+    }
+}
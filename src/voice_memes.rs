@@ -0,0 +1,277 @@
+use log::{info, warn};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use serenity::model::id::{ChannelId, GuildId};
+use serenity::model::prelude::Message;
+use serenity::model::voice::VoiceState;
+use serenity::prelude::{Context, TypeMapKey};
+
+use crate::event_memes::{fill_placeholders, post_user_meme};
+use crate::guild_settings::{GuildSettingsKey, VoiceMemes};
+use crate::permissions::is_manager;
+use crate::redact;
+use crate::registry::MemesKey;
+use crate::split_first_word;
+
+const DEFAULT_TEXT: &str = "{user} joined {channel}";
+
+// Each user gets at most one meme this often, so that reconnecting doesn't
+// flood the channel
+const COOLDOWN: Duration = Duration::from_secs(10 * 60);
+
+pub struct VoiceJoinsKey;
+
+impl TypeMapKey for VoiceJoinsKey {
+    // Guild and user IDs to when the user last got a meme there
+    type Value = HashMap<(u64, u64), Instant>;
+}
+
+/// Forgets the joins whose cooldown is over, returning how many there were
+pub fn forget_expired_joins(joins: &mut HashMap<(u64, u64), Instant>) -> usize {
+    let before = joins.len();
+    joins.retain(|_, joined| joined.elapsed() < COOLDOWN);
+
+    before - joins.len()
+}
+
+fn describe(voice_memes: Option<&VoiceMemes>) -> String {
+    let voice_memes = match voice_memes {
+        Some(voice_memes) => voice_memes,
+        None => {
+            return "This server doesn't post voice memes. (A manager can turn them on with `voicememes here <meme>`.)".into()
+        }
+    };
+
+    let users = if voice_memes.users.is_empty() {
+        "nobody yet (add users with `voicememes add @user`)".to_string()
+    } else {
+        voice_memes
+            .users
+            .iter()
+            .map(|user| format!("<@{}>", user))
+            .collect::<Vec<String>>()
+            .join(", ")
+    };
+
+    format!(
+        "`{}` is posted in <#{}> with the text \"{}\" when one of these users joins a voice channel: {}",
+        voice_memes.meme, voice_memes.channel, voice_memes.text, users
+    )
+}
+
+/// Works out the new voice meme settings for the command's `action`, or why it
+/// can't be done
+fn change(
+    ctx: &Context,
+    msg: &Message,
+    current: Option<VoiceMemes>,
+    action: &str,
+    rest: &str,
+) -> Result<Option<VoiceMemes>, String> {
+    match action {
+        "here" => {
+            let (command, text) = split_first_word(rest);
+            let command = command.to_lowercase();
+
+            if command.is_empty() {
+                return Err("Usage: `voicememes here <meme> [text]`".into());
+            }
+
+            if !ctx
+                .data
+                .read()
+                .get::<MemesKey>()
+                .expect("Command voicememes: Unable to retrieve memes")
+                .load()
+                .contains(&command)
+            {
+                return Err(format!("There's no meme called `{}`.", command));
+            }
+
+            let text = match text.trim() {
+                "" => DEFAULT_TEXT.to_string(),
+                text => text.to_string(),
+            };
+
+            Ok(Some(VoiceMemes {
+                channel: msg.channel_id.0,
+                meme: command,
+                text,
+                users: current.map(|current| current.users).unwrap_or_default(),
+            }))
+        }
+        "off" => Ok(None),
+        "add" | "remove" => {
+            let mut voice_memes = match current {
+                Some(voice_memes) => voice_memes,
+                None => {
+                    return Err(
+                        "Choose the meme first, with `voicememes here <meme> [text]`.".into(),
+                    )
+                }
+            };
+
+            // The bot itself is mentioned too, when the command is sent in a
+            // server
+            let users: Vec<_> = msg.mentions.iter().filter(|user| !user.bot).collect();
+
+            if users.is_empty() {
+                return Err(format!("Usage: `voicememes {} @user`", action));
+            }
+
+            for user in users {
+                voice_memes.users.retain(|id| *id != user.id.0);
+
+                if action == "add" {
+                    voice_memes.users.push(user.id.0);
+                }
+            }
+
+            Ok(Some(voice_memes))
+        }
+        _ => Err("Usage: `voicememes [here <meme> [text]|add @user|remove @user|off]`".into()),
+    }
+}
+
+pub fn voice_memes_command(ctx: &Context, msg: &Message, rest: &str) {
+    let guild_id = match msg.guild_id {
+        Some(guild_id) => guild_id.0,
+        None => {
+            msg.channel_id
+                .say(ctx, "Voice memes can only be set up in a server.")
+                .ok();
+            return;
+        }
+    };
+
+    let (action, rest) = split_first_word(rest);
+    let action = action.to_lowercase();
+
+    let current = ctx
+        .data
+        .read()
+        .get::<GuildSettingsKey>()
+        .expect("Command voicememes: Unable to retrieve guild settings")
+        .get(guild_id)
+        .and_then(|settings| settings.voice_memes.clone());
+
+    if action.is_empty() {
+        msg.channel_id.say(ctx, describe(current.as_ref())).ok();
+        return;
+    }
+
+    if !is_manager(ctx, msg) {
+        msg.channel_id
+            .say(
+                ctx,
+                "You need to be a manager of this server (or have the Manage Server permission) to do that.",
+            )
+            .ok();
+        return;
+    }
+
+    let voice_memes = match change(ctx, msg, current, &action, rest) {
+        Ok(voice_memes) => voice_memes,
+        Err(reason) => {
+            msg.channel_id.say(ctx, reason).ok();
+            return;
+        }
+    };
+
+    let reply = describe(voice_memes.as_ref());
+
+    let mut data = ctx.data.write();
+    let guild_settings = data
+        .get_mut::<GuildSettingsKey>()
+        .expect("Command voicememes: Unable to retrieve guild settings");
+
+    guild_settings.get_mut(guild_id).voice_memes = voice_memes;
+
+    if let Err(reason) = guild_settings.save() {
+        warn!("Command voicememes: {}", reason);
+    }
+
+    drop(data);
+
+    msg.channel_id.say(ctx, reply).ok();
+}
+
+/// Posts the guild's voice meme when one of its chosen users connects to a
+/// voice channel. Moving between channels doesn't count.
+pub fn handle_voice_state(
+    ctx: &Context,
+    guild_id: Option<GuildId>,
+    old: Option<VoiceState>,
+    new: &VoiceState,
+) {
+    let (guild_id, voice_channel) = match (guild_id, new.channel_id) {
+        (Some(guild_id), Some(voice_channel)) if old.and_then(|old| old.channel_id).is_none() => {
+            (guild_id.0, voice_channel)
+        }
+        _ => return,
+    };
+
+    let user_id = new.user_id.0;
+
+    let mut data = ctx.data.write();
+
+    let voice_memes = match data
+        .get::<GuildSettingsKey>()
+        .expect("Voice memes: Unable to retrieve guild settings")
+        .get(guild_id)
+        .and_then(|settings| settings.voice_memes.clone())
+    {
+        Some(voice_memes) if voice_memes.users.contains(&user_id) => voice_memes,
+        _ => return,
+    };
+
+    let joins = data
+        .get_mut::<VoiceJoinsKey>()
+        .expect("Voice memes: Unable to retrieve voice joins");
+
+    if joins
+        .get(&(guild_id, user_id))
+        .is_some_and(|joined| joined.elapsed() < COOLDOWN)
+    {
+        return;
+    }
+
+    joins.insert((guild_id, user_id), Instant::now());
+
+    drop(data);
+
+    let user = match new.user_id.to_user(ctx) {
+        Ok(user) => user,
+        Err(reason) => {
+            warn!(
+                "Voice memes: Unable to retrieve user {}: {}",
+                redact::user_id(user_id),
+                reason
+            );
+            return;
+        }
+    };
+
+    let channel_name = voice_channel
+        .name(ctx)
+        .unwrap_or_else(|| "voice".to_string());
+    let text = fill_placeholders(&voice_memes.text, &user, &[("channel", &channel_name)]);
+
+    info!(
+        "Posting voice meme \"{}\" for {} in guild {}",
+        voice_memes.meme,
+        redact::user(&user),
+        guild_id
+    );
+
+    post_user_meme(
+        ctx,
+        guild_id,
+        ChannelId(voice_memes.channel),
+        &voice_memes.meme,
+        &user,
+        &text,
+        None,
+    );
+}
@@ -0,0 +1,184 @@
+use log::{debug, warn};
+use reqwest::Url;
+use std::collections::HashMap;
+use tiny_http::{Header, Request, Response};
+
+use crate::code_block::find_code_block;
+use crate::flags::take_flags;
+use crate::http_api::respond_with_error;
+use crate::registry::MemeRegistry;
+use crate::render::{encode_png, render_avatar_meme, render_code, render_meme, OutputSettings};
+use crate::suggest::draw_region_preview;
+use crate::{meme_text, select_code_font, select_font, Fonts};
+
+fn query_params(url: &str) -> HashMap<String, String> {
+    match Url::parse(&format!("http://localhost{}", url)) {
+        Ok(url) => url.query_pairs().into_owned().collect(),
+        Err(_) => HashMap::new(),
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Renders `text` onto `template` like the bot would, then highlights the
+/// regions the template's config defines: the text area, or the avatars of
+/// memes with avatars. Returns the image along with the template's version.
+fn render_preview(
+    fonts: &Fonts,
+    memes: &MemeRegistry,
+    template: &str,
+    text: &str,
+) -> Result<(Vec<u8>, String), (u16, String)> {
+    let meme = match memes.find(template) {
+        Some(meme) => meme,
+        None => return Err((404, format!("Unknown template \"{}\"", template))),
+    };
+
+    let font = match select_font(fonts, meme) {
+        Some(font) => font,
+        None => return Err((500, "No fonts loaded".into())),
+    };
+
+    let (text, flags) = take_flags(text).map_err(|reason| (400, reason))?;
+    let style = flags.text_style(meme);
+
+    debug!("Web preview: Creating meme \"{}\"", meme.command);
+
+    let code = find_code_block(&text).filter(|_| meme.avatar_regions.is_empty());
+
+    let mut image = if let Some(code) = code {
+        render_code(
+            meme,
+            select_code_font(fonts, meme).unwrap_or(font),
+            code,
+            flags.color.unwrap_or(meme.code_color),
+            meme.code_background,
+        )
+    } else if meme.avatar_regions.is_empty() {
+        render_meme(
+            meme,
+            font,
+            &meme_text(meme, &text, None, flags.transform_seed(meme)),
+            style,
+        )
+    } else {
+        render_avatar_meme(
+            meme,
+            font,
+            &vec![(None, None); meme.avatar_regions.len()],
+            style,
+        )
+    };
+
+    if meme.avatar_regions.is_empty() {
+        image = draw_region_preview(&image, meme.text_region());
+    } else {
+        for avatar_region in &meme.avatar_regions {
+            image = draw_region_preview(&image, avatar_region.region);
+        }
+    }
+
+    encode_png(&image, OutputSettings::default())
+        .map(|png| (png, meme.version.clone()))
+        .map_err(|reason| (500, reason))
+}
+
+/// A form for picking a template and text, showing the preview below it
+fn preview_page(memes: &MemeRegistry, template: &str, text: &str) -> String {
+    let options: String = memes
+        .all()
+        .iter()
+        .map(|meme| {
+            format!(
+                "<option{}>{}</option>",
+                if meme.command == template {
+                    " selected"
+                } else {
+                    ""
+                },
+                escape_html(&meme.command)
+            )
+        })
+        .collect();
+
+    let image = if template.is_empty() {
+        String::new()
+    } else {
+        // The version keeps browsers from showing a cached preview of a
+        // template that has since been replaced
+        let version = memes
+            .find(template)
+            .map(|meme| meme.version.as_str())
+            .unwrap_or("");
+
+        let source = Url::parse_with_params(
+            "http://localhost/preview",
+            &[("template", template), ("text", text), ("v", version)],
+        )
+        .map(|url| format!("/preview?{}", url.query().unwrap_or("")))
+        .unwrap_or_default();
+
+        format!(
+            "<p><img src=\"{}\" alt=\"Preview\"></p>",
+            escape_html(&source)
+        )
+    };
+
+    format!(
+        "<!DOCTYPE html>
+<html>
+<head><meta charset=\"utf-8\"><title>Meme preview</title></head>
+<body>
+<form action=\"/\" method=\"get\">
+<select name=\"template\">{}</select>
+<input name=\"text\" value=\"{}\" size=\"60\">
+<button>Preview</button>
+</form>
+{}
+</body>
+</html>
+",
+        options,
+        escape_html(text),
+        image
+    )
+}
+
+/// Serves `GET /`, the preview page, and `GET /preview`, the preview image
+pub fn handle_request(fonts: &Fonts, memes: &MemeRegistry, request: Request) {
+    let params = query_params(request.url());
+    let template = params.get("template").map(String::as_str).unwrap_or("");
+    let text = params.get("text").map(String::as_str).unwrap_or("");
+
+    let response = if request.url().starts_with("/preview") {
+        match render_preview(fonts, memes, template, text) {
+            Ok((png, version)) => Response::from_data(png)
+                .with_header(
+                    Header::from_bytes("Content-Type", "image/png")
+                        .expect("Web preview: Unable to create Content-Type header"),
+                )
+                .with_header(
+                    Header::from_bytes("X-Template-Version", version)
+                        .expect("Web preview: Unable to create X-Template-Version header"),
+                ),
+            Err((status, reason)) => {
+                respond_with_error(request, status, &reason);
+                return;
+            }
+        }
+    } else {
+        Response::from_string(preview_page(memes, template, text)).with_header(
+            Header::from_bytes("Content-Type", "text/html; charset=utf-8")
+                .expect("Web preview: Unable to create Content-Type header"),
+        )
+    };
+
+    if let Err(reason) = request.respond(response) {
+        warn!("Web preview: Unable to send response: {}", reason);
+    }
+}
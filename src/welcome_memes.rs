@@ -0,0 +1,164 @@
+use log::{info, warn};
+
+use serenity::model::guild::Member;
+use serenity::model::id::{ChannelId, GuildId};
+use serenity::model::prelude::Message;
+use serenity::prelude::Context;
+
+use crate::event_memes::{fill_placeholders, post_user_meme};
+use crate::guild_settings::{GuildSettingsKey, WelcomeMeme};
+use crate::permissions::is_manager;
+use crate::redact;
+use crate::registry::MemesKey;
+use crate::split_first_word;
+
+const DEFAULT_TEXT: &str = "Welcome {user}";
+
+fn describe(welcome_meme: Option<&WelcomeMeme>) -> String {
+    match welcome_meme {
+        Some(welcome_meme) => format!(
+            "New members are welcomed in <#{}> with `{}` and the text \"{}\".",
+            welcome_meme.channel, welcome_meme.meme, welcome_meme.text
+        ),
+        None => "This server doesn't welcome new members with a meme. (A manager can turn it on with `welcome here <meme>`.)".into(),
+    }
+}
+
+pub fn welcome_command(ctx: &Context, msg: &Message, rest: &str) {
+    let guild_id = match msg.guild_id {
+        Some(guild_id) => guild_id.0,
+        None => {
+            msg.channel_id
+                .say(ctx, "Welcome memes can only be set up in a server.")
+                .ok();
+            return;
+        }
+    };
+
+    let (action, rest) = split_first_word(rest);
+    let action = action.to_lowercase();
+
+    if action.is_empty() {
+        let welcome_meme = ctx
+            .data
+            .read()
+            .get::<GuildSettingsKey>()
+            .expect("Command welcome: Unable to retrieve guild settings")
+            .get(guild_id)
+            .and_then(|settings| settings.welcome_meme.clone());
+
+        msg.channel_id
+            .say(ctx, describe(welcome_meme.as_ref()))
+            .ok();
+        return;
+    }
+
+    if !is_manager(ctx, msg) {
+        msg.channel_id
+            .say(
+                ctx,
+                "You need to be a manager of this server (or have the Manage Server permission) to do that.",
+            )
+            .ok();
+        return;
+    }
+
+    let welcome_meme = match action.as_str() {
+        "here" => {
+            let (command, text) = split_first_word(rest);
+            let command = command.to_lowercase();
+
+            if command.is_empty() {
+                msg.channel_id
+                    .say(ctx, "Usage: `welcome here <meme> [text]`")
+                    .ok();
+                return;
+            }
+
+            if !ctx
+                .data
+                .read()
+                .get::<MemesKey>()
+                .expect("Command welcome: Unable to retrieve memes")
+                .load()
+                .contains(&command)
+            {
+                msg.channel_id
+                    .say(ctx, format!("There's no meme called `{}`.", command))
+                    .ok();
+                return;
+            }
+
+            Some(WelcomeMeme {
+                channel: msg.channel_id.0,
+                meme: command,
+                text: match text.trim() {
+                    "" => DEFAULT_TEXT.to_string(),
+                    text => text.to_string(),
+                },
+            })
+        }
+        "off" => None,
+        _ => {
+            msg.channel_id
+                .say(ctx, "Usage: `welcome [here <meme> [text]|off]`")
+                .ok();
+            return;
+        }
+    };
+
+    let reply = describe(welcome_meme.as_ref());
+
+    let mut data = ctx.data.write();
+    let guild_settings = data
+        .get_mut::<GuildSettingsKey>()
+        .expect("Command welcome: Unable to retrieve guild settings");
+
+    guild_settings.get_mut(guild_id).welcome_meme = welcome_meme;
+
+    if let Err(reason) = guild_settings.save() {
+        warn!("Command welcome: {}", reason);
+    }
+
+    drop(data);
+
+    msg.channel_id.say(ctx, reply).ok();
+}
+
+/// Posts the guild's welcome meme for a member who just joined
+pub fn handle_member_addition(ctx: &Context, guild_id: GuildId, member: &Member) {
+    let user = member.user.read().clone();
+
+    if user.bot {
+        return;
+    }
+
+    let welcome_meme = match ctx
+        .data
+        .read()
+        .get::<GuildSettingsKey>()
+        .expect("Welcome memes: Unable to retrieve guild settings")
+        .get(guild_id.0)
+        .and_then(|settings| settings.welcome_meme.clone())
+    {
+        Some(welcome_meme) => welcome_meme,
+        None => return,
+    };
+
+    info!(
+        "Posting welcome meme \"{}\" for {} in guild {}",
+        welcome_meme.meme,
+        redact::user(&user),
+        guild_id
+    );
+
+    post_user_meme(
+        ctx,
+        guild_id.0,
+        ChannelId(welcome_meme.channel),
+        &welcome_meme.meme,
+        &user,
+        &fill_placeholders(&welcome_meme.text, &user, &[]),
+        None,
+    );
+}
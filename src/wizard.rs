@@ -0,0 +1,463 @@
+use image::RgbaImage;
+use log::{info, warn};
+use regex::Regex;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use serenity::model::prelude::Message;
+use serenity::prelude::{Context, TypeMapKey};
+
+use crate::decode::{decode_image, MAX_IMAGE_BYTES};
+use crate::fetch;
+use crate::flags::Flags;
+use crate::meme_edits::add_meme;
+use crate::moderation;
+use crate::redact;
+use crate::registry::{MemesKey, Region};
+use crate::render::{encode_png, render_meme, OutputSettings};
+use crate::suggest::{draw_region_preview, suggest_region};
+use crate::{meme_text, select_font, FontsKey};
+
+// Wizards that haven't been answered for this long are abandoned
+const WIZARD_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+// Words that the bot handles itself, which would hide a meme with that command
+pub const RESERVED_COMMANDS: &[&str] = &[
+    "about",
+    "accept-terms",
+    "addmeme",
+    "admins",
+    "aliases",
+    "announcements",
+    "auth",
+    "broadcast",
+    "calibrate",
+    "dumpconfig",
+    "emojistrip",
+    "event",
+    "factcheck",
+    "features",
+    "forgetme",
+    "help",
+    "hotkeys",
+    "import",
+    "locale",
+    "makeemoji",
+    "managers",
+    "memes",
+    "pollmeme",
+    "preset",
+    "purgeuser",
+    "quit",
+    "random",
+    "reload",
+    "revoke",
+    "removememe",
+    "search",
+    "setregion",
+    "suggestregion",
+    "sync",
+    "undo",
+    "voicememes",
+    "welcome",
+];
+
+const SAMPLE_TEXT: &str = "Sample text";
+
+enum Step {
+    Image,
+    Command,
+    Style,
+    Region,
+}
+
+/// A meme being added with `addmeme`, one answer at a time
+pub struct MemeWizard {
+    step: Step,
+    last_answer: Instant,
+    image: Option<RgbaImage>,
+    command: Option<String>,
+    // The command of the meme whose font and text style are used
+    parent: Option<String>,
+    region: Option<Region>,
+}
+
+pub struct WizardsKey;
+
+impl TypeMapKey for WizardsKey {
+    // User IDs to their wizards
+    type Value = HashMap<u64, MemeWizard>;
+}
+
+/// Starts adding a meme for the author of `msg`, replacing any wizard they
+/// already had going
+pub fn start_wizard(ctx: &Context, msg: &Message) {
+    ctx.data
+        .write()
+        .get_mut::<WizardsKey>()
+        .expect("Command addmeme: Unable to retrieve wizards")
+        .insert(
+            msg.author.id.0,
+            MemeWizard {
+                step: Step::Image,
+                last_answer: Instant::now(),
+                image: None,
+                command: None,
+                parent: None,
+                region: None,
+            },
+        );
+
+    msg.channel_id
+        .say(
+            ctx,
+            "Let's add a meme! First, send the template image, either attached or as a link. (Send `cancel` at any point to stop.)",
+        )
+        .ok();
+}
+
+fn send_preview(ctx: &Context, msg: &Message, image: &RgbaImage, content: &str) {
+    match encode_png(image, OutputSettings::default()) {
+        Ok(preview) => {
+            msg.channel_id
+                .send_files(ctx, vec![(&preview[..], "preview.png")], |m| {
+                    m.content(content)
+                })
+                .ok();
+        }
+        Err(reason) => {
+            warn!("Command addmeme: {}", reason);
+
+            msg.channel_id.say(ctx, content).ok();
+        }
+    }
+}
+
+pub fn parse_region(text: &str, image: &RgbaImage) -> Result<Region, String> {
+    let numbers: Vec<u32> = text
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|part| !part.is_empty())
+        .map(|part| part.parse::<u32>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| "Send the text box as four numbers: `left top right bottom`.".to_string())?;
+
+    let region = match numbers.as_slice() {
+        [left, top, right, bottom] => Region {
+            left: *left,
+            top: *top,
+            right: *right,
+            bottom: *bottom,
+        },
+        _ => return Err("Send the text box as four numbers: `left top right bottom`.".into()),
+    };
+
+    if region.left >= region.right || region.top >= region.bottom {
+        return Err("The right edge has to be past the left, and the bottom below the top.".into());
+    }
+
+    if region.right > image.width() || region.bottom > image.height() {
+        return Err(format!(
+            "The text box has to fit in the image, which is {}x{}.",
+            image.width(),
+            image.height()
+        ));
+    }
+
+    Ok(region)
+}
+
+fn answer_image(msg: &Message, wizard: &mut MemeWizard) -> Result<String, String> {
+    let url = match msg.attachments.first() {
+        Some(attachment) => attachment.url.clone(),
+        None if fetch::is_url(msg.content.trim()) => msg.content.trim().to_string(),
+        None => return Err("Send the template image, either attached or as a link.".into()),
+    };
+
+    let contents = fetch::download(&url, MAX_IMAGE_BYTES)?;
+    moderation::check(
+        &contents,
+        &format!("template image from {}", redact::user(&msg.author)),
+    )?;
+
+    let image = decode_image(&contents)?;
+    let reply = format!(
+        "Got it ({}x{}). What command should the meme have?",
+        image.width(),
+        image.height()
+    );
+
+    wizard.image = Some(image);
+    wizard.step = Step::Command;
+
+    Ok(reply)
+}
+
+/// Checks that `command` can be used for a new meme
+pub fn check_command(ctx: &Context, command: &str) -> Result<(), String> {
+    let re_command = Regex::new(r"^[\p{Ll}\p{Lo}\p{N}_-]{1,32}$")
+        .expect("Unable to create command matching pattern");
+
+    if !re_command.is_match(command) {
+        return Err("Commands are a single word of up to 32 letters, numbers, `-`, or `_`.".into());
+    }
+
+    if RESERVED_COMMANDS.contains(&command) {
+        return Err(format!("`{}` is one of the bot's own commands.", command));
+    }
+
+    if ctx
+        .data
+        .read()
+        .get::<MemesKey>()
+        .expect("Add meme: Unable to retrieve memes")
+        .load()
+        .contains(command)
+    {
+        return Err(format!("There's already a meme called `{}`.", command));
+    }
+
+    Ok(())
+}
+
+fn answer_command(ctx: &Context, text: &str, wizard: &mut MemeWizard) -> Result<String, String> {
+    let command = text.trim().to_lowercase();
+
+    check_command(ctx, &command)?;
+
+    wizard.command = Some(command);
+    wizard.step = Step::Style;
+
+    Ok("Which meme's font and text style should it use? Send that meme's command.".into())
+}
+
+fn answer_style(
+    ctx: &Context,
+    msg: &Message,
+    text: &str,
+    wizard: &mut MemeWizard,
+) -> Result<(), String> {
+    let parent = text.trim().to_lowercase();
+
+    let data = ctx.data.read();
+    let memes = data
+        .get::<MemesKey>()
+        .expect("Command addmeme: Unable to retrieve memes")
+        .load();
+
+    match memes.find(&parent) {
+        Some(meme) if meme.avatar_regions.is_empty() => {}
+        Some(_) => return Err("Pick a meme without avatars.".into()),
+        None => return Err(format!("There's no meme called `{}`.", parent)),
+    }
+
+    drop(data);
+
+    wizard.parent = Some(parent);
+    wizard.step = Step::Region;
+
+    let image = wizard.image.as_ref().expect("Wizard has no image");
+
+    match suggest_region(image) {
+        Some(region) => {
+            send_preview(
+                ctx,
+                msg,
+                &draw_region_preview(image, region),
+                &format!(
+                    "Where should the text go? Send the box as `left top right bottom`, or `auto` to use the highlighted area ({} {} {} {}).",
+                    region.left, region.top, region.right, region.bottom
+                ),
+            );
+        }
+        None => {
+            msg.channel_id
+                .say(
+                    ctx,
+                    "Where should the text go? Send the box as `left top right bottom`.",
+                )
+                .ok();
+        }
+    }
+
+    Ok(())
+}
+
+fn answer_region(
+    ctx: &Context,
+    msg: &Message,
+    text: &str,
+    wizard: &mut MemeWizard,
+) -> Result<(), String> {
+    let image = wizard.image.as_ref().expect("Wizard has no image");
+
+    let region =
+        if text.trim().eq_ignore_ascii_case("auto") {
+            match suggest_region(image) {
+                Some(region) => region,
+                None => return Err(
+                    "I couldn't find any blank area, so send the box as `left top right bottom`."
+                        .into(),
+                ),
+            }
+        } else {
+            parse_region(text, image)?
+        };
+
+    wizard.region = Some(region);
+
+    let data = ctx.data.read();
+    let memes = data
+        .get::<MemesKey>()
+        .expect("Command addmeme: Unable to retrieve memes")
+        .load();
+    let fonts = data
+        .get::<FontsKey>()
+        .expect("Command addmeme: Unable to retrieve fonts");
+
+    let parent = match memes.find(wizard.parent.as_deref().unwrap_or("")) {
+        Some(parent) => parent,
+        None => {
+            return Err(
+                "The meme whose style this uses is gone. Send `cancel` and start over.".into(),
+            )
+        }
+    };
+
+    let mut preview = parent.clone();
+    preview.image = image.clone();
+    preview.left = region.left;
+    preview.top = region.top;
+    preview.right = region.right;
+    preview.bottom = region.bottom;
+
+    // Rendering can take a while, so it's done without holding on to the data
+    let font = select_font(fonts, &preview).cloned();
+    drop(data);
+
+    let rendered = match &font {
+        Some(font) => render_meme(
+            &preview,
+            font,
+            &meme_text(&preview, SAMPLE_TEXT, None, preview.transform_seed),
+            Flags::default().text_style(&preview),
+        ),
+        None => draw_region_preview(image, region),
+    };
+
+    send_preview(
+        ctx,
+        msg,
+        &rendered,
+        "Here's how it looks. Send `done` to save the meme, or another box to move the text.",
+    );
+
+    Ok(())
+}
+
+fn finish(ctx: &Context, wizard: &MemeWizard) -> Result<String, String> {
+    let image = wizard.image.as_ref().expect("Wizard has no image");
+    let command = wizard.command.clone().expect("Wizard has no command");
+    let parent = wizard.parent.clone().expect("Wizard has no parent");
+    let region = wizard.region.expect("Wizard has no region");
+
+    add_meme(ctx, &command, &parent, image, region)?;
+
+    Ok(command)
+}
+
+/// Handles a DM from someone in the middle of adding a meme, returning
+/// whether it was an answer to the wizard
+pub fn handle_wizard_message(ctx: &Context, msg: &Message) -> bool {
+    let user_id = msg.author.id.0;
+
+    let mut wizard = match ctx
+        .data
+        .write()
+        .get_mut::<WizardsKey>()
+        .expect("Command addmeme: Unable to retrieve wizards")
+        .remove(&user_id)
+    {
+        Some(wizard) if wizard.last_answer.elapsed() < WIZARD_TIMEOUT => wizard,
+        _ => return false,
+    };
+
+    let text = msg.content.trim();
+
+    if text.eq_ignore_ascii_case("cancel") {
+        msg.channel_id
+            .say(ctx, "Okay, the meme won't be added.")
+            .ok();
+        return true;
+    }
+
+    let result = match wizard.step {
+        Step::Image => answer_image(msg, &mut wizard).map(Some),
+        Step::Command => answer_command(ctx, text, &mut wizard).map(Some),
+        Step::Style => answer_style(ctx, msg, text, &mut wizard).map(|_| None),
+        Step::Region if text.eq_ignore_ascii_case("done") && wizard.region.is_some() => {
+            match finish(ctx, &wizard) {
+                Ok(command) => {
+                    info!(
+                        "Meme \"{}\" added by {}",
+                        command,
+                        redact::user(&msg.author)
+                    );
+
+                    msg.channel_id
+                        .say(
+                            ctx,
+                            format!("Added `{}`! Try it with `{} some text`.", command, command),
+                        )
+                        .ok();
+                }
+                Err(reason) => {
+                    warn!("Command addmeme: {}", reason);
+
+                    msg.channel_id
+                        .say(ctx, format!("Unable to add the meme: {}", reason))
+                        .ok();
+                }
+            }
+
+            return true;
+        }
+        Step::Region => answer_region(ctx, msg, text, &mut wizard).map(|_| None),
+    };
+
+    match result {
+        Ok(Some(reply)) => {
+            msg.channel_id.say(ctx, reply).ok();
+        }
+        Ok(None) => {}
+        Err(reason) => {
+            msg.channel_id.say(ctx, reason).ok();
+        }
+    }
+
+    wizard.last_answer = Instant::now();
+
+    ctx.data
+        .write()
+        .get_mut::<WizardsKey>()
+        .expect("Command addmeme: Unable to retrieve wizards")
+        .insert(user_id, wizard);
+
+    true
+}
+
+#[cfg(test)]
+pub mod mock {
+    use super::*;
+
+    /// A wizard waiting for its template image
+    pub fn wizard() -> MemeWizard {
+        MemeWizard {
+            step: Step::Image,
+            last_answer: Instant::now(),
+            image: None,
+            command: None,
+            parent: None,
+            region: None,
+        }
+    }
+}
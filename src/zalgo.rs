@@ -0,0 +1,30 @@
+use regex::Regex;
+use std::sync::OnceLock;
+
+// Accents rarely stack deeper than this in real text, like Vietnamese "ệ"
+const MAX_MARKS: usize = 2;
+
+// Memes that allow zalgo text still cut stacks of marks down to this height,
+// since taller ones take ages to draw and reach far into the lines around them
+const MAX_ZALGO_MARKS: usize = 16;
+
+static RE_MARKS: OnceLock<Regex> = OnceLock::new();
+static RE_ZALGO_MARKS: OnceLock<Regex> = OnceLock::new();
+
+fn marks_pattern(max_marks: usize) -> Regex {
+    Regex::new(&format!(r"(\p{{M}}{{{}}})\p{{M}}+", max_marks))
+        .expect("Unable to create combining mark matching pattern")
+}
+
+/// Removes the combining marks that zalgo text piles onto its letters. The
+/// first couple of marks on each letter are kept so that accents survive, or
+/// many more with `allow_zalgo`.
+pub fn strip_zalgo(text: &str, allow_zalgo: bool) -> String {
+    let re_marks = if allow_zalgo {
+        RE_ZALGO_MARKS.get_or_init(|| marks_pattern(MAX_ZALGO_MARKS))
+    } else {
+        RE_MARKS.get_or_init(|| marks_pattern(MAX_MARKS))
+    };
+
+    re_marks.replace_all(text, "$1").into_owned()
+}